@@ -0,0 +1,431 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::str::Chars;
+
+use cfgrammar::TIdx;
+use cfgrammar::yacc::{yacc_grm, YaccGrammar, YaccKind};
+use lrlex::{build_lex, Lexeme, LexerDef};
+use lrpar::{Channel, lexeme_str, Node, ParseError, RecoveryKind, RTParserBuilder, TokenId};
+use lrtable::{Minimiser, from_yacc, StateGraph, StateTable};
+use num_traits::ToPrimitive;
+
+/// The lexer used by `JsonParser`. Any character which can't start a JSON token is lexed as an
+/// `ERROR` lexeme, so that lexing never fails.
+pub const JSON_LEXER: &str = include_str!("json.l");
+
+/// The grammar used by `JsonParser`. `ERROR` is never valid, so the parser always recovers from
+/// `ERROR` lexemes.
+pub const JSON_GRAMMAR: &str = include_str!("json.y");
+
+/// A JSON parser built at run-time from `JSON_LEXER` and `JSON_GRAMMAR` (rather than at
+/// compile-time, as in the `calc` example, so that its error recovery can be tuned). It is both a
+/// complete example of using lrpar (a lexer, a grammar, a typed AST, and error recovery tuned to
+/// the language) and a parser which is useful in its own right, e.g. for configuration files
+/// which should be read despite minor mistakes.
+///
+/// Recovery is tuned so that repairs make the fewest guesses about the input's content: inserting
+/// or deleting punctuation costs 1, a string or number 2, and `true`, `false`, or `null` 3. Thus
+/// `[1 2]` is repaired by inserting a `,` rather than deleting `2`.
+pub struct JsonParser {
+    lexerdef: LexerDef<u8>,
+    grm: YaccGrammar,
+    sgraph: StateGraph,
+    stable: StateTable,
+    term_costs: Box<Fn(TIdx) -> u8>,
+    error_tok_id: u8
+}
+
+impl JsonParser {
+    /// Build the lexer and the parser's tables.
+    pub fn new() -> Self {
+        let grm = yacc_grm(YaccKind::Original, JSON_GRAMMAR).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let mut lexerdef = build_lex(JSON_LEXER).unwrap();
+        {
+            let rule_ids = grm.terms_map().iter()
+                                          .map(|(&n, &i)| (n, u32::from(i).to_u8().unwrap()))
+                                          .collect();
+            lexerdef.set_rule_ids(&rule_ids);
+        }
+        let costs = (0..grm.terms_len()).map(|i| term_cost(grm.term_name(TIdx::from(i))))
+                                        .collect::<Vec<_>>();
+        let error_tok_id = u32::from(grm.term_idx("ERROR").unwrap()).to_u8().unwrap();
+        JsonParser{lexerdef,
+                   grm,
+                   sgraph,
+                   stable,
+                   term_costs: Box::new(move |t_idx| costs[usize::from(t_idx)]),
+                   error_tok_id}
+    }
+
+    /// Return the grammar built from `JSON_GRAMMAR`.
+    pub fn grammar(&self) -> &YaccGrammar {
+        &self.grm
+    }
+
+    /// Lex `input`. `ERROR` lexemes are put on `Channel::Error` (see `TokenId::channel`).
+    pub fn lex(&self, input: &str) -> Vec<Lexeme<u8>> {
+        self.lexerdef.lexer(input)
+                     .lexemes()
+                     .unwrap()
+                     .into_iter()
+                     .map(|l| {
+                         if l.tok_id() == self.error_tok_id {
+                             let tok_id = l.tok_id().with_channel(Channel::Error);
+                             Lexeme::new(tok_id, l.start(), l.len())
+                         } else {
+                             l
+                         }
+                     })
+                     .collect()
+    }
+
+    /// Return a parser builder with this parser's recovery tuning, for callers who want to use
+    /// lrpar's other APIs (e.g. `parse_with_recovery_report`) on lexemes returned by `lex`.
+    pub fn builder(&self) -> RTParserBuilder<u8> {
+        RTParserBuilder::new(&self.grm, &self.sgraph, &self.stable)
+            .recoverer(RecoveryKind::CPCTPlus)
+            .term_costs(&*self.term_costs)
+            .substitutions(true)
+    }
+
+    /// Parse `input`. On failure, return the value (if all the input was consumed) with the
+    /// parser's first repair applied to each error, and the errors.
+    pub fn parse(&self, input: &str)
+              -> Result<JsonValue, (Option<JsonValue>, Vec<ParseError<u8>>)>
+    {
+        let lexemes = self.lex(input);
+        match self.builder().parse(&lexemes) {
+            Ok(pt) => Ok(JsonValue::from_node(&self.grm, input, &pt)),
+            Err((pt, errs)) => {
+                Err((pt.map(|pt| JsonValue::from_node(&self.grm, input, &pt)), errs))
+            }
+        }
+    }
+}
+
+impl Default for JsonParser {
+    fn default() -> Self {
+        JsonParser::new()
+    }
+}
+
+/// Return the cost of inserting or deleting the terminal `name` (`None` for the end of input).
+fn term_cost(name: Option<&str>) -> u8 {
+    match name {
+        Some("STRING") | Some("NUMBER") => 2,
+        Some("TRUE") | Some("FALSE") | Some("NULL") => 3,
+        _ => 1
+    }
+}
+
+/// A JSON value: the typed AST of `JSON_GRAMMAR`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// An object's members, in input order (including any duplicate names).
+    Object(Vec<(String, JsonValue)>)
+}
+
+impl JsonValue {
+    /// Convert `node`, a parse tree for a rule of `grm` (which must be the grammar of a
+    /// `JsonParser`) derived from `input`, into a `JsonValue`. Strings and numbers inserted by
+    /// repairs become `""` and `0` respectively.
+    ///
+    /// This is iterative, since deeply nested values lead to deep trees.
+    pub fn from_node<TokId: TokenId>(grm: &YaccGrammar, input: &str, node: &Node<TokId>) -> Self {
+        // Converted values are pushed onto `vals`, from which an array or object takes its
+        // elements (which are the topmost values, in order) once they have all been converted.
+        let mut todo = vec![Task::Convert(node)];
+        let mut vals = Vec::new();
+        while let Some(t) = todo.pop() {
+            match t {
+                Task::Convert(&Node::Term{lexeme}) => {
+                    let s = lexeme_str(input, &lexeme);
+                    let t_idx = TIdx::from(lexeme.tok_id().without_channel().to_u32().unwrap());
+                    vals.push(match grm.term_name(t_idx) {
+                        Some("STRING") => JsonValue::String(unescape(s)),
+                        Some("NUMBER") => JsonValue::Number(s.parse().unwrap_or(0.0)),
+                        Some("TRUE") => JsonValue::Bool(true),
+                        Some("FALSE") => JsonValue::Bool(false),
+                        _ => JsonValue::Null
+                    });
+                },
+                Task::Convert(&Node::Nonterm{nonterm_idx, ref nodes}) => {
+                    match grm.nonterm_name(nonterm_idx) {
+                        "Object" if nodes.len() == 3 => {
+                            let members = list_items(&nodes[1]);
+                            let names = members.iter().map(|&m| member_name(input, m)).collect();
+                            todo.push(Task::Object(names));
+                            todo.extend(members.into_iter()
+                                               .rev()
+                                               .map(|m| Task::Convert(member_value(m))));
+                        },
+                        "Object" => vals.push(JsonValue::Object(Vec::new())),
+                        "Array" if nodes.len() == 3 => {
+                            let elems = list_items(&nodes[1]);
+                            todo.push(Task::Array(elems.len()));
+                            todo.extend(elems.into_iter().rev().map(Task::Convert));
+                        },
+                        "Array" => vals.push(JsonValue::Array(Vec::new())),
+                        _ => todo.push(Task::Convert(&nodes[0]))
+                    }
+                },
+                Task::Array(n) => {
+                    let len = vals.len();
+                    let elems = vals.split_off(len - n);
+                    vals.push(JsonValue::Array(elems));
+                },
+                Task::Object(names) => {
+                    let len = vals.len();
+                    let values = vals.split_off(len - names.len());
+                    vals.push(JsonValue::Object(names.into_iter().zip(values).collect()));
+                }
+            }
+        }
+        vals.pop().unwrap()
+    }
+}
+
+/// A step in `JsonValue::from_node`.
+enum Task<'a, TokId: 'a> {
+    /// Convert a node, pushing its value.
+    Convert(&'a Node<TokId>),
+    /// Replace the topmost `usize` values with an array of them.
+    Array(usize),
+    /// Replace the topmost values with an object whose members have these names.
+    Object(Vec<String>)
+}
+
+/// Return the items of `node`, an instance of a left-recursive list rule such as `Members`, in
+/// order. This is iterative, since long lists lead to deep trees.
+fn list_items<TokId: TokenId>(mut node: &Node<TokId>) -> Vec<&Node<TokId>> {
+    let mut items = Vec::new();
+    while let Node::Nonterm{ref nodes, ..} = *node {
+        items.push(&nodes[nodes.len() - 1]);
+        if nodes.len() == 1 {
+            break;
+        }
+        node = &nodes[0];
+    }
+    items.reverse();
+    items
+}
+
+/// Return the name of `node`, an instance of `Member`.
+fn member_name<TokId: TokenId>(input: &str, node: &Node<TokId>) -> String {
+    match *node {
+        Node::Nonterm{ref nodes, ..} => match nodes[0] {
+            Node::Term{lexeme} => unescape(lexeme_str(input, &lexeme)),
+            Node::Nonterm{..} => unreachable!()
+        },
+        Node::Term{..} => unreachable!()
+    }
+}
+
+/// Return the node for the value of `node`, an instance of `Member`.
+fn member_value<TokId: TokenId>(node: &Node<TokId>) -> &Node<TokId> {
+    match *node {
+        Node::Nonterm{ref nodes, ..} => &nodes[2],
+        Node::Term{..} => unreachable!()
+    }
+}
+
+/// Return the contents of the JSON string literal `s` (which may be empty, if it was inserted by a
+/// repair) with escapes replaced by the characters they stand for. A `\u` escape for a high
+/// surrogate followed by one for a low surrogate (e.g. `\uD83D\uDE00`) stands for a single
+/// character outside the Basic Multilingual Plane; invalid `\u` escapes (including unpaired
+/// surrogates) are replaced by U+FFFD.
+fn unescape(s: &str) -> String {
+    let s = if s.len() >= 2 { &s[1..s.len() - 1] } else { "" };
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('u') => {
+                let mut c = hex_escape(&mut chars);
+                if let Some(hi @ 0xd800...0xdbff) = c {
+                    // Only consume the following escape if it completes the surrogate pair.
+                    let mut la = chars.clone();
+                    let lo = match (la.next(), la.next()) {
+                        (Some('\\'), Some('u')) => hex_escape(&mut la),
+                        _ => None
+                    };
+                    c = match lo {
+                        Some(lo @ 0xdc00...0xdfff) => {
+                            chars = la;
+                            Some(0x10000 + ((hi - 0xd800) << 10) + (lo - 0xdc00))
+                        },
+                        _ => None
+                    };
+                }
+                out.push(c.and_then(::std::char::from_u32).unwrap_or('\u{fffd}'));
+            },
+            // `\"`, `\\`, and `\/`.
+            Some(c) => out.push(c),
+            None => ()
+        }
+    }
+    out
+}
+
+/// Consume the (up to) 4 hex digits of a `\u` escape from `chars`, returning the value they
+/// represent, if they are valid.
+fn hex_escape(chars: &mut Chars) -> Option<u32> {
+    let hex = chars.by_ref().take(4).collect::<String>();
+    if hex.len() == 4 {
+        u32::from_str_radix(&hex, 16).ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lrpar::{Channel, github_annotations, github_annotations_with, lint, LintKind, Localisation,
+                ParseRepair, TokenId};
+
+    use super::{JSON_GRAMMAR, JsonParser, JsonValue, unescape};
+
+    #[test]
+    fn values() {
+        let jp = JsonParser::new();
+        let us = r#"{"a": [1, -2.5e1, true, false, null, []], "b\n\u0041": {}, "a": "\"x\""}"#;
+        assert_eq!(jp.parse(us).unwrap(),
+                   JsonValue::Object(vec![
+                       ("a".to_owned(), JsonValue::Array(vec![JsonValue::Number(1.0),
+                                                              JsonValue::Number(-25.0),
+                                                              JsonValue::Bool(true),
+                                                              JsonValue::Bool(false),
+                                                              JsonValue::Null,
+                                                              JsonValue::Array(vec![])])),
+                       ("b\nA".to_owned(), JsonValue::Object(vec![])),
+                       ("a".to_owned(), JsonValue::String("\"x\"".to_owned()))]));
+        let us = (0..10000).map(|i| i.to_string()).collect::<Vec<_>>().join(",");
+        match jp.parse(&format!("[{}]", us)).unwrap() {
+            JsonValue::Array(elems) => {
+                assert_eq!(elems.len(), 10000);
+                assert_eq!(elems[9999], JsonValue::Number(9999.0));
+            },
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn surrogates() {
+        assert_eq!(unescape(r#""\uD83D\uDE00""#), "\u{1f600}");
+        assert_eq!(JsonParser::new().parse(r#""a\ud83d\ude00b""#).unwrap(),
+                   JsonValue::String("a\u{1f600}b".to_owned()));
+        // Unpaired surrogates are invalid, and don't swallow what follows them.
+        assert_eq!(unescape(r#""\uD83Dx\uDE00""#), "\u{fffd}x\u{fffd}");
+        assert_eq!(unescape(r#""\uD83D\u0041""#), "\u{fffd}A");
+    }
+
+    #[test]
+    fn nesting() {
+        let depth = 1000;
+        let us = format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        let mut v = &JsonParser::new().parse(&us).unwrap();
+        for _ in 1..depth {
+            match *v {
+                JsonValue::Array(ref elems) => v = &elems[0],
+                _ => panic!()
+            }
+        }
+        assert_eq!(*v, JsonValue::Array(vec![]));
+    }
+
+    #[test]
+    fn recovery() {
+        let jp = JsonParser::new();
+        let comma = ParseRepair::Insert(jp.grammar().term_idx(",").unwrap());
+        let (v, errs) = jp.parse("[1 2]").unwrap_err();
+        assert_eq!(v, Some(JsonValue::Array(vec![JsonValue::Number(1.0),
+                                                 JsonValue::Number(2.0)])));
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].repairs()[0][0], comma);
+
+        let colon = ParseRepair::Insert(jp.grammar().term_idx(":").unwrap());
+        let (v, errs) = jp.parse(r#"{"a" 1}"#).unwrap_err();
+        assert_eq!(v, Some(JsonValue::Object(vec![("a".to_owned(), JsonValue::Number(1.0))])));
+        assert_eq!(errs[0].repairs()[0][0], colon);
+
+        // Characters which can't start a token are lexed onto the error channel and deleted.
+        let (v, errs) = jp.parse("[1 @, 2]").unwrap_err();
+        assert_eq!(v, Some(JsonValue::Array(vec![JsonValue::Number(1.0),
+                                                 JsonValue::Number(2.0)])));
+        assert_eq!(errs[0].lexeme().tok_id().channel(), Channel::Error);
+        assert_eq!(errs[0].repairs()[0][0], ParseRepair::Delete);
+    }
+
+    #[test]
+    fn apis() {
+        let jp = JsonParser::new();
+        let lints = lint(JSON_GRAMMAR, jp.grammar());
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].kind(), LintKind::UnusedTerm);
+
+        let us = "[1 2]";
+        let lexemes = jp.lex(us);
+        let rtpb = jp.builder();
+        let (pt, errs, applied) = rtpb.parse_with_recovery_report(&lexemes);
+        assert!(pt.is_some());
+        assert_eq!(applied.len(), 1);
+        assert_eq!(applied[0].resumed_at(), Some(2));
+        let rs = rtpb.preview_repair(&lexemes, &errs[0], applied[0].applied().unwrap());
+        let term_text = |t_idx| rtpb.term_text(us, &lexemes, t_idx);
+        assert_eq!(rs.text_edits(&term_text), vec![((3, 3), ",".to_owned())]);
+        let anns = github_annotations(jp.grammar(), "x.json", us, &errs);
+        assert!(anns.starts_with("::error file=x.json,line=1,col=4::"));
+        assert_eq!(github_annotations_with(jp.grammar(),
+                                           "x.json",
+                                           us,
+                                           &errs,
+                                           &Localisation::new(),
+                                           &term_text),
+                   anns);
+    }
+}
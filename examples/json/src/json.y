@@ -0,0 +1,9 @@
+%start Value
+%token ERROR
+%%
+Value: Object | Array | 'STRING' | 'NUMBER' | 'TRUE' | 'FALSE' | 'NULL' ;
+Object: '{' Members '}' | '{' '}' ;
+Members: Members ',' Member | Member ;
+Member: 'STRING' ':' Value ;
+Array: '[' Elements ']' | '[' ']' ;
+Elements: Elements ',' Value | Value ;
@@ -0,0 +1,31 @@
+use std::io::{self, Read};
+
+extern crate cfgrammar;
+extern crate lrlex;
+extern crate lrpar;
+extern crate lrtable;
+extern crate num_traits;
+
+mod json;
+
+use json::JsonParser;
+
+fn main() {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input).unwrap();
+    // Unlike the `calc` example, the parser's tables are built when the program is run, since
+    // `JsonParser` tunes the parser's error recovery.
+    let jp = JsonParser::new();
+    match jp.parse(&input) {
+        Ok(v) => println!("{:?}", v),
+        Err((v, errs)) => {
+            for e in errs {
+                println!("Error at byte {}.", e.lexeme().start());
+            }
+            // If all the input could be consumed, the value with each error repaired is returned.
+            if let Some(v) = v {
+                println!("Repaired to {:?}", v);
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use cfgrammar::{NTIdx, PIdx, SIdx, Symbol, TIdx};
+use cfgrammar::yacc::YaccGrammar;
+use lrtable::{StateGraph, StIdx};
+use vob::Vob;
+
+/// Precomputed grammar analyses (FIRST and FOLLOW sets, and the items of each state) for those
+/// writing their own heuristics on top of the parser (e.g. custom recoverers or completion
+/// engines), so that they need not recompute them. All analyses are computed once, in `new`.
+pub struct GrammarInfo<'a> {
+    grm: &'a YaccGrammar,
+    sgraph: &'a StateGraph,
+    firsts: Vec<Vob>,
+    // Which nonterminals can derive the empty string.
+    epsilons: Vob,
+    follows: Vec<Vob>
+}
+
+impl<'a> GrammarInfo<'a> {
+    /// Compute the analyses for `grm` and its state graph `sgraph`.
+    pub fn new(grm: &'a YaccGrammar, sgraph: &'a StateGraph) -> Self {
+        let nonterms_len = grm.nonterms_len() as usize;
+        let cf_firsts = grm.firsts();
+        let mut firsts = Vec::with_capacity(nonterms_len);
+        let mut epsilons = Vob::from_elem(nonterms_len, false);
+        for i in 0..nonterms_len {
+            let nt_idx = NTIdx::from(i as u32);
+            firsts.push(cf_firsts.firsts(nt_idx).clone());
+            epsilons.set(i, cf_firsts.is_epsilon_set(nt_idx));
+        }
+
+        // FOLLOW(A) contains FIRST(b) for every production X: ... A b ..., and FOLLOW(X) if b can
+        // derive the empty string. The start rule is followed by EOF.
+        let mut follows = vec![Vob::from_elem(grm.terms_len() as usize, false); nonterms_len];
+        let start_nt = usize::from(grm.prod_to_nonterm(grm.start_prod()));
+        follows[start_nt].set(usize::from(grm.eof_term_idx()), true);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..nonterms_len {
+                let nt_idx = NTIdx::from(i as u32);
+                for &p_idx in grm.nonterm_to_prods(nt_idx) {
+                    let prod = grm.prod(p_idx);
+                    for (j, sym) in prod.iter().enumerate() {
+                        let a_idx = match *sym {
+                            Symbol::Nonterm(a_idx) => usize::from(a_idx),
+                            Symbol::Term(_) => continue
+                        };
+                        let mut rest_nullable = true;
+                        for r_sym in &prod[j + 1..] {
+                            match *r_sym {
+                                Symbol::Term(t_idx) => {
+                                    if !follows[a_idx][usize::from(t_idx)] {
+                                        follows[a_idx].set(usize::from(t_idx), true);
+                                        changed = true;
+                                    }
+                                    rest_nullable = false;
+                                },
+                                Symbol::Nonterm(r_idx) => {
+                                    changed |= follows[a_idx].or(&firsts[usize::from(r_idx)]);
+                                    if !epsilons[usize::from(r_idx)] {
+                                        rest_nullable = false;
+                                    }
+                                }
+                            }
+                            if !rest_nullable {
+                                break;
+                            }
+                        }
+                        if rest_nullable && a_idx != i {
+                            let nt_follows = follows[i].clone();
+                            changed |= follows[a_idx].or(&nt_follows);
+                        }
+                    }
+                }
+            }
+        }
+
+        GrammarInfo{grm, sgraph, firsts, epsilons, follows}
+    }
+
+    /// Return the FIRST set of `nt_idx` as a bit vector indexed by `TIdx`.
+    pub fn firsts(&self, nt_idx: NTIdx) -> &Vob {
+        &self.firsts[usize::from(nt_idx)]
+    }
+
+    /// Can `nt_idx` derive the empty string?
+    pub fn nullable(&self, nt_idx: NTIdx) -> bool {
+        self.epsilons[usize::from(nt_idx)]
+    }
+
+    /// Return the FOLLOW set of `nt_idx` as a bit vector indexed by `TIdx`.
+    pub fn follows(&self, nt_idx: NTIdx) -> &Vob {
+        &self.follows[usize::from(nt_idx)]
+    }
+
+    /// Is `t_idx` in the FIRST set of the sequence of symbols `syms`?
+    pub fn seq_firsts_contains(&self, syms: &[Symbol], t_idx: TIdx) -> bool {
+        for sym in syms {
+            match *sym {
+                Symbol::Term(s_t_idx) => return s_t_idx == t_idx,
+                Symbol::Nonterm(nt_idx) => {
+                    if self.firsts(nt_idx)[usize::from(t_idx)] {
+                        return true;
+                    }
+                    if !self.nullable(nt_idx) {
+                        return false;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    /// Return the core items of `st_idx` as `(production, dot position)` pairs, sorted by
+    /// production and then dot position.
+    pub fn state_items(&self, st_idx: StIdx) -> Vec<(PIdx, SIdx)> {
+        let mut items = self.sgraph.core_state(st_idx)
+                                   .items
+                                   .keys()
+                                   .cloned()
+                                   .collect::<Vec<_>>();
+        items.sort_by_key(|&(p_idx, s_idx)| (usize::from(p_idx), usize::from(s_idx)));
+        items
+    }
+
+    /// Return the nonterminals which `st_idx` is in the middle of recognising, i.e. those whose
+    /// productions appear in `st_idx`'s core items with the dot not at the start.
+    pub fn state_nonterms(&self, st_idx: StIdx) -> Vec<NTIdx> {
+        let mut nts = self.state_items(st_idx)
+                          .iter()
+                          .filter(|&&(_, s_idx)| usize::from(s_idx) > 0)
+                          .map(|&(p_idx, _)| self.grm.prod_to_nonterm(p_idx))
+                          .collect::<Vec<_>>();
+        nts.sort_by_key(|&nt_idx| usize::from(nt_idx));
+        nts.dedup();
+        nts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cfgrammar::{Symbol, TIdx};
+    use cfgrammar::yacc::{yacc_grm, YaccKind};
+    use lrtable::{Minimiser, from_yacc, StIdx};
+    use vob::Vob;
+    use super::GrammarInfo;
+
+    #[test]
+    fn firsts_follows() {
+        let grm = yacc_grm(YaccKind::Original, "%start E
+%%
+E: T E2 ;
+E2: '+' T E2 | ;
+T: '(' E ')' | 'ID' ;
+").unwrap();
+        let (sgraph, _) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let gi = GrammarInfo::new(&grm, &sgraph);
+        // EOF has no name of its own.
+        let terms = |v: &Vob| {
+            let mut ns = v.iter_set_bits(..)
+                          .map(|i| grm.term_name(TIdx::from(i as u32)).unwrap_or("$"))
+                          .collect::<Vec<_>>();
+            ns.sort();
+            ns
+        };
+        let e = grm.nonterm_idx("E").unwrap();
+        let e2 = grm.nonterm_idx("E2").unwrap();
+        let t = grm.nonterm_idx("T").unwrap();
+        assert_eq!(terms(gi.firsts(e)), vec!["(", "ID"]);
+        assert_eq!(terms(gi.firsts(e2)), vec!["+"]);
+        assert!(gi.nullable(e2));
+        assert!(!gi.nullable(t));
+        assert_eq!(terms(gi.follows(e)), vec!["$", ")"]);
+        assert_eq!(terms(gi.follows(e2)), vec!["$", ")"]);
+        assert_eq!(terms(gi.follows(t)), vec!["$", ")", "+"]);
+        assert!(gi.seq_firsts_contains(&[Symbol::Nonterm(e2), Symbol::Nonterm(t)],
+                                       grm.term_idx("ID").unwrap()));
+        assert!(!gi.seq_firsts_contains(&[Symbol::Nonterm(t)], grm.term_idx("+").unwrap()));
+        // The start state is the only state in the middle of recognising nothing.
+        assert!(gi.state_nonterms(StIdx::from(0 as u32)).is_empty());
+        assert!(!gi.state_items(StIdx::from(0 as u32)).is_empty());
+    }
+}
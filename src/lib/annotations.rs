@@ -0,0 +1,259 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::fmt::Write;
+
+use cfgrammar::TIdx;
+use cfgrammar::yacc::YaccGrammar;
+
+use localise::Localisation;
+use parser::ParseError;
+use snapshot::RepairsSnapshot;
+use source::Source;
+
+/// Return the 1-based (line, column) of the byte offset `off` in `input`. Columns are counted in
+/// characters rather than bytes.
+pub(crate) fn line_col(input: &str, off: usize) -> (usize, usize) {
+    let before = &input[..off];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Return a one-line, human readable, description of `err`, translated by `loc`.
+fn message<TokId: Copy>(grm: &YaccGrammar,
+                        err: &ParseError<TokId>,
+                        loc: &Localisation,
+                        term_text: &Fn(TIdx) -> String)
+                     -> String
+{
+    let mut s = loc.phrase("Parsing error.").to_owned();
+    s.push(' ');
+    match (err.repairs().get(0), err.skipped_rule()) {
+        (Some(rprs), _) => {
+            write!(s, "{} {}", loc.phrase("Suggested repair:"),
+                   RepairsSnapshot::localised(grm, rprs, loc)).unwrap();
+        },
+        (None, Some(nt_idx)) => {
+            s.push_str(&loc.phrase_with("Ignored malformed {}.",
+                                        &[loc.rule(grm.nonterm_name(nt_idx))]));
+        },
+        (None, None) => s.push_str(loc.phrase("No repairs found."))
+    }
+    if let Some(t_idx) = err.suggestion() {
+        // The suggestion is something the author might type, so unless the terminal has been
+        // translated, show it as `term_text` would write it in the input.
+        let name = grm.term_name(t_idx).unwrap();
+        let text = match loc.term(name) {
+            n if n == name => term_text(t_idx),
+            n => n.to_owned()
+        };
+        s.push(' ');
+        s.push_str(&loc.phrase_with("Did you mean `{}`?", &[&text]));
+    }
+    s
+}
+
+/// Render `errs` (the result of parsing `input`, which was read from the file `path`) as GitHub
+/// Actions workflow commands, so that CI pipelines can annotate the lines containing errors. There
+/// is one command per line e.g.:
+/// ```text
+/// ::error file=src/x.txt,line=3,col=7::Parsing error. Suggested repair: Insert ")"
+/// ```
+pub fn github_annotations<TokId, S>(grm: &YaccGrammar,
+                                    path: &str,
+                                    input: &S,
+                                    errs: &[ParseError<TokId>])
+                                 -> String
+                              where TokId: Copy,
+                                    S: Source + ?Sized
+{
+    github_annotations_with(grm,
+                            path,
+                            input,
+                            errs,
+                            &Localisation::new(),
+                            &|t_idx| grm.term_name(t_idx).unwrap().to_owned())
+}
+
+/// As `github_annotations`, but with messages translated by `loc` and with untranslated
+/// suggestions (see `ParseError::suggestion`) rendered by `term_text` (e.g. a closure calling
+/// `RTParserBuilder::term_text`, so that they are shown in the input's casing).
+pub fn github_annotations_with<TokId, S>(grm: &YaccGrammar,
+                                         path: &str,
+                                         input: &S,
+                                         errs: &[ParseError<TokId>],
+                                         loc: &Localisation,
+                                         term_text: &Fn(TIdx) -> String)
+                                      -> String
+                                   where TokId: Copy,
+                                         S: Source + ?Sized
+{
+    let mut s = String::new();
+    for e in errs {
+        let (line, col) = input.line_col(e.lexeme().start());
+        writeln!(s,
+                 "::error file={},line={},col={}::{}",
+                 gh_escape(path, true),
+                 line,
+                 col,
+                 gh_escape(&message(grm, e, loc, term_text), false)).unwrap();
+    }
+    s
+}
+
+/// Escape `s` for use in a GitHub workflow command: `property` should be `true` if `s` is a
+/// property value, which requires more characters to be escaped than a message.
+fn gh_escape(s: &str, property: bool) -> String {
+    let mut o = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '%' => o.push_str("%25"),
+            '\r' => o.push_str("%0D"),
+            '\n' => o.push_str("%0A"),
+            ':' if property => o.push_str("%3A"),
+            ',' if property => o.push_str("%2C"),
+            _ => o.push(c)
+        }
+    }
+    o
+}
+
+/// Render `errs` (the result of parsing `input`, which was read from the file `path`) as a SARIF
+/// 2.1.0 log with a single run, suitable for uploading to code scanning services.
+pub fn sarif<TokId, S>(grm: &YaccGrammar, path: &str, input: &S, errs: &[ParseError<TokId>])
+                    -> String
+                 where TokId: Copy,
+                       S: Source + ?Sized
+{
+    sarif_with(grm,
+               path,
+               input,
+               errs,
+               &Localisation::new(),
+               &|t_idx| grm.term_name(t_idx).unwrap().to_owned())
+}
+
+/// As `sarif`, but with messages translated by `loc` and untranslated suggestions rendered by
+/// `term_text` (see `github_annotations_with`).
+pub fn sarif_with<TokId, S>(grm: &YaccGrammar,
+                            path: &str,
+                            input: &S,
+                            errs: &[ParseError<TokId>],
+                            loc: &Localisation,
+                            term_text: &Fn(TIdx) -> String)
+                         -> String
+                      where TokId: Copy,
+                            S: Source + ?Sized
+{
+    let mut s = String::new();
+    s.push_str("{\"version\":\"2.1.0\",\
+                \"$schema\":\"https://json.schemastore.org/sarif-2.1.0.json\",\
+                \"runs\":[{\"tool\":{\"driver\":{\"name\":\"lrpar\"}},\"results\":[");
+    for (i, e) in errs.iter().enumerate() {
+        if i > 0 {
+            s.push(',');
+        }
+        let l = e.lexeme();
+        let (line, col) = input.line_col(l.start());
+        write!(s,
+               "{{\"ruleId\":\"parse-error\",\"level\":\"error\",\"message\":{{\"text\":{}}},\
+                \"locations\":[{{\"physicalLocation\":{{\"artifactLocation\":{{\"uri\":{}}},\
+                \"region\":{{\"startLine\":{},\"startColumn\":{},\"byteOffset\":{},\
+                \"byteLength\":{}}}}}}}]}}",
+               json_str(&message(grm, e, loc, term_text)),
+               json_str(path),
+               line,
+               col,
+               l.start(),
+               l.len()).unwrap();
+    }
+    s.push_str("]}]}");
+    s
+}
+
+/// Return `s` as a quoted JSON string.
+pub(crate) fn json_str(s: &str) -> String {
+    let mut o = String::with_capacity(s.len() + 2);
+    o.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => o.push_str("\\\""),
+            '\\' => o.push_str("\\\\"),
+            '\n' => o.push_str("\\n"),
+            '\r' => o.push_str("\\r"),
+            '\t' => o.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(o, "\\u{:04x}", c as u32).unwrap(),
+            c => o.push(c)
+        }
+    }
+    o.push('"');
+    o
+}
+
+#[cfg(test)]
+mod test {
+    use parser::RecoveryKind;
+    use parser::test::do_parse;
+    use super::{github_annotations, sarif};
+
+    #[test]
+    fn annotations() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+a 'A'
+\\n ;
+";
+        let grms = "%start E
+%%
+E: '(' E ')'
+ | 'A' ;
+";
+
+        let us = "(\n(a)";
+        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, &us);
+        let (_, errs) = pr.unwrap_err();
+        assert_eq!(github_annotations(&grm, "x,y.txt", &us, &errs),
+                   "::error file=x%2Cy.txt,line=2,col=4::Parsing error. Suggested repair: \
+                    Insert \")\"\n");
+        assert_eq!(sarif(&grm, "x.txt", &us, &errs),
+                   "{\"version\":\"2.1.0\",\
+                    \"$schema\":\"https://json.schemastore.org/sarif-2.1.0.json\",\
+                    \"runs\":[{\"tool\":{\"driver\":{\"name\":\"lrpar\"}},\"results\":[\
+                    {\"ruleId\":\"parse-error\",\"level\":\"error\",\"message\":{\"text\":\
+                    \"Parsing error. Suggested repair: Insert \\\")\\\"\"},\"locations\":[\
+                    {\"physicalLocation\":{\"artifactLocation\":{\"uri\":\"x.txt\"},\
+                    \"region\":{\"startLine\":2,\"startColumn\":4,\"byteOffset\":5,\
+                    \"byteLength\":0}}}]}]}]}");
+    }
+}
@@ -30,30 +30,162 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::hash::Hash;
 use std::fmt::Debug;
+use std::mem::size_of;
+use std::thread::LocalKey;
 
 use indexmap::map::{Entry, IndexMap};
 
+/// How many steps (a step being the popping of a node from `todo`, or the storing of one of its
+/// neighbours) a search takes between calls to `SearchLimits::cancelled`. Checking at every step
+/// would make the (possibly expensive) check dominate cheap steps; checking only between node
+/// expansions would let a node with very many neighbours, or a long run of nodes which are popped
+/// but not expanded, overshoot the deadline.
+const CANCEL_INTERVAL: usize = 16;
+
+/// The maximum number of elements a `Scratch` buffer may have capacity for and still be kept after
+/// a search: larger buffers are freed, so that a single pathological error does not leave a
+/// long-running thread holding on to the memory it needed for the rest of the thread's life.
+const SCRATCH_RETAIN: usize = 1 << 16;
+
+/// The limits on a search.
+pub(crate) struct SearchLimits<'a> {
+    /// If not `None`, approximately bounds the memory (in bytes) used by stored nodes (see
+    /// `max_nodes`).
+    pub(crate) max_memory: Option<usize>,
+    /// If not `None`, bounds the number of nodes whose neighbours are generated.
+    pub(crate) max_expansions: Option<usize>,
+    /// If not `None`, a search which has not yet found a success node is abandoned if it has
+    /// stored more than this many nodes for each priority (or cost) it has explored, since its
+    /// frontier is then growing much faster than it is making progress.
+    pub(crate) max_frontier_growth: Option<usize>,
+    /// Called every `CANCEL_INTERVAL` steps (including before the first): if it returns `true`,
+    /// the search is abandoned and returns no success nodes.
+    pub(crate) cancelled: &'a Fn() -> bool
+}
+
+/// The limit which cut a search short.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum LimitReached {
+    Memory,
+    Expansions,
+    Frontier
+}
+
+/// The buffers used by a search, which are kept (emptied, but with their capacity intact, unless
+/// that exceeds `SCRATCH_RETAIN`) in a thread-local between searches, so that a long-running
+/// thread which recovers from many errors stops allocating them afresh once they have grown large
+/// enough. `E` is the type of the elements which `neighbours` pushes.
+pub(crate) struct Scratch<N: Eq + Hash, E> {
+    todo: Vec<IndexMap<N, N>>,
+    closed: HashMap<N, u32>,
+    next: Vec<E>
+}
+
+impl<N: Eq + Hash, E> Default for Scratch<N, E> {
+    fn default() -> Self {
+        Scratch{todo: Vec::new(), closed: HashMap::new(), next: Vec::new()}
+    }
+}
+
+impl<N: Eq + Hash, E> Scratch<N, E> {
+    /// Drop all the nodes in these buffers, keeping the buffers themselves unless their capacity
+    /// exceeds `SCRATCH_RETAIN`, in which case they are freed.
+    fn clear(&mut self) {
+        let todo_cap = self.todo.iter().fold(self.todo.capacity(), |a, t| a + t.capacity());
+        if todo_cap > SCRATCH_RETAIN {
+            self.todo = Vec::new();
+        } else {
+            for t in &mut self.todo {
+                t.clear();
+            }
+        }
+        if self.closed.capacity() > SCRATCH_RETAIN {
+            self.closed = HashMap::new();
+        } else {
+            self.closed.clear();
+        }
+        if self.next.capacity() > SCRATCH_RETAIN {
+            self.next = Vec::new();
+        } else {
+            self.next.clear();
+        }
+    }
+}
+
+/// Call `f` with the `Scratch` in `key`, emptying it afterwards. If `key`'s `Scratch` is already in
+/// use (which can only happen if a callback called during a search started another parse), `f` is
+/// given a fresh `Scratch` instead.
+fn with_scratch<N, E, F, R>(key: &'static LocalKey<RefCell<Scratch<N, E>>>, f: F) -> R
+                     where N: Eq + Hash + 'static,
+                           E: 'static,
+                           F: FnOnce(&mut Scratch<N, E>) -> R
+{
+    key.with(|scratch| {
+        match scratch.try_borrow_mut() {
+            Ok(mut s) => {
+                let r = f(&mut s);
+                s.clear();
+                r
+            },
+            Err(_) => f(&mut Scratch::default())
+        }
+    })
+}
+
 /// Starting at `start_node`, return, in arbitrary order, all least-cost success nodes.
 ///
+/// * `seeds` are success nodes already known (e.g. from an earlier search), each with its cost.
+/// They are added to the search's priority queue before it starts, and, since the search can never
+/// need to explore a node more costly than the cheapest of them, neighbours more costly than that
+/// are not stored.
 /// * `neighbours` takes a node `n` and returns an iterator consisting of all `n`'s neighbouring
 /// nodes.
 /// * `success` takes a node `n` and returns `true` if it is a success node or `false` otherwise.
+/// * `limits` bounds the search (see `SearchLimits`).
+/// * `scratch` is the thread-local whose buffers the search reuses.
+///
+/// Returns a tuple `(success nodes, the limit which cut the search short, if any)`. If a limit is
+/// reached after the first success node has been found, the least-cost success nodes found so far
+/// are returned.
 ///
 /// This API is roughly modelled after
 /// [`astar_bag_collect`](https://docs.rs/pathfinding/0.6.8/pathfinding/fn.astar_bag.html)
 /// in the `pathfinding` crate. Unlike `astar_bag_collect`, this `astar_all` does not record the
 /// path taken to reach a success node: this allows it to be substantially faster.
 pub(crate) fn astar_all<N, FN, FM, FS>(start_node: N,
+                                       seeds: Vec<(u32, N)>,
                                        neighbours: FN,
                                        merge: FM,
-                                       success: FS)
-                                    -> Vec<N>
-                                 where N: Debug + Clone + Hash + Eq + PartialEq,
-                                       FN: Fn(bool, &N, &mut Vec<(u32, u32, N)>) -> bool,
+                                       success: FS,
+                                       limits: &SearchLimits,
+                                       scratch: &'static LocalKey<RefCell<Scratch<N,
+                                                                                  (u32, u32, N)>>>)
+                                    -> (Vec<N>, Option<LimitReached>)
+                                 where N: Debug + Clone + Hash + Eq + PartialEq + 'static,
+                                       FN: Fn(bool, &N, &mut Vec<(u32, u32, N)>),
                                        FM: Fn(&mut N, N),
                                        FS: Fn(&N) -> bool,
+{
+    with_scratch(scratch,
+                 |s| astar_all_in(start_node, seeds, neighbours, merge, success, limits, s))
+}
+
+fn astar_all_in<N, FN, FM, FS>(start_node: N,
+                               seeds: Vec<(u32, N)>,
+                               neighbours: FN,
+                               merge: FM,
+                               success: FS,
+                               limits: &SearchLimits,
+                               scratch: &mut Scratch<N, (u32, u32, N)>)
+                            -> (Vec<N>, Option<LimitReached>)
+                         where N: Debug + Clone + Hash + Eq + PartialEq,
+                               FN: Fn(bool, &N, &mut Vec<(u32, u32, N)>),
+                               FM: Fn(&mut N, N),
+                               FS: Fn(&N) -> bool,
 {
     // We tackle the problem in two phases. In the first phase we search for a success node, with
     // the cost monotonically increasing. All neighbouring nodes are stored for future inspection,
@@ -66,43 +198,69 @@ pub(crate) fn astar_all<N, FN, FM, FS>(start_node: N,
 
     // First phase: search for the first success node.
 
+    let Scratch{ref mut todo, ref mut next, ..} = *scratch;
     let mut scs_nodes = Vec::new(); // Store success nodes
     // todo is a map from "original node" to "merged node". We never change "original node", but,
     // as we find compatible repairs, continually update merged node. This means that when we pop
     // things off the todo we *must* use "merged node" as our node to work with.
-    let mut todo: Vec<IndexMap<N, N>> = vec![indexmap![start_node.clone() => start_node]];
+    if todo.is_empty() {
+        todo.push(IndexMap::new());
+    }
+    todo[0].insert(start_node.clone(), start_node);
+    // No node more costly than `ceiling` (the cost of the cheapest seed) need be stored.
+    let ceiling = add_seeds(todo, seeds, &merge);
     let mut c: u32 = 0; // What cost are we currently examining?
-    let mut next = Vec::new();
+    let max_nodes = max_nodes::<N>(limits.max_memory);
+    let mut progress = Progress::new(limits);
+    // How many nodes have been stored in todo?
+    let mut stored: usize = todo.iter().map(|t| t.len()).sum();
     loop {
         if todo[c as usize].is_empty() {
             c = c.checked_add(1).unwrap();
             if c as usize == todo.len() {
                 // No success node found and search exhausted.
-                return Vec::new();
+                return (Vec::new(), None);
             }
             continue;
         }
 
         let n = todo[c as usize].pop().unwrap().1;
+        if !progress.step() {
+            return (Vec::new(), None);
+        }
         if success(&n) {
             scs_nodes.push(n);
             break;
         }
 
-        if !neighbours(true, &n, &mut next) {
-            return Vec::new();
+        if !progress.expand() {
+            return (Vec::new(), Some(LimitReached::Expansions));
         }
+        neighbours(true, &n, next);
         for (nbr_cost, nbr_hrstc, nbr) in next.drain(..) {
-            assert!(nbr_cost + nbr_hrstc >= c);
-            let off = nbr_cost.checked_add(nbr_hrstc).unwrap() as usize;
+            if !progress.step() {
+                return (Vec::new(), None);
+            }
+            assert!(nbr_cost.saturating_add(nbr_hrstc) >= c);
+            let off = nbr_cost.saturating_add(nbr_hrstc) as usize;
+            if ceiling.map_or(false, |cl| off > cl as usize) {
+                continue;
+            }
             for _ in todo.len()..off + 1 {
                 todo.push(IndexMap::new());
             }
             match todo[off].entry(nbr.clone()) {
-                Entry::Vacant(e) => { e.insert(nbr); },
+                Entry::Vacant(e) => { e.insert(nbr); stored += 1; },
                 Entry::Occupied(mut e) => { merge(&mut e.get_mut(), nbr); }
             }
         }
+        if max_nodes.map_or(false, |m| stored > m) {
+            // No success node has been found yet, so there are no partial results to return.
+            return (Vec::new(), Some(LimitReached::Memory));
+        }
+        if limits.max_frontier_growth.map_or(false, |g| stored > g.saturating_mul(c as usize + 1)) {
+            return (Vec::new(), Some(LimitReached::Frontier));
+        }
     }
 
     // Second phase: find remaining success nodes.
@@ -111,105 +269,319 @@ pub(crate) fn astar_all<N, FN, FM, FS>(start_node: N,
     // lead to further success is if they only contain extra (zero-cost, by definition) shifts.
     // That never leads to more interesting repairs from our perspective.
 
-    // Free up all memory except for the cost todo that contains the first success node.
-    let mut scs_todo = todo.drain(c as usize..c as usize + 1).nth(0).unwrap();
+    // Free up all nodes except for those in the cost todo that contains the first success node.
+    for (i, t) in todo.iter_mut().enumerate() {
+        if i != c as usize {
+            t.clear();
+        }
+    }
+    let scs_todo = &mut todo[c as usize];
     while !scs_todo.is_empty() {
         let n = scs_todo.pop().unwrap().1;
+        if !progress.step() {
+            return (Vec::new(), None);
+        }
         if success(&n) {
             scs_nodes.push(n);
             continue;
         }
-        if !neighbours(false, &n, &mut next) {
-            return Vec::new();
+        if !progress.expand() {
+            return (scs_nodes, Some(LimitReached::Expansions));
         }
+        neighbours(false, &n, next);
         for (nbr_cost, nbr_hrstc, nbr) in next.drain(..) {
-            assert!(nbr_cost + nbr_hrstc >= c);
+            if !progress.step() {
+                return (Vec::new(), None);
+            }
+            assert!(nbr_cost.saturating_add(nbr_hrstc) >= c);
             // We only need to consider neighbouring nodes if they have the same cost as
             // existing success nodes and an empty heuristic.
-            if nbr_cost + nbr_hrstc == c {
+            if nbr_cost.saturating_add(nbr_hrstc) == c {
                 match scs_todo.entry(nbr.clone()) {
-                    Entry::Vacant(e) => { e.insert(nbr); },
+                    Entry::Vacant(e) => { e.insert(nbr); stored += 1; },
                     Entry::Occupied(mut e) => { merge(&mut e.get_mut(), nbr); }
                 }
             }
         }
+        if max_nodes.map_or(false, |m| stored > m) {
+            // Return the success nodes found so far: they are all of least cost, even if they
+            // aren't all the least-cost success nodes.
+            return (scs_nodes, Some(LimitReached::Memory));
+        }
     }
 
-    scs_nodes
+    (scs_nodes, None)
 }
 
-/// Starting at `start_node`, return, in arbitrary order, all least-cost success nodes.
+/// Starting at `start_node`, return, in arbitrary order, all least-priority success nodes.
 ///
+/// * `seeds` are success nodes already known, each with its cost, as for `astar_all`. Their
+/// priorities are computed with `priority`.
 /// * `neighbours` takes a node `n` and returns an iterator consisting of all `n`'s neighbouring
-/// nodes.
+/// nodes and their costs.
+/// * `priority` takes a node `n` and its cost and returns the priority with which it is explored
+/// (lower priorities first). Passing `|c, _| c` orders nodes by cost, and the success nodes
+/// returned are then the least-cost success nodes. Passing `|c, n| c + h(n)` for a heuristic `h`
+/// turns the search into A*: if `h` is consistent (i.e. `h(n) <= c + h(m)` for every neighbour `m`
+/// of `n` reached at cost `c`, which implies that `h` never overestimates the remaining cost) and
+/// is 0 for success nodes, then the success nodes returned are still least-cost, but fewer nodes
+/// may need to be explored to find them. A heuristic which never overestimates but is not
+/// consistent is not enough, since a node can then first be expanded along a path which is not
+/// least-cost, and the closed set discards it when it is later reached more cheaply. Passing
+/// `|c, n| c + w * h(n)` for some `w > 1` turns the search into weighted A*, which explores fewer
+/// nodes still, but may return success nodes of up to `w` times the least cost.
 /// * `success` takes a node `n` and returns `true` if it is a success node or `false` otherwise.
+/// * `limits` bounds the search (see `SearchLimits`).
+/// * `scratch` is the thread-local whose buffers the search reuses.
+///
+/// Returns a tuple `(success nodes, the limit which cut the search short, if any)`, as `astar_all`
+/// does.
 ///
 /// The name of this function isn't entirely accurate: this isn't Dijkstra's original algorithm or
 /// one of its well-known variants. However, unlike the astar_all function it doesn't expect a
-/// heuristic and it also filters out some duplicates.
-pub(crate) fn dijkstra<N, FM, FN, FS>(start_node: N,
-                                      neighbours: FN,
-                                      merge: FM,
-                                      success: FS)
-                                   -> Vec<N>
-                                where N: Debug + Clone + Hash + Eq + PartialEq,
-                                      FN: Fn(bool, &N, &mut Vec<(u32, N)>) -> bool,
-                                      FM: Fn(&mut N, N),
-                                      FS: Fn(&N) -> bool,
+/// heuristic by default (though one can be added with `priority`) and it also filters out some
+/// duplicates.
+///
+/// `todo` is a bucketed priority queue (one bucket per priority). A node whose priority is lower
+/// than that of the node it was reached from (which can only happen if `priority` is
+/// inconsistent) is given the latter's priority instead. Nodes are also recorded in a closed set,
+/// along with the priority at which they were first expanded: if a node equal to one that has
+/// already been expanded at a lower priority is later popped, it is discarded without being
+/// expanded, since every node reachable from it is also reachable (at lower cost) from the earlier
+/// node. This requires that node equality captures everything that determines a node's neighbours
+/// (e.g. how many more edits a node's repairs may have) and whether it is a success node, and
+/// that `priority` is monotonic in a node's cost. Nodes of the same priority are never discarded,
+/// since they may represent different, but equally good, paths.
+pub(crate) fn dijkstra<N, FM, FN, FP, FS>(start_node: N,
+                                          seeds: Vec<(u32, N)>,
+                                          neighbours: FN,
+                                          merge: FM,
+                                          priority: FP,
+                                          success: FS,
+                                          limits: &SearchLimits,
+                                          scratch: &'static LocalKey<RefCell<Scratch<N,
+                                                                                     (u32, N)>>>)
+                                       -> (Vec<N>, Option<LimitReached>)
+                                    where N: Debug + Clone + Hash + Eq + PartialEq + 'static,
+                                          FN: Fn(bool, &N, &mut Vec<(u32, N)>),
+                                          FM: Fn(&mut N, N),
+                                          FP: Fn(u32, &N) -> u32,
+                                          FS: Fn(&N) -> bool,
 {
+    with_scratch(scratch, |s| {
+        dijkstra_in(start_node, seeds, neighbours, merge, priority, success, limits, s)
+    })
+}
+
+fn dijkstra_in<N, FM, FN, FP, FS>(start_node: N,
+                                  seeds: Vec<(u32, N)>,
+                                  neighbours: FN,
+                                  merge: FM,
+                                  priority: FP,
+                                  success: FS,
+                                  limits: &SearchLimits,
+                                  scratch: &mut Scratch<N, (u32, N)>)
+                               -> (Vec<N>, Option<LimitReached>)
+                            where N: Debug + Clone + Hash + Eq + PartialEq,
+                                  FN: Fn(bool, &N, &mut Vec<(u32, N)>),
+                                  FM: Fn(&mut N, N),
+                                  FP: Fn(u32, &N) -> u32,
+                                  FS: Fn(&N) -> bool,
+{
+    let Scratch{ref mut todo, ref mut closed, ref mut next} = *scratch;
     let mut scs_nodes = Vec::new();
-    let mut todo: Vec<IndexMap<N, N>> = vec![indexmap![start_node.clone() => start_node]];
+    if todo.is_empty() {
+        todo.push(IndexMap::new());
+    }
+    todo[0].insert(start_node.clone(), start_node);
+    let seeds = seeds.into_iter().map(|(cost, n)| (priority(cost, &n), n)).collect();
+    // No node of a higher priority than `ceiling` (that of the most urgent seed) need be stored.
+    let ceiling = add_seeds(todo, seeds, &merge);
     let mut c: u32 = 0;
-    let mut next = Vec::new();
+    let max_nodes = max_nodes::<N>(limits.max_memory);
+    let mut progress = Progress::new(limits);
+    let mut stored: usize = todo.iter().map(|t| t.len()).sum();
     loop {
         if todo[c as usize].is_empty() {
             c = c.checked_add(1).unwrap();
             if c as usize == todo.len() {
-                return Vec::new();
+                return (Vec::new(), None);
             }
             continue;
         }
 
         let n = todo[c as usize].pop().unwrap().1;
+        if !progress.step() {
+            return (Vec::new(), None);
+        }
+        if closed.get(&n).map(|&cc| cc < c).unwrap_or(false) {
+            continue;
+        }
         if success(&n) {
             scs_nodes.push(n);
             break;
         }
 
-        if !neighbours(true, &n, &mut next) {
-            return Vec::new();
+        if !progress.expand() {
+            return (Vec::new(), Some(LimitReached::Expansions));
         }
+        neighbours(true, &n, next);
+        closed.entry(n).or_insert(c);
         for (nbr_cost, nbr) in next.drain(..) {
-            let off = nbr_cost as usize;
+            if !progress.step() {
+                return (Vec::new(), None);
+            }
+            let off = priority(nbr_cost, &nbr).max(c) as usize;
+            if ceiling.map_or(false, |cl| off > cl as usize) {
+                continue;
+            }
             for _ in todo.len()..off + 1 {
                 todo.push(IndexMap::new());
             }
             match todo[off].entry(nbr.clone()) {
-                Entry::Vacant(e) => { e.insert(nbr); },
+                Entry::Vacant(e) => { e.insert(nbr); stored += 1; },
                 Entry::Occupied(mut e) => { merge(&mut e.get_mut(), nbr); }
             }
         }
+        if max_nodes.map_or(false, |m| stored > m) {
+            return (Vec::new(), Some(LimitReached::Memory));
+        }
+        if limits.max_frontier_growth.map_or(false, |g| stored > g.saturating_mul(c as usize + 1)) {
+            return (Vec::new(), Some(LimitReached::Frontier));
+        }
     }
 
-    let mut scs_todo = todo.drain(c as usize..c as usize + 1).nth(0).unwrap();
+    for (i, t) in todo.iter_mut().enumerate() {
+        if i != c as usize {
+            t.clear();
+        }
+    }
+    let scs_todo = &mut todo[c as usize];
     while !scs_todo.is_empty() {
         let n = scs_todo.pop().unwrap().1;
+        if !progress.step() {
+            return (Vec::new(), None);
+        }
+        if closed.get(&n).map(|&cc| cc < c).unwrap_or(false) {
+            continue;
+        }
         if success(&n) {
             scs_nodes.push(n);
             continue;
         }
-        if !neighbours(false, &n, &mut next) {
-            return Vec::new();
+        if !progress.expand() {
+            return (scs_nodes, Some(LimitReached::Expansions));
         }
+        neighbours(false, &n, next);
         for (nbr_cost, nbr) in next.drain(..) {
-            if nbr_cost == c {
+            if !progress.step() {
+                return (Vec::new(), None);
+            }
+            if priority(nbr_cost, &nbr).max(c) == c {
                 match scs_todo.entry(nbr.clone()) {
-                    Entry::Vacant(e) => { e.insert(nbr); },
+                    Entry::Vacant(e) => { e.insert(nbr); stored += 1; },
                     Entry::Occupied(mut e) => { merge(&mut e.get_mut(), nbr); }
                 }
             }
         }
+        if max_nodes.map_or(false, |m| stored > m) {
+            return (scs_nodes, Some(LimitReached::Memory));
+        }
     }
 
-    scs_nodes
+    (scs_nodes, None)
+}
+
+/// Counts the steps a search takes, and the nodes it expands, against its `SearchLimits`.
+struct Progress<'a> {
+    cancelled: &'a Fn() -> bool,
+    max_expansions: Option<usize>,
+    steps: usize,
+    expansions: usize
+}
+
+impl<'a> Progress<'a> {
+    fn new(limits: &SearchLimits<'a>) -> Self {
+        Progress{cancelled: limits.cancelled,
+                 max_expansions: limits.max_expansions,
+                 steps: 0,
+                 expansions: 0}
+    }
+
+    /// Take a step, returning `false` if the search has been cancelled.
+    fn step(&mut self) -> bool {
+        let check = self.steps % CANCEL_INTERVAL == 0;
+        self.steps += 1;
+        !(check && (self.cancelled)())
+    }
+
+    /// Expand a node, returning `false` (without counting the expansion) if the search has already
+    /// expanded as many nodes as it may.
+    fn expand(&mut self) -> bool {
+        if self.max_expansions.map_or(false, |m| self.expansions >= m) {
+            return false;
+        }
+        self.expansions += 1;
+        true
+    }
+}
+
+/// Add each of the `(priority, node)` pairs in `seeds` to `todo`, merging nodes equal to ones
+/// already there, and return the lowest priority of any seed (or `None` if there are none).
+fn add_seeds<N, FM>(todo: &mut Vec<IndexMap<N, N>>, seeds: Vec<(u32, N)>, merge: &FM) -> Option<u32>
+             where N: Clone + Hash + Eq,
+                   FM: Fn(&mut N, N)
+{
+    let mut ceiling: Option<u32> = None;
+    for (p, n) in seeds {
+        let off = p as usize;
+        for _ in todo.len()..off + 1 {
+            todo.push(IndexMap::new());
+        }
+        match todo[off].entry(n.clone()) {
+            Entry::Vacant(e) => { e.insert(n); },
+            Entry::Occupied(mut e) => { merge(&mut e.get_mut(), n); }
+        }
+        ceiling = Some(ceiling.map_or(p, |cl| cl.min(p)));
+    }
+    ceiling
+}
+
+/// Convert a memory bound of `max_memory` bytes into a bound on the number of nodes a search may
+/// store. This is only approximate: each stored node is counted as two `N`s (since `todo` stores
+/// nodes as both keys and values), but memory shared between nodes (e.g. the `Cactus` stacks of
+/// our recoverers) and the overhead of the search's data structures are not counted, and stored
+/// nodes are counted even after they have been popped.
+fn max_nodes<N>(max_memory: Option<usize>) -> Option<usize> {
+    max_memory.map(|m| m / (2 * size_of::<N>()).max(1))
+}
+
+#[cfg(test)]
+mod test {
+    use indexmap::map::IndexMap;
+
+    use super::{Scratch, SCRATCH_RETAIN};
+
+    #[test]
+    fn scratch_retain() {
+        let mut s = Scratch::<u32, u32>::default();
+        s.todo.push(IndexMap::new());
+        s.todo[0].insert(0, 0);
+        s.closed.insert(0, 0);
+        s.next.push(0);
+        s.clear();
+        // Small buffers are emptied but kept...
+        assert!(s.todo[0].is_empty() && s.todo[0].capacity() > 0);
+        assert!(s.closed.is_empty() && s.closed.capacity() > 0);
+        assert!(s.next.is_empty() && s.next.capacity() > 0);
+
+        // ...but large buffers are freed.
+        s.todo[0].reserve(SCRATCH_RETAIN + 1);
+        s.closed.reserve(SCRATCH_RETAIN + 1);
+        s.next.reserve(SCRATCH_RETAIN + 1);
+        s.clear();
+        assert_eq!(s.todo.capacity(), 0);
+        assert_eq!(s.closed.capacity(), 0);
+        assert_eq!(s.next.capacity(), 0);
+    }
 }
@@ -30,24 +30,36 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use std::collections::HashMap;
-use std::convert::{AsRef, TryFrom};
-use std::env::{current_dir, var};
+use std::collections::{HashMap, HashSet};
+use std::convert::AsRef;
+use std::env::{current_dir, var, var_os};
 use std::error::Error;
-use std::fmt::Debug;
-use std::fs::{File, read_to_string};
+use std::fs::{File, read, read_to_string, rename};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
+use cfgrammar::Symbol;
 use cfgrammar::yacc::{yacc_grm, YaccGrammar, YaccKind};
-use lrtable::{Minimiser, from_yacc, StateGraph, StateTable};
+use cfgrammar::yacc::ast::GrammarAST;
+use lrlex::Lexeme;
+use lrtable::{Minimiser, from_yacc, StateGraph, StateTable, StIdx};
 use rmps::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
-use typename::TypeName;
+
+use conflicts::conflict_examples;
+use export::tables_json;
+use lint::{lint, prod_location, prod_str};
+use parser::{Node, pp_item};
+use pratt::{Assoc, PrattRule};
+use tokid::{channels_fit, TokenId};
 
 const YACC_SUFFIX: &str = "_y";
 const YACC_FILE_EXT: &str = "y";
 const RUST_FILE_EXT: &str = "rs";
+/// The version of the serialised form of the parsing tables in generated code. This must be
+/// incremented whenever that form changes, including when a new version of cfgrammar or lrtable
+/// changes how their types are serialised.
+const TABLES_FORMAT: u32 = 1;
 
 /// Given the filename `x.y` as input, it will statically compile the file `src/x.y` into a Rust
 /// module which can then be imported using `lrpar_mod!(x_y)`. This is a convenience function
@@ -55,28 +67,44 @@ const RUST_FILE_EXT: &str = "rs";
 /// stored in a project's `src/` directory. Note that leaf names must be unique within a single
 /// project, even if they are in different directories: in other words, `a.y` and `x/a.y` will both
 /// be mapped to the same module `a_y` (and it is undefined what the resulting Rust module will
-/// contain).
+/// contain). [`process_file_in_src_as`](fn.process_file_in_src_as.html) can be used to give such
+/// grammars different module names.
 ///
 /// # Panics
 ///
 /// If the input filename does not end in `.y`.
 pub fn process_file_in_src<TokId>(srcp: &str)
                                -> Result<(HashMap<String, TokId>), Box<Error>>
-                            where TokId: Copy + Debug + Eq + TryFrom<usize> + TypeName
+                            where TokId: TokenId
+{
+    let mut mod_name = Path::new(srcp).file_stem().unwrap().to_string_lossy().into_owned();
+    mod_name.push_str(&YACC_SUFFIX);
+    process_file_in_src_as::<TokId>(srcp, &mod_name)
+}
+
+/// As [`process_file_in_src`](fn.process_file_in_src.html), but the generated module is named
+/// `mod_name` (and can be imported with `lrpar_mod!(mod_name)`) rather than being derived from the
+/// leaf name of `srcp`. This allows grammars with the same leaf name in different directories to
+/// be used in a single project.
+///
+/// # Panics
+///
+/// If the input filename does not end in `.y`.
+pub fn process_file_in_src_as<TokId>(srcp: &str, mod_name: &str)
+                                  -> Result<(HashMap<String, TokId>), Box<Error>>
+                               where TokId: TokenId
 {
     let mut inp = current_dir()?;
     inp.push("src");
     inp.push(srcp);
-    if Path::new(srcp).extension().unwrap().to_str().unwrap() != YACC_FILE_EXT {
+    if Path::new(srcp).extension().and_then(|e| e.to_str()) != Some(YACC_FILE_EXT) {
         panic!("File name passed to process_file_in_src must have extension '{}'.", YACC_FILE_EXT);
     }
-    let mut leaf = inp.file_stem().unwrap().to_str().unwrap().to_owned();
-    leaf.push_str(&YACC_SUFFIX);
-    let mut outp = PathBuf::new();
-    outp.push(var("OUT_DIR").unwrap());
-    outp.push(leaf);
+    // OUT_DIR need not be valid Unicode, so we mustn't use `var`.
+    let mut outp = PathBuf::from(var_os("OUT_DIR").ok_or("OUT_DIR is not set")?);
+    outp.push(mod_name);
     outp.set_extension(RUST_FILE_EXT);
-    process_file::<TokId, _, _>(inp, outp)
+    process_file_as::<TokId, _, _>(inp, outp, mod_name)
 }
 
 /// Statically compile the `.y` file `inp` into Rust, placing the output into `outp`. The latter
@@ -86,14 +114,141 @@ pub fn process_file_in_src<TokId>(srcp: &str)
 ///   -> Result<Node<TokId>,
 ///            (Option<Node<TokId>>, Vec<ParseError<TokId>>)>
 /// ```
+///
+/// The parsing tables are reconstructed from their serialised form only once per process, on the
+/// first call to `parse`. Since this can take noticeable time for large grammars, the module also
+/// defines a function `warm_up()` which, when called (e.g. at program startup), performs the
+/// reconstruction immediately. Generated code can outlive the lrpar which generated it (e.g. if it
+/// is checked into version control): it records the version of the format its tables are
+/// serialised in, and a hash of the grammar and tables, and if these don't match those of the
+/// lrpar it is compiled against, `warm_up()` returns an error explaining how to fix the problem
+/// (and `parse` panics with that error). Generated code uses `lazy_static` via lrpar, so the crate
+/// including it needs no dependency of its own on `lazy_static`.
+///
+/// The generated code depends only on the grammar (including any `%include`d fragments) and the
+/// format of lrpar's serialised tables, and `outp` is only rewritten if its contents would change,
+/// so that unchanged grammars do not trigger recompilation.
+///
+/// Grammar files can include grammar fragments (e.g. an expression sub-language shared by several
+/// grammars) with a line of the form `%include "path"`, where `path` is relative to the including
+/// file. The line is replaced by the contents of the fragment, which may itself contain
+/// `%include`s. To avoid clashes between rule names, `%include "path" as ns` prefixes every rule
+/// defined in the fragment (and every reference to such a rule within the fragment) with `ns_`:
+/// the including grammar can then refer to the fragment's rule `Expr` as `ns_Expr`. Terminals are
+/// never renamed, since they are shared with the lexer.
+///
+/// Contextual keywords (see `RTParserBuilder::contextual`) can be declared with a line of the form
+/// `%contextual 'ID' 'kw1' ... 'kwn'`, meaning that each of the terminals `kw1` ... `kwn` can stand
+/// in for the terminal `ID`. Similarly, recovery anchors (see `RTParserBuilder::anchor`) can be
+/// declared with a line of the form `%anchor 't1' ... 'tn'`, and case-insensitive keywords (see
+/// `RTParserBuilder::case_insensitive`) with a line of the form `%case-insensitive 't1' ... 'tn'`.
+/// Rules can be marked as skippable (see `RTParserBuilder::skippable`) with a line of the form
+/// `%skippable R1 ... Rn`. Conflicts in which the rules `R1` ... `Rn` could be reduced can be
+/// resolved by inspecting up to `K` further lexemes at run-time (see `RTParserBuilder::lookahead`)
+/// with a line of the form `%lookahead K R1 ... Rn`. Expression rules can be parsed by an
+/// operator-precedence sub-parser (see `RTParserBuilder::pratt`) with a line of the form
+/// `%pratt R1 ... Rn`: operators take their precedences from the grammar's `%left`, `%right`, and
+/// `%nonassoc` declarations, and an error is returned if a rule is not of a suitable form.
+///
+/// The number of shift/reduce and reduce/reduce conflicts a grammar is expected to have can be
+/// declared with `%expect N` and `%expect-rr N` respectively: if either is given, an error is
+/// returned unless the grammar has exactly the declared number of each kind of conflict (an
+/// undeclared kind is expected to have no conflicts). The generated module then defines a function
+/// `conflicts()` which returns the `(shift/reduce, reduce/reduce)` conflict counts.
+///
+/// The grammar is checked for likely mistakes (see `lint`), each of which is reported as a Cargo
+/// warning (line numbers refer to the grammar after any `%include`s have been expanded). Similarly,
+/// if a grammar does not have the number of conflicts declared by `%expect` or `%expect-rr`, the
+/// error lists the location of each production involved in a conflict, and a shortest input which
+/// reaches the conflict (see `conflict_examples`).
+///
+/// A summary of the automaton (its states and conflicts) can be checked into version control with
+/// a line of the form `%golden "path"`, where `path` is relative to the grammar file. If `path`
+/// does not exist, it is created; if it exists but the grammar's automaton no longer matches it,
+/// an error containing a diff of the two is returned, unless the environment variable
+/// `LRPAR_UPDATE_GOLDEN` is set, in which case `path` is updated. This makes unexpected changes to
+/// the automaton visible in the same way as changes to any other golden file.
+///
+/// To ease the porting of Bison grammars, the GLR declaration `%glr-parser` and the GLR hints
+/// `%dprec N` and `%merge <f>` in productions are accepted but ignored, with a Cargo warning for
+/// each: lrpar has no GLR mode, so a grammar which relies on them for disambiguation must have
+/// its conflicts resolved in the normal way (e.g. with precedences) instead.
+///
+/// Grammars must be UTF-8 encoded (a leading byte order mark is ignored), and may use either
+/// Unix (`\n`) or Windows (`\r\n`) line endings: the latter are converted to the former before
+/// the grammar is processed, as they are for `%include`d fragments and `%golden` files. Paths need
+/// not be valid Unicode, and may be UNC paths.
+///
+/// Literal terminals (e.g. `'+'`, see `is_literal`) are not given lexer rules automatically, since
+/// the lexer is compiled separately (by lrlex) and `process_file` never sees it: the lexer must
+/// define them, as it must every other terminal. A build script which wants yacc-style literals can
+/// append the rules returned by `literal_lex_rules` to its lexer's source before compiling it.
+///
+/// Terminal names (and any other names cfgrammar accepts) may contain non-ASCII characters,
+/// which are passed unchanged to diagnostics and repair descriptions. Since Rust identifiers must
+/// be ASCII, identifiers in the generated code derived from non-ASCII names (e.g. the module name
+/// or `%ast` types and variants) are transliterated by default (e.g. `Ausdrück` becomes
+/// `Ausdruck`); a line `%non-ascii-idents error` makes such names an error instead.
+///
+/// Finally, a typed AST can be generated for a rule `R` with a line of the form `%ast R V1 ... Vn`.
+/// This generates, in a submodule `ast`, an enum `R` with a variant `Vi` for the `i`th production
+/// of `R` (or, if `R` has a single production and no variant names are given, a tuple struct `R`),
+/// and a function `R::from_node` which converts a `Node` for `R` into an `R`, returning `None` if
+/// the `Node` is not for `R`. Each variant (or the struct) has one field for each symbol in its
+/// production: terminals become `Lexeme`s, nonterminals with their own `%ast` declaration become
+/// boxed AST types, and other nonterminals are left as `Node`s. Type and variant names are the
+/// rule and variant names converted to CamelCase (e.g. `%ast type_decl` generates a type
+/// `TypeDecl`), so that rules named after keywords (e.g. `type`) give valid types.
+///
+/// The AST type of rules can be documented, Yacc-style, with a line of the form
+/// `%type <T> R1 ... Rn`. An error is returned unless each `Ri` has the AST type `T`: i.e. `Ri`
+/// has an `%ast` declaration which generates the type `T`, or `T` is `Node` and `Ri` has no `%ast`
+/// declaration. This catches code which relies on a rule's type going out of date when the
+/// grammar's `%ast` declarations change, reporting it against the grammar rather than as a compile
+/// error in (or against) the generated code.
 pub fn process_file<'a, TokId, P, Q>(inp: P,
                                      outp: Q)
                                   -> Result<(HashMap<String, TokId>), Box<Error>>
-                               where TokId: Copy + Debug + Eq + TryFrom<usize> + TypeName,
+                               where TokId: TokenId,
                                      P: AsRef<Path>,
                                      Q: AsRef<Path>
 {
-    let inc = read_to_string(&inp).unwrap();
+    let mut mod_name = inp.as_ref().file_stem().unwrap().to_string_lossy().into_owned();
+    mod_name.push_str(&YACC_SUFFIX);
+    process_file_as::<TokId, _, _>(inp, outp, &mod_name)
+}
+
+/// As [`process_file`](fn.process_file.html), but the generated module is named `mod_name`
+/// rather than being derived from the leaf name of `inp`. This is useful for workspaces whose
+/// layout means that leaf names are ambiguous, or do not make good module names.
+pub fn process_file_as<TokId, P, Q>(inp: P,
+                                    outp: Q,
+                                    mod_name: &str)
+                                 -> Result<(HashMap<String, TokId>), Box<Error>>
+                              where TokId: TokenId,
+                                    P: AsRef<Path>,
+                                    Q: AsRef<Path>
+{
+    let inc = read_grammar(inp.as_ref())?;
+    let dir = inp.as_ref().parent().unwrap_or_else(|| Path::new("."));
+    let canon = canonicalize(inp.as_ref())?;
+    let inc = expand_includes(&inc, dir, &mut vec![canon])?;
+    let (inc, golden) = extract_golden(&inc, dir)?;
+    let (inc, contextual_decls) = extract_directive(&inc, "%contextual", true)?;
+    let (inc, anchor_decls) = extract_directive(&inc, "%anchor", true)?;
+    let (inc, ci_decls) = extract_directive(&inc, "%case-insensitive", true)?;
+    let (inc, skippable_decls) = extract_directive(&inc, "%skippable", false)?;
+    let (inc, lookahead_decls) = extract_directive(&inc, "%lookahead", false)?;
+    let (inc, pratt_decls) = extract_directive(&inc, "%pratt", false)?;
+    let (inc, ast_decls) = extract_directive(&inc, "%ast", false)?;
+    let (inc, type_decls) = extract_types(&inc)?;
+    let (inc, expect_decls) = extract_directive(&inc, "%expect", false)?;
+    let (inc, expectrr_decls) = extract_directive(&inc, "%expect-rr", false)?;
+    let (inc, idents_decls) = extract_directive(&inc, "%non-ascii-idents", false)?;
+    let (inc, glr_hints) = strip_glr_hints(&inc);
+    let idents = idents_policy(&idents_decls)?;
+    let expect = expect_count("%expect", &expect_decls)?;
+    let expectrr = expect_count("%expect-rr", &expectrr_decls)?;
 
     let grm = match yacc_grm(YaccKind::Eco, &inc) {
         Ok(x) => x,
@@ -101,12 +256,27 @@ pub fn process_file<'a, TokId, P, Q>(inp: P,
             panic!("{:?}", s);
         }
     };
+    for l in lint(&inc, &grm) {
+        println!("cargo:warning={}:{}", inp.as_ref().display(), l);
+    }
+    for (line, hint) in glr_hints {
+        println!("cargo:warning={}:{}: {} has no effect: lrpar is not a GLR parser, so \
+                  conflicts are resolved as in any other LR grammar",
+                 inp.as_ref().display(), line, hint);
+    }
     let rule_ids = grm.terms_map().iter()
                                   .map(|(&n, &i)| (n.to_owned(),
                                                    TokId::try_from(usize::from(i))
                                                          .unwrap_or_else(|_| panic!("woo"))))
                                   .collect::<HashMap<_, _>>();
 
+    check_types(&grm, &ast_decls, &type_decls, idents)?;
+    let ast = if ast_decls.is_empty() {
+        String::new()
+    } else {
+        gen_ast(&grm, &ast_decls, &TokId::type_name(), channels_fit::<TokId>(&grm), idents)?
+    };
+
     let (sgraph, stable) = match from_yacc(&grm, Minimiser::Pager) {
         Ok(x) => x,
         Err(s) => {
@@ -114,16 +284,164 @@ pub fn process_file<'a, TokId, P, Q>(inp: P,
         }
     };
 
+    let (sr_len, rr_len) = match stable.conflicts() {
+        Some(c) => (c.sr_len(), c.rr_len()),
+        None => (0, 0)
+    };
+    if (expect.is_some() || expectrr.is_some())
+       && (sr_len != expect.unwrap_or(0) || rr_len != expectrr.unwrap_or(0)) {
+        let mut msg = format!("Expected {} shift/reduce and {} reduce/reduce conflicts, but the \
+                               grammar has {} shift/reduce and {} reduce/reduce conflicts:",
+                              expect.unwrap_or(0), expectrr.unwrap_or(0), sr_len, rr_len);
+        // Point at the productions involved in each conflict, so that users can jump to them.
+        let path = inp.as_ref().display();
+        let loc = |p_idx| {
+            let (line, col) = prod_location(&inc, &grm, p_idx).unwrap_or((1, 1));
+            format!("{}:{}:{}: '{}'", path, line, col, prod_str(&grm, p_idx))
+        };
+        // Each conflict is followed by an example input which reaches it.
+        let c = stable.conflicts().unwrap();
+        let mut examples = conflict_examples(&grm, &sgraph, &stable).into_iter();
+        for &(t_idx, p_idx, st_idx) in c.sr_conflicts() {
+            msg.push_str(&format!("\n  shift/reduce on '{}' in state {}: reduce {}\n    e.g. {}",
+                                  grm.term_name(t_idx).unwrap_or("$"), usize::from(st_idx),
+                                  loc(p_idx), examples.next().unwrap().pp(&grm)));
+        }
+        for &(t_idx, p1_idx, p2_idx, st_idx) in c.rr_conflicts() {
+            msg.push_str(&format!("\n  reduce/reduce on '{}' in state {}: reduce {} or {}\n    \
+                                   e.g. {}",
+                                  grm.term_name(t_idx).unwrap_or("$"), usize::from(st_idx),
+                                  loc(p1_idx), loc(p2_idx), examples.next().unwrap().pp(&grm)));
+        }
+        return Err(msg.into());
+    }
+
+    if let Some(ref golden) = golden {
+        let summary = automaton_summary(&grm, &sgraph, &stable);
+        match read_to_string(golden).map(|s| normalise_newlines(&s)) {
+            Ok(ref old) if *old == summary => (),
+            Ok(ref old) if var("LRPAR_UPDATE_GOLDEN").is_err() => {
+                return Err(format!("The automaton no longer matches '{}' (set \
+                                    LRPAR_UPDATE_GOLDEN to update it):\n{}",
+                                   golden.display(), line_diff(old, &summary)).into());
+            },
+            _ => {
+                let mut f = File::create(golden)?;
+                f.write_all(summary.as_bytes())?;
+            }
+        }
+    }
+
+    // The builder calls needed to set the options declared in the grammar.
+    let mut options = String::new();
+    for names in &contextual_decls {
+        if names.len() < 2 {
+            return Err(format!("Expected an identifier and at least one keyword in '%contextual \
+                                {}'", names.join(" ")).into());
+        }
+        let ident_idx = grm.term_idx(&names[0])
+                           .ok_or_else(|| format!("Unknown terminal '{}' in %contextual",
+                                                  names[0]))?;
+        for kw in &names[1..] {
+            let kw_idx = grm.term_idx(kw)
+                            .ok_or_else(|| format!("Unknown terminal '{}' in %contextual", kw))?;
+            options.push_str(&format!("\n        .contextual(TIdx::from({}u32), \
+                                       TIdx::from({}u32))",
+                                      u32::from(kw_idx), u32::from(ident_idx)));
+        }
+    }
+    for n in anchor_decls.iter().flat_map(|names| names.iter()) {
+        let t_idx = grm.term_idx(n)
+                       .ok_or_else(|| format!("Unknown terminal '{}' in %anchor", n))?;
+        options.push_str(&format!("\n        .anchor(TIdx::from({}u32))", u32::from(t_idx)));
+    }
+    for n in ci_decls.iter().flat_map(|names| names.iter()) {
+        let t_idx = grm.term_idx(n)
+                       .ok_or_else(|| format!("Unknown terminal '{}' in %case-insensitive", n))?;
+        options.push_str(&format!("\n        .case_insensitive(TIdx::from({}u32))",
+                                  u32::from(t_idx)));
+    }
+    for n in skippable_decls.iter().flat_map(|names| names.iter()) {
+        let nt_idx = grm.nonterm_idx(n)
+                        .ok_or_else(|| format!("Unknown rule '{}' in %skippable", n))?;
+        options.push_str(&format!("\n        .skippable(NTIdx::from({}u32))", u32::from(nt_idx)));
+    }
+    for decl in &lookahead_decls {
+        let k = decl.first()
+                    .and_then(|k| k.parse::<usize>().ok())
+                    .ok_or("Expected a number after %lookahead")?;
+        if decl.len() == 1 {
+            return Err("Expected at least one rule after %lookahead".into());
+        }
+        for n in &decl[1..] {
+            let nt_idx = grm.nonterm_idx(n)
+                            .ok_or_else(|| format!("Unknown rule '{}' in %lookahead", n))?;
+            options.push_str(&format!("\n        .lookahead(NTIdx::from({}u32), {})",
+                                      u32::from(nt_idx), k));
+        }
+    }
+    if !pratt_decls.is_empty() {
+        let precs = prec_levels(&inc).into_iter()
+                                     .filter_map(|(n, level, assoc)| {
+                                         grm.term_idx(&n).map(|t_idx| (t_idx, level, assoc))
+                                     })
+                                     .collect::<Vec<_>>();
+        for n in pratt_decls.iter().flat_map(|names| names.iter()) {
+            let nt_idx = grm.nonterm_idx(n)
+                            .ok_or_else(|| format!("Unknown rule '{}' in %pratt", n))?;
+            PrattRule::new(&grm, nt_idx, &precs)?;
+            let precs = precs.iter()
+                             .map(|&(t_idx, level, assoc)| {
+                                 format!("(TIdx::from({}u32), {}, ::lrpar::Assoc::{:?})",
+                                         u32::from(t_idx), level, assoc)
+                             })
+                             .collect::<Vec<_>>();
+            options.push_str(&format!("\n        .pratt(NTIdx::from({}u32), &[{}])",
+                                      u32::from(nt_idx), precs.join(", ")));
+        }
+    }
+
     let mut outs = String::new();
     // Header
-    let mod_name = inp.as_ref().file_stem().unwrap().to_str().unwrap();
-    outs.push_str(&format!("mod {}_y {{", mod_name));
-    outs.push_str(&format!("use lrpar::{{Node, parse_rcvry, ParseError, reconstitute, RecoveryKind}};
+    outs.push_str(&format!("mod {} {{", rust_ident(mod_name, idents)?));
+    outs.push_str(&format!("
+use lrpar::{{lazy_static, Node, NTIdx, ParseError, reconstitute, RecoveryKind, RTParserBuilder,
+            StateGraph, StateTable, TIdx, YaccGrammar}};
 use lrlex::Lexeme;
 
 pub fn parse(lexemes: &Vec<Lexeme<{tn}>>)
           -> Result<Node<{tn}>, (Option<Node<{tn}>>, Vec<ParseError<{tn}>>)>
-{{", tn=TokId::type_name()));
+{{
+    let &(ref grm, ref sgraph, ref stable) = match tables() {{
+        Ok(t) => t,
+        Err(e) => panic!(\"{{}}\", e)
+    }};
+    RTParserBuilder::new(grm, sgraph, stable)
+        .recoverer(RecoveryKind::MF){options}
+        .parse(lexemes)
+}}
+
+/// Reconstruct the parsing tables now, rather than on the first call to `parse`, returning an
+/// error if they can't be used (in which case `parse` will panic). Calling this is optional, and
+/// calling it more than once is harmless.
+pub fn warm_up() -> Result<(), String> {{
+    tables().map(|_| ())
+}}
+
+/// Return the number of (shift/reduce, reduce/reduce) conflicts in the grammar.
+pub fn conflicts() -> (usize, usize) {{
+    ({sr_len}, {rr_len})
+}}
+
+/// Return the parsing tables, reconstructing them only on the first call in a process.
+fn tables() -> Result<&'static (YaccGrammar, StateGraph, StateTable), String> {{
+    TABLES.as_ref().map_err(|e| e.clone())
+}}
+
+lazy_static! {{
+    static ref TABLES: Result<(YaccGrammar, StateGraph, StateTable), String> =",
+                           tn=TokId::type_name(), options=options, sr_len=sr_len,
+                           rr_len=rr_len));
 
     // grm, sgraph, stable
     let mut grm_buf = Vec::new();
@@ -132,39 +450,1288 @@ pub fn parse(lexemes: &Vec<Lexeme<{tn}>>)
     sgraph.serialize(&mut Serializer::new(&mut sgraph_buf)).unwrap();
     let mut stable_buf = Vec::new();
     stable.serialize(&mut Serializer::new(&mut stable_buf)).unwrap();
+    let grm_buf = canonical_msgpack(&grm_buf)?;
+    let sgraph_buf = canonical_msgpack(&sgraph_buf)?;
+    let stable_buf = canonical_msgpack(&stable_buf)?;
     outs.push_str(&format!("
-    let (grm, sgraph, stable) = reconstitute(&vec!{:?}, &vec!{:?}, &vec!{:?});
-    parse_rcvry(RecoveryKind::MF, &grm, |_| 1, &sgraph, &stable, lexemes)
-", grm_buf, sgraph_buf, stable_buf));
+        reconstitute({},
+                     {:#018x},
+                     &vec!{:?},
+                     &vec!{:?},
+                     &vec!{:?});
+}}
+", TABLES_FORMAT, tables_hash(&grm, &sgraph, &stable), grm_buf, sgraph_buf,
+   stable_buf));
 
-    outs.push_str("}");
+    outs.push_str(&ast);
 
     // Footer
     outs.push_str("}");
     // If the file we're about to write out already exists with the same contents, then we don't
     // overwrite it (since that will force a recompile of the file, and relinking of the binary
     // etc).
-    if let Ok(curs) = read_to_string(&outp) {
-        if curs == outs {
+    if let Ok(curs) = read(&outp) {
+        if curs == outs.as_bytes() {
             return Ok(rule_ids);
         }
     }
-    let mut f = File::create(outp)?;
-    f.write_all(outs.as_bytes())?;
+    // We write to a temporary file and then move it into place, so that tools watching the
+    // output directory never see a partially written file.
+    let tmpp = outp.as_ref().with_extension("tmp");
+    {
+        let mut f = File::create(&tmpp)?;
+        f.write_all(outs.as_bytes())?;
+    }
+    rename(&tmpp, outp)?;
     Ok(rule_ids)
 }
 
+/// Textually expand the `%include` directives in the grammar `s`, whose includes are relative to
+/// `dir`. `stack` contains the canonical paths of the files currently being included, so that
+/// recursive includes can be reported as errors.
+fn expand_includes(s: &str, dir: &Path, stack: &mut Vec<PathBuf>) -> Result<String, Box<Error>> {
+    let mut out = String::with_capacity(s.len());
+    for l in s.lines() {
+        let t = l.trim();
+        if !t.starts_with("%include") {
+            out.push_str(l);
+            out.push('\n');
+            continue;
+        }
+        let (path, after) = quoted_path(t, "%include")?;
+        let incp = dir.join(path);
+        let ns = if after.is_empty() {
+            None
+        } else if after.starts_with("as ") && !after[3..].trim().is_empty() {
+            Some(after[3..].trim())
+        } else {
+            return Err(format!("Expected 'as <namespace>' in '{}'", t).into());
+        };
+
+        let canon = canonicalize(&incp)?;
+        if stack.contains(&canon) {
+            return Err(format!("Recursive %include of '{}'", incp.display()).into());
+        }
+        let frag = read_grammar(&incp)?;
+        stack.push(canon);
+        let frag = expand_includes(&frag, incp.parent().unwrap(), stack)?;
+        stack.pop();
+        match ns {
+            Some(ns) => out.push_str(&namespace_rules(&frag, ns)),
+            None => out.push_str(&frag)
+        }
+    }
+    Ok(out)
+}
+
+/// Read the grammar (or grammar fragment) at `p`, normalising its line endings (see
+/// `normalise_newlines`).
+pub(crate) fn read_grammar(p: &Path) -> Result<String, Box<Error>> {
+    let s = read_to_string(p).map_err(|e| {
+        format!("Can't read grammar '{}' (grammars must be UTF-8): {}", p.display(), e)
+    })?;
+    Ok(normalise_newlines(&s))
+}
+
+/// Return the canonical form of `p`, which is used only to identify files: on Windows, it is a
+/// verbatim (`\\?\`) path, to which paths with forward slashes can't be joined, so it must
+/// never be used to find other files.
+fn canonicalize(p: &Path) -> Result<PathBuf, Box<Error>> {
+    p.canonicalize().map_err(|e| format!("Can't find '{}': {}", p.display(), e).into())
+}
+
+/// Convert Windows (`\r\n`) line endings in `s` to Unix (`\n`) line endings, and remove any
+/// leading byte order mark (which Windows editors are prone to adding).
+fn normalise_newlines(s: &str) -> String {
+    let s = if s.starts_with('\u{feff}') { &s['\u{feff}'.len_utf8()..] } else { s };
+    s.replace("\r\n", "\n")
+}
+
+/// Split the line `t`, which starts with `directive`, into the quoted path which follows the
+/// directive and the (trimmed) remainder of the line.
+fn quoted_path<'a>(t: &'a str, directive: &str) -> Result<(&'a str, &'a str), Box<Error>> {
+    let rest = t[directive.len()..].trim();
+    if !rest.starts_with('"') {
+        return Err(format!("Expected a quoted path in '{}'", t).into());
+    }
+    match rest[1..].find('"') {
+        Some(i) => Ok((&rest[1..i + 1], rest[i + 2..].trim())),
+        None => Err(format!("Unterminated path in '{}'", t).into())
+    }
+}
+
+/// Remove any `%golden "path"` line from the grammar `s` (replacing it with a blank line),
+/// returning the altered grammar and, if there was such a line, `path` relative to `dir`.
+fn extract_golden(s: &str, dir: &Path) -> Result<(String, Option<PathBuf>), Box<Error>> {
+    let mut out = String::with_capacity(s.len());
+    let mut golden = None;
+    for l in s.lines() {
+        let t = l.trim();
+        if t.split_whitespace().next() == Some("%golden") {
+            let (path, after) = quoted_path(t, "%golden")?;
+            if !after.is_empty() {
+                return Err(format!("Unexpected text after path in '{}'", t).into());
+            }
+            if golden.is_some() {
+                return Err("%golden can only be declared once".into());
+            }
+            golden = Some(dir.join(path));
+        } else {
+            out.push_str(l);
+        }
+        out.push('\n');
+    }
+    Ok((out, golden))
+}
+
+/// The Bison GLR hints which can appear in productions, each of which takes one argument.
+const GLR_HINTS: [&str; 2] = ["%dprec", "%merge"];
+
+/// Blank out the Bison GLR declaration `%glr-parser` and hints (`%dprec N` and `%merge <f>`) in
+/// the grammar `s`, which cfgrammar does not understand, returning the altered grammar and the
+/// (1-based) line number and name of each. Text is replaced with spaces so that line and column
+/// numbers in later error messages are unaffected.
+fn strip_glr_hints(s: &str) -> (String, Vec<(usize, &'static str)>) {
+    let mut out = String::with_capacity(s.len());
+    let mut hints = Vec::new();
+    for (i, l) in s.lines().enumerate() {
+        if l.trim() == "%glr-parser" {
+            hints.push((i + 1, "%glr-parser"));
+            out.push('\n');
+            continue;
+        }
+        // Only ASCII bytes are ever replaced, so the line remains valid UTF-8.
+        let mut bytes = l.as_bytes().to_vec();
+        let mut quote = None;
+        let mut j = 0;
+        while j < bytes.len() {
+            let b = bytes[j];
+            if let Some(q) = quote {
+                if b == q {
+                    quote = None;
+                }
+            } else if b == b'\'' || b == b'"' {
+                quote = Some(b);
+            } else if j == 0 || bytes[j - 1].is_ascii_whitespace() {
+                if let Some(&h) = GLR_HINTS.iter().find(|h| hint_at(&bytes[j..], h)) {
+                    // Blank out the hint and the word following it.
+                    let mut end = j + h.len();
+                    while end < bytes.len() && bytes[end].is_ascii_whitespace() {
+                        end += 1;
+                    }
+                    while end < bytes.len() && !b" \t;|".contains(&bytes[end]) {
+                        end += 1;
+                    }
+                    for b in &mut bytes[j..end] {
+                        *b = b' ';
+                    }
+                    hints.push((i + 1, h));
+                    j = end;
+                    continue;
+                }
+            }
+            j += 1;
+        }
+        out.push_str(&String::from_utf8(bytes).unwrap());
+        out.push('\n');
+    }
+    (out, hints)
+}
+
+/// Does `s` start with the word `hint`?
+fn hint_at(s: &[u8], hint: &str) -> bool {
+    s.starts_with(hint.as_bytes()) && s.get(hint.len()).map_or(true, |b| !b.is_ascii_alphanumeric())
+}
+
+/// Return a summary of the automaton `sgraph`/`stable` for `grm`: its states (each described by
+/// its core items) and its conflicts. State numbers are not stable from one build to the next, so
+/// they are not included: instead, states, and conflicts, are sorted textually. This is the format
+/// of `%golden` files (see `process_file`).
+pub fn automaton_summary(grm: &YaccGrammar, sgraph: &StateGraph, stable: &StateTable) -> String {
+    let mut states = Vec::new();
+    for i in 0..sgraph.all_states_len() {
+        let mut items = sgraph.core_state(StIdx::from(i))
+                              .items
+                              .keys()
+                              .map(|&(p_idx, s_idx)| format!("  {}\n", pp_item(grm, p_idx, s_idx)))
+                              .collect::<Vec<_>>();
+        items.sort();
+        states.push(items.concat());
+    }
+    states.sort();
+    let mut conflicts = Vec::new();
+    if let Some(c) = stable.conflicts() {
+        for &(t_idx, p_idx, _) in c.sr_conflicts() {
+            conflicts.push(format!("  shift/reduce on '{}': reduce {}\n",
+                                   grm.term_name(t_idx).unwrap_or("$"), prod_str(grm, p_idx)));
+        }
+        for &(t_idx, p1_idx, p2_idx, _) in c.rr_conflicts() {
+            conflicts.push(format!("  reduce/reduce on '{}': reduce {} or {}\n",
+                                   grm.term_name(t_idx).unwrap_or("$"), prod_str(grm, p1_idx),
+                                   prod_str(grm, p2_idx)));
+        }
+    }
+    conflicts.sort();
+    let mut s = format!("{} states\n", states.len());
+    for st in states {
+        s.push_str("state:\n");
+        s.push_str(&st);
+    }
+    s.push_str(&format!("{} conflicts\n", conflicts.len()));
+    s.push_str(&conflicts.concat());
+    s
+}
+
+/// Return a line-based diff from `old` to `new`, showing only the lines which differ: lines only
+/// in `old` are prefixed with `-`, and lines only in `new` with `+`.
+fn line_diff(old: &str, new: &str) -> String {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                            lcs[i + 1][j + 1] + 1
+                        } else {
+                            lcs[i + 1][j].max(lcs[i][j + 1])
+                        };
+        }
+    }
+    let mut s = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            s.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        } else {
+            s.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        }
+    }
+    s
+}
+
+/// Remove lines starting with `directive` (e.g. `%contextual`, which cfgrammar does not
+/// understand) from the grammar `s`, returning the altered grammar and, for each removed line, the
+/// list of names which followed the directive. If `quoted` is `true`, the names must be quoted
+/// terminal names (and are returned without quotes); otherwise they must be identifiers. Each
+/// removed line is replaced with a blank line so that line numbers in later error messages are
+/// unaffected.
+fn extract_directive(s: &str, directive: &str, quoted: bool)
+                  -> Result<(String, Vec<Vec<String>>), Box<Error>>
+{
+    let mut out = String::with_capacity(s.len());
+    let mut decls = Vec::new();
+    for l in s.lines() {
+        let t = l.trim();
+        if t.split_whitespace().next() == Some(directive) {
+            let mut names = Vec::new();
+            for n in t[directive.len()..].split_whitespace() {
+                if quoted {
+                    if n.len() < 2 || !n.starts_with('\'') || !n.ends_with('\'') {
+                        return Err(format!("Expected a quoted terminal name in '{}'", t).into());
+                    }
+                    names.push(n[1..n.len() - 1].to_owned());
+                } else {
+                    if !n.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                        return Err(format!("Expected an identifier in '{}'", t).into());
+                    }
+                    names.push(n.to_owned());
+                }
+            }
+            decls.push(names);
+        } else {
+            out.push_str(l);
+        }
+        out.push('\n');
+    }
+    Ok((out, decls))
+}
+
+/// Return the terminals given a precedence by the `%left`, `%right`, and `%nonassoc` declarations
+/// in the grammar `s`, each with its level (declarations later in the grammar have higher levels,
+/// and so bind more tightly) and associativity.
+fn prec_levels(s: &str) -> Vec<(String, u32, Assoc)> {
+    let mut precs = Vec::new();
+    let mut level = 0;
+    for l in s.lines() {
+        let mut words = l.split_whitespace();
+        let assoc = match words.next() {
+            Some("%left") => Assoc::Left,
+            Some("%right") => Assoc::Right,
+            Some("%nonassoc") => Assoc::Nonassoc,
+            _ => continue
+        };
+        level += 1;
+        precs.extend(words.map(|n| (n.trim_matches('\'').to_owned(), level, assoc)));
+    }
+    precs
+}
+
+/// Remove each line of the form `%type <T> R1 ... Rn` from `s` (as `extract_directive` does),
+/// returning the remaining string and a list of `(T, [R1, ..., Rn])` pairs.
+fn extract_types(s: &str) -> Result<(String, Vec<(String, Vec<String>)>), Box<Error>> {
+    let mut out = String::with_capacity(s.len());
+    let mut decls = Vec::new();
+    for l in s.lines() {
+        let t = l.trim();
+        let mut words = t.split_whitespace();
+        if words.next() == Some("%type") {
+            let ty = match words.next() {
+                Some(w) if w.len() > 2 && w.starts_with('<') && w.ends_with('>') => {
+                    &w[1..w.len() - 1]
+                },
+                _ => return Err(format!("Expected a type of the form <T> in '{}'", t).into())
+            };
+            let mut names = Vec::new();
+            for n in words {
+                if !n.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    return Err(format!("Expected an identifier in '{}'", t).into());
+                }
+                names.push(n.to_owned());
+            }
+            decls.push((ty.to_owned(), names));
+        } else {
+            out.push_str(l);
+        }
+        out.push('\n');
+    }
+    Ok((out, decls))
+}
+
+/// Return the count given by the (at most one) `%expect` or `%expect-rr` declaration (as named by
+/// `directive`) in `decls`, or `None` if there is no such declaration.
+fn expect_count(directive: &str, decls: &[Vec<String>]) -> Result<Option<usize>, Box<Error>> {
+    match decls.len() {
+        0 => Ok(None),
+        1 if decls[0].len() == 1 => {
+            decls[0][0].parse::<usize>()
+                       .map(Some)
+                       .map_err(|_| format!("Expected a number after {}", directive).into())
+        },
+        1 => Err(format!("Expected a single number after {}", directive).into()),
+        _ => Err(format!("{} can only be declared once", directive).into())
+    }
+}
+
+/// How identifiers in generated code are derived from names (e.g. of rules or `%ast` variants)
+/// which contain non-ASCII characters, which are not valid in Rust identifiers.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum NonAsciiIdents {
+    /// Replace each non-ASCII character with an ASCII approximation (see `rust_ident`).
+    Transliterate,
+    /// Report an error.
+    Error
+}
+
+/// Return the policy given by the (at most one) `%non-ascii-idents` declaration in `decls`,
+/// defaulting to `NonAsciiIdents::Transliterate`.
+fn idents_policy(decls: &[Vec<String>]) -> Result<NonAsciiIdents, Box<Error>> {
+    if decls.len() > 1 {
+        return Err("%non-ascii-idents can only be declared once".into());
+    }
+    match decls.get(0).map(|d| d.iter().map(|x| x.as_str()).collect::<Vec<_>>()) {
+        None => Ok(NonAsciiIdents::Transliterate),
+        Some(ref d) if *d == ["transliterate"] => Ok(NonAsciiIdents::Transliterate),
+        Some(ref d) if *d == ["error"] => Ok(NonAsciiIdents::Error),
+        Some(_) => Err("Expected 'transliterate' or 'error' after %non-ascii-idents".into())
+    }
+}
+
+/// Return a Rust identifier for `name` according to `policy`. ASCII names are returned unchanged.
+/// When transliterating, accented Latin letters are replaced by their unaccented equivalents
+/// (e.g. `Ausdrück` becomes `Ausdruck`) and any other non-ASCII character `c` by `U` followed by
+/// its code point in hex (e.g. `式` becomes `U5f0f`).
+fn rust_ident(name: &str, policy: NonAsciiIdents) -> Result<String, Box<Error>> {
+    if name.is_ascii() {
+        return Ok(name.to_owned());
+    }
+    if policy == NonAsciiIdents::Error {
+        return Err(format!("'{}' can't be used as a Rust identifier since it contains \
+                            non-ASCII characters (see %non-ascii-idents)", name).into());
+    }
+    let mut s = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii() {
+            s.push(c);
+        } else {
+            match ascii_approx(c) {
+                Some(a) => s.push_str(a),
+                None => s.push_str(&format!("U{:04x}", c as u32))
+            }
+        }
+    }
+    Ok(s)
+}
+
+/// Return the name of the AST type or variant generated for the rule or variant name `name`,
+/// where `policy` is as for `rust_ident`. The name is converted to CamelCase (e.g. `type_decl`
+/// becomes `TypeDecl`), so that lowercase names, and names which are keywords (e.g. `type` or
+/// `match`), give valid and conventionally named types and variants. `Self`, the only keyword in
+/// CamelCase, becomes `Self_`.
+fn ast_ident(name: &str, policy: NonAsciiIdents) -> Result<String, Box<Error>> {
+    let mut s = String::new();
+    for part in rust_ident(name, policy)?.split('_').filter(|p| !p.is_empty()) {
+        s.push_str(&part[..1].to_ascii_uppercase());
+        s.push_str(&part[1..]);
+    }
+    if s.is_empty() || s.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(format!("'{}' can't be used as the name of an AST type or variant", name)
+                   .into());
+    }
+    if s == "Self" {
+        s.push('_');
+    }
+    Ok(s)
+}
+
+/// Return an ASCII approximation of the accented Latin letter `c`, or `None` if `c` is not one.
+fn ascii_approx(c: char) -> Option<&'static str> {
+    Some(match c {
+        'À'...'Å' => "A",
+        'Æ' => "AE",
+        'Ç' => "C",
+        'È'...'Ë' => "E",
+        'Ì'...'Ï' => "I",
+        'Ð' => "D",
+        'Ñ' => "N",
+        'Ò'...'Ö' | 'Ø' => "O",
+        'Ù'...'Ü' => "U",
+        'Ý' => "Y",
+        'Þ' => "Th",
+        'ß' => "ss",
+        'à'...'å' => "a",
+        'æ' => "ae",
+        'ç' => "c",
+        'è'...'ë' => "e",
+        'ì'...'ï' => "i",
+        'ð' => "d",
+        'ñ' => "n",
+        'ò'...'ö' | 'ø' => "o",
+        'ù'...'ü' => "u",
+        'ý' | 'ÿ' => "y",
+        'þ' => "th",
+        _ => return None
+    })
+}
+
+/// Return a map from each rule with one of the `%ast` declarations `decls` to the name of its
+/// generated type, where `idents` determines how non-ASCII rule names are turned into
+/// identifiers.
+fn ast_type_names<'a>(grm: &YaccGrammar, decls: &'a [Vec<String>], idents: NonAsciiIdents)
+                   -> Result<HashMap<&'a str, String>, Box<Error>>
+{
+    let mut ast_nts = HashMap::new();
+    let mut ty_names = HashSet::new();
+    for names in decls {
+        if names.is_empty() {
+            return Err("Expected a rule name after %ast".into());
+        }
+        if grm.nonterm_idx(&names[0]).is_none() {
+            return Err(format!("Unknown rule '{}' in %ast", names[0]).into());
+        }
+        let ty_name = ast_ident(&names[0], idents)?;
+        if !ty_names.insert(ty_name.clone()) {
+            return Err(format!("%ast {} has the same type name as another rule", names[0]).into());
+        }
+        ast_nts.insert(names[0].as_str(), ty_name);
+    }
+    Ok(ast_nts)
+}
+
+/// Check that each rule in the `%type` declarations `types` has the declared type in the AST
+/// generated for the `%ast` declarations `ast_decls` (see `process_file`).
+fn check_types(grm: &YaccGrammar,
+               ast_decls: &[Vec<String>],
+               types: &[(String, Vec<String>)],
+               idents: NonAsciiIdents)
+            -> Result<(), Box<Error>>
+{
+    let ast_nts = ast_type_names(grm, ast_decls, idents)?;
+    let mut seen = HashSet::new();
+    for &(ref ty, ref names) in types {
+        if names.is_empty() {
+            return Err(format!("Expected a rule name after %type <{}>", ty).into());
+        }
+        for n in names {
+            if grm.nonterm_idx(n).is_none() {
+                return Err(format!("Unknown rule '{}' in %type", n).into());
+            }
+            if !seen.insert(n.as_str()) {
+                return Err(format!("More than one %type declared for {}", n).into());
+            }
+            match ast_nts.get(n.as_str()) {
+                Some(ast_ty) if ast_ty != ty => {
+                    return Err(format!("%type <{}> {}: the AST type of {} is {}",
+                                       ty, n, n, ast_ty).into());
+                },
+                None if ty != "Node" => {
+                    return Err(format!("%type <{}> {}: {} has no %ast declaration, so its AST type \
+                                        is Node", ty, n, n).into());
+                },
+                _ => ()
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Generate the `ast` module (see `process_file`) for the `%ast` declarations `decls`, where `tn`
+/// is the name of the type of token IDs, `channels` whether those token IDs have room for channel
+/// bits (see `TokenId`), and `idents` determines how non-ASCII rule and variant names are turned
+/// into identifiers.
+fn gen_ast(grm: &YaccGrammar,
+           decls: &[Vec<String>],
+           tn: &str,
+           channels: bool,
+           idents: NonAsciiIdents)
+        -> Result<String, Box<Error>>
+{
+    // A map from each rule with a `%ast` declaration to the name of its type.
+    let ast_nts = ast_type_names(grm, decls, idents)?;
+
+    let mut outs = String::new();
+    // Since the AST's type names come from the grammar, they may be the same as those of types
+    // we refer to (e.g. a rule `node` generates a type `Node`), so we refer to the latter only by
+    // absolute paths.
+    outs.push_str("
+#[allow(dead_code)]
+pub mod ast {
+");
+    for names in decls {
+        let nt_name = &ast_nts[names[0].as_str()];
+        let nt_idx = grm.nonterm_idx(&names[0]).unwrap();
+        let prods = grm.nonterm_to_prods(nt_idx);
+        let is_struct = names.len() == 1 && prods.len() == 1;
+        if !is_struct && names.len() - 1 != prods.len() {
+            return Err(format!("%ast {} gives {} variant names, but {} has {} productions",
+                               names[0], names.len() - 1, names[0], prods.len()).into());
+        }
+        let mut vnames = Vec::with_capacity(names.len() - 1);
+        for n in &names[1..] {
+            let vname = ast_ident(n, idents)?;
+            if vnames.contains(&vname) {
+                return Err(format!("%ast {} has more than one variant named {}", names[0], vname)
+                           .into());
+            }
+            vnames.push(vname);
+        }
+
+        // For each production: its variant name (if any); the types of its fields; the
+        // expressions which convert `nodes` into those fields; and the (is terminal, index) pairs
+        // which identify it.
+        let mut variants = Vec::new();
+        for (i, p_idx) in prods.iter().enumerate() {
+            let mut tys = Vec::new();
+            let mut convs = Vec::new();
+            let mut syms = Vec::new();
+            for (j, sym) in grm.prod(*p_idx).iter().enumerate() {
+                match *sym {
+                    Symbol::Term(t_idx) => {
+                        tys.push(format!("::lrlex::Lexeme<{}>", tn));
+                        convs.push(format!("::lrpar::ast_lexeme(&nodes[{}])?", j));
+                        syms.push(format!("(true, {})", usize::from(t_idx)));
+                    },
+                    Symbol::Nonterm(sym_nt_idx) => {
+                        if let Some(sym_name) = ast_nts.get(grm.nonterm_name(sym_nt_idx)) {
+                            tys.push(format!("::std::boxed::Box<{}>", sym_name));
+                            convs.push(format!("::std::boxed::Box::new({}::from_node(&nodes[{}])?)",
+                                               sym_name, j));
+                        } else {
+                            tys.push(format!("::lrpar::Node<{}>", tn));
+                            convs.push(format!("nodes[{}].clone()", j));
+                        }
+                        syms.push(format!("(false, {})", usize::from(sym_nt_idx)));
+                    }
+                }
+            }
+            let vname = if is_struct { None } else { Some(vnames[i].as_str()) };
+            variants.push((vname, tys, convs, syms));
+        }
+
+        // The type definition.
+        outs.push_str("\n#[derive(Clone, Debug, PartialEq)]\n");
+        if is_struct {
+            let tys = &variants[0].1;
+            if tys.is_empty() {
+                outs.push_str(&format!("pub struct {};\n", nt_name));
+            } else {
+                let fields = tys.iter().map(|t| format!("pub {}", t)).collect::<Vec<_>>();
+                outs.push_str(&format!("pub struct {}({});\n", nt_name, fields.join(", ")));
+            }
+        } else {
+            outs.push_str(&format!("pub enum {} {{\n", nt_name));
+            for &(vname, ref tys, _, _) in &variants {
+                if tys.is_empty() {
+                    outs.push_str(&format!("    {},\n", vname.unwrap()));
+                } else {
+                    outs.push_str(&format!("    {}({}),\n", vname.unwrap(), tys.join(", ")));
+                }
+            }
+            outs.push_str("}\n");
+        }
+
+        // The conversion from a Node.
+        outs.push_str(&format!("
+impl {nt} {{
+    /// Convert `node` into a `{nt}`, returning `None` if it is not a node for `{rule}` (or
+    /// contains a node which can't be converted).
+    pub fn from_node(node: &::lrpar::Node<{tn}>) -> ::std::option::Option<{nt}> {{
+        let nodes = match *node {{
+            ::lrpar::Node::Nonterm{{nonterm_idx, ref nodes}}
+                if usize::from(nonterm_idx) == {nt_idx} => nodes,
+            _ => return ::std::option::Option::None
+        }};
+", nt=nt_name, rule=names[0], tn=tn, nt_idx=usize::from(nt_idx)));
+        for &(vname, _, ref convs, ref syms) in &variants {
+            let ctor = match vname {
+                Some(v) => format!("{}::{}", nt_name, v),
+                None => nt_name.to_owned()
+            };
+            let val = if convs.is_empty() {
+                          ctor
+                      } else {
+                          format!("{}({})", ctor, convs.join(", "))
+                      };
+            outs.push_str(&format!("        if ::lrpar::ast_child_matches(nodes, {}, &[{}]) {{
+            return ::std::option::Option::Some({});
+        }}
+", channels, syms.join(", "), val));
+        }
+        outs.push_str("        ::std::option::Option::None
+    }
+}
+");
+    }
+    outs.push_str("}\n");
+    Ok(outs)
+}
+
+/// Prefix every rule defined in the grammar fragment `frag`, and every reference to such a rule in
+/// `frag`, with `ns_`.
+fn namespace_rules(frag: &str, ns: &str) -> String {
+    let mut defined = HashSet::new();
+    rewrite_idents(frag, |n, is_defn| {
+        if is_defn {
+            defined.insert(n.to_owned());
+        }
+        n.to_owned()
+    });
+    rewrite_idents(frag, |n, _| {
+        if defined.contains(n) {
+            format!("{}_{}", ns, n)
+        } else {
+            n.to_owned()
+        }
+    })
+}
+
+/// Replace every identifier in the grammar `s` with the result of calling `f` on it. `f` is also
+/// told whether the identifier is immediately followed by a `:` (i.e. whether it is the name of a
+/// rule being defined). Quoted terminals, comments, and directives (e.g. `%prec`) are left
+/// untouched.
+fn rewrite_idents<F>(s: &str, mut f: F) -> String
+              where F: FnMut(&str, bool) -> String
+{
+    let ident_end = |i: usize| {
+        s[i..].find(|c: char| !(c.is_alphanumeric() || c == '_'))
+              .map(|j| i + j)
+              .unwrap_or_else(|| s.len())
+    };
+    let mut out = String::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        let c = s[i..].chars().next().unwrap();
+        let end = if c == '\'' || c == '"' {
+            s[i + 1..].find(c).map(|j| i + j + 2).unwrap_or_else(|| s.len())
+        } else if s[i..].starts_with("/*") {
+            s[i + 2..].find("*/").map(|j| i + j + 4).unwrap_or_else(|| s.len())
+        } else if s[i..].starts_with("//") {
+            s[i..].find('\n').map(|j| i + j).unwrap_or_else(|| s.len())
+        } else if c == '%' {
+            ident_end(i + 1)
+        } else if c.is_alphabetic() || c == '_' {
+            let end = ident_end(i);
+            let is_defn = s[end..].trim_left().starts_with(':');
+            out.push_str(&f(&s[i..end], is_defn));
+            i = end;
+            continue;
+        } else {
+            i + c.len_utf8()
+        };
+        out.push_str(&s[i..end]);
+        i = end;
+    }
+    out
+}
+
+/// This function is called by generated AST conversion functions: does the sequence of `nodes`
+/// match the symbols `syms` (each a pair `(is terminal, index)`)? If `channels` is true, the
+/// channel bits of lexemes' token IDs are ignored.
+#[doc(hidden)]
+pub fn ast_child_matches<TokId: TokenId>(nodes: &[Node<TokId>],
+                                         channels: bool,
+                                         syms: &[(bool, usize)])
+                                      -> bool
+{
+    nodes.len() == syms.len()
+        && nodes.iter().zip(syms.iter()).all(|(n, &(is_term, idx))| {
+               match *n {
+                   Node::Term{lexeme} => {
+                       let tok_id = if channels {
+                                        lexeme.tok_id().without_channel()
+                                    } else {
+                                        lexeme.tok_id()
+                                    };
+                       is_term && tok_id.to_usize() == Some(idx)
+                   },
+                   Node::Nonterm{nonterm_idx, ..} => !is_term && usize::from(nonterm_idx) == idx
+               }
+           })
+}
+
+/// This function is called by generated AST conversion functions: return the lexeme of `node`,
+/// or `None` if it is not a terminal node.
+#[doc(hidden)]
+pub fn ast_lexeme<TokId: TokenId>(node: &Node<TokId>) -> Option<Lexeme<TokId>> {
+    match *node {
+        Node::Term{lexeme} => Some(lexeme),
+        Node::Nonterm{..} => None
+    }
+}
+
+/// Build a grammar, and its state graph and table, from `ast`, a grammar constructed
+/// programmatically (e.g. by a DSL framework) rather than parsed from Yacc source. The result can
+/// be passed directly to `RTParserBuilder::new`. `ast` is completed and validated (e.g. that its
+/// start rule exists and that every symbol is defined) as for a grammar parsed from text, and the
+/// state graph and table are built as `process_file` builds them. Unlike `process_file`, conflicts
+/// are not an error: callers can inspect them with `StateTable::conflicts`.
+pub fn tables_from_ast(yacc_kind: YaccKind, mut ast: GrammarAST)
+                    -> Result<(YaccGrammar, StateGraph, StateTable), Box<Error>>
+{
+    ast.complete_and_validate().map_err(|e| format!("Invalid grammar: {:?}", e))?;
+    let grm = YaccGrammar::new(yacc_kind, &ast);
+    let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager)
+                               .map_err(|e| format!("Can't build state table: {:?}", e))?;
+    Ok((grm, sgraph, stable))
+}
+
 /// This function is called by generated files; it exists so that generated files don't require a
 /// dependency on serde and rmps.
+///
+/// `format` is the `TABLES_FORMAT` of the lrpar which serialised the tables, and `hash` the
+/// `tables_hash` of the grammar and tables before they were serialised. A generated file can
+/// outlive the lrpar which generated it (e.g. because it was checked into version control), so
+/// rather than deserialising tables which may then behave arbitrarily, an error explaining how to
+/// fix the problem is returned if the tables are in a format this lrpar can't read, or if the
+/// deserialised grammar and tables aren't those which were serialised.
 #[doc(hidden)]
-pub fn reconstitute(grm_buf: &[u8], sgraph_buf: &[u8], stable_buf: &[u8])
-                -> (YaccGrammar, StateGraph, StateTable)
+pub fn reconstitute(format: u32,
+                    hash: u64,
+                    grm_buf: &[u8],
+                    sgraph_buf: &[u8],
+                    stable_buf: &[u8])
+                 -> Result<(YaccGrammar, StateGraph, StateTable), String>
 {
+    if format != TABLES_FORMAT {
+        return Err(format!("These parsing tables were serialised in format {}, but this version \
+                            of lrpar can only read format {}: regenerate them",
+                           format, TABLES_FORMAT));
+    }
     let mut grm_de = Deserializer::new(&grm_buf[..]);
-    let grm = Deserialize::deserialize(&mut grm_de).unwrap();
+    let grm = Deserialize::deserialize(&mut grm_de)
+                  .map_err(|e| format!("Can't deserialise grammar: {}", e))?;
     let mut sgraph_de = Deserializer::new(&sgraph_buf[..]);
-    let sgraph = Deserialize::deserialize(&mut sgraph_de).unwrap();
+    let sgraph = Deserialize::deserialize(&mut sgraph_de)
+                     .map_err(|e| format!("Can't deserialise state graph: {}", e))?;
     let mut stable_de = Deserializer::new(&stable_buf[..]);
-    let stable = Deserialize::deserialize(&mut stable_de).unwrap();
-    (grm, sgraph, stable)
+    let stable = Deserialize::deserialize(&mut stable_de)
+                     .map_err(|e| format!("Can't deserialise state table: {}", e))?;
+    if hash != tables_hash(&grm, &sgraph, &stable) {
+        return Err("These parsing tables differ from those which were generated (e.g. because \
+                    they have been altered): regenerate them".to_owned());
+    }
+    Ok((grm, sgraph, stable))
+}
+
+/// Return a hash of the contents of the grammar and tables: their terminals, rules, productions,
+/// and actions, as described by `tables_json`. This is computed from the deserialised values
+/// rather than the serialised bytes, so that if a change in cfgrammar or lrtable means that the
+/// same bytes deserialise to something other than what was serialised, the hashes differ. Since
+/// this is checked by code compiled separately from the code which generated it (possibly by a
+/// different compiler), it uses a fixed algorithm (64-bit FNV-1a) rather than `DefaultHasher`,
+/// whose output may change between Rust releases.
+fn tables_hash(grm: &YaccGrammar, sgraph: &StateGraph, stable: &StateTable) -> u64 {
+    tables_json(grm, sgraph, stable).bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Return the MessagePack in `buf` with the entries of every map sorted by the bytes of their
+/// keys. `HashMap`s (e.g. in `StateTable`) are serialised in an order which varies from one
+/// process to the next, so without this the tables we generate would differ from one build to
+/// the next even when nothing has changed. Since the position of a map entry doesn't affect its
+/// deserialised value, the result deserialises to the same value as `buf`.
+fn canonical_msgpack(buf: &[u8]) -> Result<Vec<u8>, Box<Error>> {
+    let mut i = 0;
+    let mut out = Vec::with_capacity(buf.len());
+    canonical_msgpack_val(buf, &mut i, &mut out)?;
+    if i != buf.len() {
+        return Err("Trailing bytes after serialised tables".into());
+    }
+    Ok(out)
+}
+
+/// Append the canonical form of the MessagePack value starting at `buf[*i]` to `out`, leaving `i`
+/// just after that value. This recurses once per level of nesting in the value, which is bounded
+/// by the nesting of the types we serialise rather than by the grammar.
+fn canonical_msgpack_val(buf: &[u8], i: &mut usize, out: &mut Vec<u8>)
+                      -> Result<(), Box<Error>>
+{
+    // Read an `n` byte big-endian unsigned integer.
+    fn uint(buf: &[u8], i: &mut usize, n: usize) -> Result<usize, Box<Error>> {
+        if *i + n > buf.len() {
+            return Err("Truncated serialised tables".into());
+        }
+        let v = buf[*i..*i + n].iter().fold(0, |v, &b| (v << 8) | usize::from(b));
+        *i += n;
+        Ok(v)
+    }
+
+    enum Val {
+        // A value with no nested values, which has this many bytes after its header.
+        Scalar(usize),
+        Array(usize),
+        Map(usize)
+    }
+
+    let start = *i;
+    let b = uint(buf, i, 1)? as u8;
+    let v = match b {
+        0x00...0x7f | 0xc0 | 0xc2 | 0xc3 | 0xe0...0xff => Val::Scalar(0),
+        0x80...0x8f => Val::Map(usize::from(b & 0x0f)),
+        0x90...0x9f => Val::Array(usize::from(b & 0x0f)),
+        0xa0...0xbf => Val::Scalar(usize::from(b & 0x1f)),
+        0xc4 | 0xd9 => Val::Scalar(uint(buf, i, 1)?),
+        0xc5 | 0xda => Val::Scalar(uint(buf, i, 2)?),
+        0xc6 | 0xdb => Val::Scalar(uint(buf, i, 4)?),
+        // ext: a length, then a type byte, then the data.
+        0xc7 => Val::Scalar(uint(buf, i, 1)? + 1),
+        0xc8 => Val::Scalar(uint(buf, i, 2)? + 1),
+        0xc9 => Val::Scalar(uint(buf, i, 4)? + 1),
+        0xca | 0xce | 0xd2 => Val::Scalar(4),
+        0xcb | 0xcf | 0xd3 => Val::Scalar(8),
+        0xcc | 0xd0 => Val::Scalar(1),
+        0xcd | 0xd1 => Val::Scalar(2),
+        // fixext: a type byte, then 1, 2, 4, 8, or 16 bytes of data.
+        0xd4...0xd8 => Val::Scalar(1 + (1 << (b - 0xd4))),
+        0xdc => Val::Array(uint(buf, i, 2)?),
+        0xdd => Val::Array(uint(buf, i, 4)?),
+        0xde => Val::Map(uint(buf, i, 2)?),
+        0xdf => Val::Map(uint(buf, i, 4)?),
+        _ => return Err(format!("Invalid MessagePack byte {:#04x}", b).into())
+    };
+    out.extend_from_slice(&buf[start..*i]);
+    match v {
+        Val::Scalar(n) => {
+            if *i + n > buf.len() {
+                return Err("Truncated serialised tables".into());
+            }
+            out.extend_from_slice(&buf[*i..*i + n]);
+            *i += n;
+        },
+        Val::Array(n) => {
+            for _ in 0..n {
+                canonical_msgpack_val(buf, i, out)?;
+            }
+        },
+        Val::Map(n) => {
+            let mut entries = Vec::with_capacity(n);
+            for _ in 0..n {
+                let mut k = Vec::new();
+                canonical_msgpack_val(buf, i, &mut k)?;
+                let mut v = Vec::new();
+                canonical_msgpack_val(buf, i, &mut v)?;
+                entries.push((k, v));
+            }
+            entries.sort();
+            for (k, v) in entries {
+                out.extend(k);
+                out.extend(v);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::env::{temp_dir, var};
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::process::{self, Command};
+
+    use cfgrammar::yacc::{yacc_grm, YaccKind};
+    use cfgrammar::yacc::ast::{GrammarAST, Symbol as AstSymbol};
+    use lrlex::{build_lex, Lexeme};
+    use lrtable::{Minimiser, from_yacc};
+    use num_traits::ToPrimitive;
+    use parser::{Node, RecoveryKind, RTParserBuilder};
+    use parser::test::do_parse;
+    use rmps::{Deserializer, Serializer};
+    use serde::{Deserialize, Serialize};
+    use tokid::{Channel, TokenId};
+    use super::{ast_child_matches, ast_ident, ast_lexeme, canonical_msgpack, check_types,
+                expect_count, extract_directive, extract_types, gen_ast, idents_policy, line_diff,
+                namespace_rules, NonAsciiIdents, normalise_newlines, reconstitute, rust_ident,
+                strip_glr_hints, tables_from_ast, tables_hash, TABLES_FORMAT};
+
+    #[test]
+    fn namespacing() {
+        let frag = "Expr: Expr '+' Term | Term;
+/* Term: is a rule */
+Term: 'INT' | '(' Expr ')' %prec 'Term';
+";
+        assert_eq!(namespace_rules(frag, "e"),
+                   "e_Expr: e_Expr '+' e_Term | e_Term;
+/* Term: is a rule */
+e_Term: 'INT' | '(' e_Expr ')' %prec 'Term';
+");
+        assert_eq!(namespace_rules("S: T U;\nT: 'a';", "ns"), "ns_S: ns_T U;\nns_T: 'a';");
+    }
+
+    #[test]
+    fn diff() {
+        assert_eq!(line_diff("a\nb\nc\n", "a\nb\nc\n"), "");
+        assert_eq!(line_diff("a\nb\nc\nd\n", "a\nx\nc\nd\ne\n"), "-b\n+x\n+e\n");
+    }
+
+    #[test]
+    fn canonical_tables() {
+        // Two maps with the same entries, inserted in different orders (and hashed with different
+        // keys), are serialised differently but have the same canonical form.
+        let m1 = (0..100u32).map(|i| (i, vec![i; 3])).collect::<HashMap<_, _>>();
+        let m2 = (0..100u32).rev().map(|i| (i, vec![i; 3])).collect::<HashMap<_, _>>();
+        let mut buf1 = Vec::new();
+        (1u8, &m1, "x").serialize(&mut Serializer::new(&mut buf1)).unwrap();
+        let mut buf2 = Vec::new();
+        (1u8, &m2, "x").serialize(&mut Serializer::new(&mut buf2)).unwrap();
+        let canon = canonical_msgpack(&buf1).unwrap();
+        assert_eq!(canon, canonical_msgpack(&buf2).unwrap());
+        assert_eq!(canon.len(), buf1.len());
+        let (n, m, s): (u8, HashMap<u32, Vec<u32>>, String)
+            = Deserialize::deserialize(&mut Deserializer::new(&canon[..])).unwrap();
+        assert_eq!((n, &m, s.as_str()), (1, &m1, "x"));
+
+        assert!(canonical_msgpack(&buf1[..buf1.len() - 1]).is_err());
+        assert!(canonical_msgpack(&[0xc1]).is_err());
+        let mut buf3 = buf1.clone();
+        buf3.push(0);
+        assert!(canonical_msgpack(&buf3).is_err());
+    }
+
+    #[test]
+    fn stale_tables() {
+        let grm = yacc_grm(YaccKind::Original, "%start S\n%%\nS: 'A' S | ;").unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let mut grm_buf = Vec::new();
+        grm.serialize(&mut Serializer::new(&mut grm_buf)).unwrap();
+        let mut sgraph_buf = Vec::new();
+        sgraph.serialize(&mut Serializer::new(&mut sgraph_buf)).unwrap();
+        let mut stable_buf = Vec::new();
+        stable.serialize(&mut Serializer::new(&mut stable_buf)).unwrap();
+        let hash = tables_hash(&grm, &sgraph, &stable);
+
+        let (grm2, _, stable2) = reconstitute(TABLES_FORMAT, hash, &grm_buf, &sgraph_buf,
+                                              &stable_buf).unwrap();
+        assert_eq!(grm2.prods_len(), grm.prods_len());
+        assert!(stable2.conflicts().is_none());
+
+        let e = reconstitute(TABLES_FORMAT + 1, hash, &grm_buf, &sgraph_buf, &stable_buf)
+                    .unwrap_err();
+        assert!(e.contains(&format!("serialised in format {}", TABLES_FORMAT + 1)));
+        // Tables that can't be deserialised are detected...
+        assert!(reconstitute(TABLES_FORMAT, hash, &grm_buf, &stable_buf, &sgraph_buf).is_err());
+        // ...as are those which deserialise to something other than what was serialised: here
+        // the terminal 'A' (the MessagePack string "A") becomes 'C'.
+        let i = grm_buf.windows(2).position(|w| w == [0xa1, b'A']).unwrap();
+        grm_buf[i + 1] = b'C';
+        let e = reconstitute(TABLES_FORMAT, hash, &grm_buf, &sgraph_buf, &stable_buf)
+                    .unwrap_err();
+        assert!(e.contains("differ from those which were generated"));
+        // The hash is of the grammar, not just its shape.
+        let grm3 = yacc_grm(YaccKind::Original, "%start S\n%%\nS: 'C' S | ;").unwrap();
+        let (sgraph3, stable3) = from_yacc(&grm3, Minimiser::Pager).unwrap();
+        assert_ne!(tables_hash(&grm3, &sgraph3, &stable3), hash);
+    }
+
+    #[test]
+    fn newlines() {
+        assert_eq!(normalise_newlines("%start A\r\n%%\r\nA: 'a';\r\n"), "%start A\n%%\nA: 'a';\n");
+        assert_eq!(normalise_newlines("\u{feff}%start A\n"), "%start A\n");
+        // A lone carriage return isn't a line ending.
+        assert_eq!(normalise_newlines("A: '\r';"), "A: '\r';");
+        let (s, decls) = extract_directive(&normalise_newlines("%anchor ';'\r\n%%\r\n"), "%anchor",
+                                           true).unwrap();
+        assert_eq!(s, "\n%%\n");
+        assert_eq!(decls, vec![vec![";".to_owned()]]);
+    }
+
+    #[test]
+    fn glr_hints() {
+        let src = "%glr-parser
+%%
+E: E '+' E %dprec 1 %merge <f>
+ | 'INT' %dprec 2;
+T: '%dprec' %prec '+';
+";
+        let (s, hints) = strip_glr_hints(src);
+        // Hints are blanked out, leaving columns unchanged, but quoted text is left alone.
+        let lines = s.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "");
+        assert_eq!(lines[2], format!("E: E '+' E{}", " ".repeat(20)));
+        assert_eq!(lines[3], format!(" | 'INT'{};", " ".repeat(9)));
+        assert_eq!(lines[4], "T: '%dprec' %prec '+';");
+        assert_eq!(hints, vec![(1, "%glr-parser"), (3, "%dprec"), (3, "%merge"), (4, "%dprec")]);
+    }
+
+    #[test]
+    fn expect() {
+        let (grm, decls) = extract_directive("%expect 2\n%expect-rr 1\n%%\n", "%expect", false)
+                           .unwrap();
+        assert_eq!(grm, "\n%expect-rr 1\n%%\n");
+        assert_eq!(expect_count("%expect", &decls).unwrap(), Some(2));
+        assert_eq!(expect_count("%expect", &[]).unwrap(), None);
+        assert!(expect_count("%expect", &[vec!["x".to_owned()]]).is_err());
+        assert!(expect_count("%expect", &[vec!["1".to_owned()], vec!["2".to_owned()]]).is_err());
+    }
+
+    #[test]
+    fn non_ascii_idents() {
+        let tl = NonAsciiIdents::Transliterate;
+        assert_eq!(rust_ident("Expr", tl).unwrap(), "Expr");
+        assert_eq!(rust_ident("Ausdrück", tl).unwrap(), "Ausdruck");
+        assert_eq!(rust_ident("Straße_式", tl).unwrap(), "Strasse_U5f0f");
+        assert!(rust_ident("Ausdrück", NonAsciiIdents::Error).is_err());
+        assert_eq!(idents_policy(&[]).unwrap(), tl);
+        assert_eq!(idents_policy(&[vec!["error".to_owned()]]).unwrap(), NonAsciiIdents::Error);
+        assert!(idents_policy(&[vec!["utf8".to_owned()]]).is_err());
+
+        let grms = "%start Expr
+%%
+Expr: Expr '+' 'ID' | 'ID' ;
+";
+        let grm = yacc_grm(YaccKind::Original, grms).unwrap();
+        let decls = vec![vec!["Expr".to_owned(), "Addé".to_owned(), "Zahl".to_owned()]];
+        let ast = gen_ast(&grm, &decls, "u16", true, tl).unwrap();
+        assert!(ast.contains("    Adde(::std::boxed::Box<Expr>, ::lrlex::Lexeme<u16>, \
+                              ::lrlex::Lexeme<u16>),"));
+        assert!(gen_ast(&grm, &decls, "u16", true, NonAsciiIdents::Error).is_err());
+        let decls = vec![vec!["Expr".to_owned(), "Addé".to_owned(), "Adde".to_owned()]];
+        assert!(gen_ast(&grm, &decls, "u16", true, tl).is_err());
+    }
+
+    #[test]
+    fn from_ast() {
+        // Calls: Calls Call | ; Call: 'ID' '(' ')';
+        let mut ast = GrammarAST::new();
+        ast.start = Some("Calls".to_owned());
+        for t in &["ID", "(", ")"] {
+            ast.tokens.insert((*t).to_owned());
+        }
+        ast.add_prod("Calls".to_owned(),
+                     vec![AstSymbol::Nonterm("Calls".to_owned()),
+                          AstSymbol::Nonterm("Call".to_owned())],
+                     None);
+        ast.add_prod("Calls".to_owned(), vec![], None);
+        ast.add_prod("Call".to_owned(),
+                     vec![AstSymbol::Term("ID".to_owned()),
+                          AstSymbol::Term("(".to_owned()),
+                          AstSymbol::Term(")".to_owned())],
+                     None);
+        let (grm, sgraph, stable) = tables_from_ast(YaccKind::Original, ast).unwrap();
+        assert!(stable.conflicts().is_none());
+
+        let mut lexerdef = build_lex::<u16>("%%
+\\( '('
+\\) ')'
+[a-zA-Z_][a-zA-Z_0-9]* 'ID'
+").unwrap();
+        let rule_ids = grm.terms_map().iter()
+                                      .map(|(&n, &i)| (n, u32::from(i).to_u16().unwrap()))
+                                      .collect();
+        lexerdef.set_rule_ids(&rule_ids);
+        let lexemes = lexerdef.lexer("f()g()").lexemes().unwrap();
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        assert!(rtpb.parse(&lexemes).is_ok());
+        assert!(rtpb.parse(&lexerdef.lexer("f(g").lexemes().unwrap()).is_err());
+
+        // An undefined start rule is reported rather than causing a panic.
+        let mut ast = GrammarAST::new();
+        ast.start = Some("Nope".to_owned());
+        assert!(tables_from_ast(YaccKind::Original, ast).is_err());
+    }
+
+    #[test]
+    fn ast() {
+        let lexs = "%%
+\\+ '+'
+[a-z] 'ID'
+";
+        let grms = "%start Expr
+%%
+Expr: Expr '+' Term | Term ;
+Term: 'ID' ;
+";
+
+        let grm = yacc_grm(YaccKind::Original, grms).unwrap();
+        let decls = vec![vec!["Expr".to_owned(), "Add".to_owned(), "Term".to_owned()],
+                         vec!["Term".to_owned()]];
+        let ast = gen_ast(&grm, &decls, "u16", true, NonAsciiIdents::Transliterate).unwrap();
+        assert!(ast.contains("pub enum Expr {
+    Add(::std::boxed::Box<Expr>, ::lrlex::Lexeme<u16>, ::std::boxed::Box<Term>),
+    Term(::std::boxed::Box<Term>),
+}"));
+        assert!(ast.contains("pub struct Term(pub ::lrlex::Lexeme<u16>);"));
+        assert!(ast.contains("return ::std::option::Option::Some(Expr::Add(\
+                              ::std::boxed::Box::new(Expr::from_node(&nodes[0])?), \
+                              ::lrpar::ast_lexeme(&nodes[1])?, \
+                              ::std::boxed::Box::new(Term::from_node(&nodes[2])?)));"));
+        compile_ast(&ast);
+        let tl = NonAsciiIdents::Transliterate;
+        assert!(gen_ast(&grm, &vec![vec!["Expr".to_owned()]], "u16", true, tl).is_err());
+        assert!(gen_ast(&grm, &vec![vec!["Nope".to_owned()]], "u16", true, tl).is_err());
+
+        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, "a+b");
+        let pt = pr.unwrap();
+        let expr_idx = usize::from(grm.nonterm_idx("Expr").unwrap());
+        let term_idx = usize::from(grm.nonterm_idx("Term").unwrap());
+        let plus_idx = usize::from(grm.term_idx("+").unwrap());
+        match pt {
+            Node::Nonterm{ref nodes, ..} => {
+                let syms = [(false, expr_idx), (true, plus_idx), (false, term_idx)];
+                assert!(ast_child_matches(nodes, true, &syms));
+                assert!(!ast_child_matches(nodes, true, &[(false, term_idx)]));
+
+                // A hidden '+' still matches the '+' terminal, but only if the token IDs have
+                // room for channel bits.
+                let mut nodes = nodes.clone();
+                let lexeme = ast_lexeme(&nodes[1]).unwrap();
+                nodes[1] = Node::Term{lexeme: Lexeme::new(lexeme.tok_id()
+                                                                .with_channel(Channel::Hidden),
+                                                          lexeme.start(),
+                                                          lexeme.len())};
+                assert!(ast_child_matches(&nodes, true, &syms));
+                assert!(!ast_child_matches(&nodes, false, &syms));
+            },
+            _ => panic!()
+        }
+        assert_eq!(ast_lexeme(&pt), None);
+    }
+
+    #[test]
+    fn ast_names() {
+        assert_eq!(ast_ident("type_decl", NonAsciiIdents::Transliterate).unwrap(), "TypeDecl");
+        assert_eq!(ast_ident("Straße_式", NonAsciiIdents::Transliterate).unwrap(),
+                   "StrasseU5f0f");
+        assert_eq!(ast_ident("self", NonAsciiIdents::Transliterate).unwrap(), "Self_");
+        assert!(ast_ident("_", NonAsciiIdents::Transliterate).is_err());
+
+        // Rules and variants named after keywords, or after the types the AST refers to, still
+        // give valid code.
+        let grms = "%start match
+%%
+match: match '=>' type | type | ;
+type: 'ID' | node ;
+node: 'ID' 'ID' ;
+";
+        let grm = yacc_grm(YaccKind::Original, grms).unwrap();
+        let decls = vec![vec!["match".to_owned(), "arm".to_owned(), "single".to_owned(),
+                              "self".to_owned()],
+                         vec!["type".to_owned(), "ident".to_owned(), "option".to_owned()],
+                         vec!["node".to_owned()]];
+        let ast = gen_ast(&grm, &decls, "u16", true, NonAsciiIdents::Transliterate).unwrap();
+        assert!(ast.contains("pub enum Match {"));
+        assert!(ast.contains("    Self_,"));
+        assert!(ast.contains("    Option(::std::boxed::Box<Node>),"));
+        assert!(ast.contains("pub struct Node(pub ::lrlex::Lexeme<u16>, \
+                              pub ::lrlex::Lexeme<u16>);"));
+        compile_ast(&ast);
+    }
+
+    /// Check that the generated AST module `ast` compiles, against minimal stand-ins for the parts
+    /// of lrpar and lrlex which it uses.
+    fn compile_ast(ast: &str) {
+        let src = format!("{}
+mod lrlex {{
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Lexeme<T>(pub T);
+}}
+mod lrpar {{
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Node<T> {{
+        Term{{lexeme: ::lrlex::Lexeme<T>}},
+        Nonterm{{nonterm_idx: usize, nodes: Vec<Node<T>>}}
+    }}
+    pub fn ast_child_matches<T>(_: &[Node<T>], _: bool, _: &[(bool, usize)]) -> bool {{
+        true
+    }}
+    pub fn ast_lexeme<T>(_: &Node<T>) -> Option<::lrlex::Lexeme<T>> {{
+        None
+    }}
+}}
+", ast);
+        let dir = temp_dir().join(format!("lrpar_ast_{}_{}", process::id(), src.len()));
+        create_dir_all(&dir).unwrap();
+        let srcp = dir.join("ast.rs");
+        write(&srcp, src).unwrap();
+        let out = Command::new(var("RUSTC").unwrap_or_else(|_| "rustc".to_owned()))
+                          .args(&["--crate-type", "lib", "--emit", "metadata", "--out-dir"])
+                          .arg(&dir)
+                          .arg(&srcp)
+                          .output()
+                          .unwrap();
+        remove_dir_all(&dir).unwrap();
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    }
+
+    #[test]
+    fn types() {
+        let (grms, types) = extract_types("%start Expr
+%type <Expr> Expr
+%type <Node> Term Factor
+%%
+Expr: Expr '+' Term | Term ;
+Term: Term '*' Factor | Factor ;
+Factor: 'ID' ;
+").unwrap();
+        assert!(grms.starts_with("%start Expr\n\n\n%%\n"));
+        assert_eq!(types, vec![("Expr".to_owned(), vec!["Expr".to_owned()]),
+                               ("Node".to_owned(), vec!["Term".to_owned(), "Factor".to_owned()])]);
+        assert!(extract_types("%type Expr Expr\n").is_err());
+        assert!(extract_types("%type <> Expr\n").is_err());
+
+        let grm = yacc_grm(YaccKind::Original, &grms).unwrap();
+        let tl = NonAsciiIdents::Transliterate;
+        let ast_decls = vec![vec!["Expr".to_owned(), "Add".to_owned(), "Term".to_owned()]];
+        assert!(check_types(&grm, &ast_decls, &types, tl).is_ok());
+        // Term has no %ast declaration.
+        let ast_term = vec![("Term".to_owned(), vec!["Term".to_owned()])];
+        assert_eq!(check_types(&grm, &ast_decls, &ast_term, tl).unwrap_err().to_string(),
+                   "%type <Term> Term: Term has no %ast declaration, so its AST type is Node");
+        // Expr's %ast declaration has been removed, so its type is now out of date.
+        assert_eq!(check_types(&grm, &[], &types, tl).unwrap_err().to_string(),
+                   "%type <Expr> Expr: Expr has no %ast declaration, so its AST type is Node");
+        let misnamed = vec![("Exp".to_owned(), vec!["Expr".to_owned()])];
+        assert_eq!(check_types(&grm, &ast_decls, &misnamed, tl).unwrap_err().to_string(),
+                   "%type <Exp> Expr: the AST type of Expr is Expr");
+        let twice = vec![("Node".to_owned(), vec!["Term".to_owned(), "Term".to_owned()])];
+        assert!(check_types(&grm, &ast_decls, &twice, tl).is_err());
+        let unknown = vec![("Node".to_owned(), vec!["Nope".to_owned()])];
+        assert!(check_types(&grm, &ast_decls, &unknown, tl).is_err());
+    }
 }
@@ -0,0 +1,302 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+use cfgrammar::TIdx;
+use cfgrammar::yacc::YaccGrammar;
+use lrtable::{StateTable, StIdx};
+use rmps::Serializer;
+use serde::Serialize;
+
+use parser::{ParseRepair, Parser, PStack, RecoveryKind};
+use tokid::TokenId;
+
+/// The number of states from the top of the parse stack in the fingerprint of an error's context
+/// (see `RecoveryContexts`).
+const CONTEXT_STATES: usize = 8;
+/// The number of terminals, starting with the one the error occurred at, in the fingerprint of an
+/// error's context.
+const CONTEXT_TERMS: usize = 4;
+
+/// Remembers the repair sequences which error recovery found for recent parse errors, keyed by the
+/// grammar (and state table) being parsed with, the state the error occurred in, and the terminal
+/// it occurred at, so that the search for repairs for a later error with the same key can be
+/// seeded with them (see `RTParserBuilder::repair_cache`). This makes repeatedly parsing the same
+/// broken file (e.g. after each keystroke in an editor) cheap, since the errors which haven't been
+/// edited are normally recovered from as soon as the search reaches the cost of the remembered
+/// repairs.
+///
+/// At most `capacity` keys are remembered: when a new key is added to a full cache, the least
+/// recently used key is forgotten. A `RepairCache` can be shared by any number of parses (and
+/// builders, even for different grammars), but not between threads.
+pub struct RepairCache {
+    capacity: usize,
+    entries: RefCell<CacheEntries>
+}
+
+/// A cache key: (grammar key, state, terminal).
+type CacheKey = (u64, StIdx, TIdx);
+
+struct CacheEntries {
+    // Each key's repairs, and the tick at which it was last used.
+    repairs: HashMap<CacheKey, (u64, Vec<Vec<ParseRepair>>)>,
+    // The key last used at each tick: the first entry is thus the least recently used key.
+    lru: BTreeMap<u64, CacheKey>,
+    tick: u64
+}
+
+impl CacheEntries {
+    /// Mark `key` (which must have an entry in `repairs`) as the most recently used key.
+    fn touch(&mut self, key: CacheKey) {
+        self.tick += 1;
+        let e = self.repairs.get_mut(&key).unwrap();
+        self.lru.remove(&e.0);
+        e.0 = self.tick;
+        self.lru.insert(self.tick, key);
+    }
+}
+
+impl RepairCache {
+    /// Create an empty cache which remembers the repairs for at most `capacity` (grammar, state,
+    /// terminal) triples.
+    pub fn new(capacity: usize) -> Self {
+        RepairCache{capacity,
+                    entries: RefCell::new(CacheEntries{repairs: HashMap::new(),
+                                                       lru: BTreeMap::new(),
+                                                       tick: 0})}
+    }
+
+    /// How many (grammar, state, terminal) triples have repairs remembered?
+    pub fn len(&self) -> usize {
+        self.entries.borrow().repairs.len()
+    }
+
+    /// Are no repairs remembered?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget all remembered repairs.
+    pub fn clear(&self) {
+        let mut entries = self.entries.borrow_mut();
+        entries.repairs.clear();
+        entries.lru.clear();
+    }
+
+    /// Return the repair sequences remembered for an error in state `st_idx` at terminal `t_idx`
+    /// when parsing with the grammar whose key (see `grammar_key`) is `grm_key` (which may be
+    /// empty), marking them as recently used.
+    pub(crate) fn get(&self, grm_key: u64, st_idx: StIdx, t_idx: TIdx) -> Vec<Vec<ParseRepair>> {
+        let mut entries = self.entries.borrow_mut();
+        let key = (grm_key, st_idx, t_idx);
+        let rprs = match entries.repairs.get(&key) {
+            Some(&(_, ref rprs)) => rprs.clone(),
+            None => return Vec::new()
+        };
+        entries.touch(key);
+        rprs
+    }
+
+    /// Remember `repairs` for an error in state `st_idx` at terminal `t_idx` when parsing with the
+    /// grammar whose key is `grm_key`, replacing any repairs previously remembered for them.
+    pub(crate) fn insert(&self,
+                         grm_key: u64,
+                         st_idx: StIdx,
+                         t_idx: TIdx,
+                         repairs: Vec<Vec<ParseRepair>>)
+    {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.borrow_mut();
+        let key = (grm_key, st_idx, t_idx);
+        if !entries.repairs.contains_key(&key) && entries.repairs.len() == self.capacity {
+            let lru_tick = *entries.lru.keys().next().unwrap();
+            let lru_key = entries.lru.remove(&lru_tick).unwrap();
+            entries.repairs.remove(&lru_key);
+        }
+        let tick = entries.repairs.get(&key).map_or(0, |e| e.0);
+        entries.repairs.insert(key, (tick, repairs));
+        entries.touch(key);
+    }
+}
+
+/// Return the key which `RepairCache` uses to distinguish the grammar `grm` and state table
+/// `stable` from others: state and terminal indices are only meaningful for the grammar and state
+/// table they come from. The key is derived from their contents, so the same grammar built twice
+/// (e.g. in two different builders) has the same key.
+pub(crate) fn grammar_key(grm: &YaccGrammar, stable: &StateTable) -> u64 {
+    let mut buf = Vec::new();
+    grm.serialize(&mut Serializer::new(&mut buf)).unwrap();
+    stable.serialize(&mut Serializer::new(&mut buf)).unwrap();
+    let mut h = DefaultHasher::new();
+    h.write(&buf);
+    h.finish()
+}
+
+/// The fingerprint of the context an error occurred in: the recoverer used, the states on top of
+/// the parse stack, and the terminals starting with the one the error occurred at.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct ContextKey {
+    kind: RecoveryKind,
+    states: Vec<StIdx>,
+    terms: Vec<TIdx>
+}
+
+/// The repairs found for the errors earlier in a single parse, keyed by the fingerprint of the
+/// context each error occurred in (see `RTParserBuilder::reuse_recoveries`). A fingerprint covers
+/// only the top of the parse stack and the next few terminals, so two errors with the same
+/// fingerprint need not be in identical contexts: repairs returned by `get` must be checked
+/// before they are applied.
+pub(crate) struct RecoveryContexts {
+    repairs: HashMap<ContextKey, Vec<Vec<ParseRepair>>>
+}
+
+impl RecoveryContexts {
+    pub(crate) fn new() -> Self {
+        RecoveryContexts{repairs: HashMap::new()}
+    }
+
+    /// Return the fingerprint of an error at `la_idx` with the parse stack `pstack`, recovered
+    /// from with `kind`.
+    fn key<TokId: TokenId>(psr: &Parser<TokId>, kind: RecoveryKind, pstack: &PStack, la_idx: usize)
+                        -> ContextKey
+    {
+        let states = pstack[pstack.len().saturating_sub(CONTEXT_STATES)..].to_vec();
+        let end = (la_idx + CONTEXT_TERMS).min(psr.lexemes.len() + 1);
+        let terms = (la_idx..end).map(|i| psr.next_tidx(i)).collect();
+        ContextKey{kind, states, terms}
+    }
+
+    /// Return the repairs remembered for an error with the same fingerprint as an error at
+    /// `la_idx` with the parse stack `pstack`, recovered from with `kind`, if there are any.
+    pub(crate) fn get<TokId: TokenId>(&self,
+                                      psr: &Parser<TokId>,
+                                      kind: RecoveryKind,
+                                      pstack: &PStack,
+                                      la_idx: usize)
+                                   -> Option<&Vec<Vec<ParseRepair>>>
+    {
+        self.repairs.get(&RecoveryContexts::key(psr, kind, pstack, la_idx))
+    }
+
+    /// Remember `repairs` for an error at `la_idx` with the parse stack `pstack`, recovered from
+    /// with `kind`. Repairs already remembered for the same fingerprint are kept.
+    pub(crate) fn insert<TokId: TokenId>(&mut self,
+                                         psr: &Parser<TokId>,
+                                         kind: RecoveryKind,
+                                         pstack: &PStack,
+                                         la_idx: usize,
+                                         repairs: &[Vec<ParseRepair>])
+    {
+        self.repairs.entry(RecoveryContexts::key(psr, kind, pstack, la_idx))
+                    .or_insert_with(|| repairs.to_vec());
+    }
+
+    /// How many fingerprints have repairs remembered?
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.repairs.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cfgrammar::TIdx;
+    use lrtable::StIdx;
+
+    use parser::{ParseRepair, RecoveryKind, RTParserBuilder};
+    use parser::test::calls_env;
+    use super::{RecoveryContexts, RepairCache};
+
+    #[test]
+    fn repair_cache_keys() {
+        let st = StIdx::from(1u32);
+        let t = TIdx::from(2u32);
+        let rprs = vec![vec![ParseRepair::Delete]];
+        let cache = RepairCache::new(2);
+        cache.insert(10, st, t, rprs.clone());
+        assert_eq!(cache.get(10, st, t), rprs);
+        // The same state and terminal in another grammar are a different key.
+        assert!(cache.get(11, st, t).is_empty());
+        cache.insert(11, st, t, vec![]);
+        assert_eq!(cache.len(), 2);
+        // Using the first key makes the second the least recently used, so it is the one
+        // forgotten when a third key is added.
+        assert_eq!(cache.get(10, st, t), rprs);
+        cache.insert(12, st, t, vec![]);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(10, st, t), rprs);
+        assert!(cache.get(11, st, t).is_empty());
+        // Replacing a key's repairs doesn't forget any other key.
+        cache.insert(12, st, t, rprs.clone());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(12, st, t), rprs);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn recovery_contexts() {
+        // Lexemes 1.. and 6.. are both "( ID ( )", but lexemes 0.. are "ID ( ID (".
+        let (grm, sgraph, stable, lexemes) = calls_env("f(g()f(g()");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let psr = rtpb.parser(&lexemes);
+        let rprs = vec![vec![ParseRepair::Insert(TIdx::from(0u32))]];
+        let pstack = (0..10).map(|i| StIdx::from(i as u32)).collect::<Vec<_>>();
+        let mut ctxs = RecoveryContexts::new();
+        assert!(ctxs.get(&psr, RecoveryKind::MF, &pstack, 1).is_none());
+        ctxs.insert(&psr, RecoveryKind::MF, &pstack, 1, &rprs);
+        assert_eq!(ctxs.get(&psr, RecoveryKind::MF, &pstack, 1), Some(&rprs));
+        // The same terminals later in the input, and a parse stack which differs only below the
+        // states in the fingerprint, share the fingerprint.
+        assert_eq!(ctxs.get(&psr, RecoveryKind::MF, &pstack, 6), Some(&rprs));
+        let mut deeper = pstack.clone();
+        deeper[0] = StIdx::from(20u32);
+        deeper.insert(0, StIdx::from(21u32));
+        assert_eq!(ctxs.get(&psr, RecoveryKind::MF, &deeper, 6), Some(&rprs));
+        // A different recoverer, top of the parse stack, or upcoming terminals don't.
+        assert!(ctxs.get(&psr, RecoveryKind::CPCTPlus, &pstack, 1).is_none());
+        let mut other = pstack.clone();
+        other[9] = StIdx::from(20u32);
+        assert!(ctxs.get(&psr, RecoveryKind::MF, &other, 1).is_none());
+        assert!(ctxs.get(&psr, RecoveryKind::MF, &pstack, 0).is_none());
+        // Inserting repairs for a known fingerprint keeps the repairs found first.
+        ctxs.insert(&psr, RecoveryKind::MF, &pstack, 6, &[]);
+        assert_eq!(ctxs.len(), 1);
+        assert_eq!(ctxs.get(&psr, RecoveryKind::MF, &pstack, 1), Some(&rprs));
+    }
+}
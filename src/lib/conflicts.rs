@@ -0,0 +1,238 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use cfgrammar::{PIdx, Symbol, TIdx};
+use cfgrammar::yacc::YaccGrammar;
+use lrtable::{Action, StateGraph, StateTable, StIdx};
+
+use gen::sentence_lexemes;
+use parser::{pp_sym, RTParserBuilder};
+
+/// An example of a conflict in a grammar's tables, as returned by `conflict_examples`: a shortest
+/// input which, when parsed, brings the parser to the conflicted state with the conflict's
+/// terminal as the lookahead, at which point it must choose between shifting that terminal and
+/// reducing (or between two reductions).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConflictExample {
+    st_idx: StIdx,
+    t_idx: TIdx,
+    reduces: Vec<PIdx>,
+    syms: Vec<Symbol>,
+    prefix: Option<Vec<TIdx>>
+}
+
+impl ConflictExample {
+    /// Return the state the conflict is in.
+    pub fn state_idx(&self) -> StIdx {
+        self.st_idx
+    }
+
+    /// Return the terminal the conflict is on.
+    pub fn term_idx(&self) -> TIdx {
+        self.t_idx
+    }
+
+    /// Return the productions which could be reduced: one for a shift/reduce conflict (the
+    /// alternative being to shift `term_idx`), and two for a reduce/reduce conflict.
+    pub fn reduces(&self) -> &[PIdx] {
+        &self.reduces
+    }
+
+    /// Return a shortest sequence of symbols which leads from the start state to the conflicted
+    /// state.
+    pub fn syms(&self) -> &[Symbol] {
+        &self.syms
+    }
+
+    /// Return a shortest sequence of terminals which the parser parses to reach the conflicted
+    /// state (`syms` with each nonterminal replaced by one of its shortest sentences): following
+    /// it with `term_idx` exhibits the conflict. This is `None` in the rare case that no such
+    /// sequence could be found (because the shortest sentences of `syms` lead the parser
+    /// elsewhere), in which case only `syms` is available.
+    pub fn prefix(&self) -> Option<&[TIdx]> {
+        self.prefix.as_ref().map(|p| p.as_slice())
+    }
+
+    /// Return a human readable version of the example, with a `.` marking where the conflict
+    /// occurs, e.g. `'INT' '+' 'INT' . '+'`.
+    pub fn pp(&self, grm: &YaccGrammar) -> String {
+        let mut s = match self.prefix {
+            Some(ref p) => p.iter().map(|&t_idx| pp_sym(grm, Symbol::Term(t_idx))).collect(),
+            None => self.syms.iter().map(|&sym| pp_sym(grm, sym)).collect::<Vec<_>>()
+        };
+        s.push(".".to_owned());
+        s.push(pp_sym(grm, Symbol::Term(self.t_idx)));
+        s.join(" ")
+    }
+}
+
+/// Return an example (see `ConflictExample`) of each of the shift/reduce and then reduce/reduce
+/// conflicts in `stable`, the table built for `grm` and `sgraph`, in the order that
+/// `StateTable::conflicts` reports them. Examples are minimal in the number of terminals before
+/// the conflict. Note that a conflict does not necessarily mean that the grammar is ambiguous
+/// (only that it is not LR(1)), so an example shows where the parser cannot decide what to do
+/// with one token of lookahead, not necessarily an input with two parse trees.
+pub fn conflict_examples(grm: &YaccGrammar, sgraph: &StateGraph, stable: &StateTable)
+                      -> Vec<ConflictExample>
+{
+    let c = match stable.conflicts() {
+        Some(c) => c,
+        None => return Vec::new()
+    };
+    let paths = shortest_paths(grm, sgraph);
+    let example = |st_idx: StIdx, t_idx, reduces| {
+        let syms = paths[usize::from(st_idx)].clone().unwrap();
+        let sengen = grm.sentence_generator(|_| 1);
+        let mut prefix = Vec::new();
+        for sym in &syms {
+            match *sym {
+                Symbol::Term(t_idx) => prefix.push(t_idx),
+                Symbol::Nonterm(nt_idx) => prefix.extend(sengen.min_sentence(nt_idx))
+            }
+        }
+        // Check that parsing the prefix, and then making the reductions which the lookahead
+        // causes, really does lead to the conflicted state.
+        let lexemes = sentence_lexemes::<u32>(&prefix);
+        let rtpb = RTParserBuilder::new(grm, sgraph, stable);
+        let psr = rtpb.parser(&lexemes);
+        let mut pstack = vec![StIdx::from(0 as u32)];
+        let mut reached = psr.lr_upto(None, 0, prefix.len(), &mut pstack, &mut None)
+                          == prefix.len();
+        while reached && *pstack.last().unwrap() != st_idx {
+            match stable.action(*pstack.last().unwrap(), t_idx) {
+                Some(Action::Reduce(p_idx)) => {
+                    let pop_idx = pstack.len() - grm.prod(p_idx).len();
+                    pstack.drain(pop_idx..);
+                    let prior = *pstack.last().unwrap();
+                    pstack.push(stable.goto(prior, grm.prod_to_nonterm(p_idx)).unwrap());
+                },
+                _ => reached = false
+            }
+        }
+        let prefix = if reached { Some(prefix) } else { None };
+        ConflictExample{st_idx, t_idx, reduces, syms, prefix}
+    };
+    let mut examples = Vec::new();
+    for &(t_idx, p_idx, st_idx) in c.sr_conflicts() {
+        examples.push(example(st_idx, t_idx, vec![p_idx]));
+    }
+    for &(t_idx, p1_idx, p2_idx, st_idx) in c.rr_conflicts() {
+        examples.push(example(st_idx, t_idx, vec![p1_idx, p2_idx]));
+    }
+    examples
+}
+
+/// For each state in `sgraph`, return a sequence of symbols which leads to it from the start
+/// state and whose shortest sentences are as short as possible (or `None` if the state is
+/// unreachable).
+fn shortest_paths(grm: &YaccGrammar, sgraph: &StateGraph) -> Vec<Option<Vec<Symbol>>> {
+    let sengen = grm.sentence_generator(|_| 1);
+    let states_len = sgraph.all_states_len() as usize;
+    let mut costs = vec![None; states_len];
+    let mut paths = vec![None; states_len];
+    let mut todo = BinaryHeap::new();
+    costs[0] = Some(0);
+    paths[0] = Some(Vec::new());
+    todo.push(Reverse((0, 0)));
+    while let Some(Reverse((cost, i))) = todo.pop() {
+        if costs[i] != Some(cost) {
+            // A cheaper path to this state has already been expanded.
+            continue;
+        }
+        let mut edges = sgraph.edges(StIdx::from(i as u32))
+                              .iter()
+                              .map(|(&sym, &st_idx)| {
+                                  let sym_cost = match sym {
+                                      Symbol::Term(_) => 1,
+                                      Symbol::Nonterm(nt_idx) => {
+                                          sengen.min_sentence_cost(nt_idx) as usize
+                                      }
+                                  };
+                                  (sym_cost, usize::from(st_idx), sym)
+                              })
+                              .collect::<Vec<_>>();
+        // Edges are stored in a hash map: sort them so that the paths found are deterministic.
+        edges.sort_by_key(|&(sym_cost, j, _)| (sym_cost, j));
+        for (sym_cost, j, sym) in edges {
+            if costs[j].map_or(true, |c| cost + sym_cost < c) {
+                costs[j] = Some(cost + sym_cost);
+                let mut path = paths[i].clone().unwrap();
+                path.push(sym);
+                paths[j] = Some(path);
+                todo.push(Reverse((cost + sym_cost, j)));
+            }
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod test {
+    use cfgrammar::yacc::{yacc_grm, YaccKind};
+    use lrtable::{Minimiser, from_yacc};
+    use lint::prod_str;
+    use super::conflict_examples;
+
+    #[test]
+    fn examples() {
+        let grm = yacc_grm(YaccKind::Original, "%start Expr
+%%
+Expr: Expr '+' Expr | 'INT' ;
+").unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let examples = conflict_examples(&grm, &sgraph, &stable);
+        assert_eq!(examples.len(), 1);
+        assert_eq!(examples[0].pp(&grm), "'INT' '+' 'INT' . '+'");
+        assert_eq!(examples[0].reduces().len(), 1);
+        assert_eq!(prod_str(&grm, examples[0].reduces()[0]), "Expr: Expr '+' Expr");
+        assert!(examples[0].prefix().is_some());
+
+        let grm = yacc_grm(YaccKind::Original, "%start S
+%%
+S: A 'x' | B 'x' ;
+A: 'a' ;
+B: 'a' ;
+").unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let examples = conflict_examples(&grm, &sgraph, &stable);
+        assert_eq!(examples.len(), 1);
+        assert_eq!(examples[0].reduces().len(), 2);
+        assert_eq!(examples[0].pp(&grm), "'a' . 'x'");
+
+        let grm = yacc_grm(YaccKind::Original, "%start S\n%%\nS: 'a' S | ;").unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        assert!(conflict_examples(&grm, &sgraph, &stable).is_empty());
+    }
+}
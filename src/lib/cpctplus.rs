@@ -30,53 +30,52 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use std::cell::RefCell;
 use std::hash::{Hash, Hasher};
 use std::time::Instant;
 
 use cactus::Cactus;
-use cfgrammar::TIdx;
 use lrlex::Lexeme;
-use lrtable::{Action, StIdx};
-use num_traits::{PrimInt, Unsigned};
-
-use astar::dijkstra;
-use mf::{apply_repairs, rank_cnds, simplify_repairs};
-use parser::{Node, Parser, ParseRepair, Recoverer};
-
-const PARSE_AT_LEAST: usize = 3; // N in Corchuelo et al.
-
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-enum Repair {
-    /// Insert a `Symbol::Term` with idx `term_idx`.
-    InsertTerm(TIdx),
-    /// Delete a symbol.
-    Delete,
-    /// Shift a symbol.
-    Shift
-}
+use lrtable::StIdx;
+
+use astar::{dijkstra, LimitReached, Scratch, SearchLimits};
+use events::TStack;
+use parser::{Parser, ParseRepair, Recoverer, RecoveryLimits};
+use search::{compatible, Engine, last_repair, merge, num_edits, Repair, RepairMerge};
+use tokid::TokenId;
 
-#[derive(Clone, Debug, Eq, PartialEq)]
-enum RepairMerge {
-    Repair(Repair),
-    Merge(Repair, Cactus<Cactus<RepairMerge>>),
-    Terminator
+thread_local! {
+    static SCRATCH: RefCell<Scratch<PathFNode, (u32, PathFNode)>> = RefCell::default();
 }
 
+/// If, before it has found a repair, the search has stored more than this many partial repair
+/// sequences for each unit of cost it has explored, its frontier is considered to have exploded
+/// (as happens on grammars with very many terminals, such as SQL's, where every node has a huge
+/// number of insert neighbours), and CPCT+ falls back to iterative deepening (see `deepen`).
+const FRONTIER_GROWTH: usize = 10_000;
+
+/// The maximum number of edits iterative deepening looks for if `RTParserBuilder::max_repair_len`
+/// isn't set. Each extra edit multiplies the time taken by the terminal fan-out, so deeper
+/// searches would in practice never finish before the deadline anyway.
+const DEEPEN_MAX_EDITS: usize = 3;
+
 #[derive(Clone, Debug, Eq)]
 struct PathFNode {
     pstack: Cactus<StIdx>,
     la_idx: usize,
     repairs: Cactus<RepairMerge>,
-    cf: u32
+    cf: u32,
+    // The number of edits in `repairs` if `RTParserBuilder::max_repair_len` is set, or 0
+    // otherwise. Nodes which differ only in their repairs can have different neighbours if the
+    // number of edits is capped, since a node with fewer edits can be edited further: such nodes
+    // must therefore be distinct, so that (e.g.) the search's closed set doesn't discard a node
+    // with fewer edits because a node with more was expanded first.
+    edits: usize
 }
 
 impl PathFNode {
     fn last_repair(&self) -> Option<Repair> {
-        match self.repairs.val().unwrap() {
-            &RepairMerge::Repair(r) => Some(r),
-            &RepairMerge::Merge(x, _) => Some(x),
-            &RepairMerge::Terminator => None
-        }
+        last_repair(&self.repairs)
     }
 }
 
@@ -84,64 +83,43 @@ impl Hash for PathFNode {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.pstack.hash(state);
         self.la_idx.hash(state);
+        self.edits.hash(state);
     }
 }
 
 impl PartialEq for PathFNode {
     fn eq(&self, other: &PathFNode) -> bool {
-        if self.la_idx != other.la_idx || self.pstack != other.pstack {
-            return false;
-        }
-        // The rest of this function is subtle: we're not looking for repair sequences which are
-        // exactly equivalent, but ones that are compatible. This is necessary so that we can merge
-        // compatible nodes. Our definition of compatible repair sequences is: they must end with
-        // exactly the same number of shifts (ending with zero shifts is fine); and if one repair
-        // sequence ends in a delete, the other must do so as well.
-
-        match (self.last_repair(), other.last_repair()) {
-            (Some(Repair::Delete), Some(Repair::Delete)) => (),
-            (Some(Repair::Delete), _) | (_, Some(Repair::Delete)) => return false,
-            (_, _) => ()
-        }
-
-        let num_shifts = |c: &Cactus<RepairMerge>| {
-            let mut n = 0;
-            for r in c.vals() {
-                match r {
-                      &RepairMerge::Repair(Repair::Shift)
-                    | &RepairMerge::Merge(Repair::Shift, _) => n += 1,
-                    _ => break
-                }
-            }
-            n
-        };
-        let self_shifts = num_shifts(&self.repairs);
-        let other_shifts = num_shifts(&other.repairs);
-        self_shifts == other_shifts
+        self.la_idx == other.la_idx
+            && self.edits == other.edits
+            && self.pstack == other.pstack
+            && compatible(&self.repairs, &other.repairs)
     }
 }
 
-struct CPCTPlus<'a, TokId: PrimInt + Unsigned> where TokId: 'a {
-    parser: &'a Parser<'a, TokId>
+struct CPCTPlus<'a, TokId: TokenId> where TokId: 'a {
+    parser: &'a Parser<'a, TokId>,
+    engine: Engine<'a, TokId>,
+    // See `FRONTIER_GROWTH`.
+    frontier_growth: usize
 }
 
-pub(crate) fn recoverer<'a, TokId: PrimInt + Unsigned>
+pub(crate) fn recoverer<'a, TokId: TokenId>
                        (parser: &'a Parser<TokId>)
                      -> Box<Recoverer<TokId> + 'a>
 {
-    Box::new(CPCTPlus{parser})
+    Box::new(CPCTPlus{parser, engine: Engine::new(parser), frontier_growth: FRONTIER_GROWTH})
 }
 
-impl<'a, TokId: PrimInt + Unsigned> Recoverer<TokId> for CPCTPlus<'a, TokId>
+impl<'a, TokId: TokenId> Recoverer<TokId> for CPCTPlus<'a, TokId>
 
 {
     fn recover(&self,
                finish_by: Instant,
                parser: &Parser<TokId>,
                in_la_idx: usize,
-               mut in_pstack: &mut Vec<StIdx>,
-               mut tstack: &mut Vec<Node<TokId>>)
-           -> (usize, Vec<Vec<ParseRepair>>)
+               in_pstack: &mut Vec<StIdx>,
+               tstack: &mut TStack<TokId>)
+           -> (usize, Vec<Vec<ParseRepair>>, RecoveryLimits)
     {
         // This function implements a minor variant of the algorithm from "Repairing syntax errors
         // in LR parsers" by Rafael Corchuelo, Jose A. Perez, Antonio Ruiz, and Miguel Toro.
@@ -159,110 +137,155 @@ impl<'a, TokId: PrimInt + Unsigned> Recoverer<TokId> for CPCTPlus<'a, TokId>
         // flavour to part of the ALL(*) algorithm (where, when the LL parser gets to a point of
         // ambiguity, it fires up non-LL sub-parsers, which then tell the LL parser which path it
         // should take).
-        let mut start_cactus_pstack = Cactus::new();
-        for st in in_pstack.iter() {
-            start_cactus_pstack = start_cactus_pstack.child(*st);
-        }
-
-        let start_node = PathFNode{pstack: start_cactus_pstack.clone(),
+        //
+        // If the search's frontier explodes, we switch to iterative deepening, which needs
+        // memory only proportional to the length of the repairs it explores, at the cost of
+        // finding repairs with the fewest edits, rather than of least cost (though we then prefer
+        // the least cost of those).
+        let (start_pstack, start_repairs) = self.engine.start(in_pstack);
+        let seeds = self.engine
+                        .seeds(in_la_idx, in_pstack, &start_pstack, &start_repairs)
+                        .into_iter()
+                        .map(|(pstack, la_idx, repairs, cf)| {
+                            let edits = if parser.recovery.max_repair_len.is_some() {
+                                            num_edits(&repairs)
+                                        } else {
+                                            0
+                                        };
+                            (cf, PathFNode{pstack, la_idx, repairs, cf, edits})
+                        })
+                        .collect();
+        let start_node = PathFNode{pstack: start_pstack,
                                    la_idx: in_la_idx,
-                                   repairs: Cactus::new().child(RepairMerge::Terminator),
-                                   cf: 0};
-        let astar_cnds = dijkstra(
-            start_node,
+                                   repairs: start_repairs,
+                                   cf: 0,
+                                   edits: 0};
+        let cancelled = || (parser.recovery.clock)() >= finish_by;
+        let limits = SearchLimits{max_memory: parser.recovery.max_recovery_memory,
+                                  max_expansions: parser.recovery.max_recovery_expansions,
+                                  max_frontier_growth: Some(self.frontier_growth),
+                                  cancelled: &cancelled};
+        let (astar_cnds, limit) = dijkstra(
+            start_node.clone(),
+            seeds,
             |explore_all, n, nbrs| {
                 // Calculate n's neighbours.
 
-                if Instant::now() >= finish_by {
-                    return false;
-                }
-
+                let can_edit = self.engine.can_edit(&n.repairs);
                 match n.last_repair() {
                     Some(Repair::Delete) => {
                         // We follow Corcheulo et al.'s suggestions and never follow Deletes with
                         // Inserts.
                     },
                     _ => {
-                        if explore_all {
+                        if can_edit && explore_all {
                             self.insert(n, nbrs);
                         }
                     }
                 }
-                if explore_all {
+                if can_edit && explore_all {
                     self.delete(n, nbrs);
                 }
                 self.shift(n, nbrs);
-                true
             },
-            |old, new| {
-                // merge new_n into old_n
-
-                if old.repairs == new.repairs {
-                    // If the repair sequences are identical, then merging is pointless.
-                    return;
+            |old, new| merge(&mut old.repairs, new.repairs),
+            |cost, n| {
+                match parser.recovery.search_priority {
+                    Some(f) => f(cost, *n.pstack.val().unwrap(), n.la_idx),
+                    None => cost
                 }
-                let merge = match old.repairs.val().unwrap() {
-                    &RepairMerge::Repair(r) => {
-                        RepairMerge::Merge(r, Cactus::new().child(new.repairs))
-                    },
-                    &RepairMerge::Merge(r, ref v) => {
-                        RepairMerge::Merge(r, v.child(new.repairs))
-                    },
-                    _ => unreachable!()
-                };
-                old.repairs = old.repairs.parent().unwrap().child(merge);
             },
-            |n| {
-                // Is n a success node?
-
-                // As presented in both Corchuelo et al. and Kim Yi, one type of success is if N
-                // symbols are parsed in one go. Indeed, without such a check, the search space
-                // quickly becomes too big. There isn't a way of encoding this check in r3s_n, so
-                // we check instead for its result: if the last N ('PARSE_AT_LEAST' in this
-                // library) repairs are shifts, then we've found a success node.
-                if ends_with_parse_at_least_shifts(&n.repairs) {
-                    return true;
-                }
+            |n| self.engine.success(&n.pstack, n.la_idx, &n.repairs),
+            &limits,
+            &SCRATCH);
+        let (astar_cnds, limit) = if limit == Some(LimitReached::Frontier) {
+            log_debug!("CPCT+: frontier exploded for the error at lexeme {}", in_la_idx);
+            (self.deepen(finish_by, start_node), None)
+        } else {
+            (astar_cnds, limit)
+        };
 
-                match parser.stable.action(*n.pstack.val().unwrap(),
-                                           parser.next_tidx(n.la_idx)) {
-                    Some(Action::Accept) => true,
-                    _ => false,
-                }
-            });
+        let cnds = astar_cnds.into_iter().map(|n| n.repairs).collect();
+        self.engine.finish("CPCT+", finish_by, in_la_idx, in_pstack, tstack, cnds, limit)
+    }
+}
 
-        if astar_cnds.is_empty() {
-            return (in_la_idx, vec![]);
+impl<'a, TokId: TokenId> CPCTPlus<'a, TokId> {
+    /// Search from `start_node` for the success nodes with the fewest edits (up to
+    /// `RTParserBuilder::max_repair_len` or, if that isn't set, `DEEPEN_MAX_EDITS`), by searching
+    /// depth-first for success nodes with at most 1 edit, then at most 2 edits, and so on, and
+    /// return those of least cost. This explores the same neighbours as the main search, but
+    /// without storing a frontier. An empty vector is returned if `finish_by` is reached.
+    fn deepen(&self, finish_by: Instant, start_node: PathFNode) -> Vec<PathFNode> {
+        let max_edits = self.parser.recovery.max_repair_len.unwrap_or(DEEPEN_MAX_EDITS);
+        for edits in 1..max_edits + 1 {
+            let mut scs_nodes = Vec::new();
+            if !self.deepen_from(finish_by, edits, &start_node, &mut scs_nodes) {
+                return Vec::new();
+            }
+            if let Some(cf) = scs_nodes.iter().map(|n: &PathFNode| n.cf).min() {
+                return scs_nodes.into_iter().filter(|n| n.cf == cf).collect();
+            }
         }
+        Vec::new()
+    }
 
-        let full_rprs = self.collect_repairs(astar_cnds);
-        let mut rnk_rprs = rank_cnds(parser,
-                                     finish_by,
-                                     in_la_idx,
-                                     &in_pstack,
-                                     full_rprs);
-        if rnk_rprs.is_empty() {
-            return (in_la_idx, vec![]);
+    /// Add the success nodes reachable from `n` with at most `max_edits` edits in total to
+    /// `scs_nodes`, merging equivalent nodes as the main search does. Returns `false` if
+    /// `finish_by` is reached.
+    fn deepen_from(&self,
+                   finish_by: Instant,
+                   max_edits: usize,
+                   n: &PathFNode,
+                   scs_nodes: &mut Vec<PathFNode>)
+                -> bool
+    {
+        if (self.parser.recovery.clock)() >= finish_by {
+            return false;
+        }
+        if self.engine.success(&n.pstack, n.la_idx, &n.repairs) {
+            match scs_nodes.iter().position(|m| m == n) {
+                Some(i) => merge(&mut scs_nodes[i].repairs, n.repairs.clone()),
+                None => scs_nodes.push(n.clone())
+            }
+            return true;
+        }
+
+        let mut nbrs = Vec::new();
+        if num_edits(&n.repairs) < max_edits {
+            // As in the main search, Deletes are never followed by Inserts.
+            if n.last_repair() != Some(Repair::Delete) {
+                self.insert(n, &mut nbrs);
+            }
+            self.delete(n, &mut nbrs);
+        }
+        self.shift(n, &mut nbrs);
+        for (_, nbr) in nbrs {
+            if !self.deepen_from(finish_by, max_edits, &nbr, scs_nodes) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Return the `edits` of a node which adds an edit to `n`.
+    fn edits_after(&self, n: &PathFNode) -> usize {
+        if self.parser.recovery.max_repair_len.is_some() {
+            n.edits + 1
+        } else {
+            0
         }
-        simplify_repairs(&mut rnk_rprs);
-        let la_idx = apply_repairs(parser,
-                                   in_la_idx,
-                                   &mut in_pstack,
-                                   &mut Some(&mut tstack),
-                                   &rnk_rprs[0]);
-
-        (la_idx, rnk_rprs)
     }
-}
 
-impl<'a, TokId: PrimInt + Unsigned> CPCTPlus<'a, TokId> {
     fn insert(&self,
              n: &PathFNode,
              nbrs: &mut Vec<(u32, PathFNode)>)
     {
         let la_idx = n.la_idx;
-        for t_idx in self.parser.stable.state_actions(*n.pstack.val().unwrap()) {
-            if t_idx == self.parser.grm.eof_term_idx() {
+        let top_pstack = *n.pstack.val().unwrap();
+        for t_idx in self.parser.stable.state_actions(top_pstack) {
+            if t_idx == self.parser.grm.eof_term_idx()
+               || self.parser.redundant_insert(top_pstack, t_idx) {
                 continue;
             }
 
@@ -273,11 +296,16 @@ impl<'a, TokId: PrimInt + Unsigned> CPCTPlus<'a, TokId> {
                 self.parser.lr_cactus(Some(new_lexeme), la_idx, la_idx + 1,
                                       n.pstack.clone(), &mut None);
             if new_la_idx > la_idx {
+                let cf = match self.engine.add_cost(n.cf, t_idx) {
+                    Some(cf) => cf,
+                    None => continue
+                };
                 let nn = PathFNode{
                     pstack: n_pstack,
                     la_idx: n.la_idx,
                     repairs: n.repairs.child(RepairMerge::Repair(Repair::InsertTerm(t_idx))),
-                    cf: n.cf.checked_add((self.parser.term_cost)(t_idx) as u32).unwrap()};
+                    cf,
+                    edits: self.edits_after(n)};
                 nbrs.push((nn.cf, nn));
             }
         }
@@ -291,12 +319,15 @@ impl<'a, TokId: PrimInt + Unsigned> CPCTPlus<'a, TokId> {
             return;
         }
 
-        let la_tidx = self.parser.next_tidx(n.la_idx);
-        let cost = (self.parser.term_cost)(la_tidx);
+        let cf = match self.engine.add_cost(n.cf, self.parser.next_tidx(n.la_idx)) {
+            Some(cf) => cf,
+            None => return
+        };
         let nn = PathFNode{pstack: n.pstack.clone(),
                            la_idx: n.la_idx + 1,
                            repairs: n.repairs.child(RepairMerge::Repair(Repair::Delete)),
-                           cf: n.cf.checked_add(cost as u32).unwrap()};
+                           cf,
+                           edits: self.edits_after(n)};
         nbrs.push((nn.cf, nn));
     }
 
@@ -343,100 +374,38 @@ impl<'a, TokId: PrimInt + Unsigned> CPCTPlus<'a, TokId> {
                 pstack: n_pstack,
                 la_idx: new_la_idx,
                 repairs: n_repairs,
-                cf: n.cf};
+                cf: n.cf,
+                edits: n.edits};
             nbrs.push((nn.cf, nn));
         }
     }
-
-    /// Convert the output from `astar_all` into something more usable.
-    fn collect_repairs(&self, cnds: Vec<PathFNode>) -> Vec<Vec<Vec<ParseRepair>>>
-    {
-        fn traverse(rm: &Cactus<RepairMerge>) -> Vec<Vec<Repair>> {
-            let mut out = Vec::new();
-            match rm.val().unwrap() {
-                &RepairMerge::Repair(r) => {
-                    let parents = traverse(&rm.parent().unwrap());
-                    if parents.is_empty() {
-                        out.push(vec![r]);
-                    } else {
-                        for mut pc in parents {
-                            pc.push(r);
-                            out.push(pc);
-                        }
-                    }
-                },
-                &RepairMerge::Merge(r, ref vc) => {
-                    let parents = traverse(&rm.parent().unwrap());
-                    if parents.is_empty() {
-                        out.push(vec![r]);
-                    } else {
-                        for mut pc in parents {
-                            pc.push(r);
-                            out.push(pc);
-                        }
-                    }
-                    for c in vc.vals() {
-                        for mut pc in traverse(c) {
-                            out.push(pc);
-                        }
-                    }
-                }
-                &RepairMerge::Terminator => ()
-            }
-            out
-        }
-
-        let mut all_rprs = Vec::with_capacity(cnds.len());
-        for cnd in cnds {
-            all_rprs.push(traverse(&cnd.repairs).into_iter()
-                                                .map(|x| self.repair_to_parse_repair(x))
-                                                .collect::<Vec<_>>());
-        }
-        all_rprs
-    }
-
-    fn repair_to_parse_repair(&self,
-                              from: Vec<Repair>)
-                           -> Vec<ParseRepair> {
-        from.iter()
-            .map(|y| {
-                 match *y {
-                     Repair::InsertTerm(term_idx) =>
-                         ParseRepair::Insert(term_idx),
-                     Repair::Delete => ParseRepair::Delete,
-                     Repair::Shift => ParseRepair::Shift,
-                 }
-             })
-            .collect()
-    }
-}
-
-/// Do `repairs` end with enough Shift repairs to be considered a success node?
-fn ends_with_parse_at_least_shifts(repairs: &Cactus<RepairMerge>) -> bool {
-    let mut shfts = 0;
-    for x in repairs.vals().take(PARSE_AT_LEAST) {
-        match x {
-            &RepairMerge::Repair(Repair::Shift) => shfts += 1,
-            &RepairMerge::Merge(Repair::Shift, _) => shfts += 1,
-            _ => return false
-        }
-    }
-    shfts == PARSE_AT_LEAST
 }
 
 #[cfg(test)]
 mod test {
+    use std::cell::RefCell;
+    use std::time::{Duration, Instant};
+
+    use cfgrammar::TIdx;
     use cfgrammar::yacc::YaccGrammar;
     use lrlex::Lexeme;
     use num_traits::ToPrimitive;
-    use parser::{ParseRepair, RecoveryKind};
-    use parser::test::do_parse;
+    use lrtable::StIdx;
+    use events::TStack;
+    use parser::{ParseRepair, Recoverer, RecoveryKind, RTParserBuilder};
+    use parser::test::{build_parse_env, do_parse};
+    use search::Engine;
+    use super::CPCTPlus;
 
     fn pp_repairs(grm: &YaccGrammar, repairs: &Vec<ParseRepair>) -> String {
         let mut out = vec![];
         for r in repairs.iter() {
             match *r {
-                ParseRepair::InsertSeq{..} => panic!("Internal error"),
+                  ParseRepair::InsertSeq{..}
+                | ParseRepair::Substitute{..}
+                | ParseRepair::Shifts(_) => {
+                    panic!("Internal error")
+                },
                 ParseRepair::Insert(term_idx) =>
                     out.push(format!("Insert \"{}\"", grm.term_name(term_idx).unwrap())),
                 ParseRepair::Delete =>
@@ -544,6 +513,140 @@ E : 'N'
                           &vec!["Insert \")\""]);
     }
 
+    #[test]
+    fn search_priority() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+\\+ '+'
+n 'N'
+";
+        let grms = "%start E
+%%
+E : 'N'
+  | E '+' 'N'
+  | '(' E ')'
+  ;
+";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "(nn");
+        // Any priority which orders nodes as their costs do finds the same repairs as the default
+        // ordering.
+        let offset = |c: u32, _: StIdx, _: usize| c + 10;
+        let doubled = |c: u32, _: StIdx, _: usize| c * 2;
+        let priorities: [&Fn(u32, StIdx, usize) -> u32; 2] = [&offset, &doubled];
+        for f in &priorities {
+            let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::CPCTPlus)
+                                                                 .search_priority(*f)
+                                                                 .parse(&lexemes);
+            let (_, errs) = pr.unwrap_err();
+            check_all_repairs(&grm,
+                              errs[0].repairs(),
+                              &vec!["Insert \")\", Insert \"+\"",
+                                    "Insert \")\", Delete",
+                                    "Insert \"+\", Shift, Insert \")\""]);
+        }
+
+        // A real A* heuristic: at least one more repair is needed if the next lexeme is an error
+        // in the current state. This is consistent, since such a node can't shift (the only
+        // step of cost 0) and any other step costs at least 1; and it is 0 for success nodes,
+        // which have either shifted enough lexemes or can accept.
+        let h = |st_idx: StIdx, la_idx: usize| {
+            let t_idx = if la_idx < lexemes.len() {
+                TIdx::from(lexemes[la_idx].tok_id().to_u32().unwrap())
+            } else {
+                grm.eof_term_idx()
+            };
+            if stable.action(st_idx, t_idx).is_none() { 1 } else { 0 }
+        };
+        let astar = |c: u32, st_idx: StIdx, la_idx: usize| c + h(st_idx, la_idx);
+        let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::CPCTPlus)
+                                                             .search_priority(&astar)
+                                                             .parse(&lexemes);
+        let (_, errs) = pr.unwrap_err();
+        check_all_repairs(&grm,
+                          errs[0].repairs(),
+                          &vec!["Insert \")\", Insert \"+\"",
+                                "Insert \")\", Delete",
+                                "Insert \"+\", Shift, Insert \")\""]);
+    }
+
+    #[test]
+    fn nested_search() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+\\+ '+'
+n 'N'
+";
+        let grms = "%start E
+%%
+E : 'N'
+  | E '+' 'N'
+  | '(' E ')'
+  ;
+";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "(nn");
+        let inner = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::CPCTPlus);
+        // A search started while another is in progress on the same thread can't reuse the
+        // thread's scratch buffers, but must still find the same repairs.
+        let nested = RefCell::new(None);
+        let repairs = {
+            let f = |c: u32, _: StIdx, _: usize| {
+                if nested.borrow().is_none() {
+                    *nested.borrow_mut() = Some(vec![]);
+                    let (_, errs) = inner.parse(&lexemes).unwrap_err();
+                    *nested.borrow_mut() = Some(errs[0].repairs().clone());
+                }
+                c
+            };
+            let outer = RTParserBuilder::new(&grm, &sgraph, &stable).search_priority(&f);
+            let (_, errs) = outer.recoverer(RecoveryKind::CPCTPlus).parse(&lexemes).unwrap_err();
+            errs[0].repairs().clone()
+        };
+        assert_eq!(nested.into_inner().unwrap(), repairs);
+        check_all_repairs(&grm,
+                          &repairs,
+                          &vec!["Insert \")\", Insert \"+\"",
+                                "Insert \")\", Delete",
+                                "Insert \"+\", Shift, Insert \")\""]);
+    }
+
+    #[test]
+    fn frontier_explosion() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+\\+ '+'
+n 'N'
+";
+        let grms = "%start E
+%%
+E : 'N'
+  | E '+' 'N'
+  | '(' E ')'
+  ;
+";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "(nn");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let psr = rtpb.parser(&lexemes);
+        let mut pstack = vec![StIdx::from(0u32)];
+        let mut tstack = TStack::new();
+        let la_idx = psr.lr_upto(None, 0, lexemes.len(), &mut pstack, &mut Some(&mut tstack));
+        assert_eq!(la_idx, 2);
+        // Any growth of the frontier is treated as an explosion, so the search falls back to
+        // iterative deepening immediately, and must still find the least-cost repairs.
+        let cpctp = CPCTPlus{parser: &psr, engine: Engine::new(&psr), frontier_growth: 1};
+        let finish_by = Instant::now() + Duration::from_secs(60);
+        let (new_la_idx, repairs, _) = cpctp.recover(finish_by, &psr, la_idx, &mut pstack,
+                                                     &mut tstack);
+        assert!(new_la_idx > la_idx);
+        check_all_repairs(&grm,
+                          &repairs,
+                          &vec!["Insert \")\", Insert \"+\"",
+                                "Insert \")\", Delete",
+                                "Insert \"+\", Shift, Insert \")\""]);
+    }
+
     #[test]
     fn test_merge() {
         let lexs = "%%
@@ -571,4 +674,38 @@ U: 'd';
                                 "Insert \"b\", Insert \"d\"",
                                 "Insert \"c\", Insert \"d\""]);
     }
+
+    #[test]
+    fn max_repair_len() {
+        // Inserting 'X' 'Y' and inserting 'Z' lead to the same parse stack once 'E' has been
+        // shifted, the former more cheaply but with more edits. With at most 2 edits, only the
+        // latter can then go on to insert 'F', so it mustn't be discarded as a duplicate.
+        let lexs = "%%
+E 'E'
+F 'F'
+X 'X'
+Y 'Y'
+Z 'Z'
+";
+        let grms = "%start S
+%%
+S: T 'E' 'F';
+T: 'X' 'Y' | 'Z';
+";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "E");
+        let costs = |t_idx: TIdx| -> u8 {
+            match grm.term_name(t_idx) {
+                Some("Z") => 3,
+                Some("E") => 5,
+                _ => 1
+            }
+        };
+        let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::CPCTPlus)
+                                                            .term_costs(&costs)
+                                                            .max_repair_len(2)
+                                                            .parse(&lexemes);
+        let (_, errs) = pr.unwrap_err();
+        assert_eq!(errs.len(), 1);
+        check_all_repairs(&grm, errs[0].repairs(), &vec!["Insert \"Z\", Shift, Insert \"F\""]);
+    }
 }
@@ -0,0 +1,420 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use cfgrammar::NTIdx;
+use lrlex::Lexeme;
+
+use parser::{Node, ParseError};
+use tokid::TokenId;
+
+/// An event in a flat description of a parse tree (see `RTParserBuilder::parse_events`). Events
+/// are emitted in the order the LR parser recognises the tree: each terminal's `Token` when it is
+/// shifted, and each rule's `Reduce` when it is reduced, i.e. after the events of all the rule's
+/// children (the tree in post-order).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseEvent<TokId: Copy> {
+    /// A terminal (which has zero length if it was inserted by error recovery).
+    Token(Lexeme<TokId>),
+    /// A node for the rule `NTIdx`, whose children are the last `usize` trees not yet made
+    /// children of another node.
+    Reduce(NTIdx, usize),
+    /// The parse error at this index in the errors returned by `RTParserBuilder::parse_events`.
+    /// This comes immediately before the first `Token` at or after the error's lexeme (i.e.
+    /// before any terminals inserted to repair it).
+    Error(usize)
+}
+
+/// Builds a `Node` from `ParseEvent`s. This is both how `Node`s relate to events and a template
+/// for consumers building their own tree types.
+pub struct TreeBuilder<TokId: TokenId> {
+    // The trees which have not yet been made children of another node.
+    stack: Vec<Node<TokId>>
+}
+
+impl<TokId: TokenId> TreeBuilder<TokId> {
+    pub fn new() -> Self {
+        TreeBuilder{stack: Vec::new()}
+    }
+
+    /// Add the event `ev` to the tree. Panics if `ev` is a `Reduce` with more children than
+    /// there are trees to take them from.
+    pub fn event(&mut self, ev: ParseEvent<TokId>) {
+        match ev {
+            ParseEvent::Token(lexeme) => self.stack.push(Node::Term{lexeme}),
+            ParseEvent::Reduce(nonterm_idx, n) => {
+                let i = self.stack.len().checked_sub(n).unwrap();
+                let nodes = self.stack.drain(i..).collect::<Vec<_>>();
+                self.stack.push(Node::Nonterm{nonterm_idx, nodes});
+            },
+            ParseEvent::Error(_) => ()
+        }
+    }
+
+    /// Return the tree built, or `None` if the events so far don't describe a single tree.
+    pub fn finish(mut self) -> Option<Node<TokId>> {
+        if self.stack.len() == 1 {
+            self.stack.pop()
+        } else {
+            None
+        }
+    }
+}
+
+impl<TokId: TokenId> Default for TreeBuilder<TokId> {
+    fn default() -> Self {
+        TreeBuilder::new()
+    }
+}
+
+/// The parse tree stack: the trees of the symbols on the parse stack (other than the start
+/// state), which the parser extends as it shifts and reduces. The trees are either `Node`s or,
+/// when only events are wanted (see `RTParserBuilder::parse_events`), the events which describe
+/// them, so that no `Node`s need be built.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TStack<TokId: TokenId> {
+    repr: TStackRepr<TokId>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TStackRepr<TokId: TokenId> {
+    Nodes(Vec<Node<TokId>>),
+    // The events of all the trees on the stack, and the index in `events` of each tree's first
+    // event. Error recovery can unwind the stack, so events can't be passed on as soon as they
+    // happen: they are kept until parsing has finished.
+    Events{events: Vec<ParseEvent<TokId>>, starts: Vec<usize>}
+}
+
+impl<TokId: TokenId> TStack<TokId> {
+    /// Create an empty stack of `Node`s.
+    pub fn new() -> Self {
+        TStack{repr: TStackRepr::Nodes(Vec::new())}
+    }
+
+    /// Create an empty stack of events.
+    pub(crate) fn events() -> Self {
+        TStack{repr: TStackRepr::Events{events: Vec::new(), starts: Vec::new()}}
+    }
+
+    /// How many trees are on the stack?
+    pub fn len(&self) -> usize {
+        match self.repr {
+            TStackRepr::Nodes(ref nodes) => nodes.len(),
+            TStackRepr::Events{ref starts, ..} => starts.len()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the trees on a stack of `Node`s. Panics if this is a stack of events.
+    pub(crate) fn nodes(&self) -> &[Node<TokId>] {
+        match self.repr {
+            TStackRepr::Nodes(ref nodes) => nodes,
+            TStackRepr::Events{..} => panic!("Internal error")
+        }
+    }
+
+    /// Push a tree consisting of the terminal `lexeme`.
+    pub(crate) fn shift(&mut self, lexeme: Lexeme<TokId>) {
+        match self.repr {
+            TStackRepr::Nodes(ref mut nodes) => nodes.push(Node::Term{lexeme}),
+            TStackRepr::Events{ref mut events, ref mut starts} => {
+                starts.push(events.len());
+                events.push(ParseEvent::Token(lexeme));
+            }
+        }
+    }
+
+    /// Replace the top `n` trees with a single tree for the rule `nonterm_idx`, whose children
+    /// they are.
+    pub(crate) fn reduce(&mut self, nonterm_idx: NTIdx, n: usize) {
+        match self.repr {
+            TStackRepr::Nodes(ref mut nodes) => {
+                let i = nodes.len() - n;
+                let children = nodes.drain(i..).collect::<Vec<_>>();
+                nodes.push(Node::Nonterm{nonterm_idx, nodes: children});
+            },
+            TStackRepr::Events{ref mut events, ref mut starts} => {
+                let i = starts.len() - n;
+                let start = if n == 0 { events.len() } else { starts[i] };
+                starts.truncate(i);
+                starts.push(start);
+                events.push(ParseEvent::Reduce(nonterm_idx, n));
+            }
+        }
+    }
+
+    /// Push the tree `node`, which was built other than by shifting and reducing (e.g. by an
+    /// operator-precedence sub-parser).
+    pub(crate) fn push_node(&mut self, node: Node<TokId>) {
+        match self.repr {
+            TStackRepr::Nodes(ref mut nodes) => nodes.push(node),
+            TStackRepr::Events{ref mut events, ref mut starts} => {
+                starts.push(events.len());
+                // `node`'s events in post-order, using an explicit stack so that deep trees can't
+                // overflow the call stack. `true` marks a node whose children have been visited.
+                let mut todo = vec![(&node, false)];
+                while let Some((n, visited)) = todo.pop() {
+                    match *n {
+                        Node::Term{lexeme} => events.push(ParseEvent::Token(lexeme)),
+                        Node::Nonterm{nonterm_idx, ref nodes} => {
+                            if visited {
+                                events.push(ParseEvent::Reduce(nonterm_idx, nodes.len()));
+                            } else {
+                                todo.push((n, true));
+                                todo.extend(nodes.iter().rev().map(|c| (c, false)));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pop trees until at most `len` remain.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        match self.repr {
+            TStackRepr::Nodes(ref mut nodes) => nodes.truncate(len),
+            TStackRepr::Events{ref mut events, ref mut starts} => {
+                if len < starts.len() {
+                    events.truncate(starts[len]);
+                    starts.truncate(len);
+                }
+            }
+        }
+    }
+
+    /// Return the span of the input covered by the trees from the `i`th onwards, ignoring
+    /// terminals inserted by error recovery (see `Node::span_with`), or `None` if they have no
+    /// span.
+    pub(crate) fn span_from(&self, i: usize) -> Option<(usize, usize)> {
+        let lexemes = match self.repr {
+            TStackRepr::Nodes(ref nodes) => {
+                let spans = nodes[i..].iter()
+                                      .filter_map(|n| n.span_with(false))
+                                      .collect::<Vec<_>>();
+                let start = spans.iter().map(|s| s.0).min()?;
+                let end = spans.iter().map(|s| s.1).max()?;
+                return Some((start, end));
+            },
+            TStackRepr::Events{ref events, ref starts} => {
+                let first = starts.get(i).cloned().unwrap_or_else(|| events.len());
+                events[first..].iter()
+                               .filter_map(|ev| match *ev {
+                                   ParseEvent::Token(l) if l.len() > 0 => Some(l),
+                                   _ => None
+                               })
+                               .collect::<Vec<_>>()
+            }
+        };
+        let start = lexemes.iter().map(|l| l.start()).min()?;
+        let end = lexemes.iter().map(|l| l.start() + l.len()).max()?;
+        Some((start, end))
+    }
+
+    /// If the stack holds exactly one tree, return it as a `Node` (building it from its events if
+    /// necessary), or `None` otherwise.
+    pub fn into_node(self) -> Option<Node<TokId>> {
+        match self.repr {
+            TStackRepr::Nodes(mut nodes) => {
+                if nodes.len() == 1 {
+                    nodes.pop()
+                } else {
+                    None
+                }
+            },
+            TStackRepr::Events{events, starts} => {
+                if starts.len() != 1 {
+                    return None;
+                }
+                let mut tb = TreeBuilder::new();
+                for ev in events {
+                    tb.event(ev);
+                }
+                tb.finish()
+            }
+        }
+    }
+
+    /// Pass the events of the trees on the stack to `sink`, in order, with an `Error` for each of
+    /// `errs` (which must be ordered by lexeme index).
+    pub(crate) fn emit_events<F>(self, errs: &[ParseError<TokId>], sink: &mut F)
+                              where F: FnMut(ParseEvent<TokId>)
+    {
+        let events = match self.repr {
+            TStackRepr::Events{events, ..} => events,
+            TStackRepr::Nodes(nodes) => {
+                let mut evs = TStack::events();
+                for n in nodes {
+                    evs.push_node(n);
+                }
+                return evs.emit_events(errs, sink);
+            }
+        };
+        let mut next_err = 0;
+        for ev in events {
+            if let ParseEvent::Token(lexeme) = ev {
+                while next_err < errs.len() && errs[next_err].lexeme().start() <= lexeme.start() {
+                    sink(ParseEvent::Error(next_err));
+                    next_err += 1;
+                }
+            }
+            sink(ev);
+        }
+        for i in next_err..errs.len() {
+            sink(ParseEvent::Error(i));
+        }
+    }
+}
+
+impl<TokId: TokenId> Default for TStack<TokId> {
+    fn default() -> Self {
+        TStack::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cfgrammar::NTIdx;
+    use lrlex::Lexeme;
+
+    use parser::{Node, RTParserBuilder};
+    use parser::test::{build_parse_env, calls_env};
+    use super::{ParseEvent, TreeBuilder, TStack};
+
+    #[test]
+    fn events() {
+        let (grm, sgraph, stable, lexemes) = calls_env("f()");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let mut evs = Vec::new();
+        assert!(rtpb.parse_events(&lexemes, |ev| evs.push(ev)).is_empty());
+        let calls = grm.nonterm_idx("Calls").unwrap();
+        let call = grm.nonterm_idx("Call").unwrap();
+        assert_eq!(evs,
+                   vec![ParseEvent::Reduce(calls, 0),
+                        ParseEvent::Token(lexemes[0]),
+                        ParseEvent::Token(lexemes[1]),
+                        ParseEvent::Token(lexemes[2]),
+                        ParseEvent::Reduce(call, 3),
+                        ParseEvent::Reduce(calls, 2)]);
+
+        let (grm, sgraph, stable, lexemes) = calls_env("f()g(");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let mut tb = TreeBuilder::new();
+        let mut evs = Vec::new();
+        let errs = rtpb.parse_events(&lexemes, |ev| {
+                                         tb.event(ev);
+                                         evs.push(ev);
+                                     });
+        assert_eq!(errs.len(), 1);
+        // The error comes just before the ')' inserted to repair it.
+        let err_pos = evs.iter().position(|&ev| ev == ParseEvent::Error(0)).unwrap();
+        match evs[err_pos + 1] {
+            ParseEvent::Token(l) => assert_eq!((l.start(), l.len()), (5, 0)),
+            _ => panic!()
+        }
+        assert_eq!(tb.finish(), rtpb.parse(&lexemes).unwrap_err().0);
+    }
+
+    #[test]
+    fn events_after_unwinding() {
+        let lexs = "%%
+= '='
+; ';'
+[a-z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' '=' 'ID' ';';";
+
+        // Skipping the malformed statement unwinds the parser's stack, which must discard the
+        // events of the trees unwound.
+        let us = "a = b ; c = = = = d ; e = f ;";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let stmt = grm.nonterm_idx("Stmt").unwrap();
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).max_repair_len(1)
+                                                            .skippable(stmt);
+        let mut tb = TreeBuilder::new();
+        let errs = rtpb.parse_events(&lexemes, |ev| tb.event(ev));
+        let (pt, errs2) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs, errs2);
+        assert_eq!(errs[0].discarded_spans(), &[(8, 21)]);
+        assert_eq!(tb.finish(), pt);
+    }
+
+    #[test]
+    fn tstack() {
+        let x = NTIdx::from(1u32);
+        let y = NTIdx::from(2u32);
+        let l = |start| Lexeme::new(0u16, start, 1);
+        let term = |start| Node::Term{lexeme: l(start)};
+        let ins = Lexeme::new(0u16, 3, 0);
+        for mut ts in vec![TStack::new(), TStack::events()] {
+            ts.shift(l(0));
+            ts.shift(l(1));
+            ts.reduce(x, 2);
+            ts.push_node(Node::Nonterm{nonterm_idx: y, nodes: vec![term(2)]});
+            ts.reduce(y, 0);
+            assert_eq!(ts.len(), 3);
+            assert_eq!(ts.span_from(1), Some((2, 3)));
+            assert_eq!(ts.span_from(2), None);
+
+            let mut unwound = ts.clone();
+            unwound.truncate(1);
+            assert_eq!(unwound.span_from(0), Some((0, 2)));
+            unwound.shift(ins);
+            // Inserted terminals have no span.
+            assert_eq!(unwound.span_from(1), None);
+            unwound.reduce(x, 2);
+            assert_eq!(unwound.into_node(),
+                       Some(Node::Nonterm{nonterm_idx: x,
+                                          nodes: vec![Node::Nonterm{nonterm_idx: x,
+                                                                    nodes: vec![term(0), term(1)]},
+                                                      Node::Term{lexeme: ins}]}));
+
+            ts.reduce(x, 3);
+            let mut evs = Vec::new();
+            ts.emit_events(&[], &mut |ev| evs.push(ev));
+            assert_eq!(evs,
+                       vec![ParseEvent::Token(l(0)),
+                            ParseEvent::Token(l(1)),
+                            ParseEvent::Reduce(x, 2),
+                            ParseEvent::Token(l(2)),
+                            ParseEvent::Reduce(y, 1),
+                            ParseEvent::Reduce(y, 0),
+                            ParseEvent::Reduce(x, 3)]);
+        }
+    }
+}
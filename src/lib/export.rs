@@ -0,0 +1,144 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::fmt::Write;
+
+use cfgrammar::{NTIdx, PIdx, TIdx};
+use cfgrammar::yacc::YaccGrammar;
+use lrtable::{Action, StateGraph, StateTable, StIdx};
+
+use annotations::json_str;
+
+/// The version of the format produced by `tables_json`, which is incremented whenever the format
+/// changes incompatibly.
+pub const TABLES_JSON_VERSION: u32 = 1;
+
+/// Return the parsing tables for `grm` as a JSON document, so that runtimes in other languages can
+/// interpret tables built by lrpar. The document is an object with the following members:
+///
+///   * `"format"`: always `"lrpar-tables"`; `"version"`: `TABLES_JSON_VERSION`.
+///   * `"terms"`: an array indexed by `TIdx`, each element an object whose `"name"` is the
+///     terminal's name, or `null` for the end-of-input terminal (whose index is `"eof"`).
+///   * `"nonterms"`: an array of rule names indexed by `NTIdx`.
+///   * `"prods"`: an array indexed by `PIdx`, each element an object with the `"nonterm"` the
+///     production belongs to and its `"len"` (the number of symbols it pops when reduced).
+///   * `"states"`: an array indexed by `StIdx`, each element an object with `"actions"`, an array
+///     of `[t_idx, "shift", st_idx]`, `[t_idx, "reduce", p_idx]`, or `[t_idx, "accept"]` elements,
+///     and `"gotos"`, an array of `[nt_idx, st_idx]` elements. Terminals and rules without an
+///     action or goto are omitted: a missing action is a parse error.
+///
+/// Parsing starts with a stack containing only state 0. In each step the action for the top
+/// state and the next terminal is looked up: a shift pushes its state and consumes the terminal;
+/// a reduction pops `len` states and pushes the goto of the new top state for `nonterm`; accept
+/// ends the parse. Error recovery is not part of the tables.
+pub fn tables_json(grm: &YaccGrammar, sgraph: &StateGraph, stable: &StateTable) -> String {
+    let mut s = String::new();
+    write!(s, "{{\"format\":\"lrpar-tables\",\"version\":{},\n\"eof\":{},\n\"terms\":[",
+           TABLES_JSON_VERSION, usize::from(grm.eof_term_idx())).unwrap();
+    for i in 0..grm.terms_len() {
+        if i > 0 {
+            s.push(',');
+        }
+        match grm.term_name(TIdx::from(i)) {
+            Some(n) => write!(s, "{{\"name\":{}}}", json_str(n)).unwrap(),
+            None => s.push_str("{\"name\":null}")
+        }
+    }
+    s.push_str("],\n\"nonterms\":[");
+    for i in 0..grm.nonterms_len() as usize {
+        if i > 0 {
+            s.push(',');
+        }
+        s.push_str(&json_str(grm.nonterm_name(NTIdx::from(i as u32))));
+    }
+    s.push_str("],\n\"prods\":[");
+    for i in 0..grm.prods_len() as usize {
+        if i > 0 {
+            s.push(',');
+        }
+        let p_idx = PIdx::from(i as u32);
+        write!(s, "{{\"nonterm\":{},\"len\":{}}}",
+               usize::from(grm.prod_to_nonterm(p_idx)), grm.prod(p_idx).len()).unwrap();
+    }
+    s.push_str("],\n\"states\":[");
+    for i in 0..sgraph.all_states_len() as usize {
+        let st_idx = StIdx::from(i as u32);
+        let mut actions = Vec::new();
+        for j in 0..grm.terms_len() {
+            let t = usize::from(TIdx::from(j));
+            match stable.action(st_idx, TIdx::from(j)) {
+                Some(Action::Shift(to)) => actions.push(format!("[{},\"shift\",{}]",
+                                                                t, usize::from(to))),
+                Some(Action::Reduce(p_idx)) => actions.push(format!("[{},\"reduce\",{}]",
+                                                                    t, usize::from(p_idx))),
+                Some(Action::Accept) => actions.push(format!("[{},\"accept\"]", t)),
+                None => ()
+            }
+        }
+        let gotos = (0..grm.nonterms_len() as usize)
+                        .filter_map(|j| {
+                            stable.goto(st_idx, NTIdx::from(j as u32))
+                                  .map(|to| format!("[{},{}]", j, usize::from(to)))
+                        })
+                        .collect::<Vec<_>>();
+        write!(s, "{}\n{{\"actions\":[{}],\"gotos\":[{}]}}",
+               if i > 0 { "," } else { "" }, actions.join(","), gotos.join(",")).unwrap();
+    }
+    s.push_str("]}\n");
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use cfgrammar::yacc::{yacc_grm, YaccKind};
+    use lrtable::{from_yacc, Minimiser};
+    use super::tables_json;
+
+    #[test]
+    fn json() {
+        let grm = yacc_grm(YaccKind::Original, "%start S\n%%\nS: 'a' S | 'b';").unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let js = tables_json(&grm, &sgraph, &stable);
+        assert!(js.starts_with("{\"format\":\"lrpar-tables\",\"version\":1,\n"));
+        assert_eq!(js.matches("\"actions\":").count(), sgraph.all_states_len() as usize);
+        let a = usize::from(grm.term_idx("a").unwrap());
+        let eof = usize::from(grm.eof_term_idx());
+        assert!(js.contains(&format!("\"eof\":{}", eof)));
+        assert!(js.contains("{\"name\":\"a\"}") && js.contains("{\"name\":null}"));
+        // The start state shifts 'a' and has a goto for S; exactly one state accepts.
+        let start = js.split("\n{\"actions\"").nth(1).unwrap();
+        assert!(start.contains(&format!("[{},\"shift\",", a)));
+        let s_idx = usize::from(grm.nonterm_idx("S").unwrap());
+        assert!(start.contains(&format!("\"gotos\":[[{},", s_idx)));
+        assert_eq!(js.matches(&format!("[{},\"accept\"]", eof)).count(), 1);
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use lrlex::Lexeme;
+
+use tokid::TokenId;
+
+/// Filter the lexemes `lexemes` before they are passed to a parser, returning the filtered
+/// lexemes. `f` is called once for each lexeme, in input order: returning `None` drops the lexeme
+/// (e.g. for comments); returning `Some(tok_id)` keeps it, with its token ID changed to `tok_id`
+/// (which can simply be the lexeme's current token ID). Since `f` is `FnMut`, it can carry state
+/// from one lexeme to the next (e.g. to collapse a run of newlines into a single newline).
+///
+/// Kept lexemes retain their start offsets and lengths, so parse trees, errors, and repairs
+/// derived from the filtered lexemes refer to positions in the original input. Note that lexeme
+/// indices (e.g. `ParseError::lexeme_idx`) are indices into the filtered lexemes, and that the
+/// end-of-input lexeme is placed directly after the last kept lexeme.
+pub fn filter_lexemes<TokId, F>(lexemes: &[Lexeme<TokId>], mut f: F) -> Vec<Lexeme<TokId>>
+                         where TokId: TokenId,
+                               F: FnMut(&Lexeme<TokId>) -> Option<TokId>
+{
+    lexemes.iter()
+           .filter_map(|l| f(l).map(|tok_id| Lexeme::new(tok_id, l.start(), l.len())))
+           .collect()
+}
+
+/// Collapse each balanced region in `lexemes` into a single opaque lexeme, so that the parser
+/// need not (and cannot) parse its contents. Each element of `regions` is a tuple `(open, close,
+/// region)` of token IDs: a region starts with an `open` lexeme and ends with the matching `close`
+/// lexeme (regions of the same kind can nest, e.g. `#if 0 ... #if 0 ... #endif ... #endif`, and
+/// everything inside a region, including other kinds of region, is opaque). The region is
+/// replaced by a single lexeme with the token ID `region` spanning the whole of it, which the
+/// grammar can then treat as it would any other terminal.
+///
+/// An `open` with no matching `close` can't sensibly swallow the rest of the input, since one
+/// typo would then hide all later errors. Instead, the `open` lexeme alone is replaced with a
+/// `region` lexeme, so that the rest of the input is parsed normally, and the `open` lexeme is
+/// returned in the second element of the result so that it can be reported. Unmatched `close`
+/// lexemes are left for the parser to report.
+pub fn collapse_regions<TokId>(lexemes: &[Lexeme<TokId>], regions: &[(TokId, TokId, TokId)])
+                            -> (Vec<Lexeme<TokId>>, Vec<Lexeme<TokId>>)
+                         where TokId: TokenId
+{
+    let mut out = Vec::with_capacity(lexemes.len());
+    let mut unterminated = Vec::new();
+    let mut i = 0;
+    while i < lexemes.len() {
+        let l = lexemes[i];
+        let (open, close, region) = match regions.iter().find(|&&(o, _, _)| o == l.tok_id()) {
+            Some(&r) => r,
+            None => {
+                out.push(l);
+                i += 1;
+                continue;
+            }
+        };
+        let mut depth = 0;
+        let mut end = None;
+        for (j, m) in lexemes.iter().enumerate().skip(i) {
+            if m.tok_id() == open {
+                depth += 1;
+            } else if m.tok_id() == close {
+                depth -= 1;
+                if depth == 0 {
+                    end = Some(j);
+                    break;
+                }
+            }
+        }
+        match end {
+            Some(j) => {
+                let e = lexemes[j];
+                out.push(Lexeme::new(region, l.start(), e.start() + e.len() - l.start()));
+                i = j + 1;
+            },
+            None => {
+                unterminated.push(l);
+                out.push(Lexeme::new(region, l.start(), l.len()));
+                i += 1;
+            }
+        }
+    }
+    (out, unterminated)
+}
+
+/// Remove the recovery pragma lexemes from `lexemes`, returning the remaining lexemes and the
+/// spans of input (as `(start, end)` byte offsets) in which recovery is turned off, suitable for
+/// passing to `RTParserBuilder::no_recovery`. A lexeme with the token ID `off` (e.g. a comment
+/// such as `// lrpar: recovery off`, given its own lexer rule) turns recovery off from the end of
+/// the lexeme until the start of the next lexeme with the token ID `on`, or, if there is none, the
+/// end of the input. Repeated `off`s, and `on`s when recovery is already on, have no effect.
+///
+/// This is intended for sections of input (e.g. generated or vendored code) where guessing at
+/// repairs is unwelcome: an error in such a section stops parsing instead.
+pub fn strip_recovery_pragmas<TokId>(lexemes: &[Lexeme<TokId>], off: TokId, on: TokId)
+                                  -> (Vec<Lexeme<TokId>>, Vec<(usize, usize)>)
+                               where TokId: TokenId
+{
+    let mut out = Vec::with_capacity(lexemes.len());
+    let mut spans = Vec::new();
+    // If recovery is off, the offset at which it was turned off.
+    let mut off_start = None;
+    for l in lexemes {
+        if l.tok_id() == off {
+            if off_start.is_none() {
+                off_start = Some(l.start() + l.len());
+            }
+        } else if l.tok_id() == on {
+            if let Some(s) = off_start.take() {
+                spans.push((s, l.start()));
+            }
+        } else {
+            out.push(*l);
+        }
+    }
+    if let Some(s) = off_start {
+        spans.push((s, usize::max_value()));
+    }
+    (out, spans)
+}
+
+#[cfg(test)]
+mod test {
+    use num_traits::ToPrimitive;
+    use parser::{lexeme_str, RTParserBuilder};
+    use parser::test::build_parse_env;
+    use super::{collapse_regions, filter_lexemes, strip_recovery_pragmas};
+
+    #[test]
+    fn filter() {
+        let lexs = "%%
+\\+ '+'
+[a-z]+ 'ID'
+#[^\\n]* 'COMMENT'
+[ \\n] ;
+";
+        let grms = "%start E
+%token COMMENT
+%%
+E: E '+' 'ID'
+ | 'ID' ;
+";
+
+        let us = "ab #c\n+ #d\n";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, &us);
+        assert_eq!(lexemes.len(), 4);
+        let comment = u32::from(grm.term_idx("COMMENT").unwrap()).to_u16().unwrap();
+        let flexemes = filter_lexemes(&lexemes, |l| {
+            if l.tok_id() == comment { None } else { Some(l.tok_id()) }
+        });
+        assert_eq!(flexemes.len(), 2);
+        assert_eq!(flexemes[1].start(), 6);
+        match RTParserBuilder::new(&grm, &sgraph, &stable).parse(&flexemes) {
+            Ok(_) => panic!(),
+            Err((_, errs)) => {
+                assert_eq!(errs.len(), 1);
+                // The missing identifier is reported just after the '+' in the original input.
+                assert_eq!(errs[0].lexeme().start(), 7);
+            }
+        }
+    }
+
+    #[test]
+    fn regions() {
+        let lexs = "%%
+#if0 'OPEN'
+#endif 'CLOSE'
+; ';'
+[a-z]+ 'ID'
+[ \\n] ;
+";
+        let grms = "%start Stmts
+%token OPEN CLOSE
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' ';' | 'REGION' ;
+";
+
+        let us = "a; #if0 b #if0 c #endif d d #endif e;";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let tok = |n| u32::from(grm.term_idx(n).unwrap()).to_u16().unwrap();
+        let kinds = [(tok("OPEN"), tok("CLOSE"), tok("REGION"))];
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (clexemes, unterminated) = collapse_regions(&lexemes, &kinds);
+        assert!(unterminated.is_empty());
+        assert_eq!(clexemes.len(), 5);
+        assert_eq!(lexeme_str(&us, &clexemes[2]), "#if0 b #if0 c #endif d d #endif");
+        assert!(rtpb.parse(&clexemes).is_ok());
+
+        // The unterminated region hides only its opener, so the error after it is still found.
+        let us = "a; #if0 b; d e;";
+        let (_, _, _, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let (clexemes, unterminated) = collapse_regions(&lexemes, &kinds);
+        assert_eq!(unterminated.len(), 1);
+        assert_eq!(unterminated[0].start(), 3);
+        assert_eq!(clexemes.len(), lexemes.len());
+        let (_, errs) = rtpb.parse(&clexemes).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(lexeme_str(&us, errs[0].lexeme()), "e");
+    }
+
+    #[test]
+    fn recovery_pragmas() {
+        let lexs = "%%
+@off 'OFF'
+@on 'ON'
+; ';'
+[a-z]+ 'ID'
+[ \\n] ;
+";
+        let grms = "%start Stmts
+%token OFF ON
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' ';' ;
+";
+
+        let us = "a b; @off c; @off d; @on e f; g;";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let tok = |n| u32::from(grm.term_idx(n).unwrap()).to_u16().unwrap();
+        let (slexemes, spans) = strip_recovery_pragmas(&lexemes, tok("OFF"), tok("ON"));
+        assert_eq!(slexemes.len(), lexemes.len() - 3);
+        assert_eq!(spans, vec![(9, 21)]);
+        // Errors outside the span are recovered from as normal...
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).no_recovery(&spans);
+        let (_, errs) = rtpb.parse(&slexemes).unwrap_err();
+        assert_eq!(errs.len(), 2);
+        assert!(errs.iter().all(|e| !e.recovery_skipped()));
+
+        // ...but an error inside it stops parsing.
+        let us = "a b; @off c d; @on e f;";
+        let (_, _, _, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let (slexemes, spans) = strip_recovery_pragmas(&lexemes, tok("OFF"), tok("ON"));
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).no_recovery(&spans);
+        let (_, errs) = rtpb.parse(&slexemes).unwrap_err();
+        assert_eq!(errs.len(), 2);
+        assert!(!errs[0].recovery_skipped());
+        assert!(errs[1].recovery_skipped());
+        assert_eq!(lexeme_str(&us, errs[1].lexeme()), "d");
+
+        // Without a matching `on`, recovery stays off until the end of the input.
+        let (_, spans) = strip_recovery_pragmas(&lexemes[..6], tok("OFF"), tok("ON"));
+        assert_eq!(spans, vec![(9, usize::max_value())]);
+    }
+}
@@ -0,0 +1,269 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use cfgrammar::{PIdx, Symbol, TIdx};
+use cfgrammar::yacc::{SentenceGenerator, YaccGrammar};
+use lrlex::Lexeme;
+
+use tokid::TokenId;
+
+/// A generator of pseudo-random sentences from a grammar, intended for testing: every sentence
+/// produced by `sentence` is in the grammar's language, while `sentence_with_errors` produces
+/// sentences which contain a bounded number of errors (and which are thus useful for stressing
+/// error recovery). Generation is deterministic for a given seed.
+pub struct SentenceGen<'a> {
+    grm: &'a YaccGrammar,
+    sengen: SentenceGenerator<'a>,
+    rng: u64,
+    max_depth: usize,
+    coverage: bool,
+    // How many times each production has been used in sentences generated so far.
+    prod_counts: Vec<usize>
+}
+
+impl<'a> SentenceGen<'a> {
+    /// Create a new sentence generator for `grm`, seeding its pseudo-random number generator with
+    /// `seed`. By default, nonterminals are expanded up to a depth of 10, after which the
+    /// shortest sentence for each nonterminal is used.
+    pub fn new(grm: &'a YaccGrammar, seed: u64) -> Self {
+        SentenceGen{grm,
+                    sengen: grm.sentence_generator(|_| 1),
+                    // xorshift cannot escape from a state of 0.
+                    rng: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed },
+                    max_depth: 10,
+                    coverage: false,
+                    prod_counts: vec![0; grm.prods_len() as usize]}
+    }
+
+    /// Set the depth beyond which nonterminals are expanded to their shortest sentence, bounding
+    /// the length of generated sentences.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// If `yes` is `true`, expand each nonterminal using whichever of its productions has been
+    /// used least often so far (breaking ties randomly) rather than choosing a production at
+    /// random, so that a series of sentences covers the grammar's productions more quickly.
+    pub fn coverage(mut self, yes: bool) -> Self {
+        self.coverage = yes;
+        self
+    }
+
+    /// Return the number of productions that have not been used in any sentence generated so far.
+    pub fn unused_prods(&self) -> usize {
+        self.prod_counts.iter().filter(|&&c| c == 0).count()
+    }
+
+    /// Generate a sentence in the grammar's language.
+    pub fn sentence(&mut self) -> Vec<TIdx> {
+        let grm = self.grm;
+        let mut out = Vec::new();
+        let mut todo = Vec::new();
+        let start = grm.start_prod();
+        self.prod_counts[usize::from(start)] += 1;
+        for sym in grm.prod(start).iter().rev() {
+            todo.push((*sym, 0));
+        }
+        while let Some((sym, depth)) = todo.pop() {
+            match sym {
+                Symbol::Term(t_idx) => {
+                    if t_idx != grm.eof_term_idx() {
+                        out.push(t_idx);
+                    }
+                }
+                Symbol::Nonterm(nt_idx) => {
+                    if depth >= self.max_depth {
+                        out.extend(self.sengen.min_sentence(nt_idx));
+                        continue;
+                    }
+                    let p_idx = self.choose_prod(grm.nonterm_to_prods(nt_idx));
+                    self.prod_counts[usize::from(p_idx)] += 1;
+                    for sym in grm.prod(p_idx).iter().rev() {
+                        todo.push((*sym, depth + 1));
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Generate a sentence in the grammar's language and then apply `errors` random edits to it,
+    /// each of which inserts, deletes, or replaces a single terminal. Note that, occasionally, the
+    /// resulting sentence may still be in the grammar's language.
+    pub fn sentence_with_errors(&mut self, errors: usize) -> Vec<TIdx> {
+        let mut s = self.sentence();
+        // Every terminal other than EOF can be inserted.
+        let eof = self.grm.eof_term_idx();
+        let terms = (0..self.grm.terms_len()).map(TIdx::from)
+                                             .filter(|&t| t != eof)
+                                             .collect::<Vec<_>>();
+        for _ in 0..errors {
+            let kind = if s.is_empty() { 0 } else { self.next_rand() % 3 };
+            let t_idx = terms[self.next_rand() % terms.len()];
+            match kind {
+                0 => {
+                    let i = self.next_rand() % (s.len() + 1);
+                    s.insert(i, t_idx);
+                },
+                1 => {
+                    let i = self.next_rand() % s.len();
+                    s.remove(i);
+                },
+                _ => {
+                    let i = self.next_rand() % s.len();
+                    s[i] = t_idx;
+                }
+            }
+        }
+        s
+    }
+
+    fn choose_prod(&mut self, prods: &[PIdx]) -> PIdx {
+        if self.coverage {
+            let min = prods.iter().map(|&p| self.prod_counts[usize::from(p)]).min().unwrap();
+            let cnds = prods.iter()
+                            .cloned()
+                            .filter(|&p| self.prod_counts[usize::from(p)] == min)
+                            .collect::<Vec<_>>();
+            cnds[self.next_rand() % cnds.len()]
+        } else {
+            prods[self.next_rand() % prods.len()]
+        }
+    }
+
+    /// Return the next number from a xorshift64* pseudo-random number generator.
+    fn next_rand(&mut self) -> usize {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        (self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as usize
+    }
+}
+
+/// Convert the terminals `sentence` (e.g. as generated by a `SentenceGen`) into lexemes which can
+/// be passed to a parser. Since there is no underlying input, the `i`th lexeme is given a start
+/// offset of `i` and a length of 1.
+pub fn sentence_lexemes<TokId: TokenId>(sentence: &[TIdx]) -> Vec<Lexeme<TokId>> {
+    sentence.iter()
+            .enumerate()
+            .map(|(i, &t_idx)| Lexeme::new(TokId::from(u32::from(t_idx)).unwrap(), i, 1))
+            .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use cfgrammar::TIdx;
+    use cfgrammar::yacc::{yacc_grm, YaccGrammar, YaccKind};
+    use lrtable::{Minimiser, from_yacc};
+    use parser::{RecoveryKind, RTParserBuilder};
+    use super::{SentenceGen, sentence_lexemes};
+
+    const GRM: &str = "%start Expr
+%%
+Expr: Term '+' Expr | Term ;
+Term: Factor '*' Term | Factor ;
+Factor: '(' Expr ')' | 'INT' | 'ID' ;
+";
+
+    /// Is `s` in the language of `GRM`? This is checked independently of the parser, by tracking
+    /// whether an operand is expected next and how deeply nested in brackets `s` is.
+    fn in_lang(grm: &YaccGrammar, s: &[TIdx]) -> bool {
+        let mut want_operand = true;
+        let mut depth = 0;
+        for &t_idx in s {
+            match (want_operand, grm.term_name(t_idx).unwrap()) {
+                (true, "INT") | (true, "ID") => want_operand = false,
+                (true, "(") => depth += 1,
+                (false, "+") | (false, "*") => want_operand = true,
+                (false, ")") if depth > 0 => depth -= 1,
+                _ => return false
+            }
+        }
+        !want_operand && depth == 0
+    }
+
+    #[test]
+    fn generated_sentences_parse() {
+        let grm = yacc_grm(YaccKind::Original, GRM).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let pb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        for seed in 0..50 {
+            let mut sg = SentenceGen::new(&grm, seed).max_depth(6);
+            let s = sg.sentence();
+            assert!(in_lang(&grm, &s));
+            assert!(pb.parse(&sentence_lexemes::<u16>(&s)).is_ok());
+        }
+    }
+
+    #[test]
+    fn coverage() {
+        let grm = yacc_grm(YaccKind::Original, GRM).unwrap();
+        let mut sg = SentenceGen::new(&grm, 1).coverage(true);
+        // Each sentence expands `Expr`, `Term`, and `Factor` at least once, each time using a
+        // least used production, so three sentences are enough to use every production.
+        for _ in 0..3 {
+            sg.sentence();
+        }
+        assert_eq!(sg.unused_prods(), 0);
+    }
+
+    #[test]
+    fn sentences_with_errors() {
+        let grm = yacc_grm(YaccKind::Original, GRM).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        for &rcvry_kind in &[RecoveryKind::MF, RecoveryKind::CPCTPlus] {
+            let pb = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(rcvry_kind);
+            let mut erroneous = 0;
+            for seed in 0..20 {
+                let mut sg = SentenceGen::new(&grm, seed).max_depth(4);
+                let s = sg.sentence_with_errors(2);
+                let lexemes = sentence_lexemes::<u16>(&s);
+                if in_lang(&grm, &s) {
+                    // The edits happened to leave the sentence in the language.
+                    assert!(pb.parse(&lexemes).is_ok());
+                    continue;
+                }
+                erroneous += 1;
+                match pb.parse(&lexemes) {
+                    Ok(_) => panic!("Sentence {:?} should not parse", s),
+                    Err((_, errs)) => {
+                        assert!(!errs.is_empty());
+                        assert!(errs.iter().all(|e| e.lexeme_idx() <= s.len()));
+                    }
+                }
+            }
+            // Most edits take a sentence out of the language.
+            assert!(erroneous >= 10);
+        }
+    }
+}
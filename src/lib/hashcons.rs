@@ -0,0 +1,212 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use cfgrammar::{NTIdx, TIdx};
+use cfgrammar::yacc::YaccGrammar;
+use lrlex::Lexeme;
+
+use parser::{lexeme_str, Node};
+use tokid::{tok_tidx, TokenId};
+
+/// A parse tree node whose identical subtrees are shared. This mirrors `Node`, except that
+/// children are reference counted: see `NodeInterner` for how such trees are created.
+#[derive(Debug, PartialEq)]
+pub enum SharedNode<TokId: TokenId> {
+    Term{lexeme: Lexeme<TokId>},
+    Nonterm{nonterm_idx: NTIdx, nodes: Vec<Rc<SharedNode<TokId>>>}
+}
+
+impl<TokId: TokenId> SharedNode<TokId> {
+    /// Return a pretty-printed version of this node. The output is identical to that of `Node::pp`
+    /// on the corresponding unshared tree.
+    pub fn pp(&self, grm: &YaccGrammar, input: &str) -> String {
+        let mut st = vec![(0, self)]; // Stack of (indent level, node) pairs
+        let mut s = String::new();
+        while let Some((indent, e)) = st.pop() {
+            for _ in 0..indent {
+                s.push_str(" ");
+            }
+            match *e {
+                SharedNode::Term{lexeme} => {
+                    let t_idx = tok_tidx(grm, lexeme.tok_id());
+                    let tn = grm.term_name(t_idx).unwrap();
+                    s.push_str(&format!("{} {}\n", tn, lexeme_str(input, &lexeme)));
+                }
+                SharedNode::Nonterm{nonterm_idx, ref nodes} => {
+                    s.push_str(&format!("{}\n", grm.nonterm_name(nonterm_idx)));
+                    for x in nodes.iter().rev() {
+                        st.push((indent + 1, x));
+                    }
+                }
+            }
+        }
+        s
+    }
+}
+
+/// Hash-conses parse trees: every structurally identical subtree is stored once, no matter how
+/// many times it occurs. Two terminals are identical if they are for the same terminal (whatever
+/// their channel) and have the same text; two nonterminals are identical if they have the same
+/// nonterminal index and identical children. Since lexemes record their position in the input, a
+/// shared subtree's lexemes are those of the first occurrence to be interned: users who need
+/// precise positions for every occurrence should use `Node` instead. `grm` is the grammar the
+/// trees were parsed with.
+///
+/// An interner can be used for several trees (over the same input), in which case subtrees are
+/// shared between trees too.
+pub struct NodeInterner<'a, TokId: TokenId> {
+    grm: &'a YaccGrammar,
+    input: &'a str,
+    terms: HashMap<(TIdx, &'a str), Rc<SharedNode<TokId>>>,
+    // Children are themselves interned, so their addresses uniquely identify their structure.
+    nonterms: HashMap<(usize, Vec<usize>), Rc<SharedNode<TokId>>>
+}
+
+impl<'a, TokId: TokenId> NodeInterner<'a, TokId> {
+    pub fn new(grm: &'a YaccGrammar, input: &'a str) -> Self {
+        NodeInterner{grm, input, terms: HashMap::new(), nonterms: HashMap::new()}
+    }
+
+    /// Return a shared version of `node`.
+    pub fn intern(&mut self, node: &Node<TokId>) -> Rc<SharedNode<TokId>> {
+        // We traverse the tree in post-order with an explicit stack so that deep trees can't
+        // overflow the call stack. `done` holds the interned versions of nodes visited so far.
+        let mut st = vec![(node, false)];
+        let mut done: Vec<Rc<SharedNode<TokId>>> = Vec::new();
+        while let Some((n, expanded)) = st.pop() {
+            match *n {
+                Node::Term{lexeme} => {
+                    let key = (tok_tidx(self.grm, lexeme.tok_id()),
+                               lexeme_str(self.input, &lexeme));
+                    let sn = self.terms.entry(key)
+                                       .or_insert_with(|| Rc::new(SharedNode::Term{lexeme}))
+                                       .clone();
+                    done.push(sn);
+                }
+                Node::Nonterm{nonterm_idx, ref nodes} => {
+                    if !expanded {
+                        st.push((n, true));
+                        for x in nodes.iter().rev() {
+                            st.push((x, false));
+                        }
+                        continue;
+                    }
+                    let children = done.split_off(done.len() - nodes.len());
+                    let key = (usize::from(nonterm_idx),
+                               children.iter()
+                                       .map(|c| &**c as *const SharedNode<TokId> as usize)
+                                       .collect());
+                    let sn = self.nonterms.entry(key)
+                                          .or_insert_with(|| Rc::new(SharedNode::Nonterm{
+                                              nonterm_idx,
+                                              nodes: children
+                                          }))
+                                          .clone();
+                    done.push(sn);
+                }
+            }
+        }
+        debug_assert_eq!(done.len(), 1);
+        done.pop().unwrap()
+    }
+
+    /// How many distinct nodes have been interned?
+    pub fn num_nodes(&self) -> usize {
+        self.terms.len() + self.nonterms.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use lrlex::Lexeme;
+
+    use parser::{Node, RecoveryKind};
+    use parser::test::do_parse;
+    use tokid::{Channel, TokenId};
+    use super::{NodeInterner, SharedNode};
+
+    #[test]
+    fn sharing() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+[a-z] 'ID'
+";
+        let grms = "%start S
+%%
+S: S P
+ | P ;
+P: '(' 'ID' ')' ;
+";
+
+        let us = "(a)(a)(b)";
+        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, &us);
+        let pt = pr.unwrap();
+        let mut ni = NodeInterner::new(&grm, &us);
+        let sn = ni.intern(&pt);
+        assert_eq!(sn.pp(&grm, &us), pt.pp(&grm, &us));
+        // (, ), a, b, two distinct P nodes, and three S nodes.
+        assert_eq!(ni.num_nodes(), 9);
+        let ps = match *sn {
+            SharedNode::Nonterm{ref nodes, ..} => match *nodes[0] {
+                SharedNode::Nonterm{ref nodes, ..} => match *nodes[0] {
+                    SharedNode::Nonterm{ref nodes, ..} => nodes[0].clone(),
+                    _ => unreachable!()
+                },
+                _ => unreachable!()
+            },
+            _ => unreachable!()
+        };
+        let sn2 = ni.intern(&pt);
+        assert!(Rc::ptr_eq(&sn, &sn2));
+        assert_eq!(ni.num_nodes(), 9);
+        match *sn {
+            SharedNode::Nonterm{ref nodes, ..} => match *nodes[0] {
+                SharedNode::Nonterm{ref nodes, ..} => assert!(Rc::ptr_eq(&nodes[1], &ps)),
+                _ => unreachable!()
+            },
+            _ => unreachable!()
+        }
+
+        // A hidden lexeme is the same terminal as a default one.
+        let a = u16::from(grm.term_idx("ID").unwrap());
+        let sa = ni.intern(&Node::Term{lexeme: Lexeme::new(a, 1, 1)});
+        let ha = ni.intern(&Node::Term{lexeme: Lexeme::new(a.with_channel(Channel::Hidden), 1, 1)});
+        assert!(Rc::ptr_eq(&sa, &ha));
+        assert_eq!(ni.num_nodes(), 9);
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use lrlex::Lexeme;
+
+use parser::Node;
+use tokid::TokenId;
+
+/// An index from byte offsets in the input to lexemes and parse tree leaves, and from lexemes back
+/// to byte offsets. Building the index takes time linear in the size of the tree, after which
+/// queries such as "what node is under the cursor?" take logarithmic time rather than requiring a
+/// walk of the tree.
+pub struct OffsetIndex<'a, TokId: TokenId> where TokId: 'a {
+    lexemes: &'a [Lexeme<TokId>],
+    // The terminal nodes of the tree in input order. Terminals inserted by error recovery are
+    // zero-length and are not included, since no byte offset can refer to them.
+    leaves: Vec<&'a Node<TokId>>
+}
+
+impl<'a, TokId: TokenId> OffsetIndex<'a, TokId> {
+    /// Create an index for `lexemes` and, optionally, the parse tree `pt` produced from them.
+    pub fn new(lexemes: &'a [Lexeme<TokId>], pt: Option<&'a Node<TokId>>) -> Self {
+        let mut leaves = Vec::new();
+        if let Some(pt) = pt {
+            let mut st = vec![pt];
+            while let Some(n) = st.pop() {
+                match *n {
+                    Node::Term{lexeme} => {
+                        if lexeme.len() > 0 {
+                            leaves.push(n);
+                        }
+                    },
+                    Node::Nonterm{ref nodes, ..} => {
+                        for x in nodes.iter().rev() {
+                            st.push(x);
+                        }
+                    }
+                }
+            }
+        }
+        OffsetIndex{lexemes, leaves}
+    }
+
+    /// Return the index of the lexeme which contains the byte offset `off`, or `None` if there is
+    /// no such lexeme (e.g. if `off` is in whitespace between lexemes).
+    pub fn lexeme_idx(&self, off: usize) -> Option<usize> {
+        find(self.lexemes, off, |l| *l)
+    }
+
+    /// Return the byte offset of the start of the lexeme at index `lexeme_idx`.
+    pub fn lexeme_offset(&self, lexeme_idx: usize) -> usize {
+        self.lexemes[lexeme_idx].start()
+    }
+
+    /// Return the terminal node of the parse tree which contains the byte offset `off`, or `None`
+    /// if there is no such node (e.g. if `off` is in whitespace, or in a lexeme that was deleted by
+    /// error recovery).
+    pub fn leaf(&self, off: usize) -> Option<&'a Node<TokId>> {
+        find(&self.leaves, off, |n| match *n {
+            Node::Term{lexeme} => lexeme,
+            Node::Nonterm{..} => unreachable!()
+        }).map(|i| self.leaves[i])
+    }
+}
+
+/// Binary search `v`, whose elements are ordered by their lexemes' (non-overlapping) spans, for
+/// the element whose lexeme contains `off`.
+fn find<T, TokId, F>(v: &[T], off: usize, lexeme: F) -> Option<usize>
+    where TokId: TokenId,
+          F: Fn(&T) -> Lexeme<TokId>
+{
+    let i = match v.binary_search_by_key(&off, |x| lexeme(x).start()) {
+        Ok(i) => return Some(i),
+        Err(0) => return None,
+        Err(i) => i - 1
+    };
+    let l = lexeme(&v[i]);
+    if off < l.start() + l.len() {
+        Some(i)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use parser::{lexeme_str, Node, RecoveryKind, RTParserBuilder};
+    use parser::test::build_parse_env;
+    use super::OffsetIndex;
+
+    #[test]
+    fn offsets() {
+        let lexs = "%%
+\\+ '+'
+[a-z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start E
+%%
+E: E '+' 'ID'
+ | 'ID' ;
+";
+
+        // The missing identifier at the end is inserted by error recovery as a zero-length leaf.
+        let us = "ab + cd  +";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let pr = RTParserBuilder::new(&grm, &sgraph, &stable).parse(&lexemes);
+        let pt = match pr {
+            Ok(_) => panic!(),
+            Err((pt, _)) => pt.unwrap()
+        };
+        let idx = OffsetIndex::new(&lexemes, Some(&pt));
+        assert_eq!(idx.lexeme_idx(0), Some(0));
+        assert_eq!(idx.lexeme_idx(1), Some(0));
+        assert_eq!(idx.lexeme_idx(2), None);
+        assert_eq!(idx.lexeme_idx(3), Some(1));
+        assert_eq!(idx.lexeme_idx(10), Some(3));
+        assert_eq!(idx.lexeme_idx(11), None);
+        assert_eq!(idx.lexeme_offset(3), 9);
+        let leaf_str = |off| {
+            idx.leaf(off).map(|n| match *n {
+                Node::Term{lexeme} => lexeme_str(&us, &lexeme),
+                _ => unreachable!()
+            })
+        };
+        assert_eq!(leaf_str(6), Some("cd"));
+        assert_eq!(leaf_str(7), None);
+        assert_eq!(leaf_str(9), Some("+"));
+        assert_eq!(leaf_str(10), None);
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use lrlex::Lexeme;
+
+use parser::{Node, ParseError, RTParserBuilder};
+use payload::Payloads;
+use tokid::TokenId;
+
+/// The result of parsing an island (see `parse_islands`).
+pub type IslandResult<TokId> = Result<Node<TokId>, (Option<Node<TokId>>, Vec<ParseError<TokId>>)>;
+
+/// Parse the "islands" of a sub-language embedded in an input (e.g. SQL in a host language's
+/// string literals) with a secondary parser. Every lexeme in `lexemes` (the lexemes of `input`)
+/// whose token ID is `island` is lexed by calling `lex` with the lexeme's text, and the resulting
+/// lexemes are parsed by `sub`. The sub-parse results are returned as `Payloads`, so that the
+/// result for a `Node::Term` in the outer parse tree can be looked up directly. The sub-parse trees
+/// are not spliced into the outer tree, since a `Node`'s rule indices can only refer to a single
+/// grammar.
+///
+/// The lexemes returned by `lex` are relative to the start of the island's text, but are moved so
+/// that the spans of the sub-parse's tree and errors refer to `input` as a whole. Each island is
+/// parsed separately, so each has its own error recovery budget, and errors in one island affect
+/// neither the outer parse nor any other island.
+pub fn parse_islands<TokId, SubTokId, F>(input: &str,
+                                         lexemes: &[Lexeme<TokId>],
+                                         island: TokId,
+                                         sub: &RTParserBuilder<SubTokId>,
+                                         mut lex: F)
+                                      -> Payloads<IslandResult<SubTokId>>
+                                   where TokId: TokenId,
+                                         SubTokId: TokenId,
+                                         F: FnMut(&str) -> Vec<Lexeme<SubTokId>>
+{
+    Payloads::new(input, lexemes, |l, s| {
+        if l.tok_id() != island {
+            return None;
+        }
+        let sub_lexemes = lex(s).iter()
+                                .map(|sl| Lexeme::new(sl.tok_id(), l.start() + sl.start(), sl.len()))
+                                .collect::<Vec<_>>();
+        Some(sub.parse(&sub_lexemes))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use cfgrammar::yacc::{yacc_grm, YaccKind};
+    use lrlex::build_lex;
+    use lrtable::{Minimiser, from_yacc};
+    use num_traits::ToPrimitive;
+    use parser::{lexeme_str, Node, RTParserBuilder};
+    use parser::test::build_parse_env;
+    use super::parse_islands;
+
+    #[test]
+    fn islands() {
+        let lexs = "%%
+[a-z]+ 'ID'
+= '='
+; ';'
+\\{[^}]*\\} 'SQL'
+[ ] ;
+";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' '=' 'SQL' ';' ;
+";
+        let sub_lexs = "%%
+[a-z]+ 'COL'
+, ','
+[{} ] ;
+";
+        let sub_grms = "%start Cols
+%%
+Cols: Cols ',' 'COL' | 'COL' ;
+";
+
+        let us = "x = {a, b}; y = {c,, d};";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let pt = RTParserBuilder::new(&grm, &sgraph, &stable).parse(&lexemes).unwrap();
+
+        let sub_grm = yacc_grm(YaccKind::Original, sub_grms).unwrap();
+        let (sub_sgraph, sub_stable) = from_yacc(&sub_grm, Minimiser::Pager).unwrap();
+        let mut sub_lexerdef = build_lex(sub_lexs).unwrap();
+        {
+            let rule_ids = sub_grm.terms_map()
+                                  .iter()
+                                  .map(|(&n, &i)| (n, u32::from(i).to_u16().unwrap()))
+                                  .collect();
+            sub_lexerdef.set_rule_ids(&rule_ids);
+        }
+        let sub = RTParserBuilder::new(&sub_grm, &sub_sgraph, &sub_stable);
+        let sql = u32::from(grm.term_idx("SQL").unwrap()).to_u16().unwrap();
+        let islands = parse_islands(&us, &lexemes, sql, &sub, |s| {
+            sub_lexerdef.lexer(s).lexemes().unwrap()
+        });
+
+        // Find the SQL terminals in the outer tree and check their islands.
+        let mut results = Vec::new();
+        let mut st = vec![&pt];
+        while let Some(n) = st.pop() {
+            match *n {
+                Node::Term{..} => {
+                    if let Some(r) = islands.node(n) {
+                        results.push(r);
+                    }
+                },
+                Node::Nonterm{ref nodes, ..} => st.extend(nodes.iter().rev())
+            }
+        }
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().pp(&sub_grm, &us),
+                   "Cols\n Cols\n  COL a\n , ,\n COL b\n");
+        let (_, errs) = results[1].as_ref().unwrap_err();
+        assert_eq!(errs.len(), 1);
+        // The error's location refers to the outer input.
+        assert_eq!(lexeme_str(&us, errs[0].lexeme()), ",");
+        assert_eq!(errs[0].lexeme().start(), 19);
+    }
+}
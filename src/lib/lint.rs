@@ -0,0 +1,347 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::fmt;
+
+use cfgrammar::{NTIdx, PIdx, Symbol, TIdx};
+use cfgrammar::yacc::YaccGrammar;
+use vob::Vob;
+
+use annotations::line_col;
+
+/// The kinds of problem that `lint` reports.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LintKind {
+    /// A terminal which is declared but not used in any production.
+    UnusedTerm,
+    /// A rule which cannot be reached from the start rule.
+    UnreachableRule,
+    /// A rule which cannot derive any sentence (e.g. because all its productions are infinitely
+    /// recursive).
+    UnproductiveRule,
+    /// A production of the form `E: E 'op' E`, which is ambiguous unless `'op'` has a
+    /// precedence, where `'op'` has no precedence and the production has no `%prec`.
+    MissingPrec
+}
+
+/// A single problem found by `lint`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Lint {
+    kind: LintKind,
+    line: usize,
+    col: usize,
+    msg: String
+}
+
+impl Lint {
+    /// Return the kind of problem this lint reports.
+    pub fn kind(&self) -> LintKind {
+        self.kind
+    }
+
+    /// Return the (1-based) line in the grammar source the problem relates to.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Return the (1-based) column in the grammar source the problem relates to.
+    pub fn col(&self) -> usize {
+        self.col
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.msg)
+    }
+}
+
+/// Check the grammar `grm`, whose source is `src`, for likely mistakes, returning one `Lint` for
+/// each problem found, ordered by source location. None of the problems stop a grammar from being
+/// used, but each usually indicates that the grammar does not mean what its author intended.
+pub fn lint(src: &str, grm: &YaccGrammar) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let decls = src.split("%%").next().unwrap();
+    let nonterms_len = grm.nonterms_len() as usize;
+    let start_nt = grm.prod_to_nonterm(grm.start_prod());
+
+    let mut used_terms = Vob::from_elem(grm.terms_len() as usize, false);
+    for i in 0..nonterms_len {
+        for &p_idx in grm.nonterm_to_prods(NTIdx::from(i as u32)) {
+            for sym in grm.prod(p_idx) {
+                if let Symbol::Term(t_idx) = *sym {
+                    used_terms.set(usize::from(t_idx), true);
+                }
+            }
+        }
+    }
+    for i in 0..grm.terms_len() {
+        let t_idx = TIdx::from(i);
+        if used_terms[usize::from(t_idx)] || t_idx == grm.eof_term_idx() {
+            continue;
+        }
+        let name = grm.term_name(t_idx).unwrap();
+        let (line, col) = locate(decls, |l| find_name(l, name)).unwrap_or((1, 1));
+        lints.push(Lint{kind: LintKind::UnusedTerm, line, col,
+                        msg: format!("terminal '{}' is never used", name)});
+    }
+
+    // Reachability from the start rule.
+    let mut reachable = Vob::from_elem(nonterms_len, false);
+    reachable.set(usize::from(start_nt), true);
+    let mut todo = vec![start_nt];
+    while let Some(nt_idx) = todo.pop() {
+        for &p_idx in grm.nonterm_to_prods(nt_idx) {
+            for sym in grm.prod(p_idx) {
+                if let Symbol::Nonterm(r_idx) = *sym {
+                    if !reachable[usize::from(r_idx)] {
+                        reachable.set(usize::from(r_idx), true);
+                        todo.push(r_idx);
+                    }
+                }
+            }
+        }
+    }
+
+    // A rule is productive if one of its productions consists only of terminals and productive
+    // rules.
+    let mut productive = Vob::from_elem(nonterms_len, false);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..nonterms_len {
+            if productive[i] {
+                continue;
+            }
+            let nt_idx = NTIdx::from(i as u32);
+            let p = grm.nonterm_to_prods(nt_idx).iter().any(|&p_idx| {
+                grm.prod(p_idx).iter().all(|sym| match *sym {
+                    Symbol::Term(_) => true,
+                    Symbol::Nonterm(r_idx) => productive[usize::from(r_idx)]
+                })
+            });
+            if p {
+                productive.set(i, true);
+                changed = true;
+            }
+        }
+    }
+
+    let prec_terms = prec_decls(decls);
+    for i in 0..nonterms_len {
+        let nt_idx = NTIdx::from(i as u32);
+        if nt_idx == start_nt {
+            continue;
+        }
+        let name = grm.nonterm_name(nt_idx);
+        let (line, col) = locate(src, |l| rule_def(l, name)).unwrap_or((1, 1));
+        if !reachable[i] {
+            lints.push(Lint{kind: LintKind::UnreachableRule, line, col,
+                            msg: format!("rule '{}' is unreachable from the start rule", name)});
+        }
+        if !productive[i] {
+            lints.push(Lint{kind: LintKind::UnproductiveRule, line, col,
+                            msg: format!("rule '{}' cannot derive any sentence", name)});
+        }
+        for &p_idx in grm.nonterm_to_prods(nt_idx) {
+            let (line, col) = prod_location(src, grm, p_idx).unwrap_or((line, col));
+            let prod = grm.prod(p_idx);
+            if prod.len() != 3 || prod[0] != Symbol::Nonterm(nt_idx)
+               || prod[2] != Symbol::Nonterm(nt_idx)
+               || prod_span(src, grm, p_idx).map_or(false, |(s, e)| src[s..e].contains("%prec"))
+            {
+                continue;
+            }
+            if let Symbol::Term(t_idx) = prod[1] {
+                let op = grm.term_name(t_idx).unwrap();
+                if !prec_terms.iter().any(|n| n == op) {
+                    lints.push(Lint{kind: LintKind::MissingPrec, line, col,
+                                    msg: format!("production '{}' is ambiguous: '{}' needs a \
+                                                  precedence",
+                                                 prod_str(grm, p_idx), op)});
+                }
+            }
+        }
+    }
+
+    lints.sort_by_key(|l| (l.line, l.col));
+    lints
+}
+
+/// Return the (1-based) line and column in the grammar source `src` of the start of the
+/// production `p_idx` of `grm` (i.e. of its first symbol, or, for an empty production, of the `|`
+/// or `;` which ends it), or `None` if it cannot be found. This allows, for example, conflicts or
+/// traced reductions to be reported in terms of the grammar file.
+pub fn prod_location(src: &str, grm: &YaccGrammar, p_idx: PIdx) -> Option<(usize, usize)> {
+    let (start, _) = prod_span(src, grm, p_idx)?;
+    let skip = src[start..].len() - src[start..].trim_left().len();
+    Some(line_col(src, start + skip))
+}
+
+/// Return the half-open range of byte offsets in the grammar source `src` of the production
+/// `p_idx` of `grm`, from just after the `:` or `|` which starts it to the `|` or `;` which ends
+/// it, or `None` if it cannot be found.
+fn prod_span(src: &str, grm: &YaccGrammar, p_idx: PIdx) -> Option<(usize, usize)> {
+    let nt_idx = grm.prod_to_nonterm(p_idx);
+    let alt = grm.nonterm_to_prods(nt_idx).iter().position(|&p| p == p_idx)?;
+    let name = grm.nonterm_name(nt_idx);
+    let (line, _) = locate(src, |l| rule_def(l, name))?;
+    let start = src.lines().take(line - 1).map(|l| l.len() + 1).sum::<usize>();
+    let colon = start + src[start..].find(':')?;
+    // Walk over the rule's alternatives (ignoring `|`s and `;`s in quoted terminals) until we
+    // reach the one we want.
+    let mut cur = 0;
+    let mut alt_start = colon + 1;
+    let mut quoted = false;
+    for (i, c) in src[colon + 1..].char_indices() {
+        let off = colon + 1 + i;
+        match c {
+            '\'' => quoted = !quoted,
+            '|' | ';' if !quoted => {
+                if cur == alt {
+                    return Some((alt_start, off));
+                }
+                cur += 1;
+                alt_start = off + 1;
+            },
+            _ => ()
+        }
+    }
+    if cur != alt {
+        return None;
+    }
+    Some((alt_start, src.len()))
+}
+
+/// Return a human readable version of the production `p_idx` e.g. `E: E '+' E`.
+pub(crate) fn prod_str(grm: &YaccGrammar, p_idx: PIdx) -> String {
+    let mut s = format!("{}:", grm.nonterm_name(grm.prod_to_nonterm(p_idx)));
+    for sym in grm.prod(p_idx) {
+        match *sym {
+            Symbol::Term(t_idx) => s.push_str(&format!(" '{}'", grm.term_name(t_idx).unwrap())),
+            Symbol::Nonterm(nt_idx) => s.push_str(&format!(" {}", grm.nonterm_name(nt_idx)))
+        }
+    }
+    s
+}
+
+/// Return the (1-based) line and column of the first match of `f` (which returns a byte offset
+/// within a line) in `s`.
+fn locate<F>(s: &str, f: F) -> Option<(usize, usize)>
+    where F: Fn(&str) -> Option<usize>
+{
+    s.lines().enumerate().filter_map(|(i, l)| f(l).map(|c| (i + 1, c + 1))).next()
+}
+
+/// Return the byte offset of `name` (either quoted or as a complete identifier) in `l`.
+fn find_name(l: &str, name: &str) -> Option<usize> {
+    if let Some(i) = l.find(&format!("'{}'", name)) {
+        return Some(i);
+    }
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    l.match_indices(name)
+     .map(|(i, _)| i)
+     .find(|&i| !l[..i].chars().next_back().map_or(false, &is_ident)
+                && !l[i + name.len()..].chars().next().map_or(false, &is_ident))
+}
+
+/// If `l` starts the definition of the rule `name` (i.e. is of the form `name:`), return the byte
+/// offset of `name`.
+fn rule_def(l: &str, name: &str) -> Option<usize> {
+    let t = l.trim_left();
+    if t.starts_with(name) && t[name.len()..].trim_left().starts_with(':') {
+        Some(l.len() - t.len())
+    } else {
+        None
+    }
+}
+
+/// Return the names of the terminals given a precedence by `%left`, `%right`, or `%nonassoc`
+/// declarations in `decls`.
+fn prec_decls(decls: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for l in decls.lines() {
+        let mut ws = l.split_whitespace();
+        match ws.next() {
+            Some("%left") | Some("%right") | Some("%nonassoc") => {
+                names.extend(ws.map(|n| n.trim_matches('\'').to_owned()));
+            },
+            _ => ()
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod test {
+    use cfgrammar::yacc::{yacc_grm, YaccKind};
+    use super::{lint, LintKind, prod_location};
+
+    #[test]
+    fn lints() {
+        let src = "%start S
+%token UNUSED
+%left '*'
+%%
+S: E | ;
+E: E '+' E
+ | E '*' E
+ | E '-' E %prec '*'
+ | 'INT' ;
+Loop: 'x' Loop ;
+";
+        let grm = yacc_grm(YaccKind::Original, src).unwrap();
+        let lints = lint(src, &grm).into_iter()
+                                   .map(|l| (l.kind(), l.line(), l.col()))
+                                   .collect::<Vec<_>>();
+        assert_eq!(lints, vec![(LintKind::UnusedTerm, 2, 8),
+                               (LintKind::UnreachableRule, 10, 1),
+                               (LintKind::UnproductiveRule, 10, 1)]);
+
+        // Only the production using '+' is ambiguous: '*' has a precedence, and the production
+        // using '-' has a %prec, which doesn't excuse the other productions in its rule.
+        let src = "%start E
+%left '*'
+%%
+E: E '+' E | E '*' E | E '-' E %prec '*' | 'INT' ;
+";
+        let grm = yacc_grm(YaccKind::Original, src).unwrap();
+        let lints = lint(src, &grm);
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].kind(), LintKind::MissingPrec);
+        assert_eq!(lints[0].to_string(),
+                   "4:4: production 'E: E '+' E' is ambiguous: '+' needs a precedence");
+        let e_prods = grm.nonterm_to_prods(grm.nonterm_idx("E").unwrap());
+        assert_eq!(prod_location(src, &grm, e_prods[1]), Some((4, 14)));
+        assert_eq!(prod_location(src, &grm, e_prods[2]), Some((4, 24)));
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+// The characters which have a special meaning in the regular expressions of lrlex rules.
+const REGEX_META: &str = "\\.+*?()|[]{}^$#&-~";
+
+/// Is the terminal name `name` a literal (e.g. `'+'` or `'=='`), i.e. a name which can only
+/// sensibly be lexed as exactly itself? Literals contain neither alphanumeric characters nor
+/// underscores (so `'ID'` and `'if'` are not literals), and no whitespace or quotes.
+pub fn is_literal(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_punctuation() && c != '_' && c != '\'' && c != '"')
+}
+
+/// Return lrlex rules, one per line, which lex each literal terminal name (see `is_literal`) in
+/// `names` as itself, so that a grammar can use literals such as `'+'` without the lexer having
+/// to define a rule for each, as in yacc. `names` is normally the terminals which the grammar
+/// references but the lexer doesn't define (as returned by lrlex's `LexerDef::set_rule_ids`): the
+/// rules should be appended to the lexer's source, and the lexer rebuilt. The `lrpar` binary does
+/// this automatically, but `process_file` does not (see its documentation). Names which are not
+/// literals are ignored, and must still be defined by the lexer. Keywords (e.g. `'if'`) are not
+/// literals, since a rule for them appended after the lexer's identifier rule would never match.
+pub fn literal_lex_rules<'a, I>(names: I) -> String where I: IntoIterator<Item=&'a str> {
+    let mut names = names.into_iter().filter(|n| is_literal(n)).collect::<Vec<_>>();
+    names.sort();
+    let mut rules = String::new();
+    for n in names {
+        for c in n.chars() {
+            if REGEX_META.contains(c) {
+                rules.push('\\');
+            }
+            rules.push(c);
+        }
+        rules.push_str(&format!(" '{}'\n", n));
+    }
+    rules
+}
+
+#[cfg(test)]
+mod test {
+    use cfgrammar::yacc::{yacc_grm, YaccKind};
+    use lrlex::build_lex;
+    use lrtable::{Minimiser, from_yacc};
+    use num_traits::ToPrimitive;
+    use parser::RTParserBuilder;
+    use super::{is_literal, literal_lex_rules};
+
+    #[test]
+    fn literals() {
+        assert!(is_literal("+"));
+        assert!(is_literal("::="));
+        assert!(!is_literal("ID"));
+        assert!(!is_literal("_"));
+        assert!(!is_literal("'"));
+        assert_eq!(literal_lex_rules(vec!["ID", "+", "("]), "\\( '('\n\\+ '+'\n");
+
+        let lexs = "%%
+[0-9]+ 'INT'
+[ ] ;
+";
+        let grms = "%start Expr
+%%
+Expr: Expr '+' Term | Term ;
+Term: Term '*' Factor | Factor ;
+Factor: '(' Expr ')' | 'INT' ;
+";
+        let grm = yacc_grm(YaccKind::Original, grms).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let rule_ids = grm.terms_map()
+                          .iter()
+                          .map(|(&n, &i)| (n, u32::from(i).to_u16().unwrap()))
+                          .collect();
+        let rules = {
+            let mut lexerdef = build_lex::<u16>(lexs).unwrap();
+            let missing = lexerdef.set_rule_ids(&rule_ids).0.unwrap();
+            literal_lex_rules(missing.iter().cloned())
+        };
+        let mut lexerdef = build_lex::<u16>(&format!("{}{}", lexs, rules)).unwrap();
+        assert!(lexerdef.set_rule_ids(&rule_ids).0.is_none());
+        let lexemes = lexerdef.lexer("(1 + 2) * 3").lexemes().unwrap();
+        assert!(RTParserBuilder::new(&grm, &sgraph, &stable).parse(&lexemes).is_ok());
+    }
+}
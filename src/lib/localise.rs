@@ -0,0 +1,214 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashMap;
+
+/// A table mapping terminal names, rule names, and the fixed phrases of error messages (e.g. as
+/// rendered by `github_annotations_with` and `sarif_with`) to another language, for tools which
+/// report parse errors to non-English speakers. Anything without an entry is left as it is, so an
+/// empty table (`Localisation::new()`) leaves messages in English.
+///
+/// Tables are usually loaded with `Localisation::parse` from text of the form:
+/// ```text
+/// # Blank lines and lines starting with '#' are ignored.
+/// term ID = identificador
+/// rule Expr = expresión
+/// phrase Parsing error. = Error de sintaxis.
+/// phrase Did you mean `{}`? = ¿Quiso decir `{}`?
+/// ```
+/// Phrases are keyed by their English text, in which each `{}` stands for a name (or number) that
+/// is substituted into them, in order, and their translations must contain the same number of
+/// `{}`s. The phrases used are: `Parsing error.`, `Suggested repair:`, `No repairs found.`,
+/// `Ignored malformed {}.`, `Did you mean `{}`?`, `Insert`, `Delete`, `Shift`,
+/// `Replace with {}`, and `Replace {} with {}` (the latter when more than one lexeme is
+/// replaced).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Localisation {
+    terms: HashMap<String, String>,
+    rules: HashMap<String, String>,
+    phrases: HashMap<String, String>
+}
+
+impl Localisation {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        Localisation::default()
+    }
+
+    /// Parse a table in the format described above, returning an error describing the first
+    /// malformed line (including a phrase whose translation does not contain the same number of
+    /// `{}`s as the phrase) if there is one.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut loc = Localisation::new();
+        for (i, l) in s.lines().enumerate() {
+            let l = l.trim();
+            if l.is_empty() || l.starts_with('#') {
+                continue;
+            }
+            let (kind, rest) = match l.find(' ') {
+                Some(j) => (&l[..j], l[j + 1..].trim_left()),
+                None => return Err(format!("line {}: expected an entry", i + 1))
+            };
+            let (from, to) = match rest.find(" = ") {
+                Some(j) => (rest[..j].trim_right(), rest[j + 3..].trim_left()),
+                None => return Err(format!("line {}: expected ' = '", i + 1))
+            };
+            let map = match kind {
+                "term" => &mut loc.terms,
+                "rule" => &mut loc.rules,
+                "phrase" if from.matches("{}").count() != to.matches("{}").count() => {
+                    return Err(format!("line {}: translation has {} '{{}}'s but '{}' has {}",
+                                       i + 1,
+                                       to.matches("{}").count(),
+                                       from,
+                                       from.matches("{}").count()));
+                },
+                "phrase" => &mut loc.phrases,
+                _ => return Err(format!("line {}: unknown entry kind '{}'", i + 1, kind))
+            };
+            map.insert(from.to_owned(), to.to_owned());
+        }
+        Ok(loc)
+    }
+
+    /// Set the translation of the terminal name `name` to `text`.
+    pub fn set_term(&mut self, name: &str, text: &str) {
+        self.terms.insert(name.to_owned(), text.to_owned());
+    }
+
+    /// Set the translation of the rule name `name` to `text`.
+    pub fn set_rule(&mut self, name: &str, text: &str) {
+        self.rules.insert(name.to_owned(), text.to_owned());
+    }
+
+    /// Set the translation of the English phrase `phrase` to `text`.
+    ///
+    /// # Panics
+    ///
+    /// If `text` does not contain the same number of `{}`s as `phrase`.
+    pub fn set_phrase(&mut self, phrase: &str, text: &str) {
+        assert_eq!(phrase.matches("{}").count(), text.matches("{}").count());
+        self.phrases.insert(phrase.to_owned(), text.to_owned());
+    }
+
+    /// Return the translation of the terminal name `name`.
+    pub fn term<'a>(&'a self, name: &'a str) -> &'a str {
+        self.terms.get(name).map(|s| s.as_str()).unwrap_or(name)
+    }
+
+    /// Return the translation of the rule name `name`.
+    pub fn rule<'a>(&'a self, name: &'a str) -> &'a str {
+        self.rules.get(name).map(|s| s.as_str()).unwrap_or(name)
+    }
+
+    /// Return the translation of the English phrase `phrase`.
+    pub fn phrase<'a>(&'a self, phrase: &'a str) -> &'a str {
+        self.phrases.get(phrase).map(|s| s.as_str()).unwrap_or(phrase)
+    }
+
+    /// Return the translation of the English phrase `phrase` with its `{}`s replaced by `args`.
+    pub(crate) fn phrase_with(&self, phrase: &str, args: &[&str]) -> String {
+        fill(self.phrase(phrase), args)
+    }
+}
+
+/// Return `phrase` with its `{}`s replaced, in order, by `args`. Any `{}`s beyond the end of
+/// `args` are left as they are.
+pub(crate) fn fill(phrase: &str, args: &[&str]) -> String {
+    let mut s = String::with_capacity(phrase.len());
+    let mut args = args.iter();
+    let mut rest = phrase;
+    while let Some(i) = rest.find("{}") {
+        s.push_str(&rest[..i]);
+        s.push_str(args.next().map_or("{}", |a| *a));
+        rest = &rest[i + 2..];
+    }
+    s.push_str(rest);
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use parser::RecoveryKind;
+    use parser::test::do_parse;
+    use annotations::github_annotations_with;
+    use super::Localisation;
+
+    #[test]
+    fn localise() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+a 'A'
+\\n ;
+";
+        let grms = "%start E
+%%
+E: '(' E ')'
+ | 'A' ;
+";
+
+        let loc = Localisation::parse("# Español
+term ) = paréntesis de cierre
+
+phrase Parsing error. = Error de sintaxis.
+phrase Suggested repair: = Reparación sugerida:
+phrase Insert = Insertar
+phrase Replace {} with {} = Sustituir {} por {}
+").unwrap();
+        assert_eq!(loc.term(")"), "paréntesis de cierre");
+        assert_eq!(loc.term("("), "(");
+        assert_eq!(loc.phrase("Delete"), "Delete");
+
+        let us = "(\n(a)";
+        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, &us);
+        let (_, errs) = pr.unwrap_err();
+        assert_eq!(github_annotations_with(&grm,
+                                           "x.txt",
+                                           &us,
+                                           &errs,
+                                           &loc,
+                                           &|t_idx| grm.term_name(t_idx).unwrap().to_owned()),
+                   "::error file=x.txt,line=2,col=4::Error de sintaxis. Reparación sugerida: \
+                    Insertar \"paréntesis de cierre\"\n");
+
+        assert_eq!(loc.phrase_with("Replace {} with {}", &["2", "\"x\""]),
+                   "Sustituir 2 por \"x\"");
+
+        assert_eq!(Localisation::parse("term )"), Err("line 1: expected ' = '".to_owned()));
+        assert_eq!(Localisation::parse("phrase Replace {} with {} = Sustituir {}"),
+                   Err("line 1: translation has 1 '{}'s but 'Replace {} with {}' has 2"
+                       .to_owned()));
+        assert_eq!(Localisation::parse("\nterms ) = x"),
+                   Err("line 2: unknown entry kind 'terms'".to_owned()));
+    }
+}
@@ -30,6 +30,10 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt::Write;
 use std::hash::{Hash, Hasher};
 use std::mem;
 use std::time::Instant;
@@ -39,30 +43,19 @@ use cfgrammar::{Grammar, Symbol, TIdx};
 use cfgrammar::yacc::YaccGrammar;
 use lrlex::Lexeme;
 use lrtable::{Action, StateGraph, StateTable, StIdx};
-use num_traits::{PrimInt, Unsigned};
 use vob::Vob;
 
-use astar::astar_all;
-use parser::{Node, Parser, ParseRepair, Recoverer};
+use astar::{astar_all, Scratch, SearchLimits};
+use events::TStack;
+use parser::{flatten_repair, Parser, ParseRepair, Recoverer, RecoveryLimits};
+use search::{compatible, ends_with_parse_at_least_shifts, Engine, last_repair, merge, Repair,
+             RepairMerge};
+use tokid::TokenId;
 
-const PARSE_AT_LEAST: usize = 3; // N in Corchuelo et al.
 const TRY_PARSE_AT_MOST: usize = 250;
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-enum Repair {
-    /// Insert a `Symbol::Term` with idx `term_idx`.
-    InsertTerm(TIdx),
-    /// Delete a symbol.
-    Delete,
-    /// Shift a symbol.
-    Shift
-}
-
-#[derive(Clone, Debug, Eq, PartialEq)]
-enum RepairMerge {
-    Repair(Repair),
-    Merge(Repair, Cactus<Cactus<RepairMerge>>),
-    Terminator
+thread_local! {
+    static SCRATCH: RefCell<Scratch<PathFNode, (u32, u32, PathFNode)>> = RefCell::default();
 }
 
 #[derive(Clone, Debug, Eq)]
@@ -76,11 +69,7 @@ struct PathFNode {
 
 impl PathFNode {
     fn last_repair(&self) -> Option<Repair> {
-        match self.repairs.val().unwrap() {
-            &RepairMerge::Repair(r) => Some(r),
-            &RepairMerge::Merge(x, _) => Some(x),
-            &RepairMerge::Terminator => None
-        }
+        last_repair(&self.repairs)
     }
 }
 
@@ -93,168 +82,209 @@ impl Hash for PathFNode {
 
 impl PartialEq for PathFNode {
     fn eq(&self, other: &PathFNode) -> bool {
-        if self.la_idx != other.la_idx || self.pstack != other.pstack {
-            return false;
-        }
-        // The rest of this function is subtle: we're not looking for repair sequences which are
-        // exactly equivalent, but ones that are compatible. This is necessary so that we can merge
-        // compatible nodes. Our definition of compatible repair sequences is: they must end with
-        // exactly the same number of shifts (ending with zero shifts is fine); and if one repair
-        // sequence ends in a delete, the other must do so as well.
-
-        match (self.last_repair(), other.last_repair()) {
-            (Some(Repair::Delete), Some(Repair::Delete)) => (),
-            (Some(Repair::Delete), _) | (_, Some(Repair::Delete)) => return false,
-            (_, _) => ()
-        }
-
-        let num_shifts = |c: &Cactus<RepairMerge>| {
-            let mut n = 0;
-            for r in c.vals() {
-                match r {
-                      &RepairMerge::Repair(Repair::Shift)
-                    | &RepairMerge::Merge(Repair::Shift, _) => n += 1,
-                    _ => break
-                }
-            }
-            n
-        };
-        let self_shifts = num_shifts(&self.repairs);
-        let other_shifts = num_shifts(&other.repairs);
-        self_shifts == other_shifts
+        self.la_idx == other.la_idx
+            && self.pstack == other.pstack
+            && compatible(&self.repairs, &other.repairs)
     }
 }
 
-struct MF<'a, TokId: PrimInt + Unsigned> where TokId: 'a {
+struct MF<'a, TokId: TokenId> where TokId: 'a {
     dist: Dist,
-    parser: &'a Parser<'a, TokId>
+    parser: &'a Parser<'a, TokId>,
+    engine: Engine<'a, TokId>,
+    // If not `None`, the search graph is recorded here.
+    graph: Option<RefCell<SearchGraph>>
 }
 
-pub(crate) fn recoverer<'a, TokId: PrimInt + Unsigned>
+pub(crate) fn recoverer<'a, TokId: TokenId>
                        (parser: &'a Parser<TokId>)
                      -> Box<Recoverer<TokId> + 'a>
 {
     let dist = Dist::new(parser.grm, parser.sgraph, parser.stable, parser.term_cost);
-    Box::new(MF{dist, parser: parser})
+    Box::new(MF{dist, parser, engine: Engine::new(parser), graph: None})
+}
+
+/// Run the MF recoverer on the error at `la_idx` with the parse stack `pstack` and tree stack
+/// `tstack`, returning the graph it searched in GraphViz's DOT format.
+pub(crate) fn search_graph<TokId: TokenId>(parser: &Parser<TokId>,
+                                          finish_by: Instant,
+                                          la_idx: usize,
+                                          pstack: &mut Vec<StIdx>,
+                                          tstack: &mut TStack<TokId>)
+                                       -> String
+{
+    let dist = Dist::new(parser.grm, parser.sgraph, parser.stable, parser.term_cost);
+    let mf = MF{dist,
+                parser,
+                engine: Engine::new(parser),
+                graph: Some(RefCell::new(SearchGraph::default()))};
+    mf.recover(finish_by, parser, la_idx, pstack, tstack);
+    mf.graph.unwrap().into_inner().to_dot(parser.grm)
+}
+
+/// The nodes explored, and the edges between them, in one run of the MF search. Nodes which the
+/// search considers to be the same (and thus merges) are recorded as a single node.
+#[derive(Default)]
+struct SearchGraph {
+    ids: HashMap<PathFNode, usize>,
+    // For each node: (top of the parse stack, lexeme index, cf, cg, number of paths merged into
+    // it, is it a success node).
+    nodes: Vec<(StIdx, usize, u32, u32, usize, bool)>,
+    // (from node, to node, the repair made, or `None` for a reduction).
+    edges: Vec<(usize, usize, Option<Repair>)>
+}
+
+impl SearchGraph {
+    fn id(&mut self, n: &PathFNode) -> usize {
+        if let Some(&id) = self.ids.get(n) {
+            return id;
+        }
+        let id = self.nodes.len();
+        self.ids.insert(n.clone(), id);
+        self.nodes.push((*n.pstack.val().unwrap(), n.la_idx, n.cf, n.cg, 0, false));
+        id
+    }
+
+    /// Record that `n` has the neighbours `nbrs`.
+    fn expanded(&mut self, n: &PathFNode, nbrs: &[(u32, u32, PathFNode)]) {
+        let n_id = self.id(n);
+        for &(_, _, ref nbr) in nbrs {
+            let nbr_id = self.id(nbr);
+            let r = if nbr.repairs == n.repairs { None } else { nbr.last_repair() };
+            if !self.edges.contains(&(n_id, nbr_id, r)) {
+                self.edges.push((n_id, nbr_id, r));
+            }
+        }
+    }
+
+    fn merged(&mut self, n: &PathFNode) {
+        let id = self.id(n);
+        self.nodes[id].4 += 1;
+    }
+
+    fn succeeded(&mut self, n: &PathFNode) {
+        let id = self.id(n);
+        self.nodes[id].5 = true;
+    }
+
+    fn to_dot(&self, grm: &YaccGrammar) -> String {
+        let mut s = String::new();
+        s.push_str("digraph recovery {\n");
+        for (i, &(st_idx, la_idx, cf, cg, merges, success)) in self.nodes.iter().enumerate() {
+            let mut label = format!("state {}, lexeme {}\\ncf={} cg={}",
+                                    usize::from(st_idx), la_idx, cf, cg);
+            if merges > 0 {
+                write!(label, "\\n{} merged", merges).unwrap();
+            }
+            writeln!(s, "  n{} [label=\"{}\"{}];",
+                     i,
+                     label,
+                     if success { ", peripheries=2" } else { "" }).unwrap();
+        }
+        for &(from, to, r) in &self.edges {
+            let label = match r {
+                Some(Repair::InsertTerm(t_idx)) => {
+                    let n = grm.term_name(t_idx).unwrap();
+                    format!("Insert \\\"{}\\\"", n.replace('\\', "\\\\").replace('"', "\\\""))
+                },
+                Some(Repair::Delete) => "Delete".to_owned(),
+                Some(Repair::Shift) => "Shift".to_owned(),
+                None => "Reduce".to_owned()
+            };
+            writeln!(s, "  n{} -> n{} [label=\"{}\"];", from, to, label).unwrap();
+        }
+        s.push_str("}\n");
+        s
+    }
 }
 
-impl<'a, TokId: PrimInt + Unsigned> Recoverer<TokId> for MF<'a, TokId>
+impl<'a, TokId: TokenId> Recoverer<TokId> for MF<'a, TokId>
 {
     fn recover(&self,
                finish_by: Instant,
                parser: &Parser<TokId>,
                in_la_idx: usize,
-               mut in_pstack: &mut Vec<StIdx>,
-               mut tstack: &mut Vec<Node<TokId>>)
-           -> (usize, Vec<Vec<ParseRepair>>)
+               in_pstack: &mut Vec<StIdx>,
+               tstack: &mut TStack<TokId>)
+           -> (usize, Vec<Vec<ParseRepair>>, RecoveryLimits)
     {
-        let mut start_cactus_pstack = Cactus::new();
-        for st in in_pstack.iter() {
-            start_cactus_pstack = start_cactus_pstack.child(*st);
-        }
-
-        let start_node = PathFNode{pstack: start_cactus_pstack.clone(),
+        let (start_pstack, start_repairs) = self.engine.start(in_pstack);
+        // Seeds are success nodes, so their heuristic is 0.
+        let seeds = self.engine
+                        .seeds(in_la_idx, in_pstack, &start_pstack, &start_repairs)
+                        .into_iter()
+                        .map(|(pstack, la_idx, repairs, cf)| {
+                            (cf, PathFNode{pstack, la_idx, repairs, cf, cg: 0})
+                        })
+                        .collect();
+        let start_node = PathFNode{pstack: start_pstack,
                                    la_idx: in_la_idx,
-                                   repairs: Cactus::new().child(RepairMerge::Terminator),
+                                   repairs: start_repairs,
                                    cf: 0,
                                    cg: 0};
-
-        let astar_cnds = astar_all(
+        let cancelled = || (parser.recovery.clock)() >= finish_by;
+        let limits = SearchLimits{max_memory: parser.recovery.max_recovery_memory,
+                                  max_expansions: parser.recovery.max_recovery_expansions,
+                                  max_frontier_growth: None,
+                                  cancelled: &cancelled};
+        let (astar_cnds, limit) = astar_all(
             start_node,
+            seeds,
             |explore_all, n, nbrs| {
                 // Calculate n's neighbours.
 
-                if Instant::now() >= finish_by {
-                    return false;
-                }
+                let nbrs_start = nbrs.len();
 
+                let can_edit = self.engine.can_edit(&n.repairs);
                 match n.last_repair() {
                     Some(Repair::Delete) => {
                         // We follow Corcheulo et al.'s suggestions and never follow Deletes with
                         // Inserts.
                     },
                     _ => {
-                        if explore_all || n.cg > 0 {
+                        if can_edit && (explore_all || n.cg > 0) {
                             self.insert(n, nbrs);
                         }
                         self.reduce(n, nbrs);
                     }
                 }
-                if explore_all || n.cg > 0 {
+                if can_edit && (explore_all || n.cg > 0) {
                     self.delete(n, nbrs);
                 }
                 self.shift(n, nbrs);
-                true
+                if let Some(ref g) = self.graph {
+                    g.borrow_mut().expanded(n, &nbrs[nbrs_start..]);
+                }
             },
             |old, new| {
-                // merge new_n into old_n
-
-                if old.repairs == new.repairs {
-                    // If the repair sequences are identical, then merging is pointless.
-                    return;
+                if let Some(ref g) = self.graph {
+                    g.borrow_mut().merged(old);
                 }
-                let merge = match old.repairs.val().unwrap() {
-                    &RepairMerge::Repair(r) => {
-                        RepairMerge::Merge(r, Cactus::new().child(new.repairs))
-                    },
-                    &RepairMerge::Merge(r, ref v) => {
-                        RepairMerge::Merge(r, v.child(new.repairs))
-                    },
-                    _ => unreachable!()
-                };
-                old.repairs = old.repairs.parent().unwrap().child(merge);
+                merge(&mut old.repairs, new.repairs);
             },
-            |n| {
-                // Is n a success node?
-
-                // As presented in both Corchuelo et al. and Kim Yi, one type of success is if N
-                // symbols are parsed in one go. Indeed, without such a check, the search space
-                // quickly becomes too big. There isn't a way of encoding this check in r3s_n, so
-                // we check instead for its result: if the last N ('PARSE_AT_LEAST' in this
-                // library) repairs are shifts, then we've found a success node.
-                if ends_with_parse_at_least_shifts(&n.repairs) {
-                    return true;
-                }
-
-                match parser.stable.action(*n.pstack.val().unwrap(),
-                                           parser.next_tidx(n.la_idx)) {
-                    Some(Action::Accept) => true,
-                    _ => false,
-                }
-            });
-
-        if astar_cnds.is_empty() {
-            return (in_la_idx, vec![]);
-        }
+            |n| self.engine.success(&n.pstack, n.la_idx, &n.repairs),
+            &limits,
+            &SCRATCH);
 
-        let full_rprs = self.collect_repairs(astar_cnds);
-        let mut rnk_rprs = rank_cnds(parser,
-                                     finish_by,
-                                     in_la_idx,
-                                     &in_pstack,
-                                     full_rprs);
-        if rnk_rprs.is_empty() {
-            return (in_la_idx, vec![]);
+        if let Some(ref g) = self.graph {
+            for n in &astar_cnds {
+                g.borrow_mut().succeeded(n);
+            }
         }
-        simplify_repairs(&mut rnk_rprs);
-        let la_idx = apply_repairs(parser,
-                                   in_la_idx,
-                                   &mut in_pstack,
-                                   &mut Some(&mut tstack),
-                                   &rnk_rprs[0]);
-
-        (la_idx, rnk_rprs)
+        let cnds = astar_cnds.into_iter().map(|n| n.repairs).collect();
+        self.engine.finish("MF", finish_by, in_la_idx, in_pstack, tstack, cnds, limit)
     }
 }
 
-impl<'a, TokId: PrimInt + Unsigned> MF<'a, TokId> {
+impl<'a, TokId: TokenId> MF<'a, TokId> {
     fn insert(&self,
               n: &PathFNode,
               nbrs: &mut Vec<(u32, u32, PathFNode)>)
     {
         let top_pstack = *n.pstack.val().unwrap();
         for t_idx in self.parser.stable.state_shifts(top_pstack) {
-            if t_idx == self.parser.grm.eof_term_idx() {
+            if t_idx == self.parser.grm.eof_term_idx()
+               || self.parser.redundant_insert(top_pstack, t_idx) {
                 continue;
             }
 
@@ -262,14 +292,19 @@ impl<'a, TokId: PrimInt + Unsigned> MF<'a, TokId> {
                 Action::Shift(s_idx) => s_idx,
                 _ => unreachable!()
             };
+            let cf = match self.engine.add_cost(n.cf, t_idx) {
+                Some(cf) => cf,
+                None => continue
+            };
             let n_repairs = n.repairs.child(RepairMerge::Repair(Repair::InsertTerm(t_idx)));
             if let Some(d) = self.dyn_dist(&n_repairs, t_st_idx, n.la_idx) {
-                assert!(n.cg == 0 || d >= n.cg - (self.parser.term_cost)(t_idx) as u32);
+                assert!(n.cg == 0
+                        || d >= n.cg.saturating_sub((self.parser.term_cost)(t_idx) as u32));
                 let nn = PathFNode{
                     pstack: n.pstack.child(t_st_idx),
                     la_idx: n.la_idx,
                     repairs: n_repairs,
-                    cf: n.cf.checked_add((self.parser.term_cost)(t_idx) as u32).unwrap(),
+                    cf,
                     cg: d};
                 nbrs.push((nn.cf, nn.cg, nn));
             }
@@ -337,14 +372,16 @@ impl<'a, TokId: PrimInt + Unsigned> MF<'a, TokId> {
             return;
         }
 
+        let cf = match self.engine.add_cost(n.cf, self.parser.next_tidx(n.la_idx)) {
+            Some(cf) => cf,
+            None => return
+        };
         let n_repairs = n.repairs.child(RepairMerge::Repair(Repair::Delete));
         if let Some(d) = self.dyn_dist(&n_repairs, *n.pstack.val().unwrap(), n.la_idx + 1) {
-            let la_tidx = self.parser.next_tidx(n.la_idx);
-            let cost = (self.parser.term_cost)(la_tidx);
             let nn = PathFNode{pstack: n.pstack.clone(),
                                la_idx: n.la_idx + 1,
                                repairs: n_repairs,
-                               cf: n.cf.checked_add(cost as u32).unwrap(),
+                               cf,
                                cg: d};
             nbrs.push((nn.cf, nn.cg, nn));
         }
@@ -356,8 +393,7 @@ impl<'a, TokId: PrimInt + Unsigned> MF<'a, TokId> {
     {
         let la_tidx = self.parser.next_tidx(n.la_idx);
         let top_pstack = *n.pstack.val().unwrap();
-        if let Some(Action::Shift(state_id)) = self.parser.stable.action(top_pstack,
-                                                                         la_tidx) {
+        if let (Some(Action::Shift(state_id)), _) = self.parser.action(top_pstack, la_tidx) {
             let n_repairs = n.repairs.child(RepairMerge::Repair(Repair::Shift));
             let new_la_idx = n.la_idx + 1;
             if let Some(d) = self.dyn_dist(&n_repairs, state_id, new_la_idx) {
@@ -372,68 +408,6 @@ impl<'a, TokId: PrimInt + Unsigned> MF<'a, TokId> {
         }
     }
 
-    /// Convert the output from `astar_all` into something more usable.
-    fn collect_repairs(&self, cnds: Vec<PathFNode>) -> Vec<Vec<Vec<ParseRepair>>>
-    {
-        fn traverse(rm: &Cactus<RepairMerge>) -> Vec<Vec<Repair>> {
-            let mut out = Vec::new();
-            match rm.val().unwrap() {
-                &RepairMerge::Repair(r) => {
-                    let parents = traverse(&rm.parent().unwrap());
-                    if parents.is_empty() {
-                        out.push(vec![r]);
-                    } else {
-                        for mut pc in parents {
-                            pc.push(r);
-                            out.push(pc);
-                        }
-                    }
-                },
-                &RepairMerge::Merge(r, ref vc) => {
-                    let parents = traverse(&rm.parent().unwrap());
-                    if parents.is_empty() {
-                        out.push(vec![r]);
-                    } else {
-                        for mut pc in parents {
-                            pc.push(r);
-                            out.push(pc);
-                        }
-                    }
-                    for c in vc.vals() {
-                        for mut pc in traverse(c) {
-                            out.push(pc);
-                        }
-                    }
-                }
-                &RepairMerge::Terminator => ()
-            }
-            out
-        }
-
-        let mut all_rprs = Vec::with_capacity(cnds.len());
-        for cnd in cnds {
-            all_rprs.push(traverse(&cnd.repairs).into_iter()
-                                                .map(|x| self.repair_to_parse_repair(x))
-                                                .collect::<Vec<_>>());
-        }
-        all_rprs
-    }
-
-    fn repair_to_parse_repair(&self,
-                              from: Vec<Repair>)
-                           -> Vec<ParseRepair> {
-        from.iter()
-            .map(|y| {
-                 match *y {
-                     Repair::InsertTerm(term_idx) =>
-                         ParseRepair::Insert(term_idx),
-                     Repair::Delete => ParseRepair::Delete,
-                     Repair::Shift => ParseRepair::Shift,
-                 }
-             })
-            .collect()
-    }
-
     /// Return the distance from `st_idx` at input position `la_idx`, given the current `repairs`.
     /// Returns `None` if no route can be found.
     fn dyn_dist(&self,
@@ -474,11 +448,15 @@ impl<'a, TokId: PrimInt + Unsigned> MF<'a, TokId> {
         let mut dc = 0; // Cumulative deletion cost
         for i in la_idx..self.parser.lexemes.len() + 1 {
             let t_idx = self.parser.next_tidx(i);
-            let d = self.dist.dist(st_idx, t_idx);
-            if d < u32::max_value() && dc + d < ld {
+            let d = match self.parser.contextual_ident(t_idx) {
+                Some(ident) => cmp::min(self.dist.dist(st_idx, t_idx),
+                                        self.dist.dist(st_idx, ident)),
+                None => self.dist.dist(st_idx, t_idx)
+            };
+            if d < u32::max_value() && dc.saturating_add(d) < ld {
                 ld = dc + d;
             }
-            dc = dc + (self.parser.term_cost)(t_idx) as u32;
+            dc = dc.saturating_add((self.parser.term_cost)(t_idx) as u32);
             if dc >= ld {
                 // Once the cumulative cost of deleting lexemes is bigger than the current least
                 // distance, there is no chance of finding a subsequent lexeme which could produce
@@ -494,36 +472,28 @@ impl<'a, TokId: PrimInt + Unsigned> MF<'a, TokId> {
     }
 }
 
-/// Do `repairs` end with enough Shift repairs to be considered a success node?
-fn ends_with_parse_at_least_shifts(repairs: &Cactus<RepairMerge>) -> bool {
-    let mut shfts = 0;
-    for x in repairs.vals().take(PARSE_AT_LEAST) {
-        match x {
-            &RepairMerge::Repair(Repair::Shift) => shfts += 1,
-            &RepairMerge::Merge(Repair::Shift, _) => shfts += 1,
-            _ => return false
-        }
-    }
-    shfts == PARSE_AT_LEAST
-}
-
-/// Convert `PathFNode` candidates in `cnds` into vectors of `ParseRepairs`s and rank them (from
-/// highest to lowest) by the distance they allow parsing to continue without error. If two or more
-/// `ParseRepair`s allow the same distance of parsing, then the `ParseRepair` which requires
-/// repairs over the shortest distance is preferred. Amongst `ParseRepair`s of the same rank, the
-/// ordering is non-deterministic.
-pub(crate) fn rank_cnds<TokId: PrimInt + Unsigned>
-                       (parser: &Parser<TokId>,
-                        finish_by: Instant,
-                        in_la_idx: usize,
-                        in_pstack: &Vec<StIdx>,
-                        in_cnds: Vec<Vec<Vec<ParseRepair>>>)
-                     -> Vec<Vec<ParseRepair>>
+/// Rank the candidate repair sequences `in_cnds` for an error at `in_la_idx` with parse stack
+/// `in_pstack`, returning only those which allow parsing to continue furthest without error.
+/// Each element of `in_cnds` is a group of repair sequences which a recoverer has merged as being
+/// equivalent: only the first sequence of each group is tried, and either the whole group is
+/// returned or none of it is. Amongst sequences of the same rank, the ordering is
+/// non-deterministic: callers will normally want to pass the result to `simplify_repairs`. If
+/// `finish_by` is reached before ranking has completed, an empty vector is returned.
+///
+/// This, `apply_repairs`, and `simplify_repairs` are the post-processing steps used by lrpar's
+/// own recoverers, and are public so that other recoverers can reuse them.
+pub fn rank_cnds<TokId: TokenId>
+                (parser: &Parser<TokId>,
+                 finish_by: Instant,
+                 in_la_idx: usize,
+                 in_pstack: &Vec<StIdx>,
+                 in_cnds: Vec<Vec<Vec<ParseRepair>>>)
+              -> Vec<Vec<ParseRepair>>
 {
     let mut cnds = Vec::new();
     let mut furthest = 0;
     for rpr_seqs in in_cnds.into_iter() {
-        if Instant::now() >= finish_by {
+        if (parser.recovery.clock)() >= finish_by {
             return vec![];
         }
         let mut pstack = in_pstack.clone();
@@ -551,15 +521,17 @@ pub(crate) fn rank_cnds<TokId: PrimInt + Unsigned>
         .collect::<Vec<Vec<ParseRepair>>>()
 }
 
-/// Apply the `repairs` to `pstack` starting at position `la_idx`: return the resulting parse
-/// distance and a new pstack.
-pub(crate) fn apply_repairs<TokId: PrimInt + Unsigned>
-                           (parser: &Parser<TokId>,
-                            mut la_idx: usize,
-                            mut pstack: &mut Vec<StIdx>,
-                            mut tstack: &mut Option<&mut Vec<Node<TokId>>>,
-                            repairs: &[ParseRepair])
-                         -> usize
+/// Apply the `repairs` to `pstack` (and, if it is not `None`, `tstack`) starting at position
+/// `la_idx`, returning the resulting lexeme index. `repairs` must not contain
+/// `ParseRepair::InsertSeq`s (though they may contain `ParseRepair::Substitute`s and
+/// `ParseRepair::Shifts`).
+pub fn apply_repairs<TokId: TokenId>
+                    (parser: &Parser<TokId>,
+                     mut la_idx: usize,
+                     mut pstack: &mut Vec<StIdx>,
+                     mut tstack: &mut Option<&mut TStack<TokId>>,
+                     repairs: &[ParseRepair])
+                  -> usize
 {
     for r in repairs.iter() {
         match *r {
@@ -577,6 +549,9 @@ pub(crate) fn apply_repairs<TokId: PrimInt + Unsigned>
             ParseRepair::Delete => {
                 la_idx += 1;
             }
+            ParseRepair::Substitute{..} | ParseRepair::Shifts(_) => {
+                la_idx = apply_repairs(parser, la_idx, pstack, tstack, &flatten_repair(r));
+            }
             ParseRepair::Shift => {
                 la_idx = parser.lr_upto(None,
                                         la_idx,
@@ -589,8 +564,9 @@ pub(crate) fn apply_repairs<TokId: PrimInt + Unsigned>
     la_idx
 }
 
-/// Simplifies repair sequences, removes duplicates, and sorts them into order.
-pub(crate) fn simplify_repairs(all_rprs: &mut Vec<Vec<ParseRepair>>)
+/// Simplifies repair sequences (removing trailing `Shift`s, which are implied), removes duplicates,
+/// and sorts them into order (shortest first, with ties broken by comparing the sequences).
+pub fn simplify_repairs(all_rprs: &mut Vec<Vec<ParseRepair>>)
 {
     for i in 0..all_rprs.len() {
         // Remove shifts from the end of repairs
@@ -770,16 +746,17 @@ mod test {
     use test::{Bencher, black_box};
 
     use cactus::Cactus;
-    use cfgrammar::Symbol;
+    use cfgrammar::{Symbol, TIdx};
     use cfgrammar::yacc::{yacc_grm, YaccGrammar, YaccKind};
     use lrlex::Lexeme;
     use lrtable::{Minimiser, from_yacc, StIdx};
     use num_traits::ToPrimitive;
 
-    use parser::{ParseRepair, RecoveryKind};
-    use parser::test::{do_parse, do_parse_with_costs};
+    use parser::{Node, ParseRepair, RecoveryKind, RTParserBuilder};
+    use parser::test::{build_parse_env, do_parse, do_parse_with_costs};
+    use search::PARSE_AT_LEAST;
 
-    use super::{ends_with_parse_at_least_shifts, Dist, PARSE_AT_LEAST, Repair, RepairMerge};
+    use super::{ends_with_parse_at_least_shifts, Dist, Repair, RepairMerge};
 
     fn pp_repairs(grm: &YaccGrammar, repairs: &Vec<ParseRepair>) -> String {
         let mut out = vec![];
@@ -807,7 +784,12 @@ mod test {
                 ParseRepair::Delete =>
                     out.push(format!("Delete")),
                 ParseRepair::Shift =>
-                    out.push(format!("Shift"))
+                    out.push(format!("Shift")),
+                ParseRepair::Substitute{delete_span, insert_term} =>
+                    out.push(format!("Substitute {} \"{}\"", delete_span,
+                                     grm.term_name(insert_term).unwrap())),
+                ParseRepair::Shifts(n) =>
+                    out.push(format!("Shift {}", n))
             }
         }
         out.join(", ")
@@ -1195,6 +1177,36 @@ E: '(' E ')'
         (lexs, grms)
     }
 
+    #[test]
+    fn search_priority_ignored() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+\\+ '+'
+n 'N'
+";
+        let grms = "%start E
+%%
+E : 'N'
+  | E '+' 'N'
+  | '(' E ')'
+  ;
+";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "(nn");
+        // A priority which explores the most expensive repair sequences first would make CPCT+
+        // find non-minimal repairs, but MF doesn't use it.
+        let reversed = |c: u32, _: StIdx, _: usize| 100u32.saturating_sub(c);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::MF);
+        let pr = rtpb.parse(&lexemes);
+        assert_eq!(rtpb.search_priority(&reversed).parse(&lexemes), pr);
+        let (_, errs) = pr.unwrap_err();
+        check_all_repairs(&grm,
+                          errs[0].repairs(),
+                          &vec!["Insert \")\", Insert \"+\"",
+                                "Insert \")\", Delete",
+                                "Insert \"+\", Shift, Insert \")\""]);
+    }
+
     #[test]
     fn kimyi_example() {
         let (lexs, grms) = kimyi_lex_grm();
@@ -1228,12 +1240,162 @@ E: '(' E ')'
         assert_eq!(errs.len(), 1);
         let err_tok_id = u32::from(grm.eof_term_idx()).to_u16().unwrap();
         assert_eq!(errs[0].lexeme(), &Lexeme::new(err_tok_id, 2, 0));
+        assert!(!errs[0].repair_len_capped());
+        check_all_repairs(&grm,
+                          errs[0].repairs(),
+                          &vec!["Insert \"A\", Insert \")\", Insert \")\"",
+                                "Insert \"B\", Insert \")\", Insert \")\""]);
+    }
+
+    #[test]
+    fn max_repair_len() {
+        let (lexs, grms) = kimyi_lex_grm();
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "((");
+        let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::MF)
+                                                             .max_repair_len(2)
+                                                             .parse(&lexemes);
+        let (pt, errs) = pr.unwrap_err();
+        assert!(pt.is_none());
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].repairs().is_empty());
+        assert!(errs[0].repair_len_capped());
+
+        let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::MF)
+                                                             .max_repair_len(3)
+                                                             .parse(&lexemes);
+        let (_, errs) = pr.unwrap_err();
+        check_all_repairs(&grm,
+                          errs[0].repairs(),
+                          &vec!["Insert \"A\", Insert \")\", Insert \")\"",
+                                "Insert \"B\", Insert \")\", Insert \")\""]);
+    }
+
+    #[test]
+    fn max_repair_cost() {
+        let (lexs, grms) = kimyi_lex_grm();
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "((");
+        let costs = |_: TIdx| 200;
+        let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::MF)
+                                                             .term_costs(&costs)
+                                                             .max_repair_cost(599)
+                                                             .parse(&lexemes);
+        let (_, errs) = pr.unwrap_err();
+        assert!(errs[0].repairs().is_empty());
+        assert!(errs[0].cost_ceiling_reached());
+
+        let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::MF)
+                                                             .term_costs(&costs)
+                                                             .max_repair_cost(600)
+                                                             .parse(&lexemes);
+        let (_, errs) = pr.unwrap_err();
         check_all_repairs(&grm,
                           errs[0].repairs(),
                           &vec!["Insert \"A\", Insert \")\", Insert \")\"",
                                 "Insert \"B\", Insert \")\", Insert \")\""]);
     }
 
+    #[test]
+    fn max_recovery_memory() {
+        let (lexs, grms) = kimyi_lex_grm();
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "((");
+        for &kind in &[RecoveryKind::CPCTPlus, RecoveryKind::MF] {
+            let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(kind)
+                                                                 .max_recovery_memory(1)
+                                                                 .parse(&lexemes);
+            let (_, errs) = pr.unwrap_err();
+            assert!(errs[0].repairs().is_empty());
+            assert!(errs[0].memory_limit_reached());
+
+            let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(kind)
+                                                                 .max_recovery_memory(1 << 30)
+                                                                 .parse(&lexemes);
+            let (_, errs) = pr.unwrap_err();
+            assert!(!errs[0].memory_limit_reached());
+            check_all_repairs(&grm,
+                              errs[0].repairs(),
+                              &vec!["Insert \"A\", Insert \")\", Insert \")\"",
+                                    "Insert \"B\", Insert \")\", Insert \")\""]);
+        }
+    }
+
+    #[test]
+    fn max_recovery_expansions() {
+        let (lexs, grms) = kimyi_lex_grm();
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "((");
+        for &kind in &[RecoveryKind::CPCTPlus, RecoveryKind::MF] {
+            let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(kind)
+                                                                 .max_recovery_expansions(1)
+                                                                 .parse(&lexemes);
+            let (_, errs) = pr.unwrap_err();
+            assert!(errs[0].repairs().is_empty());
+            assert!(errs[0].expansion_limit_reached());
+            assert!(!errs[0].memory_limit_reached());
+
+            let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(kind)
+                                                                 .max_recovery_expansions(1 << 20)
+                                                                 .parse(&lexemes);
+            let (_, errs) = pr.unwrap_err();
+            assert!(!errs[0].expansion_limit_reached());
+            check_all_repairs(&grm,
+                              errs[0].repairs(),
+                              &vec!["Insert \"A\", Insert \")\", Insert \")\"",
+                                    "Insert \"B\", Insert \")\", Insert \")\""]);
+        }
+    }
+
+    #[test]
+    fn contextual_keywords() {
+        let lexs = "%%
+async 'ASYNC'
+[a-z]+ 'ID'
+; ';'
+[ ] ;
+";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt
+     | ;
+
+Stmt: 'ASYNC' 'ID' ';'
+    | 'ASYNC' ';'
+    | 'ID' ';' ;
+";
+
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "async async;");
+        let async_tidx = grm.term_idx("ASYNC").unwrap();
+        let id_tidx = grm.term_idx("ID").unwrap();
+        let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::MF)
+                                                             .parse(&lexemes);
+        assert!(pr.is_err());
+        let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::MF)
+                                                             .contextual(async_tidx, id_tidx)
+                                                             .parse(&lexemes);
+        let pt = pr.unwrap();
+        let mut tids = Vec::new();
+        let mut st = vec![&pt];
+        while let Some(n) = st.pop() {
+            match *n {
+                Node::Term{lexeme} => tids.push(lexeme.tok_id()),
+                Node::Nonterm{ref nodes, ..} => st.extend(nodes.iter().rev())
+            }
+        }
+        assert_eq!(tids,
+                   vec![u32::from(async_tidx) as u16, u32::from(id_tidx) as u16,
+                        u32::from(grm.term_idx(";").unwrap()) as u16]);
+
+        // Inserting `async` where an identifier would do is redundant, so it should never be
+        // suggested as a repair.
+        let (_, _, _, lexemes) = build_parse_env(&lexs, &grms, ";");
+        let pr = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::MF)
+                                                             .contextual(async_tidx, id_tidx)
+                                                             .parse(&lexemes);
+        let (_, errs) = pr.unwrap_err();
+        check_some_repairs(&grm, errs[0].repairs(), &vec!["Delete"]);
+        assert!(errs[0].repairs()
+                       .iter()
+                       .all(|r| !pp_repairs(&grm, r).contains("Insert \"ASYNC\"")));
+    }
+
     #[test]
     fn expr_grammar() {
         let lexs = "%%
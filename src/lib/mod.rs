@@ -35,24 +35,105 @@
 
 extern crate cactus;
 extern crate cfgrammar;
-#[macro_use] extern crate indexmap;
+extern crate indexmap;
+extern crate lazy_static;
 extern crate lrlex;
 extern crate lrtable;
 extern crate num_traits;
+#[cfg(feature="log")]
+#[macro_use] extern crate log;
+#[cfg(feature="rayon")]
+extern crate rayon;
 extern crate rmp_serde as rmps;
 extern crate serde;
 extern crate test;
 extern crate typename;
 extern crate vob;
 
+/// Log a message at the debug level via the `log` crate if the `log` feature is enabled, or do
+/// nothing (without evaluating the arguments) otherwise.
+#[cfg(feature="log")]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { debug!($($arg)*) };
+}
+
+#[cfg(not(feature="log"))]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { () };
+}
+
+mod analysis;
+pub use analysis::GrammarInfo;
+mod annotations;
+pub use annotations::{github_annotations, github_annotations_with, sarif, sarif_with};
 mod astar;
 mod builder;
+mod cache;
+pub use cache::RepairCache;
+mod conflicts;
+pub use conflicts::{conflict_examples, ConflictExample};
 mod cpctplus;
+mod events;
+pub use events::{ParseEvent, TreeBuilder, TStack};
+mod export;
+pub use export::{tables_json, TABLES_JSON_VERSION};
+mod filter;
+mod gen;
+mod hashcons;
+mod index;
+mod island;
+mod lint;
+mod literals;
+mod localise;
+pub use localise::Localisation;
+mod panic;
+#[cfg(feature="rayon")]
+mod parallel;
 pub mod parser;
-pub use parser::{Node, parse_rcvry, ParseError, ParseRepair, RecoveryKind};
+mod payload;
+mod pratt;
+pub use pratt::Assoc;
+mod race;
+mod railroad;
+mod reduce;
+mod reload;
+pub use parser::{AppliedRecovery, Completions, DEFAULT_MAX_REPAIR_COST, EmptyInput, InputStatus,
+                 lexeme_str, Missing, Node, parse_rcvry, ParseError, ParseRepair, Parser,
+                 ParseState, PPOptions, RecoveryDecision, RecoveryError, RecoveryKind,
+                 RepairedSlice, RepairStep, RTParserBuilder, ShiftReporting};
+/// The cactus stack type used by `Parser::lr_cactus`.
+pub use cactus::Cactus;
 mod mf;
+pub use mf::{apply_repairs, rank_cnds, simplify_repairs};
+mod nodeid;
+pub use nodeid::NodeId;
+mod normalise;
+pub use normalise::{normalise_input, NormalisedInput, NormaliseOptions};
+mod outline;
+pub use outline::{FoldedRun, outline_children, OutlineChild};
+mod search;
+mod snapshot;
+pub use snapshot::{NodeSnapshot, RepairsSnapshot, SNAPSHOT_VERSION};
+mod source;
+pub use source::{lexeme_text, Source};
+mod tokid;
+pub use tokid::{Channel, TokenId};
+pub use hashcons::{NodeInterner, SharedNode};
+pub use index::OffsetIndex;
+pub use island::{IslandResult, parse_islands};
+pub use lint::{lint, Lint, LintKind, prod_location};
+pub use literals::{is_literal, literal_lex_rules};
+pub use filter::{collapse_regions, filter_lexemes, strip_recovery_pragmas};
+pub use payload::Payloads;
+pub use railroad::railroad_html;
+pub use reduce::{reduce_failure, reduce_lexemes};
+pub use reload::{GrammarWatcher, Tables};
+#[cfg(feature="rayon")]
+pub use parallel::parse_many;
+pub use gen::{SentenceGen, sentence_lexemes};
 
-pub use builder::{process_file, process_file_in_src, reconstitute};
+pub use builder::{ast_child_matches, ast_lexeme, automaton_summary, process_file, process_file_as,
+                  process_file_in_src, process_file_in_src_as, reconstitute, tables_from_ast};
 
 /// A convenience macro for including statically compiled `.y` files. A file `src/x.y` which is
 /// statically compiled by lrpar can then be used in a crate with `lrpar_mod!(x)`.
@@ -62,4 +143,10 @@ macro_rules! lrpar_mod {
 }
 
 #[doc(hidden)]
-pub use cfgrammar::NTIdx;
+pub use cfgrammar::{NTIdx, TIdx};
+#[doc(hidden)]
+pub use lazy_static::lazy_static;
+#[doc(hidden)]
+pub use cfgrammar::yacc::YaccGrammar;
+#[doc(hidden)]
+pub use lrtable::{StateGraph, StateTable};
@@ -0,0 +1,155 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashMap;
+
+use num_traits::ToPrimitive;
+
+use parser::Node;
+use tokid::TokenId;
+
+/// An identifier for a parse tree node which is stable across parses (see `Node::ids`), so that
+/// data computed for a node (e.g. type information) can be cached between re-parses of an input.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct NodeId(u64);
+
+impl From<NodeId> for u64 {
+    fn from(id: NodeId) -> u64 {
+        id.0
+    }
+}
+
+/// Return the ID of every node in `node`, in pre-order. See `Node::ids`.
+pub(crate) fn node_ids<TokId: TokenId>(node: &Node<TokId>) -> Vec<(NodeId, &Node<TokId>)> {
+    // We traverse the tree once, with an explicit stack, entering each node in pre-order (which
+    // determines its position in `ids`) and leaving it in post-order, at which point its span is
+    // computed from those of its children, and thus its ID. Each stack entry is a node and, if
+    // the node has been entered, its index in `ids`.
+    let mut ids = Vec::new();
+    let mut st = vec![(node, None)];
+    // The spans of the nodes which have been left but whose parents have not.
+    let mut spans = Vec::new();
+    // How many nodes with a given (kind, index, start, end) have been left so far.
+    let mut seen = HashMap::new();
+    // The end of the last lexeme seen, which is where any empty node is considered to be.
+    let mut pos = 0;
+    while let Some((n, entered)) = st.pop() {
+        let i = match entered {
+            Some(i) => i,
+            None => {
+                ids.push((NodeId(0), n));
+                if let Node::Nonterm{ref nodes, ..} = *n {
+                    st.push((n, Some(ids.len() - 1)));
+                    for x in nodes.iter().rev() {
+                        st.push((x, None));
+                    }
+                    continue;
+                }
+                ids.len() - 1
+            }
+        };
+        let (kind, idx, span) = match *n {
+            Node::Term{lexeme} => {
+                pos = lexeme.start() + lexeme.len();
+                (0,
+                 lexeme.tok_id().without_channel().to_u64().unwrap(),
+                 Some((lexeme.start(), pos)))
+            },
+            Node::Nonterm{nonterm_idx, ref nodes} => {
+                let span = {
+                    let cspans = &spans[spans.len() - nodes.len()..];
+                    cspans.iter()
+                          .filter_map(|s| *s)
+                          .next()
+                          .map(|(start, _)| {
+                              (start, cspans.iter().rev().filter_map(|s| *s).next().unwrap().1)
+                          })
+                };
+                let len = spans.len() - nodes.len();
+                spans.truncate(len);
+                (1, usize::from(nonterm_idx) as u64, span)
+            }
+        };
+        spans.push(span);
+        let (start, end) = span.unwrap_or((pos, pos));
+        let key = (kind, idx, start as u64, end as u64);
+        let ordinal = seen.entry(key).or_insert(0u64);
+        ids[i].0 = NodeId(fnv1a(&[kind, idx, start as u64, end as u64, *ordinal]));
+        *ordinal += 1;
+    }
+    ids
+}
+
+/// Hash `words` with 64-bit FNV-1a, which, unlike `DefaultHasher`, is guaranteed to give the same
+/// result in every process.
+fn fnv1a(words: &[u64]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for w in words {
+        for i in 0..8 {
+            h ^= (w >> (i * 8)) & 0xff;
+            h = h.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    h
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::ptr;
+
+    use parser::RecoveryKind;
+    use parser::test::{CALLS_GRMS, CALLS_LEXS, do_parse};
+
+    #[test]
+    fn stable_ids() {
+        let (_, pr) = do_parse(RecoveryKind::MF, CALLS_LEXS, CALLS_GRMS, "f()g()");
+        let pt = pr.unwrap();
+        let ids = pt.ids().iter().map(|&(id, _)| id).collect::<HashSet<_>>();
+        // Every node, including the empty `Calls` node at the bottom of the tree, has its own ID.
+        assert_eq!(ids.len(), pt.ids().len());
+        // Nodes are returned in pre-order.
+        assert!(ptr::eq(pt.ids()[0].1, &pt));
+
+        // Appending a call leaves every existing node's extent, and thus its ID, unchanged.
+        let (_, pr) = do_parse(RecoveryKind::MF, CALLS_LEXS, CALLS_GRMS, "f()g()h()");
+        let pt2 = pr.unwrap();
+        let ids2 = pt2.ids().iter().map(|&(id, _)| id).collect::<HashSet<_>>();
+        assert!(ids.is_subset(&ids2));
+        assert_eq!(ids2.len() - ids.len(), 5);
+
+        // Inserting a call at the start moves, and thus changes the IDs of, the later nodes.
+        let (_, pr) = do_parse(RecoveryKind::MF, CALLS_LEXS, CALLS_GRMS, "e()f()g()");
+        let ids3 = pr.unwrap().ids().iter().map(|&(id, _)| id).collect::<HashSet<_>>();
+        assert!(!ids.is_subset(&ids3));
+    }
+}
@@ -0,0 +1,247 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use lrlex::Lexeme;
+
+use tokid::TokenId;
+
+/// Options controlling how `normalise_input` transforms input before it is lexed.
+/// `NormaliseOptions::new()` removes a leading byte order mark and converts Windows (`\r\n`) line
+/// endings to Unix (`\n`) line endings, but leaves shebang lines and line continuations alone,
+/// since whether those mean anything depends on the language being parsed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NormaliseOptions {
+    bom: bool,
+    shebang: bool,
+    crlf: bool,
+    line_continuations: bool
+}
+
+impl NormaliseOptions {
+    /// Create the default options (see above).
+    pub fn new() -> Self {
+        NormaliseOptions{bom: true, shebang: false, crlf: true, line_continuations: false}
+    }
+
+    /// If `yes` is `true`, remove a leading byte order mark.
+    pub fn bom(mut self, yes: bool) -> Self {
+        self.bom = yes;
+        self
+    }
+
+    /// If `yes` is `true`, remove the text of a first line which starts with `#!` (e.g.
+    /// `#!/usr/bin/env mylang`), leaving its line ending so that line numbers are unchanged. As
+    /// in Rust, a first line starting with `#![` is not treated as a shebang line.
+    pub fn shebang(mut self, yes: bool) -> Self {
+        self.shebang = yes;
+        self
+    }
+
+    /// If `yes` is `true`, convert Windows (`\r\n`) line endings to Unix (`\n`) line endings.
+    pub fn crlf(mut self, yes: bool) -> Self {
+        self.crlf = yes;
+        self
+    }
+
+    /// If `yes` is `true`, splice lines ending in a backslash onto the following line by removing
+    /// the backslash and the line ending, as the C preprocessor does.
+    pub fn line_continuations(mut self, yes: bool) -> Self {
+        self.line_continuations = yes;
+        self
+    }
+}
+
+impl Default for NormaliseOptions {
+    fn default() -> Self {
+        NormaliseOptions::new()
+    }
+}
+
+/// Input transformed by `normalise_input`. Its text is what should be lexed; the resulting
+/// lexemes' offsets can then be mapped back to offsets in the original input with
+/// `original_lexemes`, so that parse trees, errors, and repairs refer to the original file's bytes
+/// (and can be rendered against its text, e.g. by `github_annotations`).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NormalisedInput {
+    text: String,
+    // Sorted (offset in `text`, number of bytes of the original input removed before that
+    // offset) pairs, with one entry for each offset at which bytes were removed.
+    removed: Vec<(usize, usize)>
+}
+
+impl NormalisedInput {
+    /// Return the normalised text.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Return the offset in the original input of the byte at offset `off` in the normalised
+    /// text. If bytes were removed immediately before `off`, the offset after them is returned.
+    pub fn original_offset(&self, off: usize) -> usize {
+        self.map(off, false)
+    }
+
+    /// Return `lexeme` (produced from the normalised text) with its offset and length changed to
+    /// the span of the original input it came from. The span includes any bytes which were
+    /// removed from within it (e.g. a line continuation in the middle of a string), but not those
+    /// removed at its edges. Zero-length lexemes (e.g. those inserted by error recovery) remain
+    /// zero-length.
+    pub fn original_lexeme<TokId: TokenId>(&self, lexeme: &Lexeme<TokId>) -> Lexeme<TokId> {
+        let start = self.map(lexeme.start(), false);
+        let end = if lexeme.len() == 0 {
+                      start
+                  } else {
+                      self.map(lexeme.start() + lexeme.len(), true)
+                  };
+        Lexeme::new(lexeme.tok_id(), start, end - start)
+    }
+
+    /// Map each of `lexemes` back to the original input (see `original_lexeme`).
+    pub fn original_lexemes<TokId: TokenId>(&self, lexemes: &[Lexeme<TokId>])
+                                         -> Vec<Lexeme<TokId>>
+    {
+        lexemes.iter().map(|l| self.original_lexeme(l)).collect()
+    }
+
+    /// Map the offset `off` in the normalised text to the original input. If `end` is `true`,
+    /// `off` is treated as the (exclusive) end of a span, so bytes removed immediately before it
+    /// are not counted.
+    fn map(&self, off: usize, end: bool) -> usize {
+        // `i` is the number of entries in `removed` which apply to `off`.
+        let i = match self.removed.binary_search_by_key(&off, |&(o, _)| o) {
+            Ok(j) => if end { j } else { j + 1 },
+            Err(j) => j
+        };
+        if i == 0 {
+            off
+        } else {
+            off + self.removed[i - 1].1
+        }
+    }
+}
+
+/// Transform `input` as specified by `opts`, recording where bytes were removed so that offsets
+/// in the result can be mapped back to `input` (see `NormalisedInput`).
+pub fn normalise_input(input: &str, opts: &NormaliseOptions) -> NormalisedInput {
+    let mut text = String::with_capacity(input.len());
+    let mut removed = Vec::new();
+
+    let mut i = 0;
+    if opts.bom && input.starts_with('\u{feff}') {
+        i += '\u{feff}'.len_utf8();
+        remove(&mut removed, text.len(), i);
+    }
+    if opts.shebang && input[i..].starts_with("#!") && !input[i..].starts_with("#![") {
+        let n = input[i..].find(|c: char| c == '\r' || c == '\n').unwrap_or(input.len() - i);
+        i += n;
+        remove(&mut removed, text.len(), n);
+    }
+    while i < input.len() {
+        let rest = &input[i..];
+        if opts.crlf && rest.starts_with("\r\n") {
+            remove(&mut removed, text.len(), 1);
+            i += 1;
+        } else if opts.line_continuations && rest.starts_with("\\\n") {
+            remove(&mut removed, text.len(), 2);
+            i += 2;
+        } else if opts.line_continuations && opts.crlf && rest.starts_with("\\\r\n") {
+            remove(&mut removed, text.len(), 3);
+            i += 3;
+        } else {
+            let c = rest.chars().next().unwrap();
+            text.push(c);
+            i += c.len_utf8();
+        }
+    }
+    NormalisedInput{text, removed}
+}
+
+/// Record in `removed` (see `NormalisedInput`) that `n` bytes were removed at offset `off` in the
+/// normalised text.
+fn remove(removed: &mut Vec<(usize, usize)>, off: usize, n: usize) {
+    let total = removed.last().map_or(0, |&(_, t)| t) + n;
+    match removed.last_mut() {
+        Some(&mut (o, ref mut t)) if o == off => {
+            *t = total;
+            return;
+        },
+        _ => ()
+    }
+    removed.push((off, total));
+}
+
+#[cfg(test)]
+mod test {
+    use parser::RTParserBuilder;
+    use parser::test::build_parse_env;
+    use source::lexeme_text;
+    use super::{normalise_input, NormaliseOptions};
+
+    #[test]
+    fn normalise() {
+        let lexs = "%%
+= '='
+; ';'
+[a-z]+ 'ID'
+[ \\n] ;
+";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' '=' 'ID' ';';";
+
+        let us = "\u{feff}#!/bin/x\r\nab\\\r\ncd = b ;\r\ne = ;";
+        let opts = NormaliseOptions::new().shebang(true).line_continuations(true);
+        let ni = normalise_input(&us, &opts);
+        assert_eq!(ni.text(), "\nabcd = b ;\ne = ;");
+        assert_eq!(ni.original_offset(0), 12);
+        assert_eq!(ni.original_offset(4), 19);
+
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, ni.text());
+        let lexemes = ni.original_lexemes(&lexemes);
+        // The spliced identifier spans the line continuation in the original input.
+        assert_eq!(lexeme_text(us, &lexemes[0]), "ab\\\r\ncd");
+        assert_eq!(lexeme_text(us, &lexemes[6]), ";");
+        let (_, errs) = RTParserBuilder::new(&grm, &sgraph, &stable).parse(&lexemes)
+                                                                    .unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].lexeme().start(), 32);
+
+        // Without line continuations, the backslash is left for the lexer.
+        let ni = normalise_input(&us, &NormaliseOptions::new());
+        assert_eq!(ni.text(), "#!/bin/x\nab\\\ncd = b ;\ne = ;");
+        assert_eq!(ni.original_offset(9), 13);
+        // Rust's inner attributes aren't shebangs.
+        let ni = normalise_input("#![a]\nb", &NormaliseOptions::new().shebang(true));
+        assert_eq!(ni.text(), "#![a]\nb");
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::rc::Rc;
+
+use cfgrammar::yacc::YaccGrammar;
+
+use parser::Node;
+use tokid::{tok_tidx, TokenId};
+
+/// A child of a node in an outline of a parse tree (see `outline_children`).
+#[derive(Debug)]
+pub enum OutlineChild<'a, TokId: TokenId> where TokId: 'a {
+    /// A child which is shown as normal.
+    Node(&'a Node<TokId>),
+    /// A run of consecutive children of the same kind, folded into a single entry.
+    Run(FoldedRun<'a, TokId>)
+}
+
+/// A run of consecutive children of the same kind (i.e. lexemes for the same terminal, whatever
+/// their channel, or nonterminals for the same rule), or of alternating kinds (e.g. list elements
+/// and their separators), folded into a single entry of an outline. A run is a view onto the
+/// children of its parent, shared by all of the parent's runs, so folding does not copy any of
+/// its children, which are only visited (e.g. to be shown in an expanded outline) if asked for.
+#[derive(Clone, Debug)]
+pub struct FoldedRun<'a, TokId: TokenId> where TokId: 'a {
+    children: Rc<Vec<&'a Node<TokId>>>,
+    start: usize,
+    end: usize
+}
+
+impl<'a, TokId: TokenId> FoldedRun<'a, TokId> {
+    /// How many children are in this run?
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Return the `i`th child in this run.
+    pub fn get(&self, i: usize) -> &'a Node<TokId> {
+        assert!(i < self.len());
+        self.children[self.start + i]
+    }
+
+    /// Return the children in this run.
+    pub fn nodes(&self) -> &[&'a Node<TokId>] {
+        &self.children[self.start..self.end]
+    }
+
+    /// Return the half-open range of byte offsets spanned by this run, or `None` if none of its
+    /// children contain terminals.
+    pub fn span(&self) -> Option<(usize, usize)> {
+        let start = self.nodes().iter().filter_map(|n| n.span()).next()?.0;
+        let end = self.nodes().iter().rev().filter_map(|n| n.span()).next()?.1;
+        Some((start, end))
+    }
+}
+
+/// Return the children of `node` for an outline view of a parse tree (e.g. in an IDE), in which
+/// each run of at least `min_run` consecutive children of the same kind, or of at least `min_run`
+/// repetitions of a pair of children of different kinds (e.g. an element and a separator, with or
+/// without a trailing separator), is folded into a single `OutlineChild::Run`, so that data-heavy
+/// inputs (e.g. an array with 100,000 elements) remain cheap to display. Lists in LR grammars are
+/// normally left (or right) recursive, so their elements are nested rather than siblings: the
+/// children of a node for rule `R` are therefore those of `node` with any child which is also a
+/// node for `R` at the start (or end) replaced, recursively, by its own children. Terminals have
+/// no children. `grm` is the grammar `node` was parsed with.
+pub fn outline_children<'a, TokId: TokenId>(grm: &YaccGrammar,
+                                            node: &'a Node<TokId>,
+                                            min_run: usize)
+                                         -> Vec<OutlineChild<'a, TokId>>
+{
+    let nonterm_idx = match *node {
+        Node::Term{..} => return Vec::new(),
+        Node::Nonterm{nonterm_idx, ..} => nonterm_idx
+    };
+    let is_list = |n: &Node<TokId>| match *n {
+        Node::Nonterm{nonterm_idx: nt_idx, ..} => nt_idx == nonterm_idx,
+        Node::Term{..} => false
+    };
+    // Flatten the list in order, using an explicit stack so that long lists can't overflow the
+    // call stack. Each entry records whether the node is to be replaced by its children.
+    let mut children = Vec::new();
+    let mut st = vec![(node, true)];
+    while let Some((n, expand)) = st.pop() {
+        match *n {
+            Node::Nonterm{ref nodes, ..} if expand => {
+                for (i, c) in nodes.iter().enumerate().rev() {
+                    st.push((c, (i == 0 || i == nodes.len() - 1) && is_list(c)));
+                }
+            },
+            _ => children.push(n)
+        }
+    }
+
+    // Fold runs of children which repeat with a period of one (children of the same kind) or two
+    // (alternating children). If the run starting at a child is too short to fold, we move on to
+    // the next child, since a run with the other period might start there; since the run's end is
+    // found within `2 * min_run` children, this is linear in the number of children.
+    let children = Rc::new(children);
+    let mut outline = Vec::new();
+    let mut i = 0;
+    while i < children.len() {
+        let p = if i + 1 < children.len() && !same_kind(grm, children[i], children[i + 1]) {
+            2
+        } else {
+            1
+        };
+        let j = (i + p..children.len()).find(|&j| !same_kind(grm, children[j - p], children[j]))
+                                       .unwrap_or_else(|| children.len());
+        if (j - i + p - 1) / p >= min_run {
+            outline.push(OutlineChild::Run(FoldedRun{children: Rc::clone(&children),
+                                                     start: i,
+                                                     end: j}));
+            i = j;
+        } else {
+            outline.push(OutlineChild::Node(children[i]));
+            i += 1;
+        }
+    }
+    outline
+}
+
+/// Are `n1` and `n2` lexemes for the same terminal, or nonterminals for the same rule?
+fn same_kind<TokId: TokenId>(grm: &YaccGrammar, n1: &Node<TokId>, n2: &Node<TokId>) -> bool {
+    match (n1, n2) {
+        (&Node::Term{lexeme: l1}, &Node::Term{lexeme: l2}) => {
+            tok_tidx(grm, l1.tok_id()) == tok_tidx(grm, l2.tok_id())
+        },
+        (&Node::Nonterm{nonterm_idx: nt1, ..}, &Node::Nonterm{nonterm_idx: nt2, ..}) => nt1 == nt2,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use parser::{Node, RecoveryKind};
+    use parser::test::do_parse;
+    use super::{outline_children, OutlineChild};
+
+    #[test]
+    fn folding() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+, ','
+[a-zA-Z_][a-zA-Z_0-9]* 'ID'
+";
+        let grms = "%start Calls
+%%
+Calls: Calls Call | ;
+Call: 'ID' '(' Args ')';
+Args: Args ',' 'ID' | 'ID' | ;";
+
+        let us = "f(a,b,c,d,e,f)g()h()";
+        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, &us);
+        let pt = pr.unwrap();
+        // The left-recursive list of calls is flattened, but there are too few to fold.
+        let calls = outline_children(&grm, &pt, 4);
+        assert_eq!(calls.len(), 3);
+        let f = match calls[0] {
+            OutlineChild::Node(n) => n,
+            OutlineChild::Run(_) => panic!()
+        };
+        assert_eq!(outline_children(&grm, f, 4).len(), 4);
+        // f's 6 arguments alternate with ','s, so they are folded, separators and all, if runs of
+        // 6 are long enough...
+        let args = match outline_children(&grm, f, 4)[2] {
+            OutlineChild::Node(n) => n,
+            OutlineChild::Run(_) => panic!()
+        };
+        assert_eq!(outline_children(&grm, args, 7).len(), 11);
+        let folded = outline_children(&grm, args, 6);
+        assert_eq!(folded.len(), 1);
+        match folded[0] {
+            OutlineChild::Run(ref r) => {
+                assert_eq!(r.len(), 11);
+                assert_eq!(r.span(), Some((2, 13)));
+            },
+            OutlineChild::Node(_) => panic!()
+        }
+        // ...and the calls are folded into one run if runs can be short enough.
+        match outline_children(&grm, &pt, 3)[0] {
+            OutlineChild::Run(ref r) => {
+                assert_eq!(r.len(), 3);
+                assert_eq!(r.span(), Some((0, us.len())));
+                match *r.get(2) {
+                    Node::Nonterm{nonterm_idx, ..} => {
+                        assert_eq!(grm.nonterm_name(nonterm_idx), "Call")
+                    },
+                    Node::Term{..} => panic!()
+                }
+            },
+            OutlineChild::Node(_) => panic!()
+        }
+    }
+}
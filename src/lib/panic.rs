@@ -0,0 +1,95 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::time::Instant;
+
+use lrtable::StIdx;
+
+use events::TStack;
+use parser::{Parser, ParseRepair, Recoverer, RecoveryLimits};
+use tokid::TokenId;
+
+/// A "panic mode" recoverer: it deletes lexemes until it reaches one which the parser can
+/// continue with (without otherwise changing the parse stack). This finds at most one repair
+/// sequence, which is often not the one a human would choose, but it is very cheap, making it
+/// suitable as a fallback (see `RTParserBuilder::fallback`).
+struct Panic;
+
+pub(crate) fn recoverer<'a, TokId: TokenId>
+                       (_: &'a Parser<TokId>)
+                     -> Box<Recoverer<TokId> + 'a>
+{
+    Box::new(Panic)
+}
+
+impl<TokId: TokenId> Recoverer<TokId> for Panic {
+    fn recover(&self,
+               finish_by: Instant,
+               parser: &Parser<TokId>,
+               in_la_idx: usize,
+               in_pstack: &mut Vec<StIdx>,
+               _: &mut TStack<TokId>)
+           -> (usize, Vec<Vec<ParseRepair>>, RecoveryLimits)
+    {
+        let mut la_idx = in_la_idx;
+        while la_idx < parser.lexemes.len() && (parser.recovery.clock)() < finish_by {
+            la_idx += 1;
+            if parser.valid_la(in_pstack, parser.next_tidx(la_idx)) {
+                let rprs = vec![ParseRepair::Delete; la_idx - in_la_idx];
+                return (la_idx, vec![rprs], RecoveryLimits::default());
+            }
+        }
+        (in_la_idx, vec![], RecoveryLimits::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use parser::{ParseRepair, RecoveryKind};
+    use parser::test::{CALLS_GRMS, CALLS_LEXS, do_parse};
+
+    #[test]
+    fn panic() {
+        let (_, pr) = do_parse(RecoveryKind::Panic, CALLS_LEXS, CALLS_GRMS, "f())g()");
+        let (pt, errs) = pr.unwrap_err();
+        assert!(pt.is_some());
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].lexeme_idx(), 3);
+        assert_eq!(errs[0].repairs(), &vec![vec![ParseRepair::Delete]]);
+
+        // Deleting everything after the first error still can't complete the input.
+        let (_, pr) = do_parse(RecoveryKind::Panic, CALLS_LEXS, CALLS_GRMS, "f(g");
+        let (pt, errs) = pr.unwrap_err();
+        assert!(pt.is_none());
+        assert!(errs[0].repairs().is_empty());
+    }
+}
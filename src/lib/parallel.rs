@@ -0,0 +1,94 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use cfgrammar::yacc::YaccGrammar;
+use lrlex::Lexeme;
+use lrtable::{StateGraph, StateTable};
+use rayon::prelude::*;
+
+use parser::{Node, ParseError, RecoveryKind, RTParserBuilder};
+use tokid::TokenId;
+
+/// Parse each of `inputs` (each a vector of lexemes, e.g. one per file) in parallel, sharing a
+/// single set of tables between all the parsers, and return the results in the same order as
+/// `inputs`. Each input is parsed exactly as `RTParserBuilder::parse` would, using the recoverer
+/// `rcvry_kind`. Only available with the `rayon` feature.
+pub fn parse_many<'a, TokId, I>(grm: &YaccGrammar,
+                                sgraph: &StateGraph,
+                                stable: &StateTable,
+                                rcvry_kind: RecoveryKind,
+                                inputs: I)
+                             -> Vec<Result<Node<TokId>, (Option<Node<TokId>>,
+                                                         Vec<ParseError<TokId>>)>>
+                          where TokId: TokenId + Send + Sync + 'a,
+                                I: IntoParallelIterator<Item=&'a Vec<Lexeme<TokId>>>
+{
+    inputs.into_par_iter()
+          .map(|lexemes| {
+              RTParserBuilder::new(grm, sgraph, stable).recoverer(rcvry_kind)
+                                                       .parse(lexemes)
+          })
+          .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use parser::{RecoveryKind, RTParserBuilder};
+    use parser::test::build_parse_env;
+    use super::parse_many;
+
+    #[test]
+    fn parse_many_in_order() {
+        let lexs = "%%
+\\+ '+'
+[a-z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start E
+%%
+E: E '+' 'ID'
+ | 'ID' ;
+";
+
+        let inputs = ["a + b", "a +", "+ b", "a + b + c", "", "a b"];
+        let (grm, sgraph, stable, _) = build_parse_env(&lexs, &grms, "");
+        let lexemes = inputs.iter()
+                            .map(|i| build_parse_env(&lexs, &grms, i).3)
+                            .collect::<Vec<_>>();
+        let rs = parse_many(&grm, &sgraph, &stable, RecoveryKind::MF, &lexemes);
+        assert_eq!(rs.len(), inputs.len());
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        for (r, l) in rs.iter().zip(lexemes.iter()) {
+            assert_eq!(r, &rtpb.parse(l));
+        }
+    }
+}
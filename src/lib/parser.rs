@@ -30,92 +30,540 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::marker::PhantomData;
 use std::time::{Duration, Instant};
 
 use cactus::Cactus;
-use cfgrammar::{Grammar, NTIdx, TIdx};
+use cfgrammar::{Grammar, NTIdx, PIdx, SIdx, Symbol, TIdx};
 use cfgrammar::yacc::YaccGrammar;
 use lrlex::Lexeme;
 use lrtable::{Action, StateGraph, StateTable, StIdx};
-use num_traits::{PrimInt, Unsigned};
 
+use cache::{grammar_key, RecoveryContexts, RepairCache};
 use mf;
 use cpctplus;
+use events::{ParseEvent, TStack};
+use panic;
+use pratt::{Assoc, PrattRule};
+use race;
+use search::PARSE_AT_LEAST;
+use nodeid::{node_ids, NodeId};
+use snapshot::NodeSnapshot;
+use source::{lexeme_text, Source};
+use tokid::{channels_fit, tok_tidx, TokenId};
 
 const RECOVERY_TIME_BUDGET: u64 = 500; // milliseconds
+// The maximum edit distance between an error lexeme's text and a keyword for the keyword to be
+// suggested (see `ParseError::suggestion`).
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+// How many lexemes, counting from an error, are considered as places to resume parsing from after
+// discarding an instance of a skippable rule (see `RTParserBuilder::skippable`).
+const SKIP_AT_MOST: usize = 250;
+/// The default value for `RTParserBuilder::max_repair_cost`. Recoverers store candidate repairs
+/// in one bucket per cost, so this is large enough not to affect reasonable cost functions but
+/// small enough that the buckets can't exhaust memory.
+pub const DEFAULT_MAX_REPAIR_COST: u32 = 0xFFFF;
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Node<TokId: PrimInt + Unsigned> {
+pub enum Node<TokId: TokenId> {
     Term{lexeme: Lexeme<TokId>},
     Nonterm{nonterm_idx: NTIdx, nodes: Vec<Node<TokId>>}
 }
 
-impl<TokId: PrimInt + Unsigned> Node<TokId> {
-    /// Return a pretty-printed version of this node.
-    pub fn pp(&self, grm: &YaccGrammar, input: &str) -> String {
-        let mut st = vec![(0, self)]; // Stack of (indent level, node) pairs
-        let mut s = String::new();
-        while !st.is_empty() {
-            let (indent, e) = st.pop().unwrap();
-            for _ in 0..indent {
-                s.push_str(" ");
-            }
-            match *e {
-                Node::Term{lexeme} => {
-                    let t_idx = TIdx::from(lexeme.tok_id().to_u32().unwrap());
-                    let tn = grm.term_name(t_idx).unwrap();
-                    let lt = &input[lexeme.start()..lexeme.start() + lexeme.len()];
-                    s.push_str(&format!("{} {}\n", tn, lt));
+impl<TokId: TokenId> Node<TokId> {
+    /// Return a pretty-printed version of this node, with each node on its own line, indented by
+    /// one space per level, and terminals followed by their text. This is equivalent to
+    /// `pp_with(grm, input, &PPOptions::new())`.
+    pub fn pp<S: Source + ?Sized>(&self, grm: &YaccGrammar, input: &S) -> String {
+        self.pp_with(grm, input, &PPOptions::new())
+    }
+
+    /// Return a pretty-printed version of this node, rendered according to `opts`.
+    pub fn pp_with<S: Source + ?Sized>(&self, grm: &YaccGrammar, input: &S, opts: &PPOptions)
+                                    -> String
+    {
+        // Each node is entered in pre-order, when its line (if it is shown) is added to `lines`.
+        // If spans are wanted, it is also left in post-order, when its span is computed from those
+        // of its children (so that each span is computed only once) and appended to its line;
+        // nodes below `max_depth` are then visited, though not shown, for their spans. Each stack
+        // entry is a node, its depth, whether it has been entered, and its line.
+        let mut st = vec![(self, 0, false, None)];
+        let mut lines = Vec::new();
+        // The spans of the nodes which have been left but whose parents have not.
+        let mut spans = Vec::new();
+        while let Some((e, depth, entered, line)) = st.pop() {
+            let line = if entered {
+                line
+            } else {
+                let line = if opts.max_depth.map_or(false, |d| depth > d) {
+                    None
+                } else {
+                    let mut s = " ".repeat(depth * opts.indent);
+                    match *e {
+                        Node::Term{lexeme} => {
+                            let t_idx = tok_tidx(grm, lexeme.tok_id());
+                            let mut parts = Vec::new();
+                            if opts.names || !opts.text {
+                                parts.push(Cow::Borrowed(grm.term_name(t_idx).unwrap()));
+                            }
+                            if opts.text {
+                                parts.push(lexeme_text(input, &lexeme));
+                            }
+                            s.push_str(&parts.join(" "));
+                        }
+                        Node::Nonterm{nonterm_idx, ref nodes} => {
+                            s.push_str(grm.nonterm_name(nonterm_idx));
+                            if opts.max_depth == Some(depth) && !nodes.is_empty() {
+                                s.push_str(" ...");
+                            }
+                        }
+                    }
+                    lines.push(s);
+                    Some(lines.len() - 1)
+                };
+                if let Node::Nonterm{ref nodes, ..} = *e {
+                    if opts.spans || opts.max_depth.map_or(true, |d| depth < d) {
+                        if opts.spans {
+                            st.push((e, depth, true, line));
+                        }
+                        for x in nodes.iter().rev() {
+                            st.push((x, depth + 1, false, None));
+                        }
+                        continue;
+                    }
                 }
-                Node::Nonterm{nonterm_idx, ref nodes} => {
-                    s.push_str(&format!("{}\n", grm.nonterm_name(nonterm_idx)));
-                    for x in nodes.iter().rev() {
-                        st.push((indent + 1, x));
+                if !opts.spans {
+                    continue;
+                }
+                line
+            };
+            let span = match *e {
+                Node::Term{lexeme} => {
+                    if opts.inserted_spans || lexeme.len() > 0 {
+                        Some((lexeme.start(), lexeme.start() + lexeme.len()))
+                    } else {
+                        None
                     }
+                },
+                Node::Nonterm{ref nodes, ..} => {
+                    let len = spans.len() - nodes.len();
+                    let span = {
+                        let cspans = &spans[len..];
+                        let start = cspans.iter().filter_map(|s| *s).next().map(|(s, _)| s);
+                        let end = cspans.iter().rev().filter_map(|s| *s).next().map(|(_, e)| e);
+                        start.and_then(|s| end.map(|e| (s, e)))
+                    };
+                    spans.truncate(len);
+                    span
                 }
+            };
+            spans.push(span);
+            if let (Some(i), Some((start, end))) = (line, span) {
+                lines[i].push_str(&format!(" [{}..{}]", start, end));
             }
         }
+        let mut s = String::new();
+        for l in lines {
+            s.push_str(&l);
+            s.push('\n');
+        }
         s
     }
+
+    /// Return the half-open range of byte offsets spanned by this node, or `None` if it contains
+    /// no terminals.
+    pub(crate) fn span(&self) -> Option<(usize, usize)> {
+        self.span_with(true)
+    }
+
+    /// Return the half-open range of byte offsets spanned by this node, or `None` if it contains
+    /// no terminals. If `inserted` is `false`, terminals inserted by error recovery (which are
+    /// zero-length, and positioned at the start of the lexeme which follows them) are ignored, so
+    /// that the span doesn't extend to, e.g., the start of the next line, where no text of the
+    /// node is present; a node consisting only of inserted terminals then has no span. If
+    /// `inserted` is `true`, they are included, which tools which need every node (e.g. to place
+    /// a cursor at an inserted terminal) to have a span may prefer.
+    pub fn span_with(&self, inserted: bool) -> Option<(usize, usize)> {
+        let first = self.extreme_lexeme(false, inserted)?;
+        let last = self.extreme_lexeme(true, inserted)?;
+        Some((first.start(), last.start() + last.len()))
+    }
+
+    /// Return the first (or, if `last` is `true`, the last) lexeme in this node, if there is one,
+    /// ignoring zero-length lexemes unless `inserted` is `true`.
+    fn extreme_lexeme(&self, last: bool, inserted: bool) -> Option<Lexeme<TokId>> {
+        // We walk down the first (or last) children using an explicit stack, so that deep trees
+        // can't overflow the call stack. Usually only one path is followed, but if a child has
+        // no (suitable) lexemes, its siblings are tried in turn.
+        let mut st = vec![self];
+        while let Some(n) = st.pop() {
+            match *n {
+                Node::Term{lexeme} => {
+                    if inserted || lexeme.len() > 0 {
+                        return Some(lexeme);
+                    }
+                },
+                Node::Nonterm{ref nodes, ..} => {
+                    if last {
+                        st.extend(nodes.iter());
+                    } else {
+                        st.extend(nodes.iter().rev());
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Return every node in this tree (including this node), in pre-order, with its ID. A node's
+    /// ID is derived from its rule (or token ID) and the span of input it covers, so a node in a
+    /// re-parse has the same ID as a node in an earlier parse if they are for the same rule and
+    /// cover the same span (IDs are disambiguated if several nodes in a tree share both, e.g. in
+    /// chains of productions which derive the empty string). Note that an edit before a node
+    /// moves its span, and thus changes its ID.
+    pub fn ids(&self) -> Vec<(NodeId, &Node<TokId>)> {
+        node_ids(self)
+    }
+
+    /// Return a compact, one-line, rendering of this node whose format is stable (see
+    /// `SNAPSHOT_VERSION`). Unlike `pp`, this is intended for golden-file testing.
+    pub fn snapshot<'a>(&'a self, grm: &'a YaccGrammar, input: &'a str)
+                     -> NodeSnapshot<'a, TokId>
+    {
+        NodeSnapshot::new(grm, input, self)
+    }
+}
+
+/// Options controlling how `Node::pp_with` renders a parse tree. `PPOptions::new()` gives the
+/// format used by `Node::pp`, which existing tests depend upon.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PPOptions {
+    indent: usize,
+    spans: bool,
+    inserted_spans: bool,
+    names: bool,
+    text: bool,
+    max_depth: Option<usize>
+}
+
+impl PPOptions {
+    /// Create the default options: an indent of one space per level, terminals' names and text,
+    /// no spans, and no maximum depth.
+    pub fn new() -> Self {
+        PPOptions{indent: 1,
+                  spans: false,
+                  inserted_spans: true,
+                  names: true,
+                  text: true,
+                  max_depth: None}
+    }
+
+    /// Indent each level of the tree by `indent` spaces.
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    /// If `yes` is `true`, follow each node with the half-open range of byte offsets it spans
+    /// e.g. `[3..7]`. Nodes which contain no terminals have no span.
+    pub fn spans(mut self, yes: bool) -> Self {
+        self.spans = yes;
+        self
+    }
+
+    /// If `yes` is `false`, spans ignore terminals inserted by error recovery (see
+    /// `Node::span_with`), so that they cover only text which is present in the input. Defaults
+    /// to `true`.
+    pub fn inserted_spans(mut self, yes: bool) -> Self {
+        self.inserted_spans = yes;
+        self
+    }
+
+    /// If `yes` is `true`, show terminals' names.
+    pub fn names(mut self, yes: bool) -> Self {
+        self.names = yes;
+        self
+    }
+
+    /// If `yes` is `true`, show terminals' text. If neither names nor text are shown, names are
+    /// shown anyway.
+    pub fn text(mut self, yes: bool) -> Self {
+        self.text = yes;
+        self
+    }
+
+    /// Do not show the children of nodes at depth `max_depth` or deeper (the root is at depth 0):
+    /// such nodes are followed by `...` if they have any children.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+}
+
+impl Default for PPOptions {
+    fn default() -> Self {
+        PPOptions::new()
+    }
+}
+
+/// Return the text in `input` that `lexeme` spans. For inputs which are not `str`s, see
+/// `lexeme_text`.
+pub fn lexeme_str<'a, TokId: Copy>(input: &'a str, lexeme: &Lexeme<TokId>) -> &'a str {
+    &input[lexeme.start()..lexeme.start() + lexeme.len()]
 }
 
 pub(crate) type Lexemes<TokId> = Vec<Lexeme<TokId>>;
 pub(crate) type PStack = Vec<StIdx>; // Parse stack
-pub(crate) type TStack<TokId> = Vec<Node<TokId>>; // Parse tree stack
 pub(crate) type Errors<TokId> = Vec<ParseError<TokId>>;
 
-pub struct Parser<'a, TokId: PrimInt + Unsigned> where TokId: 'a {
+pub struct Parser<'a, TokId: TokenId> where TokId: 'a {
     pub rcvry_kind: RecoveryKind,
     pub grm: &'a YaccGrammar,
     pub term_cost: &'a Fn(TIdx) -> u8,
     pub sgraph: &'a StateGraph,
     pub stable: &'a StateTable,
-    pub lexemes: &'a Lexemes<TokId>
+    pub lexemes: &'a [Lexeme<TokId>],
+    pub(crate) recovery: RecoveryOptions<'a, TokId>,
+    pub(crate) syntax: SyntaxOptions<'a>
+}
+
+/// The options of an `RTParserBuilder` which control how errors are recovered from and reported,
+/// as passed to a `Parser` (see the builder's methods of the same names).
+#[derive(Clone, Copy)]
+pub(crate) struct RecoveryOptions<'a, TokId: TokenId> where TokId: 'a {
+    pub(crate) max_repair_len: Option<usize>,
+    pub(crate) max_repair_cost: u32,
+    pub(crate) max_recovery_memory: Option<usize>,
+    pub(crate) max_recovery_expansions: Option<usize>,
+    pub(crate) search_priority: Option<&'a Fn(u32, StIdx, usize) -> u32>,
+    pub(crate) max_errors: Option<usize>,
+    pub(crate) no_recovery: &'a [(usize, usize)],
+    // The repair cache and the key of this parser's grammar in it (see `cache::grammar_key`).
+    pub(crate) repair_cache: Option<(&'a RepairCache, u64)>,
+    pub(crate) reuse_recoveries: bool,
+    pub(crate) clock: &'a Fn() -> Instant,
+    pub(crate) fallback: Option<Duration>,
+    pub(crate) anchors: &'a [TIdx],
+    pub(crate) skippable: &'a [NTIdx],
+    pub(crate) isolate_errors: bool,
+    pub(crate) minimal_span: bool,
+    pub(crate) typo_input: Option<&'a Source>,
+    pub(crate) substitutions: bool,
+    pub(crate) shift_reporting: ShiftReporting,
+    pub(crate) on_error: Option<&'a Fn(&ParseError<TokId>)>,
+    pub(crate) recovery_decision: Option<&'a RecoveryDecider<TokId>>
+}
+
+/// The options of an `RTParserBuilder` which change how the input is parsed, beyond what the
+/// grammar's tables say: contextual keywords, lookahead hints, and operator-precedence
+/// sub-parsers (see `RTParserBuilder::contextual`, `lookahead`, and `pratt`).
+#[derive(Clone, Copy)]
+pub(crate) struct SyntaxOptions<'a> {
+    pub(crate) contextual: &'a [(TIdx, TIdx)],
+    // The lookahead hints, or `None` if there are none.
+    pub(crate) lookaheads: Option<&'a HashMap<(StIdx, TIdx), (Action, usize)>>,
+    pub(crate) pratts: &'a [PrattRule]
 }
 
-impl<'a, TokId: PrimInt + Unsigned> Parser<'a, TokId> {
-    fn parse<F>(rcvry_kind: RecoveryKind,
-                grm: &YaccGrammar,
-                term_cost: F,
-                sgraph: &StateGraph,
-                stable: &StateTable,
-                lexemes: &Lexemes<TokId>)
-             -> Result<Node<TokId>, (Option<Node<TokId>>, Vec<ParseError<TokId>>)>
-          where F: Fn(TIdx) -> u8
+impl<'a, TokId: TokenId> Parser<'a, TokId> {
+    /// Parse `lexemes` using the options in `rtpb`. If `two_phase` is `true`, parsing starts with
+    /// error recovery turned off, and the recoverer is only turned on if an error is encountered.
+    fn parse(rtpb: &RTParserBuilder<TokId>,
+             lexemes: &[Lexeme<TokId>],
+             two_phase: bool)
+          -> Result<Node<TokId>, (Option<Node<TokId>>, Vec<ParseError<TokId>>)>
+    {
+        let (tstack, errors) = Parser::parse_into(rtpb, lexemes, two_phase, TStack::new());
+        match (tstack.and_then(|t| t.into_node()), errors.is_empty()) {
+            (Some(pt), true) => Ok(pt),
+            (pt, false)      => Err((pt, errors)),
+            (None, true)     => panic!("Internal error")
+        }
+    }
+
+    /// Parse `lexemes` as `parse` does, building the parse tree in `tstack` (which must be
+    /// empty). Returns the tree stack if all the input was consumed (in which case it holds a
+    /// single tree), and the errors encountered.
+    fn parse_into(rtpb: &RTParserBuilder<TokId>,
+                  lexemes: &[Lexeme<TokId>],
+                  two_phase: bool,
+                  mut tstack: TStack<TokId>)
+               -> (Option<TStack<TokId>>, Vec<ParseError<TokId>>)
     {
-        for i in 0..grm.terms_len() {
-            assert!(term_cost(TIdx::from(i)) > 0);
+        let mut psr = rtpb.parser(lexemes);
+        if lexemes.is_empty() && rtpb.empty_input != EmptyInput::Recover {
+            return match psr.parse_empty(rtpb.empty_input) {
+                Ok(pt) => {
+                    tstack.push_node(pt);
+                    (Some(tstack), Vec::new())
+                },
+                Err((pt, errors)) => {
+                    (pt.map(move |pt| {
+                            tstack.push_node(pt);
+                            tstack
+                        }),
+                     errors)
+                }
+            };
+        }
+        let recovery_decision = psr.recovery.recovery_decision;
+        if two_phase {
+            // The first phase's error (if any) is only provisional, so it isn't reported.
+            psr.rcvry_kind = RecoveryKind::None;
+            psr.recovery.on_error = None;
+            psr.recovery.recovery_decision = None;
         }
-        let psr = Parser{rcvry_kind, grm, term_cost: &term_cost, sgraph, stable, lexemes};
         let mut pstack = vec![StIdx::from(0 as u32)];
-        let mut tstack: Vec<Node<TokId>> = Vec::new();
         let mut errors: Vec<ParseError<TokId>> = Vec::new();
-        let accpt = psr.lr(0, &mut pstack, &mut tstack, &mut errors);
-        match (accpt, errors.is_empty()) {
-            (true, true)   => Ok(tstack.drain(..).nth(0).unwrap()),
-            (true, false)  => Err((Some(tstack.drain(..).nth(0).unwrap()), errors)),
-            (false, false) => Err((None, errors)),
-            (false, true)  => panic!("Internal error")
+        log_debug!("Parsing {} lexemes", lexemes.len());
+        let mut accpt = psr.lr(0, &mut pstack, &mut tstack, &mut errors);
+        if !accpt && psr.rcvry_kind != rtpb.rcvry_kind {
+            // Without recovery, the first phase stops at the first error it encounters, leaving
+            // the parse and tree stacks exactly as they were at that point. We can thus resume
+            // parsing from the error with recovery turned on, without reparsing any of the input.
+            let la_idx = errors.pop().unwrap().lexeme_idx();
+            log_debug!("Restarting from the error at lexeme {} with recovery on", la_idx);
+            psr.rcvry_kind = rtpb.rcvry_kind;
+            psr.recovery.on_error = rtpb.on_error;
+            psr.recovery.recovery_decision = recovery_decision;
+            accpt = psr.lr(la_idx, &mut pstack, &mut tstack, &mut errors);
+        }
+        log_debug!("Parse {} with {} errors",
+                   if accpt { "accepted" } else { "failed" },
+                   errors.len());
+        // This must come first, since the repair sequences which would have avoided later errors
+        // are precisely those which `remove_inconsistent_repairs` removes.
+        if rtpb.note_cascades {
+            psr.note_cascades(&mut errors);
+        }
+        if rtpb.consistent_repairs {
+            psr.remove_inconsistent_repairs(&mut errors);
+        }
+        if rtpb.shift_reporting != ShiftReporting::Each {
+            for e in &mut errors {
+                report_shifts(&mut e.repairs, rtpb.shift_reporting);
+            }
+        }
+        if accpt {
+            (Some(tstack), errors)
+        } else {
+            (None, errors)
+        }
+    }
+
+    /// Parse the empty input as specified by `empty_input` (which must not be
+    /// `EmptyInput::Recover`) without using a recoverer.
+    fn parse_empty(&self, empty_input: EmptyInput)
+                -> Result<Node<TokId>, (Option<Node<TokId>>, Vec<ParseError<TokId>>)>
+    {
+        debug_assert!(self.lexemes.is_empty());
+        let psr = Parser{rcvry_kind: RecoveryKind::None,
+                         recovery: RecoveryOptions{on_error: None,
+                                                   recovery_decision: None,
+                                                   ..self.recovery},
+                         ..*self};
+        let mut pstack = vec![StIdx::from(0 as u32)];
+        let mut tstack = TStack::new();
+        let mut errors = Vec::new();
+        if psr.lr(0, &mut pstack, &mut tstack, &mut errors) {
+            return Ok(tstack.into_node().unwrap());
+        }
+        let mut pt = None;
+        if empty_input == EmptyInput::MinimalInsert {
+            // The repairs are exactly the minimal cost sentences of the start rule, which the
+            // sentence generator computes far more cheaply than a general recovery search.
+            let start_nt = self.grm.prod_to_nonterm(self.grm.start_prod());
+            let sengen = self.grm.sentence_generator(self.term_cost);
+            let rprs = sengen.min_sentences(start_nt)
+                             .into_iter()
+                             .map(|s| s.into_iter().map(ParseRepair::Insert).collect::<Vec<_>>())
+                             .collect::<Vec<_>>();
+            if let Some(rpr) = rprs.get(0) {
+                // As with other repairs, the tree contains the first repair's insertions.
+                let ins = rpr.iter()
+                             .map(|r| match *r {
+                                 ParseRepair::Insert(t_idx) => {
+                                     Lexeme::new(TokId::from(u32::from(t_idx)).unwrap(), 0, 0)
+                                 },
+                                 _ => unreachable!()
+                             })
+                             .collect::<Vec<_>>();
+                let ins_psr = Parser{lexemes: &ins, ..psr};
+                let mut pstack = vec![StIdx::from(0 as u32)];
+                let mut tstack = TStack::new();
+                if ins_psr.lr(0, &mut pstack, &mut tstack, &mut Vec::new()) {
+                    pt = tstack.into_node();
+                }
+            }
+            errors[0].repairs = rprs;
+        }
+        self.report_error(&errors);
+        Err((pt, errors))
+    }
+
+    /// Record, for each error in `errors` after the first, whether it was plausibly caused by the
+    /// repair applied for the error before it: that is, whether one of the other repair sequences
+    /// found for the previous error would have let the parser continue past the lexeme at which
+    /// this error was detected.
+    fn note_cascades(&self, errors: &mut Errors<TokId>) {
+        let mut pstack = vec![StIdx::from(0 as u32)];
+        let mut la_idx = 0;
+        for i in 0..errors.len().saturating_sub(1) {
+            la_idx = self.lr_upto(None, la_idx, errors[i].lexeme_idx, &mut pstack, &mut None);
+            if errors[i].applied.is_none() || errors[i].skipped_to.is_some() {
+                break;
+            }
+            let next_la_idx = errors[i + 1].lexeme_idx;
+            let avoided = errors[i].repairs[1..].iter().any(|rprs| {
+                let mut r_pstack = pstack.clone();
+                let r_la_idx = mf::apply_repairs(self, la_idx, &mut r_pstack, &mut None, rprs);
+                if r_la_idx > next_la_idx {
+                    return true;
+                }
+                self.lr_upto(None, r_la_idx, next_la_idx, &mut r_pstack, &mut None) == next_la_idx
+                    && self.valid_la(&r_pstack, self.next_tidx(next_la_idx))
+            });
+            if avoided {
+                errors[i + 1].caused_by = Some(i);
+            }
+            let applied = errors[i].applied.as_ref().unwrap();
+            la_idx = mf::apply_repairs(self, la_idx, &mut pstack, &mut None, applied);
+        }
+    }
+
+    /// Remove from `errors` any repair sequences which are inconsistent with the repairs made for
+    /// later errors. The parser always applies the first repair sequence of each error, so the
+    /// errors after it (and their repairs) were found relative to the parser configuration that
+    /// sequence leads to. Another repair sequence for the same error is consistent if it leads to
+    /// the same configuration by the time the next error is reached; if not (e.g. because it
+    /// deletes a lexeme that the next error's repairs refer to) it is removed. The last error's
+    /// repair sequences are always consistent.
+    fn remove_inconsistent_repairs(&self, errors: &mut Errors<TokId>) {
+        let mut pstack = vec![StIdx::from(0 as u32)];
+        let mut la_idx = 0;
+        for i in 0..errors.len().saturating_sub(1) {
+            la_idx = self.lr_upto(None, la_idx, errors[i].lexeme_idx, &mut pstack, &mut None);
+            if errors[i].applied.is_none() {
+                break;
+            }
+            let next_la_idx = errors[i + 1].lexeme_idx;
+            let (new_la_idx, new_pstack) = {
+                let run = |rprs: &Vec<ParseRepair>| {
+                    let mut r_pstack = pstack.clone();
+                    let r_la_idx = mf::apply_repairs(self, la_idx, &mut r_pstack, &mut None, rprs);
+                    let r_la_idx = self.lr_upto(None, r_la_idx, next_la_idx, &mut r_pstack,
+                                                &mut None);
+                    (r_la_idx, r_pstack)
+                };
+                let applied = run(errors[i].applied.as_ref().unwrap());
+                errors[i].repairs.retain(|rprs| run(rprs) == applied);
+                applied
+            };
+            la_idx = new_la_idx;
+            pstack = new_pstack;
         }
     }
 
@@ -132,78 +580,555 @@ impl<'a, TokId: PrimInt + Unsigned> Parser<'a, TokId> {
     /// Return `true` if the parse reached an accept state (i.e. all the input was consumed,
     /// possibly after making repairs) or `false` (i.e. some of the input was not consumed, even
     /// after possibly making repairs) otherwise.
-    pub fn lr(&self, mut la_idx: usize, pstack: &mut PStack, tstack: &mut TStack<TokId>,
+    pub fn lr(&self, la_idx: usize, pstack: &mut PStack, tstack: &mut TStack<TokId>,
               errors: &mut Errors<TokId>)
            -> bool
     {
-        let mut recoverer = None;
+        self.lr_until(la_idx, None, pstack, tstack, errors).1
+    }
+
+    /// As `lr`, but if `end_la_idx` is not `None`, parsing stops as soon as the lookahead reaches
+    /// `end_la_idx` (before any reductions are made on the lookahead there). Returns a tuple
+    /// `(la_idx, accepted)` where `la_idx` is the index of the lookahead when parsing stopped.
+    fn lr_until(&self,
+                mut la_idx: usize,
+                end_la_idx: Option<usize>,
+                pstack: &mut PStack,
+                tstack: &mut TStack<TokId>,
+                errors: &mut Errors<TokId>)
+             -> (usize, bool)
+    {
+        // Recoverers are created lazily and then reused for later errors.
+        let mut recoverers = Vec::new();
         let mut recovery_budget = Duration::from_millis(RECOVERY_TIME_BUDGET);
+        let mut contexts = RecoveryContexts::new();
+        // Set if we've recovered from an error and have not yet reached the next anchor (see
+        // `RTParserBuilder::isolate_errors`).
+        let mut isolating = false;
+        // The operator-precedence sub-parsers aren't tried again before this lexeme, since they
+        // have already failed on the input up to it: retrying them at each lexeme in between would
+        // make parsing (e.g. of erroneous input) quadratic.
+        let mut pratt_from = 0;
         loop {
+            if end_la_idx.map_or(false, |e| la_idx >= e) {
+                return (la_idx, false);
+            }
+            if !self.syntax.pratts.is_empty() && end_la_idx.is_none() && !isolating
+               && la_idx >= pratt_from
+            {
+                match self.pratt_parse(la_idx, pstack, tstack) {
+                    Ok(new_la_idx) => {
+                        la_idx = new_la_idx;
+                        continue;
+                    },
+                    Err(furthest) => pratt_from = furthest
+                }
+            }
             let st = *pstack.last().unwrap();
             let la_tidx = self.next_tidx(la_idx);
+            let (mut act, act_tidx) = self.action(st, la_tidx);
+            if self.syntax.lookaheads.is_some() {
+                act = self.lookahead_action(la_idx, act_tidx, act, pstack);
+            }
 
-            match self.stable.action(st, la_tidx) {
+            match act {
                 Some(Action::Reduce(prod_id)) => {
                     let nonterm_idx = self.grm.prod_to_nonterm(prod_id);
                     let pop_idx = pstack.len() - self.grm.prod(prod_id).len();
-                    let nodes = tstack.drain(pop_idx - 1..).collect::<Vec<Node<TokId>>>();
-                    tstack.push(Node::Nonterm{nonterm_idx: nonterm_idx, nodes: nodes});
+                    tstack.reduce(nonterm_idx, self.grm.prod(prod_id).len());
 
                     pstack.drain(pop_idx..);
                     let prior = *pstack.last().unwrap();
                     pstack.push(self.stable.goto(prior, nonterm_idx).unwrap());
                 },
                 Some(Action::Shift(state_id)) => {
-                    let la_lexeme = self.next_lexeme(la_idx);
-                    tstack.push(Node::Term{lexeme: la_lexeme});
+                    let la_lexeme = self.retag(self.next_lexeme(la_idx), act_tidx);
+                    tstack.shift(la_lexeme);
                     pstack.push(state_id);
+                    if isolating && self.recovery.anchors.contains(&la_tidx) {
+                        isolating = false;
+                    }
                     la_idx += 1;
                 },
                 Some(Action::Accept) => {
                     debug_assert_eq!(la_tidx, self.grm.eof_term_idx());
                     debug_assert_eq!(tstack.len(), 1);
-                    return true;
+                    return (la_idx, true);
                 },
                 None => {
-                    if recoverer.is_none() {
-                        recoverer = Some(match self.rcvry_kind {
-                                             RecoveryKind::CPCTPlus => cpctplus::recoverer(self),
-                                             RecoveryKind::MF => mf::recoverer(self),
-                                             RecoveryKind::None => {
-                                                let la_lexeme = self.next_lexeme(la_idx);
-                                                errors.push(ParseError{state_idx: st,
-                                                                       lexeme_idx: la_idx,
-                                                                       lexeme: la_lexeme,
-                                                                       repairs: vec![]});
-                                                return false;
-                                             }
-                                         });
+                    if isolating {
+                        let err = errors.last_mut().unwrap();
+                        if let Some(anchor_idx) = self.skip_to_anchor(la_idx, pstack, tstack, err) {
+                            la_idx = anchor_idx;
+                            continue;
+                        }
                     }
+                    match self.recover_error(&mut recoverers, &mut recovery_budget, &mut contexts,
+                                             la_idx, pstack, tstack, errors) {
+                        Some(new_la_idx) => {
+                            // The repairs may themselves have shifted past the end of the unit.
+                            isolating = self.recovery.isolate_errors
+                                        && !(la_idx..new_la_idx).any(|i| {
+                                               self.recovery.anchors.contains(&self.next_tidx(i))
+                                           });
+                            la_idx = new_la_idx;
+                        },
+                        None => return (la_idx, false)
+                    }
+                }
+            }
+        }
+    }
 
-                    let before = Instant::now();
-                    let finish_by = before + recovery_budget;
-                    let (new_la_idx, repairs) = recoverer.as_ref()
-                                                         .unwrap()
-                                                         .as_ref()
-                                                         .recover(finish_by,
-                                                                  self,
-                                                                  la_idx,
-                                                                  pstack,
-                                                                  tstack);
-                    let after = Instant::now();
-                    recovery_budget = recovery_budget.checked_sub(after - before)
-                                                     .unwrap_or_else(|| Duration::new(0, 0));
-                    let keep_going = !repairs.is_empty();
-                    let la_lexeme = self.next_lexeme(la_idx);
-                    errors.push(ParseError{state_idx: st, lexeme_idx: la_idx,
-                                           lexeme: la_lexeme, repairs: repairs});
-                    if !keep_going {
-                        return false;
+    /// If the state on top of `pstack` can go to a rule with an operator-precedence sub-parser
+    /// (see `RTParserBuilder::pratt`), and that sub-parser can parse an instance of the rule
+    /// starting at `la_idx` after which the lookahead is valid, push the instance onto `pstack`
+    /// and `tstack` and return `Ok` with the index of the lexeme after it. Otherwise return `Err`
+    /// with the index of the furthest lexeme examined, leaving the LR parser to parse (or recover
+    /// from errors in) the input as normal.
+    fn pratt_parse(&self, la_idx: usize, pstack: &mut PStack, tstack: &mut TStack<TokId>)
+                -> Result<usize, usize>
+    {
+        let st = *pstack.last().unwrap();
+        let mut furthest = la_idx;
+        for rule in self.syntax.pratts {
+            let goto_st = match self.stable.goto(st, rule.nt_idx()) {
+                Some(s) => s,
+                None => continue
+            };
+            match rule.parse(self, la_idx) {
+                Ok((node, new_la_idx)) => {
+                    if self.action(goto_st, self.next_tidx(new_la_idx)).0.is_some() {
+                        pstack.push(goto_st);
+                        tstack.push_node(node);
+                        return Ok(new_la_idx);
                     }
-                    la_idx = new_la_idx;
+                    furthest = furthest.max(new_la_idx);
+                },
+                Err(e) => furthest = furthest.max(e)
+            }
+        }
+        Err(furthest)
+    }
+
+    /// Find the first anchor at or after `la_idx` and unwind `pstack` (and `tstack`) until that
+    /// anchor can be parsed, returning the anchor's index and recording the skip in `err`, or
+    /// `None` (leaving `pstack`, `tstack`, and `err` unchanged) if there is no such anchor or no
+    /// way of unwinding the stacks.
+    fn skip_to_anchor(&self,
+                      la_idx: usize,
+                      pstack: &mut PStack,
+                      tstack: &mut TStack<TokId>,
+                      err: &mut ParseError<TokId>)
+                   -> Option<usize>
+    {
+        let anchor_idx = (la_idx..self.lexemes.len()).find(|&i| {
+                             self.recovery.anchors.contains(&self.next_tidx(i))
+                         })?;
+        let anchor_tidx = self.next_tidx(anchor_idx);
+        let depth = (1..pstack.len() + 1).rev().find(|&d| {
+                        self.valid_la(&pstack[..d], anchor_tidx)
+                    })?;
+        self.unwind_and_skip(depth, la_idx, anchor_idx, pstack, tstack, err);
+        Some(anchor_idx)
+    }
+
+    /// Find the innermost instance of a skippable rule (see `RTParserBuilder::skippable`) which
+    /// the error at `la_idx` may be in, and the first lexeme from which parsing can continue as if
+    /// that instance had never been there. If there is one, unwind `pstack` (and `tstack`) to the
+    /// start of the instance, record the skip in `err`, and return that lexeme's index; otherwise
+    /// return `None`, leaving `pstack`, `tstack`, and `err` unchanged.
+    fn skip_rule(&self,
+                 la_idx: usize,
+                 pstack: &mut PStack,
+                 tstack: &mut TStack<TokId>,
+                 err: &mut ParseError<TokId>)
+              -> Option<usize>
+    {
+        for depth in (1..pstack.len() + 1).rev() {
+            let st_idx = pstack[depth - 1];
+            let nt_idx = match self.recovery.skippable.iter().find(|&&nt| {
+                                   self.stable.goto(st_idx, nt).is_some()
+                               }) {
+                Some(&nt_idx) => nt_idx,
+                None => continue
+            };
+            // As with recoverers' repairs, parsing must then be able to continue for at least
+            // `PARSE_AT_LEAST` lexemes (or accept the input). Only lexemes which can follow the
+            // unwound stack (which `valid_la` checks without copying it) are worth trying.
+            let end = (la_idx + SKIP_AT_MOST).min(self.lexemes.len()) + 1;
+            let resume_idx = (la_idx..end).find(|&i| {
+                if !self.valid_la(&pstack[..depth], self.next_tidx(i)) {
+                    return false;
+                }
+                let mut r_pstack = pstack[..depth].to_vec();
+                let end_la_idx = i + PARSE_AT_LEAST;
+                let r_la_idx = self.lr_upto(None, i, end_la_idx, &mut r_pstack, &mut None);
+                match self.action(*r_pstack.last().unwrap(), self.next_tidx(r_la_idx)).0 {
+                    _ if r_la_idx == end_la_idx => true,
+                    Some(Action::Accept) => true,
+                    _ => false
+                }
+            });
+            if let Some(resume_idx) = resume_idx {
+                self.unwind_and_skip(depth, la_idx, resume_idx, pstack, tstack, err);
+                err.skipped_rule = Some(nt_idx);
+                return Some(resume_idx);
+            }
+        }
+        None
+    }
+
+    /// Unwind `pstack` to `depth` (and `tstack` correspondingly), and skip the lexemes from
+    /// `la_idx` up to (but excluding) `resume_idx`, recording in `err` what was discarded.
+    fn unwind_and_skip(&self,
+                       depth: usize,
+                       la_idx: usize,
+                       resume_idx: usize,
+                       pstack: &mut PStack,
+                       tstack: &mut TStack<TokId>,
+                       err: &mut ParseError<TokId>)
+    {
+        // The input discarded is that of the unwound nodes and the lexemes skipped.
+        let mut spans = tstack.span_from(depth - 1).into_iter().collect::<Vec<_>>();
+        if resume_idx > la_idx {
+            let last = self.lexemes[resume_idx - 1];
+            spans.push((self.lexemes[la_idx].start(), last.start() + last.len()));
+        }
+        if !spans.is_empty() {
+            let start = spans.iter().map(|s| s.0).min().unwrap();
+            let end = spans.iter().map(|s| s.1).max().unwrap();
+            add_discarded(&mut err.discarded, (start, end));
+        }
+        pstack.truncate(depth);
+        tstack.truncate(depth - 1);
+        err.skipped_to = Some(resume_idx);
+    }
+
+    /// Recover from the error at `la_idx`, pushing a `ParseError` for it onto `errors`, and
+    /// return the index of the lexeme to continue parsing from, or `None` if parsing cannot
+    /// continue. Recoverers are reused from, or added to, `recoverers`, and the time spent
+    /// recovering is deducted from `recovery_budget`. If `reuse_recoveries` is on, the repairs of
+    /// earlier errors are reused from, or added to, `contexts`.
+    fn recover_error<'b>(&'b self,
+                         recoverers: &mut Vec<(RecoveryKind, Box<Recoverer<TokId> + 'b>)>,
+                         recovery_budget: &mut Duration,
+                         contexts: &mut RecoveryContexts,
+                         la_idx: usize,
+                         pstack: &mut PStack,
+                         tstack: &mut TStack<TokId>,
+                         errors: &mut Errors<TokId>)
+                      -> Option<usize>
+    {
+        let st = *pstack.last().unwrap();
+        let recovery_error = self.recovery_error();
+        let mut err = ParseError{state_idx: st,
+                                 lexeme_idx: la_idx,
+                                 lexeme: self.next_lexeme(la_idx),
+                                 repairs: vec![],
+                                 applied: None,
+                                 repair_len_capped: false,
+                                 cost_ceiling_reached: false,
+                                 memory_limit_reached: false,
+                                 expansion_limit_reached: false,
+                                 recovery_skipped: false,
+                                 used_fallback: false,
+                                 repairs_cached: false,
+                                 discarded: Vec::new(),
+                                 skipped_to: None,
+                                 skipped_rule: None,
+                                 resumed_at: None,
+                                 recovery_error,
+                                 caused_by: None,
+                                 suggestion: self.suggest_keyword(st, la_idx)};
+        if recovery_error.is_some() {
+            log_debug!("Error at lexeme {} in state {}: can't recover ({:?})",
+                       la_idx, usize::from(st), recovery_error.unwrap());
+            errors.push(err);
+            self.report_error(errors);
+            return None;
+        }
+        let err_start = err.lexeme.start();
+        let rcvry = &self.recovery;
+        let no_recovery = rcvry.no_recovery.iter().any(|&(s, e)| err_start >= s && err_start < e);
+        let decision = if no_recovery || rcvry.max_errors.map_or(false, |n| errors.len() >= n) {
+                           RecoveryDecision::Skip
+                       } else if let Some(d) = rcvry.recovery_decision {
+                           d.decide(&err)
+                       } else {
+                           RecoveryDecision::default()
+                       };
+        let (kind, budget) = match decision {
+            RecoveryDecision::Skip => {
+                err.recovery_skipped = true;
+                (RecoveryKind::None, None)
+            },
+            RecoveryDecision::Recover{recoverer, budget} => {
+                (recoverer.unwrap_or(self.rcvry_kind), budget)
+            }
+        };
+        log_debug!("Error at lexeme {} in state {}: recovering with {:?}",
+                   la_idx, usize::from(st), kind);
+        if !recoverers.iter().any(|&(k, _)| k == kind) {
+            if let Some(r) = self.new_recoverer(kind) {
+                recoverers.push((kind, r));
+            }
+        }
+        let recoverer = match recoverers.iter().find(|&&(k, _)| k == kind) {
+            Some(&(_, ref r)) => r,
+            None => {
+                errors.push(err);
+                self.report_error(errors);
+                return None;
+            }
+        };
+
+        let before = (self.recovery.clock)();
+        let mut budget = budget.map_or(*recovery_budget, |b| b.min(*recovery_budget));
+        let fallback = self.recovery.fallback.filter(|_| kind != RecoveryKind::Panic);
+        if let Some(fb) = fallback {
+            budget = budget.min(fb);
+        }
+        // The parse stack before recovery, which is needed if this recovery might be reused.
+        let context_pstack = if self.recovery.reuse_recoveries {
+                                 Some(pstack.clone())
+                             } else {
+                                 None
+                             };
+        let mut reused = Vec::new();
+        if self.recovery.reuse_recoveries {
+            if let Some(rprs) = contexts.get(self, kind, pstack, la_idx) {
+                reused.extend(rprs.iter()
+                                  .filter(|rprs| self.repairs_succeed(la_idx, pstack, rprs))
+                                  .cloned());
+            }
+        }
+        let (mut new_la_idx, mut repairs, limits) = if reused.is_empty() {
+            recoverer.recover(before + budget, self, la_idx, pstack, tstack)
+        } else {
+            log_debug!("Reusing {} repair sequences for the error at lexeme {}",
+                       reused.len(), la_idx);
+            err.repairs_cached = true;
+            let rprs = reused[0].iter().flat_map(flatten_repair).collect::<Vec<_>>();
+            let new_la_idx = mf::apply_repairs(self, la_idx, pstack, &mut Some(tstack), &rprs);
+            (new_la_idx, reused, RecoveryLimits::default())
+        };
+        if repairs.is_empty() {
+            // The search (which the repair cache, if there is one, seeded) was cut short: the
+            // remembered repairs are better than none.
+            let cached = self.cached_repairs(la_idx, pstack);
+            if !cached.is_empty() {
+                log_debug!("Using {} cached repair sequences for the error at lexeme {}",
+                           cached.len(), la_idx);
+                err.repairs_cached = true;
+                let rprs = cached[0].iter().flat_map(flatten_repair).collect::<Vec<_>>();
+                new_la_idx = mf::apply_repairs(self, la_idx, pstack, &mut Some(tstack), &rprs);
+                repairs = cached;
+            }
+        }
+        if repairs.is_empty() && fallback.is_some() {
+            log_debug!("No repairs found within the fallback budget: falling back to panic mode");
+            // Panic mode is cheap enough that it's given whatever is left of the overall budget
+            // rather than the (presumably tight) fallback budget.
+            let finish_by = before + *recovery_budget;
+            let (nli, rprs, _) = panic::recoverer(self).recover(finish_by,
+                                                                self,
+                                                                la_idx,
+                                                                pstack,
+                                                                tstack);
+            new_la_idx = nli;
+            repairs = rprs;
+            err.used_fallback = true;
+        }
+        // The parser has now applied the first repair sequence (if there is one): record it, and
+        // where parsing resumes, before the repair sequences are rewritten for reporting (e.g. by
+        // merging substitutions or collapsing shifts).
+        if let Some(rprs) = repairs.get(0) {
+            err.applied = Some(rprs.iter().flat_map(flatten_repair).collect());
+            err.resumed_at = Some(new_la_idx);
+        }
+        if repairs.is_empty() && !self.recovery.skippable.is_empty() {
+            if let Some(resume_idx) = self.skip_rule(la_idx, pstack, tstack, &mut err) {
+                log_debug!("Skipped an instance of a skippable rule, resuming at lexeme {}",
+                           resume_idx);
+                err.resumed_at = Some(resume_idx);
+            }
+        }
+        let after = (self.recovery.clock)();
+        log_debug!("Recovery from the error at lexeme {} took {:?} and found {} repair sequences",
+                   la_idx, after - before, repairs.len());
+        *recovery_budget = recovery_budget.checked_sub(after - before)
+                                          .unwrap_or_else(|| Duration::new(0, 0));
+        let repaired = !repairs.is_empty();
+        if let Some((cache, grm_key)) = self.recovery.repair_cache {
+            if repaired && !err.repairs_cached {
+                cache.insert(grm_key, st, self.next_tidx(la_idx), repairs.clone());
+            }
+        }
+        if let Some(pstack) = context_pstack {
+            if repaired && !err.repairs_cached && !err.used_fallback
+               && limits == RecoveryLimits::default()
+            {
+                contexts.insert(self, kind, &pstack, la_idx, &repairs);
+            }
+        }
+        if let Some(ref applied) = err.applied {
+            // Record the lexemes the applied repair sequence deletes.
+            let mut la_idx = la_idx;
+            let mut deleting = false;
+            for r in applied {
+                match *r {
+                    ParseRepair::Delete => {
+                        let l = self.next_lexeme(la_idx);
+                        let span = if deleting {
+                                       (err.discarded.pop().unwrap().0, l.start() + l.len())
+                                   } else {
+                                       (l.start(), l.start() + l.len())
+                                   };
+                        add_discarded(&mut err.discarded, span);
+                        deleting = true;
+                        la_idx += 1;
+                    },
+                    ParseRepair::Shift => {
+                        deleting = false;
+                        la_idx += 1;
+                    },
+                    _ => deleting = false
                 }
             }
         }
+        let repairs = if self.recovery.substitutions {
+                          repairs.into_iter().map(merge_substitutions).collect()
+                      } else {
+                          repairs
+                      };
+        err.repairs = repairs;
+        err.repair_len_capped = limits.repair_len;
+        err.cost_ceiling_reached = limits.cost_ceiling;
+        err.memory_limit_reached = limits.memory;
+        err.expansion_limit_reached = limits.expansions;
+        let resumed_at = err.resumed_at;
+        errors.push(err);
+        self.report_error(errors);
+        resumed_at
+    }
+
+    /// Return those of the repair sequences in the repair cache (if any) for the error at `la_idx`
+    /// with the parse stack `pstack` which are still valid.
+    pub(crate) fn cached_repairs(&self, la_idx: usize, pstack: &PStack) -> Vec<Vec<ParseRepair>> {
+        let (cache, grm_key) = match self.recovery.repair_cache {
+            Some(c) => c,
+            None => return Vec::new()
+        };
+        cache.get(grm_key, *pstack.last().unwrap(), self.next_tidx(la_idx))
+             .into_iter()
+             .filter(|rprs| self.repairs_succeed(la_idx, pstack, rprs))
+             .collect()
+    }
+
+    /// Can each repair in `rprs` be applied in turn to the error at `la_idx` with the parse stack
+    /// `pstack`, after which at least `PARSE_AT_LEAST` lexemes can be parsed (or the input
+    /// accepted)? This is the condition recoverers use for a repair sequence to be successful.
+    fn repairs_succeed(&self, mut la_idx: usize, pstack: &PStack, rprs: &[ParseRepair]) -> bool {
+        let mut pstack = pstack.clone();
+        for r in rprs.iter().flat_map(flatten_repair) {
+            let valid = match r {
+                ParseRepair::Insert(t_idx) => self.valid_la(&pstack, t_idx),
+                ParseRepair::Delete => la_idx < self.lexemes.len(),
+                ParseRepair::Shift => {
+                    la_idx < self.lexemes.len() && self.valid_la(&pstack, self.next_tidx(la_idx))
+                },
+                  ParseRepair::InsertSeq(_)
+                | ParseRepair::Substitute{..}
+                | ParseRepair::Shifts(_) => unreachable!()
+            };
+            if !valid {
+                return false;
+            }
+            la_idx = mf::apply_repairs(self, la_idx, &mut pstack, &mut None, &[r]);
+        }
+        let end_la_idx = la_idx + PARSE_AT_LEAST;
+        let la_idx = self.lr_upto(None, la_idx, end_la_idx, &mut pstack, &mut None);
+        match self.action(*pstack.last().unwrap(), self.next_tidx(la_idx)).0 {
+            _ if la_idx == end_la_idx => true,
+            Some(Action::Accept) => true,
+            _ => false
+        }
+    }
+
+    /// Can `t_idx` be shifted (or accepted) from `pstack`, after any reductions it causes?
+    pub(crate) fn valid_la(&self, pstack: &[StIdx], t_idx: TIdx) -> bool {
+        // Rather than copying `pstack`, the states pushed by reductions are kept in `pushed`, and
+        // once those run out, reductions pop states by shrinking `pstack[..base]`.
+        let mut base = pstack.len();
+        let mut pushed = Vec::new();
+        loop {
+            match self.action(*pushed.last().unwrap_or(&pstack[base - 1]), t_idx).0 {
+                Some(Action::Reduce(p_idx)) => {
+                    let n = self.grm.prod(p_idx).len();
+                    let from_pushed = n.min(pushed.len());
+                    let len = pushed.len() - from_pushed;
+                    pushed.truncate(len);
+                    base -= n - from_pushed;
+                    let prior = *pushed.last().unwrap_or(&pstack[base - 1]);
+                    pushed.push(self.stable.goto(prior, self.grm.prod_to_nonterm(p_idx)).unwrap());
+                },
+                Some(Action::Shift(_)) | Some(Action::Accept) => return true,
+                None => return false
+            }
+        }
+    }
+
+    /// Pass the most recently found error in `errors` to the `on_error` callback, if there is one,
+    /// with its repair sequences in the form `parse` will return them. Since the first repair
+    /// sequence is recorded separately when it is applied, and earlier errors' repair sequences
+    /// are only used by post-processing steps which run once parsing has finished, `errors`
+    /// itself is left unchanged.
+    fn report_error(&self, errors: &Errors<TokId>) {
+        if let Some(f) = self.recovery.on_error {
+            let e = errors.last().unwrap();
+            if self.recovery.shift_reporting == ShiftReporting::Each {
+                f(e);
+            } else {
+                let mut e = e.clone();
+                report_shifts(&mut e.repairs, self.recovery.shift_reporting);
+                f(&e);
+            }
+        }
+    }
+
+    /// Replay the parse up to the error `errs[err_idx]`, applying the repair sequence `lr` applied
+    /// for each earlier error, returning the index of the lexeme the error occurred at.
+    fn replay(&self,
+              errs: &[ParseError<TokId>],
+              err_idx: usize,
+              pstack: &mut PStack,
+              tstack: &mut TStack<TokId>)
+           -> usize
+    {
+        let mut la_idx = 0;
+        for e in &errs[..err_idx] {
+            la_idx = self.lr_upto(None, la_idx, e.lexeme_idx(), pstack, &mut Some(tstack));
+            if let Some(ref rprs) = e.applied {
+                la_idx = mf::apply_repairs(self, la_idx, pstack, &mut Some(tstack), rprs);
+            }
+        }
+        self.lr_upto(None, la_idx, errs[err_idx].lexeme_idx(), pstack, &mut Some(tstack))
+    }
+
+    /// Return a new recoverer of the kind `kind`, or `None` if `kind` is `RecoveryKind::None` or
+    /// if recovery isn't possible at all (see `recovery_error`).
+    fn new_recoverer<'b>(&'b self, kind: RecoveryKind) -> Option<Box<Recoverer<TokId> + 'b>> {
+        if self.recovery_error().is_some() {
+            return None;
+        }
+        match kind {
+            RecoveryKind::CPCTPlus => Some(cpctplus::recoverer(self)),
+            RecoveryKind::MF => Some(mf::recoverer(self)),
+            RecoveryKind::Panic => Some(panic::recoverer(self)),
+            RecoveryKind::Race => Some(race::recoverer(self)),
+            RecoveryKind::None => None
+        }
     }
 
     /// Parse from `la_idx` up to (but excluding) `end_la_idx` mutating `pstack` as parsing occurs.
@@ -215,25 +1140,28 @@ impl<'a, TokId: PrimInt + Unsigned> Parser<'a, TokId> {
                    mut la_idx: usize,
                    end_la_idx: usize,
                    pstack: &mut PStack,
-                   tstack: &mut Option<&mut Vec<Node<TokId>>>)
+                   tstack: &mut Option<&mut TStack<TokId>>)
            -> usize
     {
         assert!(lexeme_prefix.is_none() || end_la_idx == la_idx + 1);
         while la_idx != end_la_idx && la_idx <= self.lexemes.len() {
             let st = *pstack.last().unwrap();
             let la_tidx = if let Some(l) = lexeme_prefix {
-                              TIdx::from(l.tok_id().to_u32().unwrap())
+                              tok_tidx(self.grm, l.tok_id())
                           } else {
                               self.next_tidx(la_idx)
                           };
+            let (mut act, act_tidx) = self.action(st, la_tidx);
+            if lexeme_prefix.is_none() && self.syntax.lookaheads.is_some() {
+                act = self.lookahead_action(la_idx, act_tidx, act, pstack);
+            }
 
-            match self.stable.action(st, la_tidx) {
+            match act {
                 Some(Action::Reduce(prod_id)) => {
                     let nonterm_idx = self.grm.prod_to_nonterm(prod_id);
                     let pop_idx = pstack.len() - self.grm.prod(prod_id).len();
                     if let Some(ref mut tstack_uw) = *tstack {
-                        let nodes = tstack_uw.drain(pop_idx - 1..).collect::<Vec<Node<TokId>>>();
-                        tstack_uw.push(Node::Nonterm{nonterm_idx: nonterm_idx, nodes: nodes});
+                        tstack_uw.reduce(nonterm_idx, self.grm.prod(prod_id).len());
                     }
 
                     pstack.drain(pop_idx..);
@@ -247,7 +1175,7 @@ impl<'a, TokId: PrimInt + Unsigned> Parser<'a, TokId> {
                                         } else {
                                             self.next_lexeme(la_idx)
                                         };
-                        tstack_uw.push(Node::Term{lexeme: la_lexeme});
+                        tstack_uw.shift(self.retag(la_lexeme, act_tidx));
                     }
                     pstack.push(state_id);
                     la_idx += 1;
@@ -263,9 +1191,296 @@ impl<'a, TokId: PrimInt + Unsigned> Parser<'a, TokId> {
         la_idx
     }
 
+    /// Return the action for terminal `t_idx` in state `st_idx`, and the terminal that action is
+    /// for. The latter is normally `t_idx` itself but, if there is no action for `t_idx` and it is
+    /// a contextual keyword (see `RTParserBuilder::contextual`), the action of the terminal it can
+    /// stand in for is returned instead (if there is one).
+    pub fn action(&self, st_idx: StIdx, t_idx: TIdx) -> (Option<Action>, TIdx) {
+        match self.stable.action(st_idx, t_idx) {
+            None => {
+                if let Some(ident) = self.contextual_ident(t_idx) {
+                    if let Some(a) = self.stable.action(st_idx, ident) {
+                        return (Some(a), ident);
+                    }
+                }
+                (None, t_idx)
+            },
+            a => (a, t_idx)
+        }
+    }
+
+    /// If `act` is the table's action for a conflict on `t_idx` (the terminal of the lexeme at
+    /// `la_idx`) in the state at the top of `pstack`, and that conflict has a lookahead hint (see
+    /// `RTParserBuilder::lookahead`), return whichever of `act` and the conflict's other action
+    /// parses further through the hint's lexemes (`act` if neither does). Otherwise return `act`.
+    fn lookahead_action(&self, la_idx: usize, t_idx: TIdx, act: Option<Action>, pstack: &PStack)
+                       -> Option<Action>
+    {
+        let st_idx = *pstack.last().unwrap();
+        let (alt, k) = match self.syntax.lookaheads.and_then(|las| las.get(&(st_idx, t_idx))) {
+            Some(&(alt, k)) => (alt, k),
+            None => return act
+        };
+        // Nested conflicts within the lookahead lexemes are resolved by the table.
+        let psr = Parser{syntax: SyntaxOptions{lookaheads: None, ..self.syntax}, ..*self};
+        let end_la_idx = la_idx + k + 1;
+        let dflt = act?;
+        if psr.try_action(alt, la_idx, end_la_idx, pstack)
+           > psr.try_action(dflt, la_idx, end_la_idx, pstack)
+        {
+            Some(alt)
+        } else {
+            act
+        }
+    }
+
+    /// Return the index of the lexeme (at most `end_la_idx`) parsing reaches, without error
+    /// recovery, if `act` is taken for the lexeme at `la_idx` with the parse stack `pstack`. If
+    /// parsing reaches the end of the input and accepts it, `end_la_idx` is returned.
+    fn try_action(&self, act: Action, la_idx: usize, end_la_idx: usize, pstack: &PStack) -> usize {
+        let mut pstack = pstack.clone();
+        let la_idx = match act {
+            Action::Reduce(prod_id) => {
+                let pop_idx = pstack.len() - self.grm.prod(prod_id).len();
+                pstack.drain(pop_idx..);
+                let prior = *pstack.last().unwrap();
+                pstack.push(self.stable.goto(prior, self.grm.prod_to_nonterm(prod_id)).unwrap());
+                la_idx
+            },
+            Action::Shift(st_idx) => {
+                pstack.push(st_idx);
+                la_idx + 1
+            },
+            Action::Accept => return end_la_idx
+        };
+        let la_idx = self.lr_upto(None, la_idx, end_la_idx, &mut pstack, &mut None);
+        let (act, _) = self.action(*pstack.last().unwrap(), self.next_tidx(la_idx));
+        if la_idx < end_la_idx && act == Some(Action::Accept) {
+            return end_la_idx;
+        }
+        la_idx
+    }
+
+    /// If `t_idx` is a contextual keyword, return the terminal it can stand in for.
+    pub fn contextual_ident(&self, t_idx: TIdx) -> Option<TIdx> {
+        self.syntax.contextual.iter()
+                       .find(|&&(kw, _)| kw == t_idx)
+                       .map(|&(_, ident)| ident)
+    }
+
+    /// Return the cost of a repair sequence of cost `cf` extended by inserting or deleting `t_idx`,
+    /// or `None` if that would exceed `max_repair_cost`.
+    pub(crate) fn add_cost(&self, cf: u32, t_idx: TIdx) -> Option<u32> {
+        let c = cf.saturating_add(u32::from((self.term_cost)(t_idx)));
+        if c > self.recovery.max_repair_cost {
+            None
+        } else {
+            Some(c)
+        }
+    }
+
+    /// Stably sort the (simplified) repair sequences `rprs` for an error at `la_idx` so that those
+    /// which resume parsing by shifting an anchor (see `RTParserBuilder::anchor`) come first.
+    pub(crate) fn prefer_anchors(&self, la_idx: usize, rprs: &mut Vec<Vec<ParseRepair>>) {
+        if self.recovery.anchors.is_empty() {
+            return;
+        }
+        rprs.sort_by_key(|r| !self.shifts_anchor(la_idx, r));
+    }
+
+    /// If `minimal_span` is set, stably sort the (simplified) repair sequences `rprs` for an error
+    /// at `la_idx` so that those whose edits span the fewest bytes of the input come first.
+    pub(crate) fn prefer_minimal_span(&self, la_idx: usize, rprs: &mut Vec<Vec<ParseRepair>>) {
+        if !self.recovery.minimal_span {
+            return;
+        }
+        rprs.sort_by_key(|r| self.edit_span(la_idx, r));
+    }
+
+    /// If `typo_input` is set, stably sort the (simplified) repair sequences `rprs` for an error at
+    /// `la_idx` so that those containing a substitution (a `Delete` adjacent to an `Insert`) whose
+    /// deleted text is similar to the inserted terminal's name (e.g. deleting `retrun` and
+    /// inserting `return`) come first, most similar first.
+    pub(crate) fn prefer_similar(&self, la_idx: usize, rprs: &mut Vec<Vec<ParseRepair>>) {
+        let input = match self.recovery.typo_input {
+            Some(input) => input,
+            None => return
+        };
+        rprs.sort_by_key(|r| self.substitution_dissimilarity(input, la_idx, r));
+    }
+
+    /// If `typo_input` is set, return the keyword (i.e. a terminal whose name is an identifier)
+    /// valid in state `st_idx` whose name is most similar to the text of the lexeme at `la_idx`,
+    /// provided that they are similar enough for the lexeme to plausibly be a misspelling of it.
+    fn suggest_keyword(&self, st_idx: StIdx, la_idx: usize) -> Option<TIdx> {
+        let input = self.recovery.typo_input?;
+        if la_idx >= self.lexemes.len() {
+            return None;
+        }
+        let text = lexeme_text(input, &self.lexemes[la_idx]).to_lowercase();
+        let text_len = text.chars().count();
+        let la_tidx = self.next_tidx(la_idx);
+        let mut best: Option<(usize, TIdx)> = None;
+        for t_idx in self.stable.state_actions(st_idx) {
+            if t_idx == la_tidx || t_idx == self.grm.eof_term_idx() {
+                continue;
+            }
+            let name = match self.grm.term_name(t_idx) {
+                Some(n) if n.chars().next().map_or(false, |c| c.is_alphabetic())
+                           && n.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+                    n.to_lowercase()
+                },
+                _ => continue
+            };
+            let name_len = name.chars().count();
+            // Cheaply rule out keywords whose length alone puts them too far away.
+            if name_len.max(text_len) - name_len.min(text_len) > MAX_SUGGESTION_DISTANCE {
+                continue;
+            }
+            // The edit distance must be small relative to the keyword's length too, so that
+            // e.g. `x` isn't taken to be a misspelling of `if`.
+            let d = edit_distance(&text, &name);
+            if d > 0 && d <= MAX_SUGGESTION_DISTANCE && d * 2 < name_len
+               && best.map_or(true, |(bd, _)| d < bd) {
+                best = Some((d, t_idx));
+            }
+        }
+        best.map(|(_, t_idx)| t_idx)
+    }
+
+    /// Return the dissimilarity (0 for identical, 100 for completely different) of the most
+    /// similar substitution in the repair sequence `rprs` applied at `la_idx`, or 100 if there
+    /// are no substitutions.
+    fn substitution_dissimilarity(&self, input: &Source, mut la_idx: usize, rprs: &[ParseRepair])
+                               -> usize
+    {
+        let mut best = 100;
+        let mut prev: Option<(&ParseRepair, usize)> = None;
+        for r in rprs {
+            let (ins, del_idx) = match (prev, r) {
+                (Some((&ParseRepair::Delete, d_idx)), &ParseRepair::Insert(t_idx)) => {
+                    (t_idx, d_idx)
+                },
+                (Some((&ParseRepair::Insert(t_idx), _)), &ParseRepair::Delete) => (t_idx, la_idx),
+                _ => {
+                    prev = Some((r, la_idx));
+                    match *r {
+                        ParseRepair::Delete | ParseRepair::Shift => la_idx += 1,
+                        ParseRepair::Insert(_) | ParseRepair::InsertSeq(_) => (),
+                        ParseRepair::Substitute{delete_span, ..} => la_idx += delete_span,
+                        ParseRepair::Shifts(n) => la_idx += n
+                    }
+                    continue;
+                }
+            };
+            let del = lexeme_text(input, &self.next_lexeme(del_idx)).to_lowercase();
+            let ins = self.grm.term_name(ins).unwrap_or("").to_lowercase();
+            let len = del.chars().count().max(ins.chars().count());
+            if len > 0 {
+                best = best.min(edit_distance(&del, &ins) * 100 / len);
+            }
+            prev = Some((r, la_idx));
+            if *r == ParseRepair::Delete {
+                la_idx += 1;
+            }
+        }
+        best
+    }
+
+    /// Return the number of bytes between the start of the first edit (i.e. insertion or
+    /// deletion) and the end of the last edit made by the repair sequence `rprs` applied at
+    /// `la_idx`. Insertions are zero bytes wide, so a sequence consisting only of insertions at a
+    /// single point has a span of 0.
+    fn edit_span(&self, mut la_idx: usize, rprs: &[ParseRepair]) -> usize {
+        let mut span: Option<(usize, usize)> = None;
+        for r in rprs {
+            let lexeme = self.next_lexeme(la_idx);
+            let (start, end) = match *r {
+                ParseRepair::Insert(_) | ParseRepair::InsertSeq(_) => {
+                    (lexeme.start(), lexeme.start())
+                },
+                ParseRepair::Delete => {
+                    la_idx += 1;
+                    (lexeme.start(), lexeme.start() + lexeme.len())
+                },
+                ParseRepair::Substitute{delete_span, ..} => {
+                    la_idx += delete_span;
+                    let last = self.next_lexeme(la_idx - 1);
+                    (lexeme.start(), last.start() + last.len())
+                },
+                ParseRepair::Shift => {
+                    la_idx += 1;
+                    continue;
+                },
+                ParseRepair::Shifts(n) => {
+                    la_idx += n;
+                    continue;
+                }
+            };
+            span = Some(match span {
+                Some((s, e)) => (s.min(start), e.max(end)),
+                None => (start, end)
+            });
+        }
+        span.map(|(s, e)| e - s).unwrap_or(0)
+    }
+
+    /// Does the repair sequence `rprs`, applied at `la_idx`, shift an anchor? Since simplified
+    /// repair sequences have their trailing shifts removed, the lexeme after the sequence is also
+    /// considered.
+    fn shifts_anchor(&self, mut la_idx: usize, rprs: &[ParseRepair]) -> bool {
+        for r in rprs {
+            match *r {
+                ParseRepair::Insert(_) | ParseRepair::InsertSeq(_) => (),
+                ParseRepair::Delete => la_idx += 1,
+                ParseRepair::Substitute{delete_span, ..} => la_idx += delete_span,
+                ParseRepair::Shift => {
+                    if self.recovery.anchors.contains(&self.next_tidx(la_idx)) {
+                        return true;
+                    }
+                    la_idx += 1;
+                },
+                ParseRepair::Shifts(n) => {
+                    for _ in 0..n {
+                        if self.recovery.anchors.contains(&self.next_tidx(la_idx)) {
+                            return true;
+                        }
+                        la_idx += 1;
+                    }
+                }
+            }
+        }
+        self.recovery.anchors.contains(&self.next_tidx(la_idx))
+    }
+
+    /// Is inserting `t_idx` in state `st_idx` a repair that should not be suggested? This is the
+    /// case when `t_idx` is a contextual keyword and the terminal it stands in for is valid in
+    /// `st_idx`, since inserting the keyword is then indistinguishable from inserting (e.g.) an
+    /// identifier.
+    pub(crate) fn redundant_insert(&self, st_idx: StIdx, t_idx: TIdx) -> bool {
+        match self.contextual_ident(t_idx) {
+            Some(ident) => self.stable.action(st_idx, ident).is_some(),
+            None => false
+        }
+    }
+
+    /// Return `lexeme` with its token ID changed to `t_idx` (keeping its channel, if any). This
+    /// is needed when a contextual keyword is shifted as the terminal it stands in for.
+    fn retag(&self, lexeme: Lexeme<TokId>, t_idx: TIdx) -> Lexeme<TokId> {
+        if tok_tidx(self.grm, lexeme.tok_id()) == t_idx {
+            lexeme
+        } else {
+            let mut tok_id = TokId::from(u32::from(t_idx)).unwrap();
+            if channels_fit::<TokId>(self.grm) {
+                tok_id = tok_id.with_channel(lexeme.tok_id().channel());
+            }
+            Lexeme::new(tok_id, lexeme.start(), lexeme.len())
+        }
+    }
+
     /// Return a `Lexeme` for the next lemexe (if `la_idx` == `self.lexemes.len()` this will be
     /// a lexeme constructed to look as if contains the EOF terminal).
-    pub(crate) fn next_lexeme(&self, la_idx: usize) -> Lexeme<TokId>
+    pub fn next_lexeme(&self, la_idx: usize) -> Lexeme<TokId>
     {
         let llen = self.lexemes.len();
         debug_assert!(la_idx <= llen);
@@ -281,17 +1496,48 @@ impl<'a, TokId: PrimInt + Unsigned> Parser<'a, TokId> {
                     last_la.start() + last_la.len()
                 };
 
-            Lexeme::new(TokId::from(u32::from(self.grm.eof_term_idx())).unwrap(), last_la_end, 0)
+            // If `TokId` is too small to represent the EOF terminal, this can only be an error's
+            // lexeme (see `RecoveryError::TokIdTooSmall`).
+            let tok_id = TokId::from(u32::from(self.grm.eof_term_idx()))
+                               .unwrap_or_else(TokId::max_value);
+            Lexeme::new(tok_id, last_la_end, 0)
         }
     }
 
+    /// If errors can't be recovered from with this parser's grammar and options, return why.
+    /// Recoverers can assume that this returns `None`: they are never called otherwise.
+    pub fn recovery_error(&self) -> Option<RecoveryError> {
+        for i in 0..self.grm.terms_len() {
+            let t_idx = TIdx::from(i);
+            if (self.term_cost)(t_idx) == 0 {
+                return Some(RecoveryError::ZeroTermCost(t_idx));
+            }
+            if TokId::from(u32::from(t_idx)).is_none() {
+                return Some(RecoveryError::TokIdTooSmall(t_idx));
+            }
+        }
+        None
+    }
+
+    /// Return (at most) the `k` lexemes starting at `la_idx`, without altering the parser's state.
+    /// Fewer than `k` lexemes are returned if the end of the input is reached (the EOF lexeme is
+    /// never included). This allows code which needs to make decisions based on more than the next
+    /// lexeme (e.g. a hook disambiguating a construct which LR(1) can't) to look further ahead in a
+    /// controlled fashion: the text of each lexeme can then be obtained with `lexeme_str`.
+    pub fn peek(&self, la_idx: usize, k: usize) -> &[Lexeme<TokId>] {
+        let llen = self.lexemes.len();
+        let start = if la_idx < llen { la_idx } else { llen };
+        let end = if k < llen - start { start + k } else { llen };
+        &self.lexemes[start..end]
+    }
+
     /// Return the `TIdx` of the next lexeme (if `la_idx` == `self.lexemes.len()` this will be the
     /// EOF `TIdx`).
-    pub(crate) fn next_tidx(&self, la_idx: usize) -> TIdx {
+    pub fn next_tidx(&self, la_idx: usize) -> TIdx {
         let ll = self.lexemes.len();
         debug_assert!(la_idx <= ll);
         if la_idx < ll {
-            TIdx::from(self.lexemes[la_idx].tok_id().to_u32().unwrap())
+            tok_tidx(self.grm, self.lexemes[la_idx].tok_id())
         } else {
             self.grm.eof_term_idx()
         }
@@ -304,31 +1550,39 @@ impl<'a, TokId: PrimInt + Unsigned> Parser<'a, TokId> {
     /// Note that if `lexeme_prefix` is specified, `la_idx` will still be incremented, and thus
     /// `end_la_idx` *must* be set to `la_idx + 1` in order that the parser doesn't skip the real
     /// lexeme at position `la_idx`.
-    pub(crate) fn lr_cactus(&self,
-                            lexeme_prefix: Option<Lexeme<TokId>>,
-                            mut la_idx: usize,
-                            end_la_idx: usize,
-                            mut pstack: Cactus<StIdx>,
-                            tstack: &mut Option<&mut Vec<Node<TokId>>>)
-      -> (usize, Cactus<StIdx>)
+    ///
+    /// Returns the index of the lexeme parsing stopped at and the resulting parse stack. Since
+    /// `pstack` is a cactus stack, the caller's copy is left untouched, so many alternative parses
+    /// can cheaply be simulated from the same starting point. If `tstack` is `None`, no parse tree
+    /// is constructed.
+    ///
+    /// This function is the basis of lrpar's recoverers and is part of lrpar's stable API, so
+    /// that new recovery algorithms can be prototyped outside lrpar (see
+    /// `RTParserBuilder::parser`).
+    pub fn lr_cactus(&self,
+                     lexeme_prefix: Option<Lexeme<TokId>>,
+                     mut la_idx: usize,
+                     end_la_idx: usize,
+                     mut pstack: Cactus<StIdx>,
+                     tstack: &mut Option<&mut TStack<TokId>>)
+              -> (usize, Cactus<StIdx>)
     {
         assert!(lexeme_prefix.is_none() || end_la_idx == la_idx + 1);
         while la_idx != end_la_idx {
             let st = *pstack.val().unwrap();
             let la_tidx = if let Some(l) = lexeme_prefix {
-                              TIdx::from(l.tok_id().to_u32().unwrap())
+                              tok_tidx(self.grm, l.tok_id())
                           } else {
                               self.next_tidx(la_idx)
                           };
+            let (act, act_tidx) = self.action(st, la_tidx);
 
-            match self.stable.action(st, la_tidx) {
+            match act {
                 Some(Action::Reduce(prod_id)) => {
                     let nonterm_idx = self.grm.prod_to_nonterm(prod_id);
                     let pop_num = self.grm.prod(prod_id).len();
                     if let Some(ref mut tstack_uw) = *tstack {
-                        let nodes = tstack_uw.drain(pstack.len() - pop_num - 1..)
-                                             .collect::<Vec<Node<TokId>>>();
-                        tstack_uw.push(Node::Nonterm{nonterm_idx: nonterm_idx, nodes: nodes});
+                        tstack_uw.reduce(nonterm_idx, pop_num);
                     }
 
                     for _ in 0..pop_num {
@@ -344,7 +1598,7 @@ impl<'a, TokId: PrimInt + Unsigned> Parser<'a, TokId> {
                                         } else {
                                             self.next_lexeme(la_idx)
                                         };
-                        tstack_uw.push(Node::Term{lexeme: la_lexeme});
+                        tstack_uw.shift(self.retag(la_lexeme, act_tidx));
                     }
                     pstack = pstack.child(state_id);
                     la_idx += 1;
@@ -363,22 +1617,186 @@ impl<'a, TokId: PrimInt + Unsigned> Parser<'a, TokId> {
         }
         (la_idx, pstack)
     }
+
+    /// Return the state of a parser which has not yet parsed anything.
+    pub fn initial_state(&self) -> ParseState<TokId> {
+        ParseState{pstack: vec![StIdx::from(0 as u32)], tstack: TStack::new(), la_idx: 0}
+    }
+
+    /// Try to parse from `st`'s lookahead up to (but excluding) `end_la_idx`, without error
+    /// recovery. If this succeeds, `st` is advanced to `end_la_idx` and `true` is returned. If an
+    /// error is encountered, `st` is left unchanged and `false` is returned, so that the caller can
+    /// try an alternative (e.g. parsing the same region with some lexemes retagged, using a
+    /// `Parser` for different lexemes): this allows a few non-LR corners of a language to be
+    /// handled by backtracking. Failed attempts are cheap, since they do not build any part of the
+    /// parse tree: only a successful attempt is parsed a second time to build its part of the
+    /// tree. If more control is needed, `ParseState`s can be cloned as checkpoints, though the
+    /// cost of doing so is proportional to the size of the trees on the stack.
+    pub fn try_advance(&self, st: &mut ParseState<TokId>, end_la_idx: usize) -> bool {
+        let mut pstack = st.pstack.clone();
+        if self.lr_upto(None, st.la_idx, end_la_idx, &mut pstack, &mut None) != end_la_idx {
+            return false;
+        }
+        st.la_idx = self.lr_upto(None, st.la_idx, end_la_idx, &mut st.pstack,
+                                 &mut Some(&mut st.tstack));
+        true
+    }
+
+    /// Parse from `st`'s lookahead up to (but excluding) `end_la_idx`, which must be no greater
+    /// than the number of lexemes, with error recovery (if it is turned on), appending any errors
+    /// encountered to `errors`. Parsing stops as soon as the lookahead reaches `end_la_idx`, before
+    /// the lexeme there has been used in any way, so `st` can later be passed to a `Parser` for
+    /// lexemes which differ from `end_la_idx` onwards. However, a repair sequence which spans
+    /// `end_la_idx` will leave `st`'s lookahead beyond it. Note too that error recovery looks past
+    /// `end_la_idx` (to check that parsing can continue after a repair sequence), so only if no
+    /// errors were encountered does `st` not depend on the lexemes from `end_la_idx` onwards.
+    /// Returns `true` if parsing reached `end_la_idx`, or `false` if it stopped at an error which
+    /// could not be recovered from (in which case `st` is left where that error occurred).
+    pub fn advance(&self,
+                   st: &mut ParseState<TokId>,
+                   end_la_idx: usize,
+                   errors: &mut Vec<ParseError<TokId>>)
+                -> bool
+    {
+        assert!(end_la_idx <= self.lexemes.len());
+        let (la_idx, _) = self.lr_until(st.la_idx,
+                                        Some(end_la_idx),
+                                        &mut st.pstack,
+                                        &mut st.tstack,
+                                        errors);
+        st.la_idx = la_idx;
+        la_idx >= end_la_idx
+    }
+
+    /// Parse the rest of the input from `st` (with error recovery, if it is turned on), returning
+    /// the same result as `RTParserBuilder::parse` would for the whole input.
+    pub fn finish(&self, mut st: ParseState<TokId>)
+               -> Result<Node<TokId>, (Option<Node<TokId>>, Vec<ParseError<TokId>>)>
+    {
+        let mut errors = Vec::new();
+        let accpt = self.lr(st.la_idx, &mut st.pstack, &mut st.tstack, &mut errors);
+        match (accpt, errors.is_empty()) {
+            (true, true)   => Ok(st.tstack.into_node().unwrap()),
+            (true, false)  => Err((st.tstack.into_node(), errors)),
+            (false, false) => Err((None, errors)),
+            (false, true)  => panic!("Internal error")
+        }
+    }
+}
+
+/// The state of a parse (its parse stack, tree stack, and lookahead index) part way through the
+/// input, as manipulated by `Parser::try_advance`, `Parser::advance`, and `Parser::finish`. A
+/// state is independent of the lexemes it was created from, so it can be passed between `Parser`s
+/// for different lexemes (as long as the lexemes already parsed are the same).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseState<TokId: TokenId> {
+    pstack: PStack,
+    tstack: TStack<TokId>,
+    la_idx: usize
+}
+
+impl<TokId: TokenId> ParseState<TokId> {
+    /// Return the index of the next lexeme to be parsed.
+    pub fn la_idx(&self) -> usize {
+        self.la_idx
+    }
+
+    /// Return the parse stack.
+    pub fn pstack(&self) -> &[StIdx] {
+        &self.pstack
+    }
+
+    /// Return the tree stack, which has one fewer element than the parse stack.
+    pub fn tstack(&self) -> &[Node<TokId>] {
+        self.tstack.nodes()
+    }
 }
 
-pub trait Recoverer<TokId: PrimInt + Unsigned> {
+pub trait Recoverer<TokId: TokenId> {
+    /// Attempt to recover from an error at `la_idx`. Returns a tuple `(new_la_idx, repairs,
+    /// limits)` where `limits` records which limits, if any, cut the search short.
     fn recover(&self, Instant, &Parser<TokId>, usize, &mut PStack, &mut TStack<TokId>)
-           -> (usize, Vec<Vec<ParseRepair>>);
+           -> (usize, Vec<Vec<ParseRepair>>, RecoveryLimits);
 }
 
+/// Records which of the limits on a recoverer's search for repairs were reached.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RecoveryLimits {
+    /// Were partial repair sequences discarded for reaching `RTParserBuilder::max_repair_len`?
+    pub repair_len: bool,
+    /// Were partial repair sequences discarded for reaching `RTParserBuilder::max_repair_cost`?
+    pub cost_ceiling: bool,
+    /// Was the search cut short for reaching `RTParserBuilder::max_recovery_memory`?
+    pub memory: bool,
+    /// Was the search cut short for reaching `RTParserBuilder::max_recovery_expansions`?
+    pub expansions: bool
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum RecoveryKind {
     CPCTPlus,
     MF,
+    /// Delete lexemes until the parser can continue. This is fast but rarely finds the repairs a
+    /// human would make, so it is mostly useful as a fallback (see `RTParserBuilder::fallback`).
+    Panic,
+    /// Race CPCT+ against panic mode, using CPCT+'s repairs unless it runs out of time, or would
+    /// have to consider repairs more expensive than panic mode's, first. This bounds the cost of
+    /// each recovery by that of panic mode, improving the worst case without changing the repairs
+    /// found in the common case.
+    Race,
     None
 }
 
+/// Why the parser could not attempt to recover from a parse error at all (see
+/// `ParseError::recovery_error`). Recovery would otherwise abort the process.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecoveryError {
+    /// The cost of the terminal (see `RTParserBuilder::term_costs`) is zero, which would allow
+    /// recoverers to consider infinitely many repairs of the same cost.
+    ZeroTermCost(TIdx),
+    /// The terminal can't be represented as a `TokId`, so recoverers can't insert it. If the error
+    /// is at the end of the input, the token ID of its lexeme is `TokId::max_value()`.
+    TokIdTooSmall(TIdx)
+}
+
+/// A `RefCell`-wrapped `FnMut` passed to `RTParserBuilder::recovery_decision`. Storing it behind
+/// this trait, rather than as a `RefCell` of an `FnMut` trait object, keeps the builder (and the
+/// parsers it creates) covariant in their lifetime, since `RefCell` is invariant in its contents.
+pub(crate) trait RecoveryDecider<TokId: Copy> {
+    fn decide(&self, err: &ParseError<TokId>) -> RecoveryDecision;
+}
+
+impl<TokId: Copy, F> RecoveryDecider<TokId> for RefCell<F>
+                                          where F: FnMut(&ParseError<TokId>) -> RecoveryDecision
+{
+    fn decide(&self, err: &ParseError<TokId>) -> RecoveryDecision {
+        (&mut *self.borrow_mut())(err)
+    }
+}
+
+/// What to do about a parse error before recovering from it (see
+/// `RTParserBuilder::recovery_decision`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecoveryDecision {
+    /// Recover from the error using `recoverer` (or, if `None`, the parser's recoverer),
+    /// spending at most `budget` (or, if `None`, the remainder of the parser's time budget)
+    /// doing so. A `budget` larger than the remainder of the parser's time budget has no effect.
+    Recover{recoverer: Option<RecoveryKind>, budget: Option<Duration>},
+    /// Don't recover from the error: parsing stops, and the error (which has no repairs) reports
+    /// `recovery_skipped() == true`.
+    Skip
+}
+
+impl Default for RecoveryDecision {
+    /// Recover as the parser would have done without being asked.
+    fn default() -> Self {
+        RecoveryDecision::Recover{recoverer: None, budget: None}
+    }
+}
+
 /// Parse the lexemes. On success return a parse tree. On failure, return a parse tree (if all the
 /// input was consumed) or `None` otherwise, and a vector of `ParseError`s.
-pub fn parse<TokId: PrimInt + Unsigned>
+pub fn parse<TokId: TokenId>
        (grm: &YaccGrammar, sgraph: &StateGraph, stable: &StateTable,
         lexemes: &Lexemes<TokId>)
     -> Result<Node<TokId>, (Option<Node<TokId>>, Vec<ParseError<TokId>>)>
@@ -390,7 +1808,7 @@ pub fn parse<TokId: PrimInt + Unsigned>
 /// tree. On failure, return a parse tree (if all the input was consumed) or `None` otherwise, and
 /// a vector of `ParseError`s.
 pub fn parse_rcvry
-       <TokId: PrimInt + Unsigned, F>
+       <TokId: TokenId, F>
        (rcvry_kind: RecoveryKind,
         grm: &YaccGrammar,
         term_cost: F,
@@ -400,231 +1818,3287 @@ pub fn parse_rcvry
     -> Result<Node<TokId>, (Option<Node<TokId>>, Vec<ParseError<TokId>>)>
     where F: Fn(TIdx) -> u8
 {
-    Parser::parse(rcvry_kind, grm, term_cost, sgraph, stable, lexemes)
+    RTParserBuilder::new(grm, sgraph, stable).recoverer(rcvry_kind)
+                                             .term_costs(&term_cost)
+                                             .parse(lexemes)
 }
 
-/// After a parse error is encountered, the parser attempts to find a way of recovering. Each entry
-/// in the sequence of repairs is represented by a `ParseRepair`.
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub enum ParseRepair {
-    /// Insert a `Symbol::Term`.
-    Insert(TIdx),
-    /// Insert one of the sequences of `Symbol::Term`s.
-    InsertSeq(Vec<Vec<TIdx>>),
-    /// Delete a symbol.
-    Delete,
-    /// Shift a symbol.
-    Shift
+/// A run-time parser builder, allowing the various parsing and recovery options to be set before
+/// parsing occurs. Options which are not explicitly set take on sensible defaults.
+pub struct RTParserBuilder<'a, TokId: TokenId> where TokId: 'a {
+    grm: &'a YaccGrammar,
+    sgraph: &'a StateGraph,
+    stable: &'a StateTable,
+    rcvry_kind: RecoveryKind,
+    term_costs: &'a Fn(TIdx) -> u8,
+    max_repair_len: Option<usize>,
+    max_repair_cost: u32,
+    max_recovery_memory: Option<usize>,
+    max_recovery_expansions: Option<usize>,
+    search_priority: Option<&'a Fn(u32, StIdx, usize) -> u32>,
+    max_errors: Option<usize>,
+    no_recovery: &'a [(usize, usize)],
+    repair_cache: Option<(&'a RepairCache, u64)>,
+    reuse_recoveries: bool,
+    clock: &'a Fn() -> Instant,
+    fallback: Option<Duration>,
+    contextual: Vec<(TIdx, TIdx)>,
+    anchors: Vec<TIdx>,
+    skippable: Vec<NTIdx>,
+    lookaheads: HashMap<(StIdx, TIdx), (Action, usize)>,
+    pratts: Vec<PrattRule>,
+    case_insensitive: Vec<TIdx>,
+    isolate_errors: bool,
+    minimal_span: bool,
+    typo_input: Option<Box<Source + 'a>>,
+    substitutions: bool,
+    consistent_repairs: bool,
+    note_cascades: bool,
+    on_error: Option<&'a Fn(&ParseError<TokId>)>,
+    recovery_decision: Option<&'a RecoveryDecider<TokId>>,
+    empty_input: EmptyInput,
+    shift_reporting: ShiftReporting,
+    phantom: PhantomData<TokId>
 }
 
-/// Records a single parse error.
-#[derive(Clone, Debug, PartialEq)]
-pub struct ParseError<TokId: Copy> {
-    state_idx: StIdx,
-    lexeme_idx: usize,
-    lexeme: Lexeme<TokId>,
-    repairs: Vec<Vec<ParseRepair>>
-}
+impl<'a, TokId: TokenId> RTParserBuilder<'a, TokId> {
+    /// Create a new run-time parser from a `YaccGrammar`, a `StateGraph`, and a `StateTable`. By
+    /// default the parser uses `RecoveryKind::MF` and gives each terminal a cost of 1.
+    pub fn new(grm: &'a YaccGrammar, sgraph: &'a StateGraph, stable: &'a StateTable) -> Self {
+        RTParserBuilder{grm,
+                        sgraph,
+                        stable,
+                        rcvry_kind: RecoveryKind::MF,
+                        term_costs: &|_| 1,
+                        max_repair_len: None,
+                        max_repair_cost: DEFAULT_MAX_REPAIR_COST,
+                        max_recovery_memory: None,
+                        max_recovery_expansions: None,
+                        search_priority: None,
+                        max_errors: None,
+                        no_recovery: &[],
+                        repair_cache: None,
+                        reuse_recoveries: false,
+                        clock: &Instant::now,
+                        fallback: None,
+                        contextual: Vec::new(),
+                        anchors: Vec::new(),
+                        skippable: Vec::new(),
+                        lookaheads: HashMap::new(),
+                        pratts: Vec::new(),
+                        case_insensitive: Vec::new(),
+                        isolate_errors: false,
+                        minimal_span: false,
+                        typo_input: None,
+                        substitutions: false,
+                        consistent_repairs: false,
+                        note_cascades: false,
+                        on_error: None,
+                        recovery_decision: None,
+                        empty_input: EmptyInput::Recover,
+                        shift_reporting: ShiftReporting::Each,
+                        phantom: PhantomData}
+    }
 
-impl<TokId: Copy> ParseError<TokId> {
-    /// Return the state table index where this error was detected.
-    pub fn state_idx(&self) -> StIdx {
-        self.state_idx
+    /// Set the recoverer for this parser to `rcvry_kind`.
+    pub fn recoverer(mut self, rcvry_kind: RecoveryKind) -> Self {
+        self.rcvry_kind = rcvry_kind;
+        self
     }
 
-    /// Return the lexeme where this error was detected.
-    pub fn lexeme_idx(&self) -> usize {
-        self.lexeme_idx
+    /// Set the function which returns the cost of inserting or deleting a given terminal. Every
+    /// terminal must have a cost greater than zero: otherwise no attempt is made to recover from
+    /// errors (see `ParseError::recovery_error`).
+    pub fn term_costs(mut self, term_costs: &'a Fn(TIdx) -> u8) -> Self {
+        self.term_costs = term_costs;
+        self
     }
 
-    /// Return the lexeme where this error was detected.
-    pub fn lexeme(&self) -> &Lexeme<TokId> {
-        &self.lexeme
+    /// Limit the number of insertions and deletions in any repair sequence to `max_repair_len`
+    /// (`Shift`s are not counted). Long repair sequences often mean that recovery has started from
+    /// the wrong place, so rather than letting the search slowly degrade, partial repair sequences
+    /// which reach this limit are not explored further; the resulting `ParseError` then reports
+    /// `repair_len_capped() == true`. By default there is no limit.
+    pub fn max_repair_len(mut self, max_repair_len: usize) -> Self {
+        self.max_repair_len = Some(max_repair_len);
+        self
     }
 
-    /// Return the repairs found that would fix this error. Note that there are infinite number of
-    /// possible repairs for any error, so this is by definition a (finite) subset.
-    pub fn repairs(&self) -> &Vec<Vec<ParseRepair>> {
-        &self.repairs
+    /// Set the maximum cost of any repair sequence to `max_repair_cost`. Partial repair sequences
+    /// whose cost would exceed this are not explored further, and the resulting `ParseError` then
+    /// reports `cost_ceiling_reached() == true`. As well as bounding the search, this guarantees
+    /// that no term cost function, however extreme, can cause repair costs to overflow. Defaults to
+    /// `DEFAULT_MAX_REPAIR_COST`.
+    pub fn max_repair_cost(mut self, max_repair_cost: u32) -> Self {
+        self.max_repair_cost = max_repair_cost;
+        self
     }
-}
 
-#[cfg(test)]
-pub(crate) mod test {
-    use std::collections::HashMap;
+    /// Limit the memory used by a recoverer's search for repairs to approximately
+    /// `max_recovery_memory` bytes. The time budget bounds how long a search can run, but not how
+    /// much memory it uses in the meantime: adversarial inputs can cause a search to store a great
+    /// many partial repair sequences before its time runs out. Searches which reach this limit
+    /// stop, returning whatever minimal cost repairs they had found so far (possibly none); the
+    /// resulting `ParseError` then reports `memory_limit_reached() == true`. The memory used is
+    /// estimated from the number of partial repair sequences stored, so this is only a rough
+    /// bound. By default there is no limit.
+    pub fn max_recovery_memory(mut self, max_recovery_memory: usize) -> Self {
+        self.max_recovery_memory = Some(max_recovery_memory);
+        self
+    }
 
-    use cfgrammar::yacc::{YaccGrammar, yacc_grm, YaccKind};
-    use lrlex::{build_lex, Lexeme};
-    use lrtable::{Minimiser, from_yacc};
-    use num_traits::ToPrimitive;
-    use super::*;
+    /// Limit a recoverer's search for repairs to generating the neighbours of at most
+    /// `max_recovery_expansions` partial repair sequences. Searches check the time budget every
+    /// few steps, but a clock can be too coarse, or too expensive to call often, to stop
+    /// pathological searches promptly: this is a hard, clock-independent, bound on the work a
+    /// search does, which also makes recovery deterministic. Searches which reach this limit stop,
+    /// returning whatever minimal cost repairs they had found so far (possibly none); the
+    /// resulting `ParseError` then reports `expansion_limit_reached() == true`. By default there
+    /// is no limit.
+    pub fn max_recovery_expansions(mut self, max_recovery_expansions: usize) -> Self {
+        self.max_recovery_expansions = Some(max_recovery_expansions);
+        self
+    }
 
-    pub(crate) fn do_parse(rcvry_kind: RecoveryKind,
-                           lexs: &str,
-                           grms: &str,
-                           input: &str)
-                       -> (YaccGrammar,
-                           Result<Node<u16>, (Option<Node<u16>>,
-                                              Vec<ParseError<u16>>)>)
-    {
-        do_parse_with_costs(rcvry_kind, lexs, grms, input, &HashMap::new())
+    /// Set the order in which `RecoveryKind::CPCTPlus` explores partial repair sequences. By
+    /// default they are explored in order of cost; instead, they are explored in order of the
+    /// priority returned by `search_priority(c, st_idx, la_idx)` for a partial repair sequence of
+    /// cost `c` which has reached state `st_idx` at lexeme `la_idx`. `search_priority` must never
+    /// return a lower priority for a higher cost (with other arguments unchanged). This allows the
+    /// search to use A*, by returning `c + h` where `h` is a lower bound on the cost of any repair
+    /// needed to complete the partial repair sequence (e.g. one derived from the number of
+    /// unclosed brackets in the grammar's state `st_idx`), which must be 0 when no repairs are
+    /// needed. `h` must also be consistent: it must never decrease by more than the cost of a
+    /// single step of the search (so, in particular, not at all when a lexeme is shifted), since
+    /// the search does not revisit states it has already reached at a lower priority. The
+    /// repairs found are then the same as by default, but far fewer partial repair sequences may
+    /// need to be explored. Returning `c + w * h` for some `w > 1` (weighted A*)
+    /// explores fewer still, at the risk of finding repairs of up to `w` times the minimal cost.
+    /// Other recoverers (e.g. `RecoveryKind::MF`) ignore `search_priority`.
+    pub fn search_priority(mut self, search_priority: &'a Fn(u32, StIdx, usize) -> u32) -> Self {
+        self.search_priority = Some(search_priority);
+        self
     }
 
-    pub(crate) fn do_parse_with_costs(rcvry_kind: RecoveryKind,
-                                      lexs: &str,
-                                      grms: &str,
-                                      input: &str,
-                                      costs: &HashMap<&str, u8>)
-                                  -> (YaccGrammar,
-                                      Result<Node<u16>, (Option<Node<u16>>,
-                                                         Vec<ParseError<u16>>)>)
-    {
-        let mut lexerdef = build_lex(lexs).unwrap();
-        let grm = yacc_grm(YaccKind::Original, grms).unwrap();
-        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
-        {
-            let rule_ids = grm.terms_map().iter()
-                                          .map(|(&n, &i)| (n, u32::from(i).to_u16().unwrap()))
-                                          .collect();
-            lexerdef.set_rule_ids(&rule_ids);
-        }
-        let lexemes = lexerdef.lexer(&input).lexemes().unwrap();
-        let costs_tidx = costs.iter()
-                              .map(|(k, v)| (grm.term_idx(k).unwrap(), v))
-                              .collect::<HashMap<_, _>>();
-        let pr = parse_rcvry(rcvry_kind,
-                             &grm,
-                             move |t_idx| **costs_tidx.get(&t_idx).unwrap_or(&&1),
-                             &sgraph,
-                             &stable,
-                             &lexemes);
-        (grm, pr)
+    /// Only attempt to recover from the first `max_errors` errors. A badly garbled (e.g. minified
+    /// or binary) input can contain so many errors that recovering from each in turn consumes the
+    /// entire recovery time budget to little purpose; instead, once `max_errors` errors have been
+    /// encountered, the next error stops parsing without recovery being attempted, and its
+    /// `ParseError` reports `recovery_skipped() == true`. By default there is no limit.
+    pub fn max_errors(mut self, max_errors: usize) -> Self {
+        self.max_errors = Some(max_errors);
+        self
     }
 
-    fn check_parse_output(lexs: &str, grms: &str, input: &str, expected: &str) {
-        let (grm, pt) = do_parse(RecoveryKind::MF, lexs, grms, input);
-        assert_eq!(expected, pt.unwrap().pp(&grm, &input));
+    /// Don't recover from errors whose lexeme starts in any of the spans of input `spans`, each of
+    /// which is a `(start, end)` pair of byte offsets (`end` is exclusive). Parsing stops at such
+    /// an error, and its `ParseError` reports `recovery_skipped() == true`. Spans are normally
+    /// marked in the input with pragmas (see `strip_recovery_pragmas`). By default recovery is
+    /// attempted everywhere.
+    pub fn no_recovery(mut self, spans: &'a [(usize, usize)]) -> Self {
+        self.no_recovery = spans;
+        self
     }
 
-    #[test]
-    fn simple_parse() {
-        // From p4 of https://www.cs.umd.edu/class/spring2014/cmsc430/lectures/lec07.pdf
-        check_parse_output("%%
-[a-zA-Z_] 'ID'
-\\+ '+'",
-"
-%start E
-%%
-E: T '+' E
- | T;
-T: 'ID';
-",
-"a+b",
-"E
- T
-  ID a
- + +
- E
-  T
-   ID b
-");
+    /// Remember the repairs found for each error in `repair_cache`, and seed the search for
+    /// repairs for a later error in the same state and at the same terminal (with the same
+    /// grammar) with those of the remembered repair sequences which still let the parser get as
+    /// far past the error as a recoverer would require. The search then still finds the minimal
+    /// cost repairs, but stores no partial repair sequences more costly than the remembered ones;
+    /// and if it is cut short (e.g. by running out of time), the remembered repairs are used
+    /// instead, and the error reports `repairs_cached() == true`. This makes repeatedly parsing
+    /// the same broken input (e.g. as a user edits it) much cheaper. By default no repairs are
+    /// remembered.
+    pub fn repair_cache(mut self, repair_cache: &'a RepairCache) -> Self {
+        self.repair_cache = Some((repair_cache, grammar_key(self.grm, self.stable)));
+        self
     }
 
-    #[test]
-    fn parse_empty_rules() {
-        let lexs = "%%
-[a-zA-Z_] 'ID'";
-        let grms = "%start S
-%%
-S: L;
-L: 'ID'
- | ;
-";
-        check_parse_output(&lexs, &grms, "",
-"S
- L
-");
+    /// If `yes` is `true`, then when an error occurs in the same context as an error earlier in
+    /// the same parse (e.g. the same mistake repeated in generated code), the earlier error's
+    /// repairs are reused rather than searched for again; such errors report
+    /// `repairs_cached() == true`. A context is fingerprinted by the recoverer, the states on top
+    /// of the parse stack, and the next few terminals, and the earlier repairs are looked up by
+    /// fingerprint. Since a fingerprint doesn't capture the whole parse stack or all the input
+    /// that the repairs consume, each reused repair sequence is first checked to be valid for the
+    /// new error, and a search is made if none are. Recoverers may look further ahead to rank
+    /// repairs, so reused repairs are occasionally ranked differently from those a search would
+    /// have found. Off by default.
+    pub fn reuse_recoveries(mut self, yes: bool) -> Self {
+        self.reuse_recoveries = yes;
+        self
+    }
 
-        check_parse_output(&lexs, &grms, "x",
-"S
- L
-  ID x
-");
+    /// Use `clock` rather than `Instant::now` to measure the time spent on error recovery. This is
+    /// intended for tests: under heavy load (e.g. on a CI machine) a recovery which normally
+    /// finishes well within the time budget can run out of time, making tests of recovery flaky.
+    /// A clock which never advances (e.g. `&|| start`, where `start` is a fixed `Instant`) makes
+    /// recovery independent of timing; a clock which advances a fixed amount on each call makes
+    /// timeouts reproducible. No other source of non-determinism affects recovery: repair
+    /// sequences of equal cost are always explored, and reported, in the same order.
+    pub fn clock(mut self, clock: &'a Fn() -> Instant) -> Self {
+        self.clock = clock;
+        self
     }
 
-    #[test]
-    fn recursive_parse() {
-        let lexs = "%%
-\\+ '+'
-\\* '*'
-[0-9]+ 'INT'
-";
-        let grms = "%start Expr
-%%
-Expr : Term '+' Expr | Term;
-Term : Factor '*' Term | Factor;
-Factor : 'INT';";
+    /// Give the recoverer at most `budget` to recover from each error and, if it finds no repairs
+    /// in that time, fall back to panic mode (see `RecoveryKind::Panic`), which quickly deletes
+    /// lexemes until parsing can continue. This bounds the latency of error recovery (e.g. for
+    /// interactive use, where a budget of a few milliseconds may be appropriate) at the cost of
+    /// sometimes poor repairs. Errors recovered from in this way report `used_fallback() == true`:
+    /// their better repairs can then be found later, when time allows, with `full_repairs`. By
+    /// default there is no fallback.
+    pub fn fallback(mut self, budget: Duration) -> Self {
+        self.fallback = Some(budget);
+        self
+    }
 
-        check_parse_output(&lexs, &grms, "2+3*4",
-"Expr
- Term
-  Factor
-   INT 2
- + +
- Expr
-  Term
-   Factor
-    INT 3
-   * *
-   Term
-    Factor
-     INT 4
-");
-        check_parse_output(&lexs, &grms, "2*3+4",
-"Expr
- Term
-  Factor
-   INT 2
-  * *
-  Term
-   Factor
-    INT 3
- + +
- Expr
-  Term
-   Factor
-    INT 4
-");
+    /// Declare the terminal `kw` to be a contextual keyword which can stand in for the terminal
+    /// `ident` (normally an identifier). Whenever `kw` is encountered in a state where it is not
+    /// valid but `ident` is, the parser treats the lexeme as if it were an `ident` (and the parse
+    /// tree records it as such). Recoverers do not suggest inserting `kw` in states where `ident`
+    /// is valid.
+    pub fn contextual(mut self, kw: TIdx, ident: TIdx) -> Self {
+        self.contextual.push((kw, ident));
+        self
     }
 
-    #[test]
-    fn parse_error() {
-        let lexs = "%%
-\\( '('
-\\) ')'
-[a-zA-Z_][a-zA-Z_0-9]* 'ID'
-";
-        let grms = "%start Call
-%%
-Call: 'ID' '(' ')';";
+    /// Mark the terminal `t_idx` (e.g. `;` or `}`) as a recovery anchor. When choosing amongst
+    /// repair sequences of equal (minimal) cost, recoverers prefer those which resume parsing by
+    /// shifting an anchor, in the spirit of panic mode recovery: for example, for an error in the
+    /// middle of a statement, deleting the rest of the statement up to its terminating `;` will be
+    /// preferred to an equally cheap insertion which leaves the parser out of step with the input.
+    /// This changes only the order of repairs (and thus which repair is applied), never their cost.
+    pub fn anchor(mut self, t_idx: TIdx) -> Self {
+        self.anchors.push(t_idx);
+        self
+    }
 
-        check_parse_output(&lexs, &grms, "f()",
-"Call
- ID f
- ( (
- ) )
-");
+    /// Mark the rule `nt_idx` (e.g. a statement) as skippable. If a recoverer finds no repairs
+    /// for an error which may be in an instance of a skippable rule (e.g. because the instance is
+    /// so malformed that repairing it would take more edits than `max_repair_len` allows), the
+    /// whole instance is discarded instead: the parse is unwound to where the innermost such
+    /// instance started, and lexemes are deleted until parsing can continue as if the instance
+    /// had never been there. The error then reports the rule in `skipped_rule`, rather than
+    /// token-level repairs, so that it can be described as e.g. "ignored malformed statement".
+    /// Parsing must be able to continue within 250 lexemes of the error, so an instance whose
+    /// remainder is longer than that can't be discarded.
+    pub fn skippable(mut self, nt_idx: NTIdx) -> Self {
+        self.skippable.push(nt_idx);
+        self
+    }
 
-        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, "f(");
-        let (_, errs) = pr.unwrap_err();
-        assert_eq!(errs.len(), 1);
-        let err_tok_id = usize::from(grm.eof_term_idx()).to_u16().unwrap();
-        assert_eq!(errs[0].lexeme(), &Lexeme::new(err_tok_id, 2, 0));
+    /// Resolve the conflicts in which a production of the rule `nt_idx` could be reduced (e.g.
+    /// in one awkward construct which LR(1) cannot parse without rewriting the grammar) by
+    /// inspecting up to `k` lexemes beyond the usual lookahead at run-time. When the parser meets
+    /// such a conflict, it tries both the table's action (a shift, or a reduction by the earlier
+    /// production) and the conflict's other action on the next `k` lexemes, and takes the other
+    /// action only if it parses strictly further through them. The conflicts remain in the table,
+    /// so they still count towards `%expect`. Recoverers search for repair sequences using the
+    /// table's actions, but rank the sequences they find (by how far parsing then gets through the
+    /// input) and apply them with the hints, so that the ranking reflects the parse actually made.
+    /// Hints are never used for the terminals a repair sequence inserts.
+    pub fn lookahead(mut self, nt_idx: NTIdx, k: usize) -> Self {
+        if let Some(c) = self.stable.conflicts() {
+            for &(t_idx, p_idx, st_idx) in c.sr_conflicts() {
+                if self.grm.prod_to_nonterm(p_idx) == nt_idx {
+                    self.lookaheads.entry((st_idx, t_idx)).or_insert((Action::Reduce(p_idx), k));
+                }
+            }
+            for &(t_idx, p1_idx, p2_idx, st_idx) in c.rr_conflicts() {
+                if self.grm.prod_to_nonterm(p1_idx) == nt_idx
+                   || self.grm.prod_to_nonterm(p2_idx) == nt_idx
+                {
+                    self.lookaheads.entry((st_idx, t_idx))
+                                   .or_insert((Action::Reduce(p2_idx), k));
+                }
+            }
+        }
+        self
+    }
 
-        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, "f(f(");
-        let (_, errs) = pr.unwrap_err();
-        assert_eq!(errs.len(), 1);
-        let err_tok_id = usize::from(grm.term_idx("ID").unwrap()).to_u16().unwrap();
-        assert_eq!(errs[0].lexeme(), &Lexeme::new(err_tok_id, 2, 1));
-     }
+    /// Parse instances of the expression rule `nt_idx` with an operator-precedence (precedence
+    /// climbing) sub-parser rather than the LR tables, where `precs` gives the level (higher
+    /// levels bind more tightly) and associativity of each binary operator, as `%left`, `%right`,
+    /// and `%nonassoc` declarations do. Each of the rule's productions must be either a binary
+    /// operator `E: E 'op' E` or an operand which starts with a terminal and refers to no rule
+    /// other than `E` (e.g. `E: '(' E ')'`). Expression-heavy input is then parsed with far fewer
+    /// table lookups, and the parse tree is the same as the LR parser would have built.
+    ///
+    /// The sub-parser takes over whenever the parser is in a state which can go to `nt_idx` and
+    /// it can parse an instance of the rule after which the next lexeme is valid; otherwise
+    /// (e.g. if the instance contains a syntax error) the LR parser parses the input as normal,
+    /// so errors are detected and recovered from exactly as they would be without the sub-parser.
+    /// The tables are still built from the whole grammar, so they are no smaller. Panics if the
+    /// rule has a production of any other form, or an operator without a precedence.
+    pub fn pratt(mut self, nt_idx: NTIdx, precs: &[(TIdx, u32, Assoc)]) -> Self {
+        let rule = PrattRule::new(self.grm, nt_idx, precs).unwrap_or_else(|e| panic!("{}", e));
+        self.pratts.push(rule);
+        self
+    }
+
+    /// Mark the terminal `t_idx` as a case-insensitive keyword (e.g. `SELECT` in SQL), which the
+    /// lexer matches regardless of case (e.g. with the regex `(?i)select`). This does not affect
+    /// parsing, but `term_text` then renders the keyword in the input's own casing convention.
+    pub fn case_insensitive(mut self, t_idx: TIdx) -> Self {
+        self.case_insensitive.push(t_idx);
+        self
+    }
+
+    /// If `yes` is `true`, then when choosing amongst repair sequences of equal (minimal) cost,
+    /// recoverers prefer those whose insertions and deletions span the fewest bytes of the input,
+    /// since a repair which makes all its edits close together is usually closer to the fix a
+    /// human would make than one which (e.g.) inserts a terminal at the error and deletes another
+    /// much later. Anchors (see `anchor`) take priority over this. Off by default.
+    pub fn minimal_span(mut self, yes: bool) -> Self {
+        self.minimal_span = yes;
+        self
+    }
+
+    /// If `yes` is `true`, treat the input between anchors (see `anchor`) as isolated units (e.g.
+    /// statements) for the purposes of error recovery: once the parser has recovered from an
+    /// error, any further error before the next anchor is not recovered from (or reported) in its
+    /// own right. Instead, the lexemes from that error up to the next anchor are skipped, and the
+    /// parse stack (and the parse tree with it) is unwound until that anchor can be parsed, so
+    /// that parsing restarts cleanly at the end of the unit. This stops one badly garbled region
+    /// from producing a cascade of spurious errors, at the cost of dropping the garbled part of
+    /// the unit from the parse tree. The first error's `skipped_to` records where parsing
+    /// restarted. If there is no later anchor, or no way of unwinding the parse stack so that it
+    /// can be parsed, the further error is recovered from as normal. Off by default.
+    pub fn isolate_errors(mut self, yes: bool) -> Self {
+        self.isolate_errors = yes;
+        self
+    }
+
+    /// Rank repair sequences of equal (minimal) cost by how plausible a typo they fix, where
+    /// `input` is the text the lexemes to be parsed were produced from. A `Delete` adjacent to an
+    /// `Insert` is a substitution, and the more similar the deleted text is to the inserted
+    /// terminal's name (which, for keywords, is normally its text), the earlier the repair
+    /// sequence is ranked: for example, for the input `retrun x;`, replacing `retrun` with `return`
+    /// is preferred to other repairs of the same cost. Anchors (see `anchor`) take priority over
+    /// this, which in turn takes priority over `minimal_span`. Each `ParseError` also records
+    /// which keyword, if any, its lexeme is likely to be a misspelling of (see
+    /// `ParseError::suggestion`). Off by default.
+    pub fn typo_aware<S: Source + ?Sized>(mut self, input: &'a S) -> Self {
+        // `S` may be unsized (e.g. `str`), so it is the reference which becomes the trait object.
+        self.typo_input = Some(Box::new(input));
+        self
+    }
+
+    /// If `yes` is `true`, then each run of `Delete`s immediately followed by an `Insert` in the
+    /// repair sequences of a `ParseError` is reported as a single `ParseRepair::Substitute`, so
+    /// that diagnostics can say (e.g.) "replace `then` with `{`" rather than listing two separate
+    /// edits. This changes only how repairs are reported, never which are found or applied. Off
+    /// by default.
+    pub fn substitutions(mut self, yes: bool) -> Self {
+        self.substitutions = yes;
+        self
+    }
+
+    /// If `yes` is `true`, then after parsing, remove from each `ParseError` any repair sequences
+    /// which are inconsistent with the repairs reported for later errors. Each error's repairs are
+    /// found relative to the parser configuration left by the first repair sequence of the
+    /// previous error, so an alternative repair sequence for an error (e.g. one which deletes a
+    /// lexeme that the next error's repairs refer to) may not be compatible with the repairs of
+    /// later errors. Since this requires reparsing the input, it is off by default.
+    ///
+    /// Note that recovery is not rerun where a repair sequence conflicts with later errors: the
+    /// later errors are always those found after the repair sequence the parser applied, so the
+    /// conflicting alternatives are simply removed and the remaining ones keep their order.
+    pub fn consistent_repairs(mut self, yes: bool) -> Self {
+        self.consistent_repairs = yes;
+        self
+    }
+
+    /// If `yes` is `true`, then after parsing, note which errors were plausibly caused by the
+    /// repair made for the error before them (see `ParseError::caused_by`), so that user interfaces
+    /// can group cascading errors with the error which caused them. For example, if the repair for
+    /// one error deletes an opening bracket, the closing bracket may cause an error later on.
+    /// Since this requires reparsing the input, it is off by default.
+    pub fn note_cascades(mut self, yes: bool) -> Self {
+        self.note_cascades = yes;
+        self
+    }
+
+    /// Call `on_error` with each `ParseError` as soon as it is found (i.e. as soon as recovery
+    /// from it has completed), rather than only when parsing has finished, so that interactive
+    /// tools can report errors early in a large input while the rest of it is still being
+    /// parsed. `on_error` can pass the errors on elsewhere (e.g. via a channel). The errors are
+    /// passed to `on_error` as `parse` returns them (e.g. with shifts reported as specified by
+    /// `shifts`), except that the post-processing which needs all of the errors has not yet
+    /// happened: `consistent_repairs` may remove some of the repair sequences from the returned
+    /// errors which were present in the errors passed to `on_error`, and `note_cascades` may
+    /// record which earlier error caused them.
+    pub fn on_error(mut self, on_error: &'a Fn(&ParseError<TokId>)) -> Self {
+        self.on_error = Some(on_error);
+        self
+    }
+
+    /// Call `recovery_decision` with each `ParseError` (which has no repairs yet) before recovering
+    /// from it, and recover (or not) as it decides: it can skip recovery altogether (e.g. because
+    /// the caller will discard errors after the first, or at certain locations, anyway), choose a
+    /// different recoverer, or limit the time spent on recovery from that error. Since
+    /// `recovery_decision` is an `FnMut`, it can keep state (e.g. a count of errors) between calls;
+    /// it is wrapped in a `RefCell` so that the builder (and the parsers it creates) can share it,
+    /// and is borrowed mutably for the duration of each call, so it must not start a parse which
+    /// uses it. `max_errors`, if set, takes priority over this.
+    pub fn recovery_decision<F>(mut self, recovery_decision: &'a RefCell<F>) -> Self
+                             where F: FnMut(&ParseError<TokId>) -> RecoveryDecision + 'a
+    {
+        self.recovery_decision = Some(recovery_decision);
+        self
+    }
+
+    /// Set what happens when there are no lexemes to parse (e.g. because the input is empty or
+    /// contains only whitespace and comments) and the grammar does not accept empty input.
+    /// Defaults to `EmptyInput::Recover`.
+    pub fn empty_input(mut self, empty_input: EmptyInput) -> Self {
+        self.empty_input = empty_input;
+        self
+    }
+
+    /// Set how `Shift`s are reported in the repair sequences of the `ParseError`s returned by
+    /// `parse`: consumers which only care about edits may prefer to collapse or omit them. This
+    /// affects both recoverers identically, and changes only how repairs are reported, never which
+    /// are found or applied. Note that repair sequences with omitted shifts cannot be passed to
+    /// methods such as `explain_repairs` or `preview_repair`, since it is no longer known which
+    /// lexemes their edits apply to. Defaults to `ShiftReporting::Each`.
+    pub fn shifts(mut self, shift_reporting: ShiftReporting) -> Self {
+        self.shift_reporting = shift_reporting;
+        self
+    }
+
+    /// Return a `Parser` for `lexemes` with this builder's options. This is only needed by those
+    /// who want to drive the parser at a low level (e.g. via `Parser::lr_cactus`): most users
+    /// should call `parse` instead.
+    pub fn parser<'b>(&'b self, lexemes: &'b [Lexeme<TokId>]) -> Parser<'b, TokId> {
+        Parser{rcvry_kind: self.rcvry_kind,
+               grm: self.grm,
+               term_cost: self.term_costs,
+               sgraph: self.sgraph,
+               stable: self.stable,
+               lexemes,
+               recovery: RecoveryOptions{max_repair_len: self.max_repair_len,
+                                         max_repair_cost: self.max_repair_cost,
+                                         max_recovery_memory: self.max_recovery_memory,
+                                         max_recovery_expansions: self.max_recovery_expansions,
+                                         search_priority: self.search_priority,
+                                         max_errors: self.max_errors,
+                                         no_recovery: self.no_recovery,
+                                         repair_cache: self.repair_cache,
+                                         reuse_recoveries: self.reuse_recoveries,
+                                         clock: self.clock,
+                                         fallback: self.fallback,
+                                         anchors: &self.anchors,
+                                         skippable: &self.skippable,
+                                         isolate_errors: self.isolate_errors,
+                                         minimal_span: self.minimal_span,
+                                         typo_input: self.typo_input.as_ref().map(|s| &**s),
+                                         substitutions: self.substitutions,
+                                         shift_reporting: self.shift_reporting,
+                                         on_error: self.on_error,
+                                         recovery_decision: self.recovery_decision},
+               syntax: SyntaxOptions{contextual: &self.contextual,
+                                     lookaheads: if self.lookaheads.is_empty() {
+                                                     None
+                                                 } else {
+                                                     Some(&self.lookaheads)
+                                                 },
+                                     pratts: &self.pratts}}
+    }
+
+    /// Parse the lexemes. On success return a parse tree. On failure, return a parse tree (if all
+    /// the input was consumed) or `None` otherwise, and a vector of `ParseError`s.
+    pub fn parse(&self, lexemes: &Lexemes<TokId>)
+              -> Result<Node<TokId>, (Option<Node<TokId>>, Vec<ParseError<TokId>>)>
+    {
+        Parser::parse(self, lexemes, false)
+    }
+
+    /// Parse the lexemes as `parse` does, but return the parse tree (if any) and the errors
+    /// separately from an explicit record, for each error in order, of how the parser recovered
+    /// from it: which repair sequence it applied, which were the alternatives, and where parsing
+    /// resumed. This allows tools to explain what the parser actually did to the input, rather
+    /// than relying on the convention that the first repair sequence is the one applied. If the
+    /// parse succeeded, both vectors are empty.
+    pub fn parse_with_recovery_report(&self, lexemes: &Lexemes<TokId>)
+                                   -> (Option<Node<TokId>>,
+                                       Vec<ParseError<TokId>>,
+                                       Vec<AppliedRecovery>)
+    {
+        match Parser::parse(self, lexemes, false) {
+            Ok(pt) => (Some(pt), Vec::new(), Vec::new()),
+            Err((pt, errs)) => {
+                let applied = errs.iter().map(AppliedRecovery::new).collect();
+                (pt, errs, applied)
+            }
+        }
+    }
+
+    /// Parse `lexemes[start..eof_idx]` as if it were the complete input (i.e. with a "virtual" EOF
+    /// at `eof_idx`), so that a large document can be parsed (e.g.) one statement at a time
+    /// without copying or relexing it. The lexemes retain their offsets into the document and the
+    /// `lexeme_idx` of any `ParseError`s is relative to the start of `lexemes`.
+    ///
+    /// If `accept_early` is `true`, and the slice contains a prefix which the grammar accepts but
+    /// which cannot be extended by the lexeme following it, then that prefix is parsed instead of
+    /// the whole slice, and is a success. This allows a caller to repeatedly parse from the end of
+    /// the previous parse (passing e.g. `lexemes.len()` as `eof_idx`) without having to know where
+    /// each statement ends. If `accept_early` is `false`, such a slice is an error as normal.
+    ///
+    /// Returns the index of the lexeme after the last one parsed (i.e. `eof_idx` unless the parse
+    /// was accepted early) and the result of parsing.
+    pub fn parse_slice(&self,
+                       lexemes: &Lexemes<TokId>,
+                       start: usize,
+                       eof_idx: usize,
+                       accept_early: bool)
+                    -> (usize, Result<Node<TokId>, (Option<Node<TokId>>, Vec<ParseError<TokId>>)>)
+    {
+        // The lexemes are parsed in place: a parser for a slice treats the end of the slice as
+        // the end of the input.
+        let mut end = eof_idx;
+        if accept_early {
+            let slice = &lexemes[start..eof_idx];
+            let psr = self.parser(slice);
+            let mut pstack = vec![StIdx::from(0 as u32)];
+            let la_idx = psr.lr_upto(None, 0, slice.len() + 1, &mut pstack, &mut None);
+            if la_idx < slice.len() && psr.valid_la(&pstack, self.grm.eof_term_idx()) {
+                end = start + la_idx;
+            }
+        }
+        let r = Parser::parse(self, &lexemes[start..end], false).map_err(|(pt, mut errs)| {
+            for e in &mut errs {
+                e.lexeme_idx += start;
+            }
+            (pt, errs)
+        });
+        (end, r)
+    }
+
+    /// Parse the lexemes produced by `lexemes` while holding at most `2 * window` of them in
+    /// memory at any one time, so that very large inputs can be checked without a correspondingly
+    /// large lexeme vector (e.g. by passing an iterator which lexes its input on demand). Since a
+    /// parse tree would itself be proportional in size to the input, none is built: this only
+    /// reports whether the input is valid and, if not, its errors, whose `lexeme_idx` counts from
+    /// the start of the stream.
+    ///
+    /// Error recovery is as for `parse` except that each recovery sees only the `window` (or more)
+    /// lexemes after its error, so `window` should comfortably exceed the length of any repair
+    /// sequence (a few hundred lexemes is plenty for most grammars), and `consistent_repairs` has
+    /// no effect.
+    pub fn parse_stream<I>(&self, lexemes: I, window: usize) -> Result<(), Vec<ParseError<TokId>>>
+        where I: IntoIterator<Item=Lexeme<TokId>>
+    {
+        assert!(window > 0);
+        let mut lexemes = lexemes.into_iter();
+        let mut buf = Vec::with_capacity(2 * window);
+        // The index in the stream of `buf[0]`.
+        let mut base = 0;
+        let mut eof = false;
+        let mut pstack = vec![StIdx::from(0 as u32)];
+        let mut errors = Vec::new();
+        let mut la_idx = 0;
+        let mut recovery_budget = Duration::from_millis(RECOVERY_TIME_BUDGET);
+        // Fingerprints don't depend on the position of an error in `buf`, so earlier recoveries
+        // can be reused across the whole stream.
+        let mut contexts = RecoveryContexts::new();
+        loop {
+            base += la_idx;
+            buf.drain(..la_idx);
+            while !eof && buf.len() < 2 * window {
+                match lexemes.next() {
+                    Some(l) => buf.push(l),
+                    None => eof = true
+                }
+            }
+            // Errors are reported to `on_error` below, once their `lexeme_idx` is correct.
+            let psr = self.parser(&buf);
+            let psr = Parser{recovery: RecoveryOptions{on_error: None, ..psr.recovery}, ..psr};
+            // Until the stream is exhausted, stop `window` lexemes short of the end of `buf`, so
+            // that the parser never mistakes the end of `buf` for the end of the input.
+            let end = if eof { buf.len() + 1 } else { buf.len() - window };
+            la_idx = psr.lr_upto(None, 0, end, &mut pstack, &mut None);
+            if la_idx == end {
+                continue;
+            }
+            if let Some(Action::Accept) = self.stable.action(*pstack.last().unwrap(),
+                                                             psr.next_tidx(la_idx)) {
+                break;
+            }
+            // Recoverers need a `TStack` with a tree for each state on the parse stack (other
+            // than the start state). Only the spans of its trees are ever used (when unwinding the
+            // stack for `isolate_errors` or `skippable`), so the trees are zero-length terminals,
+            // which add nothing to the input recorded as discarded.
+            let err_lexeme = psr.next_lexeme(la_idx);
+            let placeholder = Lexeme::new(err_lexeme.tok_id(), err_lexeme.start(), 0);
+            let mut tstack = TStack::new();
+            for _ in 1..pstack.len() {
+                tstack.push_node(Node::Term{lexeme: placeholder});
+            }
+            let mut recoverers = Vec::new();
+            let r = psr.recover_error(&mut recoverers, &mut recovery_budget, &mut contexts, la_idx,
+                                      &mut pstack, &mut tstack, &mut errors);
+            {
+                let e = errors.last_mut().unwrap();
+                e.lexeme_idx += base;
+                if self.shift_reporting != ShiftReporting::Each {
+                    report_shifts(&mut e.repairs, self.shift_reporting);
+                }
+            }
+            if let Some(f) = self.on_error {
+                f(errors.last().unwrap());
+            }
+            match r {
+                Some(new_la_idx) => la_idx = new_la_idx,
+                None => return Err(errors)
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parse `lexemes` up to (but excluding) the lexeme at `upto_la_idx` (which must be no greater
+    /// than `lexemes.len()`), with error recovery, returning the state of the parser at that point
+    /// (which includes the parse trees built so far) and the errors encountered. If an error
+    /// can't be recovered from, the errors are returned instead.
+    ///
+    /// This is the basis of line-incremental parsing: an editor can cache the state at the start
+    /// of each line and, after an edit, resume parsing from the last state before the edit with
+    /// `Parser::advance` (to reach the next cut-off) or `Parser::finish` (to complete the parse),
+    /// using a `Parser` for the edited lexemes. If no errors were encountered, the parser never
+    /// looked at the lexeme at `upto_la_idx`, so the state remains valid for any edit from there
+    /// onwards. This is not true of a prefix with errors: error recovery looks past the cut-off
+    /// when ranking and checking repair sequences (and a repair sequence may even span it, leaving
+    /// the state's `la_idx` beyond `upto_la_idx`), so an edit after the cut-off can change the
+    /// repairs that should have been made before it. Editors should thus only resume from states
+    /// whose prefixes had no errors. Note that `note_cascades`, `consistent_repairs`, and `shifts`
+    /// have no effect on the errors returned.
+    pub fn parse_prefix(&self, lexemes: &Lexemes<TokId>, upto_la_idx: usize)
+                     -> Result<(ParseState<TokId>, Vec<ParseError<TokId>>), Vec<ParseError<TokId>>>
+    {
+        let psr = self.parser(lexemes);
+        let mut st = psr.initial_state();
+        let mut errors = Vec::new();
+        if psr.advance(&mut st, upto_la_idx, &mut errors) {
+            Ok((st, errors))
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Classify `lexemes` as being complete input, an incomplete prefix of valid input, or as
+    /// containing a genuine error. REPLs can use this to determine whether they should prompt the
+    /// user for more input before parsing. No error recovery is performed.
+    ///
+    /// LR parsers have the "viable prefix" property: they detect an error at the first lexeme
+    /// which cannot be part of any valid input. Thus if the only error is at the end of the input,
+    /// there must be some sequence of lexemes which, if appended, would lead to a successful
+    /// parse, and the input is classified as `InputStatus::Incomplete` (this assumes that every
+    /// nonterminal in the grammar can derive at least one sentence).
+    pub fn input_status(&self, lexemes: &Lexemes<TokId>) -> InputStatus {
+        let psr = self.parser(lexemes);
+        let mut pstack = vec![StIdx::from(0 as u32)];
+        let la_idx = psr.lr_upto(None, 0, lexemes.len() + 1, &mut pstack, &mut None);
+        match self.stable.action(*pstack.last().unwrap(), psr.next_tidx(la_idx)) {
+            Some(Action::Accept) => InputStatus::Complete,
+            _ if la_idx == lexemes.len() => InputStatus::Incomplete,
+            _ => InputStatus::Error
+        }
+    }
+
+    /// Return the terminals which could validly follow the lexemes which end at, or before, the
+    /// byte offset `cursor` in the input. The prefix is parsed with error recovery (using the
+    /// recoverer specified by `recoverer`) so that errors earlier in the input do not prevent
+    /// completions being found. If `expansions` is `true`, the nonterminals which could follow are
+    /// also returned, each with the shortest sequence of terminals it can expand to. This is
+    /// intended to power basic autocompletion in editors.
+    pub fn completions(&self, lexemes: &Lexemes<TokId>, cursor: usize, expansions: bool)
+                    -> Completions
+    {
+        let prefix = lexemes.iter()
+                            .take_while(|l| l.start() + l.len() <= cursor)
+                            .cloned()
+                            .collect::<Vec<_>>();
+        let psr = self.parser(&prefix);
+        let mut pstack = vec![StIdx::from(0 as u32)];
+        let mut tstack = TStack::new();
+        let mut la_idx = 0;
+        let recoverer = psr.new_recoverer(self.rcvry_kind);
+        let finish_by = (self.clock)() + Duration::from_millis(RECOVERY_TIME_BUDGET);
+        loop {
+            la_idx = psr.lr_upto(None, la_idx, prefix.len(), &mut pstack, &mut Some(&mut tstack));
+            if la_idx == prefix.len() {
+                break;
+            }
+            match recoverer {
+                Some(ref r) => {
+                    let (new_la_idx, repairs, _) = r.recover(finish_by,
+                                                             &psr,
+                                                             la_idx,
+                                                             &mut pstack,
+                                                             &mut tstack);
+                    if repairs.is_empty() {
+                        break;
+                    }
+                    la_idx = new_la_idx;
+                },
+                None => break
+            }
+        }
+
+        let st = *pstack.last().unwrap();
+        let mut terms = self.stable.state_actions(st)
+                                   .filter(|t_idx| *t_idx != self.grm.eof_term_idx())
+                                   .collect::<Vec<_>>();
+        terms.sort();
+        let mut nonterms = Vec::new();
+        if expansions {
+            let sengen = self.grm.sentence_generator(self.term_costs);
+            for sym in self.sgraph.edges(st).keys() {
+                if let Symbol::Nonterm(nt_idx) = *sym {
+                    nonterms.push((nt_idx, sengen.min_sentence(nt_idx)));
+                }
+            }
+            nonterms.sort_by(|x, y| self.grm.nonterm_name(x.0).cmp(self.grm.nonterm_name(y.0)));
+        }
+        Completions{terms, nonterms}
+    }
+
+    /// Explain how the repair sequence `errs[err_idx].repairs()[rprs_idx]` fixes the error
+    /// `errs[err_idx]`, where `errs` are the errors returned by parsing `lexemes` with this
+    /// builder. The returned vector has one `RepairStep` for each step of the repair sequence,
+    /// recording the state in which that step is applied and the grammar items active in that
+    /// state. For example, a step `Insert(')')` applied in a state with the item `E: '(' E . ')'`
+    /// can be explained as "inserted ')' to complete `E: '(' E ')'`". The parse up to the error is
+    /// replayed, applying the repair sequence the parser applied for each earlier error.
+    pub fn explain_repairs(&self,
+                           lexemes: &Lexemes<TokId>,
+                           errs: &[ParseError<TokId>],
+                           err_idx: usize,
+                           rprs_idx: usize)
+                        -> Vec<RepairStep>
+    {
+        let psr = self.parser(lexemes);
+        let mut pstack = vec![StIdx::from(0 as u32)];
+        let mut la_idx = 0;
+        let mut steps = Vec::new();
+        for (i, e) in errs[..err_idx + 1].iter().enumerate() {
+            la_idx = psr.lr_upto(None, la_idx, e.lexeme_idx(), &mut pstack, &mut None);
+            let rprs = if i == err_idx {
+                           &e.repairs()[rprs_idx]
+                       } else if let Some(ref rprs) = e.applied {
+                           rprs
+                       } else {
+                           continue;
+                       };
+            for r in rprs.iter().flat_map(flatten_repair) {
+                let (st_idx, t_idx, new_la_idx) = match r {
+                    ParseRepair::Delete => {
+                        (*pstack.last().unwrap(), psr.next_tidx(la_idx), la_idx + 1)
+                    },
+                    ParseRepair::Insert(t_idx) => {
+                        let next_lexeme = psr.next_lexeme(la_idx);
+                        let new_lexeme = Lexeme::new(TokId::from(u32::from(t_idx)).unwrap(),
+                                                     next_lexeme.start(), 0);
+                        psr.lr_upto(Some(new_lexeme), la_idx, la_idx + 1, &mut pstack, &mut None);
+                        (pstack[pstack.len() - 2], t_idx, la_idx)
+                    },
+                    ParseRepair::Shift => {
+                        let t_idx = psr.next_tidx(la_idx);
+                        let new_la_idx = psr.lr_upto(None, la_idx, la_idx + 1, &mut pstack,
+                                                     &mut None);
+                        (pstack[pstack.len() - 2], t_idx, new_la_idx)
+                    },
+                      ParseRepair::InsertSeq(_)
+                    | ParseRepair::Substitute{..}
+                    | ParseRepair::Shifts(_) => unreachable!()
+                };
+                la_idx = new_la_idx;
+                if i == err_idx {
+                    let mut items = self.sgraph.core_state(st_idx)
+                                               .items
+                                               .keys()
+                                               .cloned()
+                                               .collect::<Vec<_>>();
+                    items.sort_by_key(|&(p_idx, s_idx)| (usize::from(p_idx), usize::from(s_idx)));
+                    steps.push(RepairStep{repair: r, st_idx, t_idx, items});
+                }
+            }
+        }
+        steps
+    }
+
+    /// Preview the effect of applying the repair sequence `repairs` (e.g. one of `err.repairs()`)
+    /// at the error `err` in `lexemes`, without parsing: for each step of the sequence, report the
+    /// lexemes and bytes it affects, and report the lexeme that parsing would resume with
+    /// afterwards. This is intended for tools which want to show a repair to the user without
+    /// applying it.
+    pub fn preview_repair(&self,
+                          lexemes: &Lexemes<TokId>,
+                          err: &ParseError<TokId>,
+                          repairs: &[ParseRepair])
+                       -> RepairedSlice<TokId>
+    {
+        let psr = self.parser(lexemes);
+        let mut la_idx = err.lexeme_idx();
+        let mut steps = Vec::new();
+        for r in repairs.iter().flat_map(flatten_repair) {
+            let lexeme = psr.next_lexeme(la_idx);
+            let span = match r {
+                ParseRepair::Insert(_) => {
+                    (la_idx, la_idx, lexeme.start(), lexeme.start())
+                },
+                ParseRepair::Delete | ParseRepair::Shift => {
+                    la_idx += 1;
+                    (la_idx - 1, la_idx, lexeme.start(), lexeme.start() + lexeme.len())
+                },
+                  ParseRepair::InsertSeq(_)
+                | ParseRepair::Substitute{..}
+                | ParseRepair::Shifts(_) => unreachable!()
+            };
+            steps.push((r, span));
+        }
+        RepairedSlice{steps, lookahead_idx: la_idx, lookahead: psr.next_lexeme(la_idx)}
+    }
+
+    /// Return, in GraphViz's DOT format, the graph searched by the MF recoverer when recovering
+    /// from the error `errs[err_idx]`, where `errs` are the errors returned by parsing `lexemes`
+    /// with this builder. This is a debugging aid for understanding why a desired repair was not
+    /// found: each node records the state on top of the parse stack, the lexeme index, the cost of
+    /// the repairs so far (`cf`), and the estimated cost of the rest of the repair (`cg`), as well
+    /// as the number of other paths merged into it; success nodes are drawn with a double border.
+    /// Each edge is labelled with the repair (or reduction) which leads from one node to another.
+    /// The parse up to the error is replayed, using the first repair sequence of each earlier
+    /// error as the parser itself does. The MF recoverer is used regardless of this builder's
+    /// recoverer.
+    pub fn recovery_graph(&self,
+                          lexemes: &Lexemes<TokId>,
+                          errs: &[ParseError<TokId>],
+                          err_idx: usize)
+                       -> String
+    {
+        let psr = self.parser(lexemes);
+        let mut pstack = vec![StIdx::from(0 as u32)];
+        let mut tstack = TStack::new();
+        let la_idx = psr.replay(errs, err_idx, &mut pstack, &mut tstack);
+        let finish_by = (self.clock)() + Duration::from_millis(RECOVERY_TIME_BUDGET);
+        mf::search_graph(&psr, finish_by, la_idx, &mut pstack, &mut tstack)
+    }
+
+    /// Return the repairs which this builder's recoverer finds for the error `errs[err_idx]`, where
+    /// `errs` are the errors returned by parsing `lexemes` with this builder, given the full
+    /// recovery time budget. This is intended to be used with `fallback`: an interactive client can
+    /// show the errors from a quick parse straight away and then, when idle, ask for the better
+    /// repairs of those errors which report `used_fallback() == true`. The parse up to the error is
+    /// replayed, using the first repair sequence of each earlier error as the parser itself does.
+    pub fn full_repairs(&self,
+                        lexemes: &Lexemes<TokId>,
+                        errs: &[ParseError<TokId>],
+                        err_idx: usize)
+                     -> Vec<Vec<ParseRepair>>
+    {
+        let psr = self.parser(lexemes);
+        let psr = Parser{recovery: RecoveryOptions{fallback: None, ..psr.recovery}, ..psr};
+        let mut pstack = vec![StIdx::from(0 as u32)];
+        let mut tstack = TStack::new();
+        let la_idx = psr.replay(errs, err_idx, &mut pstack, &mut tstack);
+        let recoverer = match psr.new_recoverer(self.rcvry_kind) {
+            Some(r) => r,
+            None => return vec![]
+        };
+        let finish_by = (self.clock)() + Duration::from_millis(RECOVERY_TIME_BUDGET);
+        let (_, repairs, _) = recoverer.recover(finish_by, &psr, la_idx, &mut pstack, &mut tstack);
+        if self.substitutions {
+            repairs.into_iter().map(merge_substitutions).collect()
+        } else {
+            repairs
+        }
+    }
+
+    /// For each repair sequence of the error `errs[err_idx]`, where `errs` are the errors returned
+    /// by parsing `lexemes` with this builder, return how many lexemes, counting from the error's
+    /// lexeme, the parser consumes after applying that repair sequence before it encounters
+    /// another error (or reaches the end of the input). This is the measure by which recoverers
+    /// rank repair sequences (though they only look a short distance ahead), and allows a UI to
+    /// present a repair as e.g. "gets 25 lexemes further". Repair sequences whose `Shift`s have
+    /// been omitted (see `shifts`) can't be measured. The parse up to the error is replayed, using
+    /// the first repair sequence of each earlier error as the parser itself does.
+    pub fn repair_progress(&self,
+                           lexemes: &Lexemes<TokId>,
+                           errs: &[ParseError<TokId>],
+                           err_idx: usize)
+                        -> Vec<usize>
+    {
+        let psr = self.parser(lexemes);
+        let mut pstack = vec![StIdx::from(0 as u32)];
+        let la_idx = psr.replay(errs, err_idx, &mut pstack, &mut TStack::new());
+        errs[err_idx].repairs()
+                     .iter()
+                     .map(|rprs| {
+                         let rprs = rprs.iter().flat_map(flatten_repair).collect::<Vec<_>>();
+                         let mut r_pstack = pstack.clone();
+                         let r_la_idx = mf::apply_repairs(&psr, la_idx, &mut r_pstack, &mut None,
+                                                          &rprs);
+                         let end_la_idx = psr.lr_upto(None, r_la_idx, lexemes.len() + 1,
+                                                      &mut r_pstack, &mut None);
+                         end_la_idx - la_idx
+                     })
+                     .collect()
+    }
+
+    /// Parse `lexemes`, the lexemes of `input`, and fix `input` by applying the repair sequence
+    /// the parser applied for each error (see `AppliedRecovery::applied`): deleted lexemes are
+    /// removed from the text, and each inserted terminal is replaced by `term_text` of it (which
+    /// should lex as that terminal, including any whitespace needed to separate it from its
+    /// neighbours). The fixed text is then lexed with `lex` and parsed again to check that it has
+    /// no errors. On success, return the fixed text (`input` itself if there were no errors) and
+    /// its parse tree. Otherwise return the errors of the original parse (if an error had no
+    /// repairs applied) or of the parse of the fixed text. Since the applied repair sequences are
+    /// recorded in full, this works however shifts are reported (see `shifts`).
+    pub fn parse_and_fix<F, L>(&self,
+                               input: &str,
+                               lexemes: &Lexemes<TokId>,
+                               term_text: F,
+                               mut lex: L)
+                            -> Result<(String, Node<TokId>), Vec<ParseError<TokId>>>
+                         where F: Fn(TIdx) -> String,
+                               L: FnMut(&str) -> Lexemes<TokId>
+    {
+        let errs = match self.parse(lexemes) {
+            Ok(pt) => return Ok((input.to_owned(), pt)),
+            Err((_, errs)) => errs
+        };
+        if errs.iter().any(|e| e.applied.is_none()) {
+            return Err(errs);
+        }
+
+        let psr = self.parser(lexemes);
+        let mut fixed = String::with_capacity(input.len());
+        // The offset in `input` up to which text has been copied to `fixed`.
+        let mut copied = 0;
+        for e in &errs {
+            let mut la_idx = e.lexeme_idx();
+            // Unlike the reported repair sequences, which may omit shifts, the applied repair
+            // sequence is recorded flattened and with every shift.
+            for r in e.applied.as_ref().unwrap() {
+                let lexeme = psr.next_lexeme(la_idx);
+                match *r {
+                    ParseRepair::Insert(t_idx) => {
+                        fixed.push_str(&input[copied..lexeme.start()]);
+                        copied = lexeme.start();
+                        fixed.push_str(&term_text(t_idx));
+                    },
+                    ParseRepair::Delete => {
+                        fixed.push_str(&input[copied..lexeme.start()]);
+                        copied = lexeme.start() + lexeme.len();
+                        la_idx += 1;
+                    },
+                    ParseRepair::Shift => la_idx += 1,
+                      ParseRepair::InsertSeq(_)
+                    | ParseRepair::Substitute{..}
+                    | ParseRepair::Shifts(_) => unreachable!()
+                }
+            }
+        }
+        fixed.push_str(&input[copied..]);
+
+        match self.parse(&lex(&fixed)) {
+            Ok(pt) => Ok((fixed, pt)),
+            Err((_, errs)) => Err(errs)
+        }
+    }
+
+    /// Parse `lexemes`, passing the parse tree to `sink` as a flat sequence of `ParseEvent`s, and
+    /// return the errors (if any) to which `ParseEvent::Error`s refer. This allows consumers to
+    /// build their own tree types (see `TreeBuilder`), or to process the tree without building one
+    /// at all, without recursion. The events are those the parser records as it shifts and
+    /// reduces, so no `Node`s are built (other than by operator-precedence sub-parsers, see
+    /// `pratt`). However, since error recovery can unwind the parser's stack (discarding the
+    /// events of the trees on it), events are only passed to `sink` once parsing has finished. If
+    /// parsing fails without recovering, only `ParseEvent::Error`s are emitted.
+    pub fn parse_events<F>(&self, lexemes: &Lexemes<TokId>, mut sink: F) -> Vec<ParseError<TokId>>
+                        where F: FnMut(ParseEvent<TokId>)
+    {
+        let (tstack, errs) = Parser::parse_into(self, lexemes, false, TStack::events());
+        match tstack {
+            Some(tstack) => tstack.emit_events(&errs, &mut sink),
+            None => {
+                for i in 0..errs.len() {
+                    sink(ParseEvent::Error(i));
+                }
+            }
+        }
+        errs
+    }
+
+    /// Return the text with which to show the terminal `t_idx` to the author of `input` (lexed as
+    /// `lexemes`), e.g. when a repair inserts it or it is an error's `suggestion`. This is the
+    /// terminal's name except that, if it is a case-insensitive keyword (see `case_insensitive`),
+    /// it is rendered in the casing (lower case, upper case, or capitalised) that `input` most
+    /// commonly uses for such keywords, so that (for example) a repair to `select a b` inserts
+    /// `from` rather than `FROM`. If `input` contains no such keywords, the name is used as-is.
+    /// `term_text` can thus be passed to `parse_and_fix` and `RepairedSlice::text_edits`.
+    pub fn term_text<S>(&self, input: &S, lexemes: &Lexemes<TokId>, t_idx: TIdx) -> String
+                     where S: Source + ?Sized
+    {
+        let name = self.grm.term_name(t_idx).unwrap_or("");
+        if !self.case_insensitive.contains(&t_idx) {
+            return name.to_owned();
+        }
+        // The number of case-insensitive keywords in each of the casings in `CASINGS`.
+        let mut counts = [0; 3];
+        for l in lexemes {
+            if self.case_insensitive.contains(&tok_tidx(self.grm, l.tok_id())) {
+                if let Some(i) = CASINGS.iter().position(|c| c(&lexeme_text(input, l))) {
+                    counts[i] += 1;
+                }
+            }
+        }
+        match (0..CASINGS.len()).filter(|&i| counts[i] > 0).max_by_key(|&i| counts[i]) {
+            Some(0) => name.to_lowercase(),
+            Some(1) => name.to_uppercase(),
+            Some(_) => {
+                let lower = name.to_lowercase();
+                let mut cs = lower.chars();
+                cs.next().map_or_else(String::new, |c| c.to_uppercase().chain(cs).collect())
+            },
+            None => name.to_owned()
+        }
+    }
+
+    /// Explain the error `errs[err_idx]`, where `errs` are the errors returned by parsing `lexemes`
+    /// with this builder, in terms of the grammar's rules rather than terminals: for each item
+    /// `A: ... . X1 ... Xn` being recognised in the state the error occurred in, find the shortest
+    /// prefix `X1 ... Xk` whose completion would make the lookahead valid (e.g. "missing Cond in
+    /// `If: 'if' . Cond 'then' Stmt`"). Only the shortest such prefixes are returned. The parse up
+    /// to the error is replayed, applying the repair sequence the parser applied for each earlier
+    /// error.
+    pub fn missing(&self,
+                   lexemes: &Lexemes<TokId>,
+                   errs: &[ParseError<TokId>],
+                   err_idx: usize)
+                -> Vec<Missing>
+    {
+        let psr = self.parser(lexemes);
+        let mut pstack = vec![StIdx::from(0 as u32)];
+        let mut la_idx = 0;
+        for e in &errs[..err_idx] {
+            la_idx = psr.lr_upto(None, la_idx, e.lexeme_idx(), &mut pstack, &mut None);
+            if let Some(ref rprs) = e.applied {
+                la_idx = mf::apply_repairs(&psr, la_idx, &mut pstack, &mut None, rprs);
+            }
+        }
+        la_idx = psr.lr_upto(None, la_idx, errs[err_idx].lexeme_idx(), &mut pstack, &mut None);
+
+        let la_tidx = psr.next_tidx(la_idx);
+        let sengen = self.grm.sentence_generator(self.term_costs);
+        let mut items = self.sgraph.core_state(*pstack.last().unwrap())
+                                   .items
+                                   .keys()
+                                   .cloned()
+                                   .collect::<Vec<_>>();
+        items.sort_by_key(|&(p_idx, s_idx)| (usize::from(p_idx), usize::from(s_idx)));
+        let mut missing = Vec::new();
+        for (p_idx, s_idx) in items {
+            let prod = self.grm.prod(p_idx);
+            let mut m_pstack = pstack.clone();
+            for k in usize::from(s_idx)..prod.len() {
+                // Complete the symbol by inserting its cheapest sentence.
+                let ins = match prod[k] {
+                    Symbol::Term(t_idx) => vec![t_idx],
+                    Symbol::Nonterm(nt_idx) => sengen.min_sentence(nt_idx)
+                };
+                let start = psr.next_lexeme(la_idx).start();
+                let inserted = ins.iter().all(|&t_idx| {
+                    let l = Lexeme::new(TokId::from(u32::from(t_idx)).unwrap(), start, 0);
+                    psr.lr_upto(Some(l), la_idx, la_idx + 1, &mut m_pstack, &mut None)
+                        == la_idx + 1
+                });
+                if !inserted {
+                    break;
+                }
+                if psr.valid_la(&m_pstack, la_tidx) {
+                    missing.push(Missing{p_idx, s_idx, syms: prod[usize::from(s_idx)..k + 1]
+                                                                 .to_vec()});
+                    break;
+                }
+            }
+        }
+        if let Some(min) = missing.iter().map(|m| m.syms.len()).min() {
+            missing.retain(|m| m.syms.len() == min);
+        }
+        missing
+    }
+
+    /// Parse the lexemes in two phases, returning the same result as `parse`. The first phase
+    /// parses with error recovery turned off, which is the cheapest way of parsing error-free
+    /// input. Only if an error is encountered does the second phase start: this resumes parsing
+    /// from the point of the error with the recoverer specified by `recoverer` turned on.
+    pub fn parse_two_phase(&self, lexemes: &Lexemes<TokId>)
+                        -> Result<Node<TokId>, (Option<Node<TokId>>, Vec<ParseError<TokId>>)>
+    {
+        Parser::parse(self, lexemes, true)
+    }
+}
+
+/// The completions returned by `RTParserBuilder::completions`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Completions {
+    terms: Vec<TIdx>,
+    nonterms: Vec<(NTIdx, Vec<TIdx>)>
+}
+
+impl Completions {
+    /// Return the terminals which could validly follow the input, sorted by `TIdx`.
+    pub fn terms(&self) -> &[TIdx] {
+        &self.terms
+    }
+
+    /// Return the nonterminals which could validly follow the input, each paired with the shortest
+    /// sequence of terminals it can expand to, sorted by name. This is empty unless expansions were
+    /// requested.
+    pub fn nonterms(&self) -> &[(NTIdx, Vec<TIdx>)] {
+        &self.nonterms
+    }
+}
+
+/// Recognisers for the casings of keywords used by `RTParserBuilder::term_text`: lower case,
+/// upper case, and capitalised.
+const CASINGS: [fn(&str) -> bool; 3] = [is_lower, is_upper, is_capitalised];
+
+fn is_lower(s: &str) -> bool {
+    s.chars().any(|c| c.is_lowercase()) && !s.chars().any(|c| c.is_uppercase())
+}
+
+fn is_upper(s: &str) -> bool {
+    s.chars().any(|c| c.is_uppercase()) && !s.chars().any(|c| c.is_lowercase())
+}
+
+fn is_capitalised(s: &str) -> bool {
+    let mut cs = s.chars();
+    cs.next().map_or(false, |c| c.is_uppercase()) && is_lower(cs.as_str())
+}
+
+/// Return the Levenshtein distance between `a` and `b` (in characters).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..b.len() + 1).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == cb {
+                             diag
+                         } else {
+                             1 + diag.min(above).min(row[j])
+                         };
+            diag = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// Add the range `span` to the ordered, non-overlapping ranges `spans`, merging it with any
+/// ranges it overlaps.
+fn add_discarded(spans: &mut Vec<(usize, usize)>, span: (usize, usize)) {
+    spans.push(span);
+    spans.sort();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+    for &(start, end) in spans.iter() {
+        if let Some(last) = merged.last_mut() {
+            if start <= last.1 {
+                last.1 = last.1.max(end);
+                continue;
+            }
+        }
+        merged.push((start, end));
+    }
+    *spans = merged;
+}
+
+/// Convert `r` into a sequence of `Insert`, `Delete`, and `Shift` repairs. `InsertSeq`s are
+/// converted into their first sequence of inserts, `Substitute`s into their deletes followed by
+/// their insert, and `Shifts` into their individual shifts.
+pub(crate) fn flatten_repair(r: &ParseRepair) -> Vec<ParseRepair> {
+    match *r {
+        ParseRepair::InsertSeq(ref seqs) => {
+            seqs[0].iter().map(|t_idx| ParseRepair::Insert(*t_idx)).collect()
+        },
+        ParseRepair::Substitute{delete_span, insert_term} => {
+            let mut rprs = vec![ParseRepair::Delete; delete_span];
+            rprs.push(ParseRepair::Insert(insert_term));
+            rprs
+        },
+        ParseRepair::Shifts(n) => vec![ParseRepair::Shift; n],
+        ref r => vec![r.clone()]
+    }
+}
+
+/// Change how `Shift`s are reported in the repair sequences `all_rprs` to `sr`, which must not be
+/// `ShiftReporting::Each`.
+fn report_shifts(all_rprs: &mut Vec<Vec<ParseRepair>>, sr: ShiftReporting) {
+    for rprs in all_rprs.iter_mut() {
+        let mut new_rprs = Vec::with_capacity(rprs.len());
+        for r in rprs.drain(..) {
+            match (sr, r) {
+                (ShiftReporting::Omit, ParseRepair::Shift) => (),
+                (ShiftReporting::Collapse, ParseRepair::Shift) => {
+                    if let Some(&mut ParseRepair::Shifts(ref mut n)) = new_rprs.last_mut() {
+                        *n += 1;
+                        continue;
+                    }
+                    new_rprs.push(ParseRepair::Shifts(1));
+                },
+                (_, r) => new_rprs.push(r)
+            }
+        }
+        *rprs = new_rprs;
+    }
+    // Omitting shifts can make distinct repair sequences identical.
+    let mut seen = Vec::with_capacity(all_rprs.len());
+    all_rprs.retain(|rprs| {
+        if seen.contains(rprs) {
+            false
+        } else {
+            seen.push(rprs.clone());
+            true
+        }
+    });
+}
+
+/// Merge each run of one or more `Delete`s immediately followed by an `Insert` in `rprs` into a
+/// single `Substitute`.
+fn merge_substitutions(rprs: Vec<ParseRepair>) -> Vec<ParseRepair> {
+    let mut merged = Vec::with_capacity(rprs.len());
+    let mut dels = 0;
+    for r in rprs {
+        match r {
+            ParseRepair::Delete => dels += 1,
+            ParseRepair::Insert(t_idx) if dels > 0 => {
+                merged.push(ParseRepair::Substitute{delete_span: dels, insert_term: t_idx});
+                dels = 0;
+            },
+            r => {
+                merged.extend((0..dels).map(|_| ParseRepair::Delete));
+                dels = 0;
+                merged.push(r);
+            }
+        }
+    }
+    merged.extend((0..dels).map(|_| ParseRepair::Delete));
+    merged
+}
+
+/// The effect of a repair sequence, as returned by `RTParserBuilder::preview_repair`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepairedSlice<TokId: Copy> {
+    // Each step of the repair sequence with the (start lexeme index, end lexeme index, start byte,
+    // end byte) it affects.
+    steps: Vec<(ParseRepair, (usize, usize, usize, usize))>,
+    lookahead_idx: usize,
+    lookahead: Lexeme<TokId>
+}
+
+impl<TokId: Copy> RepairedSlice<TokId> {
+    /// Return the number of steps in the repair sequence (with `InsertSeq`s converted into their
+    /// first sequence of `Insert`s).
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Is the repair sequence empty?
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Return the `i`th step of the repair sequence. This is never `ParseRepair::InsertSeq` or
+    /// `ParseRepair::Substitute`.
+    pub fn repair(&self, i: usize) -> &ParseRepair {
+        &self.steps[i].0
+    }
+
+    /// Return the half-open range of lexeme indices deleted or shifted by the `i`th step. For an
+    /// `Insert`, this is the empty range at the lexeme before which the insertion is made.
+    pub fn lexeme_range(&self, i: usize) -> (usize, usize) {
+        let (start, end, _, _) = self.steps[i].1;
+        (start, end)
+    }
+
+    /// Return the half-open range of byte offsets in the input affected by the `i`th step. For an
+    /// `Insert`, this is the empty range at the point of insertion.
+    pub fn byte_range(&self, i: usize) -> (usize, usize) {
+        let (_, _, start, end) = self.steps[i].1;
+        (start, end)
+    }
+
+    /// Return the half-open range of byte offsets in the input edited (i.e. inserted into or
+    /// deleted from, but not merely shifted) by the repair sequence as a whole, or `None` if the
+    /// sequence makes no edits.
+    pub fn edited_byte_range(&self) -> Option<(usize, usize)> {
+        let mut edited = self.steps.iter().filter(|s| s.0 != ParseRepair::Shift).map(|s| s.1);
+        edited.next().map(|(_, _, start, end)| {
+            edited.fold((start, end), |(s, e), (_, _, start, end)| (s.min(start), e.max(end)))
+        })
+    }
+
+    /// Return the repair sequence as text edits, each a half-open range of byte offsets in the
+    /// input and the text to replace it with, in the style of an LSP `TextEdit` (though callers
+    /// must convert byte offsets to line/character positions themselves). Each inserted terminal
+    /// is replaced by `term_text` of it (which should lex as that terminal, including any
+    /// whitespace needed to separate it from its neighbours); deleted lexemes are replaced by the
+    /// empty string; shifts are omitted. Edits which touch are merged, so the edits returned are
+    /// in order and never overlap or touch, and can be applied directly as a quick fix.
+    pub fn text_edits<F>(&self, term_text: F) -> Vec<((usize, usize), String)>
+                  where F: Fn(TIdx) -> String
+    {
+        let mut edits: Vec<((usize, usize), String)> = Vec::new();
+        for &(ref r, (_, _, start, end)) in &self.steps {
+            let text = match *r {
+                ParseRepair::Insert(t_idx) => term_text(t_idx),
+                ParseRepair::Delete => String::new(),
+                ParseRepair::Shift => continue,
+                  ParseRepair::InsertSeq(_)
+                | ParseRepair::Substitute{..}
+                | ParseRepair::Shifts(_) => unreachable!()
+            };
+            if let Some(&mut (ref mut range, ref mut last_text)) = edits.last_mut() {
+                if range.1 == start {
+                    range.1 = end;
+                    last_text.push_str(&text);
+                    continue;
+                }
+            }
+            edits.push(((start, end), text));
+        }
+        edits
+    }
+
+    /// Return the index of the lexeme parsing would resume with after the repair sequence.
+    pub fn lookahead_idx(&self) -> usize {
+        self.lookahead_idx
+    }
+
+    /// Return the lexeme parsing would resume with after the repair sequence (which may be the
+    /// zero-length end-of-input lexeme).
+    pub fn lookahead(&self) -> &Lexeme<TokId> {
+        &self.lookahead
+    }
+}
+
+/// How the parser recovered from a single error, as returned by
+/// `RTParserBuilder::parse_with_recovery_report`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AppliedRecovery {
+    applied: Option<Vec<ParseRepair>>,
+    alternatives: Vec<Vec<ParseRepair>>,
+    resumed_at: Option<usize>
+}
+
+impl AppliedRecovery {
+    fn new<TokId: Copy>(err: &ParseError<TokId>) -> Self {
+        AppliedRecovery{applied: err.applied.clone(),
+                        alternatives: err.repairs.iter().skip(1).cloned().collect(),
+                        resumed_at: err.resumed_at}
+    }
+
+    /// Return the repair sequence the parser applied to the input exactly as it was applied (i.e.
+    /// with every `Shift`, and with `InsertSeq`, `Substitute`, and `Shifts` repairs flattened into
+    /// their constituent repairs, whatever `RTParserBuilder::shifts` and
+    /// `RTParserBuilder::substitutions` are set to), or `None` if no repairs were found (in which
+    /// case parsing either stopped at the error or, if `ParseError::skipped_rule` is not `None`,
+    /// skipped an instance of a rule).
+    pub fn applied(&self) -> Option<&[ParseRepair]> {
+        self.applied.as_ref().map(|rprs| rprs.as_slice())
+    }
+
+    /// Return the repair sequences which were found but not applied, in the order they were
+    /// ranked, as they are reported in `ParseError::repairs`.
+    pub fn alternatives(&self) -> &[Vec<ParseRepair>] {
+        &self.alternatives
+    }
+
+    /// Return the index of the lexeme parsing resumed with after recovering, or `None` if parsing
+    /// stopped at the error. Any later skip to an anchor (see `ParseError::skipped_to`) is not
+    /// reflected here.
+    pub fn resumed_at(&self) -> Option<usize> {
+        self.resumed_at
+    }
+}
+
+/// A single step of a repair sequence, as returned by `RTParserBuilder::explain_repairs`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepairStep {
+    repair: ParseRepair,
+    st_idx: StIdx,
+    t_idx: TIdx,
+    items: Vec<(PIdx, SIdx)>
+}
+
+impl RepairStep {
+    /// Return the repair applied at this step. This is never `ParseRepair::InsertSeq` or
+    /// `ParseRepair::Substitute`.
+    pub fn repair(&self) -> &ParseRepair {
+        &self.repair
+    }
+
+    /// Return the state in which this step was applied (i.e. after any reductions the step
+    /// caused, but before anything was shifted).
+    pub fn state_idx(&self) -> StIdx {
+        self.st_idx
+    }
+
+    /// Return the terminal that this step inserted, deleted, or shifted.
+    pub fn term_idx(&self) -> TIdx {
+        self.t_idx
+    }
+
+    /// Return the core items of `state_idx` as `(production, dot position)` pairs.
+    pub fn items(&self) -> &[(PIdx, SIdx)] {
+        &self.items
+    }
+
+    /// Return a human readable version of each of `items` e.g. `E: '(' E . ')'`.
+    pub fn pp_items(&self, grm: &YaccGrammar) -> Vec<String> {
+        self.items.iter().map(|&(p_idx, s_idx)| pp_item(grm, p_idx, s_idx)).collect()
+    }
+}
+
+/// Return a human readable version of the item `(p_idx, s_idx)` e.g. `E: '(' E . ')'`.
+pub(crate) fn pp_item(grm: &YaccGrammar, p_idx: PIdx, s_idx: SIdx) -> String {
+    let mut s = format!("{}:", grm.nonterm_name(grm.prod_to_nonterm(p_idx)));
+    let prod = grm.prod(p_idx);
+    for (i, sym) in prod.iter().enumerate() {
+        if i == usize::from(s_idx) {
+            s.push_str(" .");
+        }
+        s.push_str(&format!(" {}", pp_sym(grm, *sym)));
+    }
+    if usize::from(s_idx) == prod.len() {
+        s.push_str(" .");
+    }
+    s
+}
+
+/// Return a human readable version of `sym`: terminals are quoted.
+pub(crate) fn pp_sym(grm: &YaccGrammar, sym: Symbol) -> String {
+    match sym {
+        Symbol::Term(t_idx) => format!("'{}'", grm.term_name(t_idx).unwrap()),
+        Symbol::Nonterm(nt_idx) => grm.nonterm_name(nt_idx).to_owned()
+    }
+}
+
+/// A way of continuing a partially recognised production so that the lookahead at an error
+/// becomes valid, as returned by `RTParserBuilder::missing`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Missing {
+    p_idx: PIdx,
+    s_idx: SIdx,
+    syms: Vec<Symbol>
+}
+
+impl Missing {
+    /// Return the partially recognised item `(production, dot position)` in the state the error
+    /// occurred in.
+    pub fn item(&self) -> (PIdx, SIdx) {
+        (self.p_idx, self.s_idx)
+    }
+
+    /// Return the symbols which follow the dot in `item` and which, once completed, make the
+    /// lookahead valid.
+    pub fn syms(&self) -> &[Symbol] {
+        &self.syms
+    }
+
+    /// Return a human readable explanation e.g. `missing Cond in If: 'if' . Cond 'then' Stmt`.
+    pub fn pp(&self, grm: &YaccGrammar) -> String {
+        format!("missing {} in {}",
+                self.syms.iter().map(|&sym| pp_sym(grm, sym)).collect::<Vec<_>>().join(" "),
+                pp_item(grm, self.p_idx, self.s_idx))
+    }
+}
+
+/// The classification of input returned by `RTParserBuilder::input_status`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InputStatus {
+    /// The input parsed without error.
+    Complete,
+    /// The input is a prefix of valid input: more input is needed for it to parse successfully.
+    Incomplete,
+    /// The input contains an error which no amount of additional input can fix.
+    Error
+}
+
+/// What a parser does when there are no lexemes to parse and the grammar does not accept empty
+/// input (see `RTParserBuilder::empty_input`). In all cases a single `ParseError` is reported at
+/// the end of the (empty) input.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EmptyInput {
+    /// Use the parser's recoverer, as for any other error.
+    Recover,
+    /// Report the minimal cost sequences of insertions which make a valid input as the error's
+    /// repairs (with the parse tree containing the first), computed directly from the grammar
+    /// rather than by the recoverer.
+    MinimalInsert,
+    /// Report the error without any repairs.
+    Error
+}
+
+/// How `Shift`s are reported in repair sequences (see `RTParserBuilder::shifts`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ShiftReporting {
+    /// Report each `Shift` individually.
+    Each,
+    /// Report each run of `n` consecutive `Shift`s as a single `ParseRepair::Shifts(n)`.
+    Collapse,
+    /// Omit `Shift`s, so that repair sequences consist only of edits. Repair sequences which then
+    /// become identical are reported once.
+    Omit
+}
+
+/// After a parse error is encountered, the parser attempts to find a way of recovering. Each entry
+/// in the sequence of repairs is represented by a `ParseRepair`.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ParseRepair {
+    /// Insert a `Symbol::Term`.
+    Insert(TIdx),
+    /// Insert one of the sequences of `Symbol::Term`s.
+    InsertSeq(Vec<Vec<TIdx>>),
+    /// Delete a symbol.
+    Delete,
+    /// Shift a symbol.
+    Shift,
+    /// Delete `delete_span` symbols and insert the `Symbol::Term` `insert_term` in their place.
+    /// This is only produced if `RTParserBuilder::substitutions` is turned on.
+    Substitute{delete_span: usize, insert_term: TIdx},
+    /// Shift `n` symbols. This is only produced if `RTParserBuilder::shifts` is set to
+    /// `ShiftReporting::Collapse`.
+    Shifts(usize)
+}
+
+/// Records a single parse error.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError<TokId: Copy> {
+    state_idx: StIdx,
+    lexeme_idx: usize,
+    lexeme: Lexeme<TokId>,
+    repairs: Vec<Vec<ParseRepair>>,
+    // The repair sequence the parser applied, recorded when it was applied (so it is unaffected
+    // by later rewriting of `repairs`), with any compound repairs flattened.
+    applied: Option<Vec<ParseRepair>>,
+    repair_len_capped: bool,
+    cost_ceiling_reached: bool,
+    memory_limit_reached: bool,
+    expansion_limit_reached: bool,
+    recovery_skipped: bool,
+    used_fallback: bool,
+    repairs_cached: bool,
+    discarded: Vec<(usize, usize)>,
+    skipped_to: Option<usize>,
+    skipped_rule: Option<NTIdx>,
+    resumed_at: Option<usize>,
+    recovery_error: Option<RecoveryError>,
+    caused_by: Option<usize>,
+    suggestion: Option<TIdx>
+}
+
+impl<TokId: Copy> ParseError<TokId> {
+    /// Return the state table index where this error was detected.
+    pub fn state_idx(&self) -> StIdx {
+        self.state_idx
+    }
+
+    /// Return the lexeme where this error was detected.
+    pub fn lexeme_idx(&self) -> usize {
+        self.lexeme_idx
+    }
+
+    /// Return the lexeme where this error was detected.
+    pub fn lexeme(&self) -> &Lexeme<TokId> {
+        &self.lexeme
+    }
+
+    /// Return the repairs found that would fix this error. Note that there are infinite number of
+    /// possible repairs for any error, so this is by definition a (finite) subset.
+    pub fn repairs(&self) -> &Vec<Vec<ParseRepair>> {
+        &self.repairs
+    }
+
+    /// Return `true` if the search for repairs was cut short because partial repair sequences
+    /// reached the maximum repair length (see `RTParserBuilder::max_repair_len`). If so, `repairs`
+    /// may be empty or may not contain the minimal cost repairs.
+    pub fn repair_len_capped(&self) -> bool {
+        self.repair_len_capped
+    }
+
+    /// Return `true` if the search for repairs was cut short because partial repair sequences
+    /// reached the maximum repair cost (see `RTParserBuilder::max_repair_cost`). If so, `repairs`
+    /// may be empty.
+    pub fn cost_ceiling_reached(&self) -> bool {
+        self.cost_ceiling_reached
+    }
+
+    /// Return `true` if the search for repairs was cut short because it reached its memory limit
+    /// (see `RTParserBuilder::max_recovery_memory`). If so, `repairs` may be empty or may not
+    /// contain all the minimal cost repairs.
+    pub fn memory_limit_reached(&self) -> bool {
+        self.memory_limit_reached
+    }
+
+    /// Return `true` if the search for repairs was cut short because it expanded as many partial
+    /// repair sequences as it may (see `RTParserBuilder::max_recovery_expansions`). If so,
+    /// `repairs` may be empty or may not contain all the minimal cost repairs.
+    pub fn expansion_limit_reached(&self) -> bool {
+        self.expansion_limit_reached
+    }
+
+    /// Return `true` if no attempt was made to recover from this error because the parser had
+    /// already encountered the maximum number of errors (see `RTParserBuilder::max_errors`),
+    /// because the error is in a span where recovery is off (see `RTParserBuilder::no_recovery`),
+    /// or because `RTParserBuilder::recovery_decision` said not to. If so, `repairs` is empty and
+    /// parsing stopped at this error.
+    pub fn recovery_skipped(&self) -> bool {
+        self.recovery_skipped
+    }
+
+    /// Return `true` if the recoverer found no repairs within the fallback budget (see
+    /// `RTParserBuilder::fallback`) and so `repairs` were found by panic mode instead. Better
+    /// repairs can be found with `RTParserBuilder::full_repairs`.
+    pub fn used_fallback(&self) -> bool {
+        self.used_fallback
+    }
+
+    /// Return `true` if `repairs` were remembered from an earlier error (see
+    /// `RTParserBuilder::repair_cache` and `RTParserBuilder::reuse_recoveries`) rather than found
+    /// by searching. If so, they are still valid repairs, but may not be the minimal cost repairs.
+    pub fn repairs_cached(&self) -> bool {
+        self.repairs_cached
+    }
+
+    /// Return the half-open ranges of byte offsets in the input which were discarded in recovering
+    /// from this error, in order and without overlaps, so that (for example) an editor can grey
+    /// them out. These are the lexemes deleted by the first repair sequence (which is the one the
+    /// parser applied), with consecutive deleted lexemes (and any text between them) merged into
+    /// a single range, and, if `skipped_to` is not `None`, the skipped input (including that
+    /// of any already parsed nodes which were unwound).
+    pub fn discarded_spans(&self) -> &[(usize, usize)] {
+        &self.discarded
+    }
+
+    /// If `RTParserBuilder::isolate_errors` is on and a further error occurred after recovering
+    /// from this one but before the next anchor, return the index of that anchor: the lexemes from
+    /// the further error up to (but excluding) it were skipped, and no error was reported for
+    /// them. If `skipped_rule` is not `None`, return the index of the lexeme parsing resumed from
+    /// after skipping the rule's instance. Otherwise return `None`.
+    pub fn skipped_to(&self) -> Option<usize> {
+        self.skipped_to
+    }
+
+    /// If no repairs were found for this error, and an instance of a skippable rule (see
+    /// `RTParserBuilder::skippable`) was discarded instead, return that rule. `repairs` is then
+    /// empty, but parsing continued from `skipped_to`, and the discarded input is reported by
+    /// `discarded_spans`.
+    pub fn skipped_rule(&self) -> Option<NTIdx> {
+        self.skipped_rule
+    }
+
+    /// If no attempt was made to recover from this error because recovery is impossible with the
+    /// parser's grammar and options (e.g. a terminal has a cost of zero), return why. If so,
+    /// `repairs` is empty and parsing stopped at this error.
+    pub fn recovery_error(&self) -> Option<RecoveryError> {
+        self.recovery_error
+    }
+
+    /// If `RTParserBuilder::note_cascades` is on, and this error was plausibly caused by the
+    /// repair applied for the previous error (i.e. another of that error's repair sequences would
+    /// have avoided this error), return the index of the previous error (in the errors returned
+    /// by the parser). Otherwise return `None`.
+    pub fn caused_by(&self) -> Option<usize> {
+        self.caused_by
+    }
+
+    /// If `RTParserBuilder::typo_aware` is on, and the text of the lexeme where this error was
+    /// detected is a near miss for a keyword which would have been valid in its place (e.g. `whlie`
+    /// where `while` was expected), return that keyword's `TIdx`, so that a "did you mean ...?"
+    /// note can be added to diagnostics (`RTParserBuilder::term_text` renders the keyword in the
+    /// input's casing). Otherwise return `None`. This is independent of the repairs found.
+    pub fn suggestion(&self) -> Option<TIdx> {
+        self.suggestion
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    use cfgrammar::yacc::{YaccGrammar, yacc_grm, YaccKind};
+    use lrlex::{build_lex, Lexeme};
+    use lrtable::{Minimiser, from_yacc, StateGraph, StateTable};
+    use num_traits::ToPrimitive;
+
+    use annotations::{github_annotations, github_annotations_with};
+    use localise::Localisation;
+    use super::*;
+
+    pub(crate) fn do_parse(rcvry_kind: RecoveryKind,
+                           lexs: &str,
+                           grms: &str,
+                           input: &str)
+                       -> (YaccGrammar,
+                           Result<Node<u16>, (Option<Node<u16>>,
+                                              Vec<ParseError<u16>>)>)
+    {
+        do_parse_with_costs(rcvry_kind, lexs, grms, input, &HashMap::new())
+    }
+
+    /// Build the grammar, state graph, and state table for `grms`, and lex `input` using `lexs`,
+    /// so that tests can then parse with whatever `RTParserBuilder` options they need.
+    pub(crate) fn build_parse_env(lexs: &str,
+                                  grms: &str,
+                                  input: &str)
+                              -> (YaccGrammar, StateGraph, StateTable, Vec<Lexeme<u16>>)
+    {
+        let mut lexerdef = build_lex(lexs).unwrap();
+        let grm = yacc_grm(YaccKind::Original, grms).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        {
+            let rule_ids = grm.terms_map().iter()
+                                          .map(|(&n, &i)| (n, u32::from(i).to_u16().unwrap()))
+                                          .collect();
+            lexerdef.set_rule_ids(&rule_ids);
+        }
+        let lexemes = lexerdef.lexer(&input).lexemes().unwrap();
+        (grm, sgraph, stable, lexemes)
+    }
+
+    /// The lexer and grammar of a sequence of calls such as `f() g()`, which many tests use.
+    pub(crate) const CALLS_LEXS: &str = "%%
+\\( '('
+\\) ')'
+[a-zA-Z_][a-zA-Z_0-9]* 'ID'
+[ \\n] ;
+";
+    pub(crate) const CALLS_GRMS: &str = "%start Calls
+%%
+Calls: Calls Call | ;
+Call: 'ID' '(' ')';";
+
+    /// As `build_parse_env`, but for `CALLS_LEXS` and `CALLS_GRMS`.
+    pub(crate) fn calls_env(input: &str)
+                        -> (YaccGrammar, StateGraph, StateTable, Vec<Lexeme<u16>>)
+    {
+        build_parse_env(CALLS_LEXS, CALLS_GRMS, input)
+    }
+
+    pub(crate) fn do_parse_with_costs(rcvry_kind: RecoveryKind,
+                                      lexs: &str,
+                                      grms: &str,
+                                      input: &str,
+                                      costs: &HashMap<&str, u8>)
+                                  -> (YaccGrammar,
+                                      Result<Node<u16>, (Option<Node<u16>>,
+                                                         Vec<ParseError<u16>>)>)
+    {
+        let (grm, sgraph, stable, lexemes) = build_parse_env(lexs, grms, input);
+        let costs_tidx = costs.iter()
+                              .map(|(k, v)| (grm.term_idx(k).unwrap(), v))
+                              .collect::<HashMap<_, _>>();
+        let pr = parse_rcvry(rcvry_kind,
+                             &grm,
+                             move |t_idx| **costs_tidx.get(&t_idx).unwrap_or(&&1),
+                             &sgraph,
+                             &stable,
+                             &lexemes);
+        (grm, pr)
+    }
+
+    fn check_parse_output(lexs: &str, grms: &str, input: &str, expected: &str) {
+        let (grm, pt) = do_parse(RecoveryKind::MF, lexs, grms, input);
+        assert_eq!(expected, pt.unwrap().pp(&grm, &input));
+    }
+
+    #[test]
+    fn simple_parse() {
+        // From p4 of https://www.cs.umd.edu/class/spring2014/cmsc430/lectures/lec07.pdf
+        check_parse_output("%%
+[a-zA-Z_] 'ID'
+\\+ '+'",
+"
+%start E
+%%
+E: T '+' E
+ | T;
+T: 'ID';
+",
+"a+b",
+"E
+ T
+  ID a
+ + +
+ E
+  T
+   ID b
+");
+    }
+
+    #[test]
+    fn parse_empty_rules() {
+        let lexs = "%%
+[a-zA-Z_] 'ID'";
+        let grms = "%start S
+%%
+S: L;
+L: 'ID'
+ | ;
+";
+        check_parse_output(&lexs, &grms, "",
+"S
+ L
+");
+
+        check_parse_output(&lexs, &grms, "x",
+"S
+ L
+  ID x
+");
+    }
+
+    #[test]
+    fn recursive_parse() {
+        let lexs = "%%
+\\+ '+'
+\\* '*'
+[0-9]+ 'INT'
+";
+        let grms = "%start Expr
+%%
+Expr : Term '+' Expr | Term;
+Term : Factor '*' Term | Factor;
+Factor : 'INT';";
+
+        check_parse_output(&lexs, &grms, "2+3*4",
+"Expr
+ Term
+  Factor
+   INT 2
+ + +
+ Expr
+  Term
+   Factor
+    INT 3
+   * *
+   Term
+    Factor
+     INT 4
+");
+        check_parse_output(&lexs, &grms, "2*3+4",
+"Expr
+ Term
+  Factor
+   INT 2
+  * *
+  Term
+   Factor
+    INT 3
+ + +
+ Expr
+  Term
+   Factor
+    INT 4
+");
+    }
+
+    #[test]
+    fn parse_error() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+[a-zA-Z_][a-zA-Z_0-9]* 'ID'
+";
+        let grms = "%start Call
+%%
+Call: 'ID' '(' ')';";
+
+        check_parse_output(&lexs, &grms, "f()",
+"Call
+ ID f
+ ( (
+ ) )
+");
+
+        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, "f(");
+        let (_, errs) = pr.unwrap_err();
+        assert_eq!(errs.len(), 1);
+        let err_tok_id = usize::from(grm.eof_term_idx()).to_u16().unwrap();
+        assert_eq!(errs[0].lexeme(), &Lexeme::new(err_tok_id, 2, 0));
+
+        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, "f(f(");
+        let (_, errs) = pr.unwrap_err();
+        assert_eq!(errs.len(), 1);
+        let err_tok_id = usize::from(grm.term_idx("ID").unwrap()).to_u16().unwrap();
+        assert_eq!(errs[0].lexeme(), &Lexeme::new(err_tok_id, 2, 1));
+     }
+
+    #[test]
+    fn lr_cactus_simulation() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+[a-zA-Z_][a-zA-Z_0-9]* 'ID'
+";
+        let grms = "%start Call
+%%
+Call: 'ID' '(' ')';";
+
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "f()");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let psr = rtpb.parser(&lexemes);
+        let start = Cactus::new().child(StIdx::from(0 as u32));
+        let (la_idx, pstack) = psr.lr_cactus(None, 0, lexemes.len() + 1, start.clone(), &mut None);
+        assert_eq!(la_idx, lexemes.len());
+        match stable.action(*pstack.val().unwrap(), grm.eof_term_idx()) {
+            Some(Action::Accept) => (),
+            _ => panic!("Simulated parse did not reach an accept state")
+        }
+        // The starting stack must not have been altered by the simulation.
+        assert_eq!(start.len(), 1);
+    }
+
+    #[test]
+    fn peek() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+[a-zA-Z_][a-zA-Z_0-9]* 'ID'
+";
+        let grms = "%start Call
+%%
+Call: 'ID' '(' ')';";
+
+        let input = "f()";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, input);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let psr = rtpb.parser(&lexemes);
+        assert_eq!(psr.peek(0, 2).iter()
+                                 .map(|l| lexeme_str(input, l))
+                                 .collect::<Vec<_>>(),
+                   vec!["f", "("]);
+        assert_eq!(psr.peek(1, 5).len(), 2);
+        assert!(psr.peek(3, 1).is_empty());
+        assert!(psr.peek(5, 1).is_empty());
+    }
+
+    #[test]
+    fn input_status() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+[a-zA-Z_][a-zA-Z_0-9]* 'ID'
+";
+        let grms = "%start Call
+%%
+Call: 'ID' '(' Args ')';
+Args: Call | ;";
+
+        for &(input, status) in &[("f()", InputStatus::Complete),
+                                  ("f(g())", InputStatus::Complete),
+                                  ("", InputStatus::Incomplete),
+                                  ("f(", InputStatus::Incomplete),
+                                  ("f(g(", InputStatus::Incomplete),
+                                  ("f)", InputStatus::Error),
+                                  ("f())", InputStatus::Error)] {
+            let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, input);
+            let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+            assert_eq!(rtpb.input_status(&lexemes), status, "{}", input);
+        }
+    }
+
+    #[test]
+    fn completions() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+, ','
+[a-zA-Z_][a-zA-Z_0-9]* 'ID'
+";
+        let grms = "%start Call
+%%
+Call: 'ID' '(' Args ')';
+Args: Args ',' Call | Call | ;";
+
+        let input = "f(g(),";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, input);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let names = |cs: &Completions| {
+            cs.terms().iter()
+                      .map(|t_idx| grm.term_name(*t_idx).unwrap())
+                      .collect::<Vec<_>>()
+        };
+        let mut terms = names(&rtpb.completions(&lexemes, 2, false));
+        terms.sort();
+        assert_eq!(terms, vec![")", ",", "ID"]);
+        let mut terms = names(&rtpb.completions(&lexemes, 5, false));
+        terms.sort();
+        assert_eq!(terms, vec![")", ","]);
+        let cs = rtpb.completions(&lexemes, input.len(), true);
+        assert_eq!(names(&cs), vec!["ID"]);
+        assert_eq!(cs.nonterms().len(), 1);
+        let (nt_idx, ref sentence) = cs.nonterms()[0];
+        assert_eq!(grm.nonterm_name(nt_idx), "Call");
+        assert_eq!(sentence.iter()
+                           .map(|t_idx| grm.term_name(*t_idx).unwrap())
+                           .collect::<Vec<_>>(),
+                   vec!["ID", "(", ")"]);
+
+        // An error before the cursor is recovered from.
+        let input = "f(g)(),";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, input);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let cs = rtpb.completions(&lexemes, input.len(), false);
+        assert_eq!(cs.terms().iter()
+                             .map(|t_idx| grm.term_name(*t_idx).unwrap())
+                             .collect::<Vec<_>>(),
+                   vec!["ID"]);
+    }
+
+    #[test]
+    fn explain_repairs() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+a 'A'
+";
+        let grms = "%start E
+%%
+E: '(' E ')'
+ | 'A' ;
+";
+
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "((a");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        let rprs_idx = errs[0].repairs()
+                              .iter()
+                              .position(|rprs| {
+                                  rprs == &vec![ParseRepair::Insert(grm.term_idx(")").unwrap()),
+                                                ParseRepair::Insert(grm.term_idx(")").unwrap())]
+                              })
+                              .unwrap();
+        let steps = rtpb.explain_repairs(&lexemes, &errs, 0, rprs_idx);
+        assert_eq!(steps.len(), 2);
+        for st in &steps {
+            assert_eq!(st.repair(), &ParseRepair::Insert(grm.term_idx(")").unwrap()));
+            assert_eq!(st.pp_items(&grm), vec!["E: '(' E . ')'"]);
+        }
+    }
+
+    #[test]
+    fn two_phase_parse() {
+        for input in &["f()", "f(", "f(f(", "f()(g()h)"] {
+            let (grm, sgraph, stable, lexemes) = calls_env(input);
+            let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable)
+                                       .recoverer(RecoveryKind::CPCTPlus);
+            assert_eq!(rtpb.parse(&lexemes), rtpb.parse_two_phase(&lexemes));
+        }
+    }
+
+    #[test]
+    fn prefer_anchors() {
+        let lexs = "%%
+; ';'
+[a-z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' ';';";
+
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "a b ; c");
+        let semi = grm.term_idx(";").unwrap();
+        let rprs = vec![vec![ParseRepair::Insert(semi)], vec![ParseRepair::Delete]];
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let mut sorted = rprs.clone();
+        rtpb.parser(&lexemes).prefer_anchors(1, &mut sorted);
+        assert_eq!(sorted, rprs);
+
+        let rtpb = rtpb.anchor(semi);
+        let mut sorted = rprs.clone();
+        rtpb.parser(&lexemes).prefer_anchors(1, &mut sorted);
+        assert_eq!(sorted, vec![vec![ParseRepair::Delete], vec![ParseRepair::Insert(semi)]]);
+        // "Delete, Shift" shifts the anchor before reaching the end of the sequence.
+        let mut sorted = vec![vec![ParseRepair::Insert(semi)],
+                              vec![ParseRepair::Delete, ParseRepair::Shift, ParseRepair::Delete]];
+        rtpb.parser(&lexemes).prefer_anchors(1, &mut sorted);
+        assert_eq!(sorted[0][0], ParseRepair::Delete);
+    }
+
+    #[test]
+    fn anchor_changes_applied_repair() {
+        let lexs = "%%
+; ';'
+[a-z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' ';';";
+
+        // Inserting a ';' after "a" and deleting "b" are equally cheap and both let the rest of
+        // the input parse, so without anchors the (lexicographically smaller) insertion is applied.
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "a b ; c ; d ;");
+        let semi = grm.term_idx(";").unwrap();
+        for &rk in &[RecoveryKind::MF, RecoveryKind::CPCTPlus] {
+            let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(rk);
+            let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+            assert_eq!(errs.len(), 1);
+            assert_eq!(errs[0].applied(), Some(&[ParseRepair::Insert(semi)][..]));
+            assert!(errs[0].repairs().contains(&vec![ParseRepair::Delete]));
+
+            // With ';' as an anchor, deleting "b" resumes parsing by shifting the anchor, so it is
+            // ranked first and applied instead.
+            let (_, errs) = rtpb.anchor(semi).parse(&lexemes).unwrap_err();
+            assert_eq!(errs.len(), 1);
+            assert_eq!(errs[0].applied(), Some(&[ParseRepair::Delete][..]));
+            assert_eq!(errs[0].repairs()[0], vec![ParseRepair::Delete]);
+        }
+    }
+
+    #[test]
+    fn note_cascades() {
+        let (grm, sgraph, stable, lexemes) = calls_env("f)g)");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).note_cascades(true);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs.len(), 2);
+        assert!(errs.iter().all(|e| e.caused_by().is_none()));
+
+        // Suppose that, for "f(x)", the parser had inserted ")" before "x" rather than deleting
+        // "x": the error at the final ")" would then be caused by that choice.
+        let (grm, sgraph, stable, lexemes) = calls_env("f(x)");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, mut errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs[0].lexeme_idx(), 2);
+        let close = grm.term_idx(")").unwrap();
+        errs[0].repairs = vec![vec![ParseRepair::Insert(close)], vec![ParseRepair::Delete]];
+        let mut next = errs[0].clone();
+        next.lexeme_idx = 3;
+        next.repairs = vec![vec![ParseRepair::Delete]];
+        errs.truncate(1);
+        errs.push(next);
+        rtpb.parser(&lexemes).note_cascades(&mut errs);
+        assert_eq!(errs[0].caused_by(), None);
+        assert_eq!(errs[1].caused_by(), Some(0));
+
+        // Without the alternative, there's nothing to suggest that the second error was caused by
+        // the first.
+        errs[0].repairs.truncate(1);
+        errs[1].caused_by = None;
+        rtpb.parser(&lexemes).note_cascades(&mut errs);
+        assert_eq!(errs[1].caused_by(), None);
+    }
+
+    #[test]
+    fn consistent_repairs() {
+        for input in &["f(g(h)", "f(g)h(", "f)g((h)i(", "f(()g)(h"] {
+            let (grm, sgraph, stable, lexemes) = calls_env(input);
+            let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+            let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+            let rtpb = rtpb.consistent_repairs(true);
+            let (_, c_errs) = rtpb.parse(&lexemes).unwrap_err();
+            assert_eq!(errs.len(), c_errs.len());
+            for (e, c_e) in errs.iter().zip(c_errs.iter()) {
+                assert_eq!(e.lexeme_idx(), c_e.lexeme_idx());
+                // The repair sequence the parser applied is always consistent.
+                assert_eq!(e.repairs().get(0), c_e.repairs().get(0));
+                assert!(c_e.repairs().iter().all(|rprs| e.repairs().contains(rprs)));
+            }
+            assert_eq!(errs.last(), c_errs.last());
+        }
+    }
+
+    #[test]
+    fn on_error() {
+        let (grm, sgraph, stable, lexemes) = calls_env("f(g)h(i(");
+        let early = RefCell::new(Vec::new());
+        let f = |e: &ParseError<u16>| early.borrow_mut().push(e.clone());
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).on_error(&f);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert!(!errs.is_empty());
+        assert_eq!(*early.borrow(), errs);
+        // The provisional error found by the first phase of a two-phase parse is not reported.
+        early.borrow_mut().clear();
+        let (_, errs) = rtpb.parse_two_phase(&lexemes).unwrap_err();
+        assert_eq!(*early.borrow(), errs);
+        // Errors are passed to `on_error` in the form `parse` returns them.
+        early.borrow_mut().clear();
+        let (_, errs) = rtpb.shifts(ShiftReporting::Omit).parse(&lexemes).unwrap_err();
+        assert_eq!(*early.borrow(), errs);
+    }
+
+    #[test]
+    fn empty_input() {
+        let lexs = "%%
+\\( '('
+\\) ')'
+[a-zA-Z_][a-zA-Z_0-9]* 'ID'
+[ ] ;
+";
+        let grms = "%start Call
+%%
+Call: 'ID' '(' Args ')';
+Args: Call | ;";
+
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "  ");
+        assert!(lexemes.is_empty());
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).empty_input(EmptyInput::Error);
+        let (pt, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert!(pt.is_none());
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].repairs().is_empty());
+
+        let rtpb = rtpb.empty_input(EmptyInput::MinimalInsert);
+        let (pt, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        let ins = |n| ParseRepair::Insert(grm.term_idx(n).unwrap());
+        assert_eq!(errs[0].repairs(), &vec![vec![ins("ID"), ins("("), ins(")")]]);
+        assert_eq!(pt.unwrap().pp(&grm, ""), "Call
+ ID 
+ ( 
+ Args
+ ) 
+");
+    }
+
+    #[test]
+    fn preview_repair() {
+        let (grm, sgraph, stable, lexemes) = calls_env("f( ) g ) h()");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        let ins = ParseRepair::Insert(grm.term_idx("(").unwrap());
+        let rs = rtpb.preview_repair(&lexemes,
+                                     &errs[0],
+                                     &[ParseRepair::Delete, ParseRepair::Shift, ins.clone()]);
+        assert_eq!(errs[0].lexeme_idx(), 4);
+        assert_eq!(rs.len(), 3);
+        assert_eq!(rs.lexeme_range(0), (4, 5));
+        assert_eq!(rs.byte_range(0), (7, 8));
+        assert_eq!(rs.byte_range(1), (9, 10));
+        assert_eq!(rs.repair(2), &ins);
+        assert_eq!(rs.lexeme_range(2), (6, 6));
+        assert_eq!(rs.byte_range(2), (10, 10));
+        assert_eq!(rs.edited_byte_range(), Some((7, 10)));
+        assert_eq!(rs.lookahead_idx(), 6);
+        assert_eq!(rs.lookahead().start(), 10);
+        let rs = rtpb.preview_repair(&lexemes, &errs[0], &[]);
+        assert_eq!(rs.edited_byte_range(), None);
+        assert_eq!(rs.lookahead_idx(), errs[0].lexeme_idx());
+    }
+
+    #[test]
+    fn recovery_report() {
+        let (grm, sgraph, stable, lexemes) = calls_env("f( ) g ) h()");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (pt, errs, applied) = rtpb.parse_with_recovery_report(&lexemes);
+        assert!(pt.is_some());
+        assert_eq!(errs.len(), 1);
+        assert_eq!(applied.len(), 1);
+        let rprs = applied[0].applied().unwrap();
+        assert_eq!(rprs, &errs[0].repairs()[0][..]);
+        assert_eq!(applied[0].alternatives(), &errs[0].repairs()[1..]);
+        let rs = rtpb.preview_repair(&lexemes, &errs[0], rprs);
+        assert_eq!(applied[0].resumed_at(), Some(rs.lookahead_idx()));
+
+        // The repair sequences applied are recorded as they were applied, however the repair
+        // sequences are reported.
+        let (grm, sgraph, stable, lexemes) = calls_env("f(()g)(h");
+        let (_, errs, applied) = RTParserBuilder::new(&grm, &sgraph, &stable)
+                                     .parse_with_recovery_report(&lexemes);
+        for (e, a) in errs.iter().zip(applied.iter()) {
+            assert_eq!(a.applied(), e.repairs().get(0).map(|rprs| &rprs[..]));
+        }
+        for &sr in &[ShiftReporting::Collapse, ShiftReporting::Omit] {
+            let (_, _, s_applied) = RTParserBuilder::new(&grm, &sgraph, &stable)
+                                        .shifts(sr)
+                                        .parse_with_recovery_report(&lexemes);
+            assert_eq!(applied.len(), s_applied.len());
+            for (a, s_a) in applied.iter().zip(s_applied.iter()) {
+                assert_eq!(a.applied(), s_a.applied());
+                assert_eq!(a.resumed_at(), s_a.resumed_at());
+            }
+        }
+
+        let (grm, sgraph, stable, lexemes) = calls_env("f() g()");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (pt, errs, applied) = rtpb.parse_with_recovery_report(&lexemes);
+        assert!(pt.is_some());
+        assert!(errs.is_empty());
+        assert!(applied.is_empty());
+    }
+
+    #[test]
+    fn text_edits() {
+        let (grm, sgraph, stable, lexemes) = calls_env("f( ) g ) h()");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        let term_text = |t_idx| grm.term_name(t_idx).unwrap().to_owned();
+        let ins = ParseRepair::Insert(grm.term_idx("(").unwrap());
+        let rs = rtpb.preview_repair(&lexemes,
+                                     &errs[0],
+                                     &[ParseRepair::Delete, ParseRepair::Shift, ins.clone()]);
+        assert_eq!(rs.text_edits(&term_text),
+                   vec![((7, 8), "".to_owned()), ((10, 10), "(".to_owned())]);
+        // An insert followed by a delete of the next lexeme becomes a single replacement.
+        let rs = rtpb.preview_repair(&lexemes, &errs[0], &[ins.clone(), ParseRepair::Delete]);
+        assert_eq!(rs.text_edits(&term_text), vec![((7, 8), "(".to_owned())]);
+        let rs = rtpb.preview_repair(&lexemes, &errs[0], &[ParseRepair::Shift]);
+        assert!(rs.text_edits(&term_text).is_empty());
+    }
+
+    #[test]
+    fn minimal_span() {
+        let lexs = "%%
+; ';'
+[a-z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' ';';";
+
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "a b  c ;");
+        let semi = grm.term_idx(";").unwrap();
+        let rprs = vec![vec![ParseRepair::Delete, ParseRepair::Shift, ParseRepair::Delete],
+                        vec![ParseRepair::Insert(semi), ParseRepair::Shift, ParseRepair::Delete]];
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let mut sorted = rprs.clone();
+        rtpb.parser(&lexemes).prefer_minimal_span(1, &mut sorted);
+        assert_eq!(sorted, rprs);
+
+        // The first sequence's edits span from `b` to `;` (6 bytes), the second's from just
+        // before `b` to `c` (4 bytes).
+        let rtpb = rtpb.minimal_span(true);
+        let mut sorted = rprs.clone();
+        rtpb.parser(&lexemes).prefer_minimal_span(1, &mut sorted);
+        assert_eq!(sorted, vec![rprs[1].clone(), rprs[0].clone()]);
+    }
+
+    #[test]
+    fn missing() {
+        let lexs = "%%
+if 'IF'
+then 'THEN'
+[a-z]+ 'ID'
+= '='
+[ ] ;
+";
+        let grms = "%start Stmt
+%%
+Stmt: 'IF' Cond 'THEN' Stmt | 'ID' '=' 'ID';
+Cond: 'ID' '=' 'ID';";
+
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "if then x = y");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        let ms = rtpb.missing(&lexemes, &errs, 0);
+        assert_eq!(ms.len(), 1);
+        assert_eq!(ms[0].syms(), &[Symbol::Nonterm(grm.nonterm_idx("Cond").unwrap())]);
+        assert_eq!(ms[0].pp(&grm), "missing Cond in Stmt: 'IF' . Cond 'THEN' Stmt");
+    }
+
+    #[test]
+    fn pp_with() {
+        let lexs = "%%
+[a-zA-Z_] 'ID'
+\\+ '+'
+[ ] ;
+";
+        let grms = "%start E
+%%
+E: T '+' E | T ;
+T: 'ID' ;
+";
+
+        let us = "a + b";
+        let (grm, pt) = do_parse(RecoveryKind::MF, &lexs, &grms, &us);
+        let pt = pt.unwrap();
+        assert_eq!(pt.pp_with(&grm, &us, &PPOptions::new()), pt.pp(&grm, &us));
+        assert_eq!(pt.pp_with(&grm, &us, &PPOptions::new().indent(2).spans(true).names(false)),
+                   "E [0..5]
+  T [0..1]
+    a [0..1]
+  + [2..3]
+  E [4..5]
+    T [4..5]
+      b [4..5]
+");
+        assert_eq!(pt.pp_with(&grm, &us, &PPOptions::new().text(false).max_depth(1)),
+                   "E
+ T ...
+ +
+ E ...
+");
+        // The spans of nodes at `max_depth` include their unshown children.
+        assert_eq!(pt.pp_with(&grm, &us, &PPOptions::new().spans(true).max_depth(1)),
+                   "E [0..5]
+ T ... [0..1]
+ + + [2..3]
+ E ... [4..5]
+");
+    }
+
+    #[test]
+    fn inserted_spans() {
+        let lexs = "%%
+[a-zA-Z_] 'ID'
+\\+ '+'
+; ';'
+[ \\n] ;
+";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: E ';' ;
+E: E '+' 'ID' | 'ID' ;
+";
+
+        // The inserted 'ID' is positioned at the ';' on the second line.
+        let us = "a +\n;";
+        let mut costs = HashMap::new();
+        costs.insert("+", 3);
+        let (grm, pr) = do_parse_with_costs(RecoveryKind::MF, &lexs, &grms, &us, &costs);
+        let (pt, errs) = pr.unwrap_err();
+        let pt = pt.unwrap();
+        assert_eq!(errs.len(), 1);
+        let e = match pt {
+            Node::Nonterm{ref nodes, ..} => match nodes[1] {
+                Node::Nonterm{ref nodes, ..} => &nodes[0],
+                _ => panic!()
+            },
+            _ => panic!()
+        };
+        assert_eq!(e.span_with(true), Some((0, 4)));
+        assert_eq!(e.span_with(false), Some((0, 3)));
+        match *e {
+            Node::Nonterm{ref nodes, ..} => assert_eq!(nodes[2].span_with(false), None),
+            _ => panic!()
+        }
+        assert_eq!(e.pp_with(&grm, &us, &PPOptions::new().spans(true).inserted_spans(false)),
+                   "E [0..3]
+ E [0..1]
+  ID a [0..1]
+ + + [2..3]
+ ID \n");
+        assert_eq!(e.pp_with(&grm, &us, &PPOptions::new().spans(true)),
+                   "E [0..4]
+ E [0..1]
+  ID a [0..1]
+ + + [2..3]
+ ID  [4..4]\n");
+    }
+
+    #[test]
+    fn try_advance() {
+        // In this language, `a < b > c` is a generic call if `<` is a type bracket and a
+        // comparison chain otherwise. The grammar is LR with the right tokens, but the lexer can't
+        // know which `<` it has seen: the caller decides by trying one and backtracking.
+        let lexs = "%%
+< '<'
+> '>'
+[a-z]+ 'ID'
+\\( '('
+\\) ')'
+[ ] ;
+";
+        let grms = "%start E
+%token LT GT
+%%
+E: 'ID' '<' 'ID' '>' '(' ')' | 'ID' 'LT' 'ID' 'GT' 'ID' ;
+";
+
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "a < b > c");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let psr = rtpb.parser(&lexemes);
+        let mut st = psr.initial_state();
+        assert!(!psr.try_advance(&mut st, 5));
+        assert_eq!(st, psr.initial_state());
+
+        let retag = |l: &Lexeme<u16>, n| {
+            Lexeme::new(u32::from(grm.term_idx(n).unwrap()).to_u16().unwrap(), l.start(), l.len())
+        };
+        let alt_lexemes = vec![lexemes[0], retag(&lexemes[1], "LT"), lexemes[2],
+                               retag(&lexemes[3], "GT"), lexemes[4]];
+        let alt_psr = rtpb.parser(&alt_lexemes);
+        assert!(alt_psr.try_advance(&mut st, 5));
+        assert_eq!(st.la_idx(), 5);
+        assert!(alt_psr.finish(st).is_ok());
+    }
+
+    #[test]
+    fn typo_aware() {
+        assert_eq!(edit_distance("retrun", "return"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+
+        let lexs = "%%
+return 'return'
+break 'break'
+[a-z]+ 'ID'
+; ';'
+[ ] ;
+";
+        let grms = "%start Stmt
+%%
+Stmt: 'return' 'ID' ';' | 'break' 'ID' ';' ;";
+
+        let us = "retrun x;";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let ins = |n| ParseRepair::Insert(grm.term_idx(n).unwrap());
+        let rprs = vec![vec![ParseRepair::Delete, ins("break")],
+                        vec![ParseRepair::Delete, ins("return")]];
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let mut sorted = rprs.clone();
+        rtpb.parser(&lexemes).prefer_similar(0, &mut sorted);
+        assert_eq!(sorted, rprs);
+
+        let rtpb = rtpb.typo_aware(&us);
+        let mut sorted = rprs.clone();
+        rtpb.parser(&lexemes).prefer_similar(0, &mut sorted);
+        assert_eq!(sorted, vec![rprs[1].clone(), rprs[0].clone()]);
+        // The substitution can also be expressed as an Insert followed by a Delete.
+        let mut sorted = vec![vec![ins("break"), ParseRepair::Delete],
+                              vec![ins("return"), ParseRepair::Delete]];
+        rtpb.parser(&lexemes).prefer_similar(0, &mut sorted);
+        assert_eq!(sorted[0][0], ins("return"));
+    }
+
+    #[test]
+    fn suggestion() {
+        let lexs = "%%
+return 'return'
+break 'break'
+[a-z]+ 'ID'
+; ';'
+[ ] ;
+";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'return' 'ID' ';' | 'break' 'ID' ';' ;";
+
+        // Return the suggestion for the first error in `us`.
+        let suggestion = |us: &str, typo_aware: bool| {
+            let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, us);
+            let mut rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+            if typo_aware {
+                rtpb = rtpb.typo_aware(us);
+            }
+            let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+            errs[0].suggestion().map(|t_idx| grm.term_name(t_idx).unwrap().to_owned())
+        };
+        assert_eq!(suggestion("retrun x;", false), None);
+        assert_eq!(suggestion("retrun x;", true), Some("return".to_owned()));
+        assert_eq!(suggestion("break x; braek y;", true), Some("break".to_owned()));
+        // Lexemes too dissimilar from, or too short to be a misspelling of, any valid keyword.
+        assert_eq!(suggestion("banana x;", true), None);
+        assert_eq!(suggestion("r x;", true), None);
+        // Only keywords valid at the error are suggested.
+        assert_eq!(suggestion("return return;", true), None);
+    }
+
+    #[test]
+    fn substitutions() {
+        let lexs = "%%
+if 'IF'
+then 'THEN'
+\\{ '{'
+\\} '}'
+[a-z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start If
+%%
+If: 'IF' 'ID' '{' 'ID' '}';";
+
+        let us = "if x then y }";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let brace = grm.term_idx("{").unwrap();
+        assert_eq!(merge_substitutions(vec![ParseRepair::Delete, ParseRepair::Delete,
+                                            ParseRepair::Insert(brace), ParseRepair::Shift,
+                                            ParseRepair::Delete]),
+                   vec![ParseRepair::Substitute{delete_span: 2, insert_term: brace},
+                        ParseRepair::Shift, ParseRepair::Delete]);
+
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        let (_, s_errs) = rtpb.substitutions(true).parse(&lexemes).unwrap_err();
+        assert_eq!(errs.len(), s_errs.len());
+        let subst = vec![ParseRepair::Substitute{delete_span: 1, insert_term: brace}];
+        assert!(s_errs[0].repairs().contains(&subst));
+        for (e, s_e) in errs.iter().zip(s_errs.iter()) {
+            assert_eq!(e.repairs().len(), s_e.repairs().len());
+            for (rprs, s_rprs) in e.repairs().iter().zip(s_e.repairs().iter()) {
+                assert_eq!(*rprs, s_rprs.iter().flat_map(flatten_repair).collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn parse_slice() {
+        let lexs = "%%
+; ';'
+[a-z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start Stmt
+%%
+Stmt: 'ID' ';';";
+
+        let us = "a; b; c";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (end, r) = rtpb.parse_slice(&lexemes, 2, 4, false);
+        assert_eq!(end, 4);
+        assert_eq!(lexeme_str(&us, &r.unwrap().extreme_lexeme(false).unwrap()), "b");
+
+        let (end, r) = rtpb.parse_slice(&lexemes, 0, lexemes.len(), false);
+        assert_eq!(end, lexemes.len());
+        assert!(r.is_err());
+        let (end, r) = rtpb.parse_slice(&lexemes, 0, lexemes.len(), true);
+        assert_eq!(end, 2);
+        assert!(r.is_ok());
+        let (end, r) = rtpb.parse_slice(&lexemes, end, lexemes.len(), true);
+        assert_eq!(end, 4);
+        assert!(r.is_ok());
+
+        // The last statement is missing its ';', which can't be accepted early.
+        let (end, r) = rtpb.parse_slice(&lexemes, end, lexemes.len(), true);
+        assert_eq!(end, 5);
+        let (_, errs) = r.unwrap_err();
+        assert_eq!(errs[0].lexeme_idx(), 5);
+        assert_eq!(errs[0].lexeme().start(), us.len());
+    }
+
+    #[test]
+    fn parse_stream() {
+        let us = "f()g()h()i()j()k()";
+        let (grm, sgraph, stable, lexemes) = calls_env(&us);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        assert!(rtpb.parse_stream(lexemes.iter().cloned(), 1).is_ok());
+        assert!(rtpb.parse_stream(vec![], 1).is_ok());
+
+        let us = "f()g)h()i()j(k()l()m()n()";
+        let (grm, sgraph, stable, lexemes) = calls_env(&us);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        let stream_errs = rtpb.parse_stream(lexemes.iter().cloned(), 6).unwrap_err();
+        assert_eq!(stream_errs.iter().map(|e| e.lexeme_idx()).collect::<Vec<_>>(),
+                   errs.iter().map(|e| e.lexeme_idx()).collect::<Vec<_>>());
+        assert_eq!(stream_errs[1].lexeme(), errs[1].lexeme());
+    }
+
+    #[test]
+    fn parse_prefix() {
+        let us = "f()g()h()";
+        let (grm, sgraph, stable, lexemes) = calls_env(&us);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (st, errs) = rtpb.parse_prefix(&lexemes, 3).unwrap();
+        assert!(errs.is_empty());
+        assert_eq!(st.la_idx(), 3);
+        // The parser stops before it has seen the lexeme at the cut-off, so `f()` has not yet
+        // been reduced to a `Call`.
+        assert_eq!(st.tstack().len(), 4);
+        let (mid, errs) = rtpb.parse_prefix(&lexemes, 6).unwrap();
+        assert!(errs.is_empty());
+
+        // Resuming from either state gives the same tree as parsing from scratch.
+        let full = rtpb.parse(&lexemes).unwrap();
+        assert_eq!(rtpb.parser(&lexemes).finish(st.clone()).unwrap(), full);
+        let psr = rtpb.parser(&lexemes);
+        let mut st2 = st.clone();
+        assert!(psr.advance(&mut st2, 6, &mut Vec::new()));
+        assert_eq!(st2, mid);
+
+        // A state can be resumed with lexemes which differ from its cut-off onwards.
+        let (_, _, _, edited) = calls_env("f()x(");
+        let (_, errs) = rtpb.parser(&edited).finish(st.clone()).unwrap_err();
+        assert_eq!(errs[0].lexeme_idx(), 5);
+
+        // Errors before the cut-off are recovered from as normal.
+        let (_, _, _, lexemes) = calls_env("f(g()h()");
+        let (st, errs) = rtpb.parse_prefix(&lexemes, 5).unwrap();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].lexeme_idx(), 2);
+        assert!(st.la_idx() >= 5);
+    }
+
+    #[test]
+    fn term_text() {
+        let lexs = "%%
+(?i)select 'SELECT'
+(?i)from 'FROM'
+[a-zA-Z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start Stmt
+%%
+Stmt: 'SELECT' 'ID' 'FROM' 'ID';";
+
+        for &(us, from) in &[("select a b", "from"),
+                             ("SELECT a b", "FROM"),
+                             ("Select a b", "From"),
+                             ("select a FROM b select", "from")] {
+            let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, us);
+            let from_idx = grm.term_idx("FROM").unwrap();
+            let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+            assert_eq!(rtpb.term_text(us, &lexemes, from_idx), "FROM");
+            let rtpb = rtpb.case_insensitive(grm.term_idx("SELECT").unwrap())
+                           .case_insensitive(from_idx);
+            assert_eq!(rtpb.term_text(us, &lexemes, from_idx), from);
+            assert_eq!(rtpb.term_text(us, &lexemes, grm.term_idx("ID").unwrap()), "ID");
+        }
+
+        // Suggestions in annotations are rendered in the input's casing too.
+        let us = "select a fron b";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, us);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable)
+                                   .case_insensitive(grm.term_idx("SELECT").unwrap())
+                                   .case_insensitive(grm.term_idx("FROM").unwrap())
+                                   .typo_aware(us);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs[0].suggestion(), grm.term_idx("FROM"));
+        let term_text = |t_idx| rtpb.term_text(us, &lexemes, t_idx);
+        assert!(github_annotations_with(&grm, "x.txt", us, &errs, &Localisation::new(), &term_text)
+                    .ends_with(" Did you mean `from`?\n"));
+        assert!(github_annotations(&grm, "x.txt", us, &errs).ends_with(" Did you mean `FROM`?\n"));
+    }
+
+    #[test]
+    fn skippable() {
+        let lexs = "%%
+= '='
+; ';'
+[a-z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' '=' 'ID' ';';";
+
+        let us = "a = b ; c = = = = d ; e = f ;";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).max_repair_len(1);
+        let (pt, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert!(pt.is_none());
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].repairs().is_empty() && errs[0].skipped_rule().is_none());
+
+        // The malformed statement "c = = = = d ;" is discarded as a whole.
+        let stmt = grm.nonterm_idx("Stmt").unwrap();
+        let (pt, errs) = rtpb.skippable(stmt).parse(&lexemes).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].lexeme_idx(), 6);
+        assert!(errs[0].repairs().is_empty());
+        assert_eq!(errs[0].skipped_rule(), Some(stmt));
+        assert_eq!(errs[0].skipped_to(), Some(11));
+        assert_eq!(errs[0].discarded_spans(), &[(8, 21)]);
+        assert!(github_annotations(&grm, "x.txt", &us, &errs)
+                    .ends_with("::Parsing error. Ignored malformed Stmt.\n"));
+        assert_eq!(pt.unwrap().pp(&grm, &us), "Stmts
+ Stmts
+  Stmts
+  Stmt
+   ID a
+   = =
+   ID b
+   ; ;
+ Stmt
+  ID e
+  = =
+  ID f
+  ; ;
+");
+    }
+
+    #[test]
+    fn recovery_error() {
+        let lexs = "%%
+= '='
+; ';'
+[a-z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' '=' 'ID' ';';";
+
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "a = b ; c = ;");
+        let semi = grm.term_idx(";").unwrap();
+        let costs = |t_idx| if t_idx == semi { 0 } else { 1 };
+        let (pt, errs) = RTParserBuilder::new(&grm, &sgraph, &stable).term_costs(&costs)
+                                                                      .parse(&lexemes)
+                                                                      .unwrap_err();
+        assert!(pt.is_none());
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].lexeme_idx(), 6);
+        assert!(errs[0].repairs().is_empty());
+        assert_eq!(errs[0].recovery_error(), Some(RecoveryError::ZeroTermCost(semi)));
+
+        // Parsing without errors doesn't need to recover, so the costs don't matter.
+        let (_, _, _, lexemes) = build_parse_env(&lexs, &grms, "a = b ;");
+        assert!(RTParserBuilder::new(&grm, &sgraph, &stable).term_costs(&costs)
+                                                            .parse(&lexemes)
+                                                            .is_ok());
+    }
+
+    #[test]
+    fn lookahead() {
+        let lexs = "%%
+a 'a'
+x 'x'
+y 'y'
+z 'z'
+[ ] ;
+";
+        // After 'a', LR(1) sees only 'x', so can't tell whether to reduce A or B.
+        let grms = "%start S
+%%
+S: A 'x' 'y' | B 'x' 'z';
+A: 'a';
+B: 'a';";
+
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, "a x z");
+        let b_idx = grm.nonterm_idx("B").unwrap();
+        assert_eq!(stable.conflicts().unwrap().rr_len(), 1);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::None);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs[0].lexeme_idx(), 2);
+
+        let rtpb = rtpb.lookahead(b_idx, 1);
+        match rtpb.parse(&lexemes).unwrap() {
+            Node::Nonterm{ref nodes, ..} => match nodes[0] {
+                Node::Nonterm{nonterm_idx, ..} => assert_eq!(nonterm_idx, b_idx),
+                _ => panic!()
+            },
+            _ => panic!()
+        }
+        // The table's resolution is still taken when it parses at least as far.
+        let (_, _, _, lexemes) = build_parse_env(&lexs, &grms, "a x y");
+        assert!(rtpb.parse(&lexemes).is_ok());
+        // A hint which doesn't look far enough makes no difference.
+        let (_, _, _, lexemes) = build_parse_env(&lexs, &grms, "a x z");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::None)
+                                                               .lookahead(b_idx, 0);
+        assert!(rtpb.parse(&lexemes).is_err());
+    }
+
+    #[test]
+    fn pratt() {
+        let lexs = "%%
+\\+ '+'
+\\* '*'
+\\^ '^'
+= '='
+; ';'
+\\( '('
+\\) ')'
+[0-9]+ 'INT'
+[a-z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start Stmts
+%left '+'
+%left '*'
+%right '^'
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' '=' E ';';
+E: E '+' E | E '*' E | E '^' E | '(' E ')' | 'INT';";
+
+        let us = "a = 1 + 2 * 3 ^ 4 ^ 5 + 6; b = (1 + 2) * 3;";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, us);
+        let e_idx = grm.nonterm_idx("E").unwrap();
+        let prec = |n, level: u32, assoc| (grm.term_idx(n).unwrap(), level, assoc);
+        let precs = vec![prec("+", 1, Assoc::Left),
+                         prec("*", 2, Assoc::Left),
+                         prec("^", 3, Assoc::Right)];
+        let lr = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let hybrid = RTParserBuilder::new(&grm, &sgraph, &stable).pratt(e_idx, &precs);
+        let pt = hybrid.parse(&lexemes).unwrap();
+        assert_eq!(pt.pp(&grm, us), lr.parse(&lexemes).unwrap().pp(&grm, us));
+
+        // Errors are left to the LR parser, so are reported and repaired as usual.
+        let us = "a = 1 + ; b = 2;";
+        let (_, _, _, lexemes) = build_parse_env(&lexs, &grms, us);
+        let (lr_pt, lr_errs) = lr.parse(&lexemes).unwrap_err();
+        let (pt, errs) = hybrid.parse(&lexemes).unwrap_err();
+        assert_eq!(pt.unwrap().pp(&grm, us), lr_pt.unwrap().pp(&grm, us));
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].lexeme_idx(), lr_errs[0].lexeme_idx());
+        assert_eq!(errs[0].repairs(), lr_errs[0].repairs());
+
+        // Deeply nested expressions are parsed without recursion...
+        let us = format!("a = {}1{};", "(".repeat(1000), ")".repeat(1000));
+        let (_, _, _, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let pt = hybrid.parse(&lexemes).unwrap();
+        assert_eq!(pt.pp(&grm, &us), lr.parse(&lexemes).unwrap().pp(&grm, &us));
+        // ...and long erroneous ones are handed over to the LR parser once, rather than being
+        // reparsed from each lexeme in turn.
+        let us = format!("a = {}1 + ; b = 2;", "1 + ".repeat(1000));
+        let (_, _, _, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let (lr_pt, lr_errs) = lr.parse(&lexemes).unwrap_err();
+        let (pt, errs) = hybrid.parse(&lexemes).unwrap_err();
+        assert_eq!(pt.unwrap().pp(&grm, &us), lr_pt.unwrap().pp(&grm, &us));
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].lexeme_idx(), lr_errs[0].lexeme_idx());
+
+        // The sub-parser's precedences, not the grammar's, are used.
+        let us = "a = 1 + 2 * 3;";
+        let (_, _, _, lexemes) = build_parse_env(&lexs, &grms, us);
+        let precs = vec![prec("+", 2, Assoc::Left), prec("*", 1, Assoc::Left)];
+        let swapped = RTParserBuilder::new(&grm, &sgraph, &stable).pratt(e_idx, &precs);
+        let pt = swapped.parse(&lexemes).unwrap();
+        assert_ne!(pt.pp(&grm, us), lr.parse(&lexemes).unwrap().pp(&grm, us));
+    }
+
+    #[test]
+    fn pratt_unsupported() {
+        let grm = yacc_grm(YaccKind::Original, "%start E
+%%
+E: E '+' E | E '!' | 'INT';").unwrap();
+        let e_idx = grm.nonterm_idx("E").unwrap();
+        let plus = grm.term_idx("+").unwrap();
+        assert!(PrattRule::new(&grm, e_idx, &[(plus, 1, Assoc::Left)]).is_err());
+    }
+
+    #[test]
+    fn isolate_errors() {
+        let lexs = "%%
+= '='
+; ';'
+[a-z]+ 'ID'
+[ ] ;
+";
+        let grms = "%start Stmts
+%%
+Stmts: Stmts Stmt | ;
+Stmt: 'ID' '=' 'ID' ';';";
+
+        let us = "a = = b = c ; d = = e ;";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(RecoveryKind::Panic)
+                                                              .anchor(grm.term_idx(";").unwrap());
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.lexeme_idx()).collect::<Vec<_>>(), vec![2, 4, 9]);
+        assert!(errs.iter().all(|e| e.skipped_to().is_none()));
+
+        // "= c" is in the same statement as the first error, so is skipped; the error in the next
+        // statement is still reported.
+        let (pt, errs) = rtpb.isolate_errors(true).parse(&lexemes).unwrap_err();
+        assert!(pt.is_some());
+        assert_eq!(errs.iter().map(|e| e.lexeme_idx()).collect::<Vec<_>>(), vec![2, 9]);
+        assert_eq!(errs[0].skipped_to(), Some(6));
+        assert_eq!(errs[1].skipped_to(), None);
+        // The first error deleted the second "=", and the skip discarded "= c".
+        assert_eq!(errs[0].discarded_spans(), &[(4, 5), (8, 11)]);
+    }
+
+    #[test]
+    fn max_errors() {
+        let us = "f)g)h)i)";
+        let (grm, sgraph, stable, lexemes) = calls_env(&us);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs.len(), 4);
+        assert!(errs.iter().all(|e| !e.recovery_skipped()));
+
+        let (pt, errs) = rtpb.max_errors(2).parse(&lexemes).unwrap_err();
+        assert!(pt.is_none());
+        assert_eq!(errs.len(), 3);
+        assert!(!errs[1].recovery_skipped());
+        assert!(errs[2].recovery_skipped());
+        assert!(errs[2].repairs().is_empty());
+        assert_eq!(errs[2].lexeme_idx(), 5);
+    }
+
+    #[test]
+    fn clock() {
+        let us = "f)";
+        let (grm, sgraph, stable, lexemes) = calls_env(&us);
+        let start = Instant::now();
+        // A clock which never advances can never run out of time...
+        let frozen = || start;
+        // ...whereas one which advances by the whole budget on each call always does.
+        let now = Cell::new(start);
+        let slow = || {
+            now.set(now.get() + Duration::from_millis(RECOVERY_TIME_BUDGET));
+            now.get()
+        };
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).clock(&frozen);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert!(!errs[0].repairs().is_empty());
+
+        let rtpb = rtpb.clock(&slow);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert!(errs[0].repairs().is_empty());
+    }
+
+    #[test]
+    fn repair_progress() {
+        // Each repair lets the parser consume the rest of the input...
+        let (grm, sgraph, stable, lexemes) = calls_env("f(g()h()");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        let progress = rtpb.repair_progress(&lexemes, &errs, 0);
+        assert_eq!(progress.len(), errs[0].repairs().len());
+        assert!(progress.iter().all(|&p| p == lexemes.len() - 2));
+
+        // ...or up to the next error.
+        let (grm, sgraph, stable, lexemes) = calls_env("f(g()h(i");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert!(rtpb.repair_progress(&lexemes, &errs, 0).iter().all(|&p| p == 5));
+    }
+
+    #[test]
+    fn reuse_recoveries() {
+        // The same mistake three times, each with the same parse stack and upcoming lexemes.
+        let (grm, sgraph, stable, lexemes) = calls_env("f(g()f(g()f(g()");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs.len(), 3);
+        assert!(errs.iter().all(|e| !e.repairs_cached()));
+
+        let rtpb = rtpb.reuse_recoveries(true);
+        let (_, reused_errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(reused_errs.len(), 3);
+        assert!(!reused_errs[0].repairs_cached());
+        assert!(reused_errs[1].repairs_cached() && reused_errs[2].repairs_cached());
+        for (e, re) in errs.iter().zip(reused_errs.iter()) {
+            assert_eq!(e.repairs(), re.repairs());
+        }
+
+        // A different upcoming lexeme means a different context.
+        let (grm, sgraph, stable, lexemes) = calls_env("f(g()f(g)");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).reuse_recoveries(true);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert!(errs.iter().all(|e| !e.repairs_cached()));
+    }
+
+    #[test]
+    fn repair_cache() {
+        let (grm, sgraph, stable, lexemes) = calls_env("f(g()");
+        let cache = RepairCache::new(8);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).repair_cache(&cache);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert!(!errs[0].repairs_cached());
+        assert_eq!(cache.len(), 1);
+
+        // Parsing the same input again doesn't need to search: even with no time to do so, the
+        // same repairs are found.
+        let no_time = RefCell::new(|_: &ParseError<u16>| {
+            RecoveryDecision::Recover{recoverer: None, budget: Some(Duration::from_millis(0))}
+        });
+        let (_, cached_errs) = rtpb.recovery_decision(&no_time).parse(&lexemes).unwrap_err();
+        assert!(cached_errs[0].repairs_cached());
+        assert_eq!(cached_errs[0].repairs(), errs[0].repairs());
+
+        // With time to search, the remembered repairs seed the search, which finds the same
+        // (minimal cost) repairs for both recoverers.
+        for &rk in &[RecoveryKind::MF, RecoveryKind::CPCTPlus] {
+            let uncached = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(rk)
+                                                                        .parse(&lexemes);
+            let (_, uncached_errs) = uncached.unwrap_err();
+            let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).recoverer(rk)
+                                                                   .repair_cache(&cache);
+            let (_, seeded_errs) = rtpb.parse(&lexemes).unwrap_err();
+            assert!(!seeded_errs[0].repairs_cached());
+            assert_eq!(seeded_errs[0].repairs(), uncached_errs[0].repairs());
+        }
+
+        // A different grammar's repairs are remembered separately, since its state and terminal
+        // indices mean something else: the repairs remembered above are never tried for it.
+        let other_grms = "%start Calls
+%%
+Calls: Calls Call | ;
+Call: 'ID' '(' 'ID' ')';";
+        let (o_grm, o_sgraph, o_stable, o_lexemes) = build_parse_env(CALLS_LEXS,
+                                                                     &other_grms,
+                                                                     "f(g(");
+        let len = cache.len();
+        let o_rtpb = RTParserBuilder::new(&o_grm, &o_sgraph, &o_stable).repair_cache(&cache);
+        let (_, o_errs) = o_rtpb.parse(&o_lexemes).unwrap_err();
+        assert!(!o_errs[0].repairs_cached());
+        assert!(cache.len() > len);
+
+        // None of the remembered repairs work for an error in the same state and at the same
+        // terminal here, so the recoverer searches as normal.
+        let (grm, sgraph, stable, lexemes) = calls_env("f(g");
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable).repair_cache(&cache);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert!(!errs[0].repairs_cached());
+        assert!(!errs[0].repairs().is_empty());
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn parse_and_fix() {
+        let us = "f(g() h(";
+        let (grm, sgraph, stable, lexemes) = calls_env(&us);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let term_text = |t_idx| grm.term_name(t_idx).unwrap().to_owned();
+        let lex = |s: &str| calls_env(s).3;
+        let (fixed, pt) = rtpb.parse_and_fix(&us, &lexemes, &term_text, &lex).unwrap();
+        assert_eq!(fixed, "f()g() h()");
+        assert_eq!(pt.pp(&grm, &fixed), rtpb.parse(&lex(&fixed)).unwrap().pp(&grm, &fixed));
+
+        // Omitting shifts from the reported repair sequences doesn't change the fix.
+        for &sr in &[ShiftReporting::Collapse, ShiftReporting::Omit] {
+            let (o_fixed, _) = RTParserBuilder::new(&grm, &sgraph, &stable)
+                                   .shifts(sr)
+                                   .parse_and_fix(&us, &lexemes, &term_text, &lex)
+                                   .unwrap();
+            assert_eq!(o_fixed, fixed);
+        }
+
+        // Input without errors is returned as is.
+        let (fixed, _) = rtpb.parse_and_fix("f()", &lex("f()"), &term_text, &lex).unwrap();
+        assert_eq!(fixed, "f()");
+
+        // An error which isn't recovered from can't be fixed.
+        let rtpb = rtpb.max_errors(0);
+        let errs = rtpb.parse_and_fix(&us, &lexemes, &term_text, &lex).unwrap_err();
+        assert!(errs[0].recovery_skipped());
+    }
+
+    #[test]
+    fn fallback() {
+        let us = "f(g()";
+        let (grm, sgraph, stable, lexemes) = calls_env(&us);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert!(!errs[0].used_fallback());
+        let mf_rprs = errs[0].repairs().clone();
+
+        // With no time at all, MF can't find anything, so panic mode deletes "g(".
+        let rtpb = rtpb.fallback(Duration::from_millis(0));
+        let (pt, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert!(pt.is_some());
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].used_fallback());
+        assert_eq!(errs[0].repairs(), &vec![vec![ParseRepair::Delete, ParseRepair::Delete]]);
+        assert_eq!(rtpb.full_repairs(&lexemes, &errs, 0), mf_rprs);
+    }
+
+    #[test]
+    fn recovery_decision() {
+        let us = "f)g)h)";
+        let (grm, sgraph, stable, lexemes) = calls_env(&us);
+        let skip_after_first = RefCell::new(|e: &ParseError<u16>| {
+            if e.lexeme_idx() > 1 {
+                RecoveryDecision::Skip
+            } else {
+                RecoveryDecision::default()
+            }
+        });
+        let cpctplus = RefCell::new(|_: &ParseError<u16>| {
+            RecoveryDecision::Recover{recoverer: Some(RecoveryKind::CPCTPlus), budget: None}
+        });
+        let no_time = RefCell::new(|_: &ParseError<u16>| {
+            RecoveryDecision::Recover{recoverer: None, budget: Some(Duration::new(0, 0))}
+        });
+        let rtpb = || RTParserBuilder::new(&grm, &sgraph, &stable);
+
+        let (_, errs) = rtpb().recovery_decision(&skip_after_first).parse(&lexemes).unwrap_err();
+        assert_eq!(errs.len(), 2);
+        assert!(!errs[0].repairs().is_empty() && !errs[0].recovery_skipped());
+        assert!(errs[1].repairs().is_empty() && errs[1].recovery_skipped());
+
+        let (_, errs) = rtpb().recovery_decision(&cpctplus).parse(&lexemes).unwrap_err();
+        let (_, c_errs) = rtpb().recoverer(RecoveryKind::CPCTPlus).parse(&lexemes).unwrap_err();
+        assert_eq!(errs, c_errs);
+
+        let (_, errs) = rtpb().recovery_decision(&no_time).parse(&lexemes).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0].repairs().is_empty() && !errs[0].recovery_skipped());
+
+        // The decision can depend on state kept by the closure itself.
+        let mut seen = 0;
+        let errs = {
+            let skip_after_two = RefCell::new(|_: &ParseError<u16>| {
+                seen += 1;
+                if seen > 2 {
+                    RecoveryDecision::Skip
+                } else {
+                    RecoveryDecision::default()
+                }
+            });
+            rtpb().recovery_decision(&skip_after_two).parse(&lexemes).unwrap_err().1
+        };
+        assert_eq!(seen, 3);
+        assert_eq!(errs.len(), 3);
+        assert!(!errs[1].recovery_skipped() && errs[2].recovery_skipped());
+    }
+
+    #[test]
+    fn recovery_graph() {
+        let us = "f)";
+        let (grm, sgraph, stable, lexemes) = calls_env(&us);
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        let dot = rtpb.recovery_graph(&lexemes, &errs, 0);
+        assert!(dot.starts_with("digraph recovery {\n  n0 [label=\"state "));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("lexeme 1\\ncf=0 "));
+        assert!(dot.contains(" [label=\"Insert \\\"(\\\"\"];"));
+        assert!(dot.contains(", peripheries=2];"));
+    }
+
+    #[test]
+    fn shift_reporting() {
+        let ins = ParseRepair::Insert(TIdx::from(0 as u32));
+        let rprs = vec![vec![ParseRepair::Delete, ParseRepair::Shift, ParseRepair::Shift,
+                             ins.clone(), ParseRepair::Shift],
+                        vec![ParseRepair::Shift, ParseRepair::Delete, ins.clone()]];
+        let mut collapsed = rprs.clone();
+        report_shifts(&mut collapsed, ShiftReporting::Collapse);
+        assert_eq!(collapsed,
+                   vec![vec![ParseRepair::Delete, ParseRepair::Shifts(2), ins.clone(),
+                             ParseRepair::Shifts(1)],
+                        vec![ParseRepair::Shifts(1), ParseRepair::Delete, ins.clone()]]);
+        assert_eq!(rprs[0], collapsed[0].iter().flat_map(flatten_repair).collect::<Vec<_>>());
+        let mut omitted = rprs.clone();
+        report_shifts(&mut omitted, ShiftReporting::Omit);
+        assert_eq!(omitted, vec![vec![ParseRepair::Delete, ins.clone()]]);
+
+        let us = "f(()g)(h";
+        let (grm, sgraph, stable, lexemes) = calls_env(&us);
+        let (_, errs) = RTParserBuilder::new(&grm, &sgraph, &stable).parse(&lexemes)
+                                                                    .unwrap_err();
+        for &sr in &[ShiftReporting::Collapse, ShiftReporting::Omit] {
+            let (_, s_errs) = RTParserBuilder::new(&grm, &sgraph, &stable).shifts(sr)
+                                                                          .parse(&lexemes)
+                                                                          .unwrap_err();
+            assert_eq!(errs.len(), s_errs.len());
+            for (e, s_e) in errs.iter().zip(s_errs.iter()) {
+                let mut rprs = e.repairs().clone();
+                report_shifts(&mut rprs, sr);
+                assert_eq!(&rprs, s_e.repairs());
+            }
+        }
+    }
 }
@@ -0,0 +1,128 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use lrlex::Lexeme;
+
+use parser::Node;
+use source::{lexeme_text, Source};
+use tokid::TokenId;
+
+/// Values (e.g. the number an integer literal denotes, or a string literal with its escapes
+/// processed) attached to lexemes when they are lexed, so that they need not be recomputed from
+/// the input text when the parse tree is later traversed. Values are looked up by lexeme, so the
+/// `Lexeme`s in `Node::Term`s can be used directly. Lexemes inserted by error recovery have no
+/// value.
+pub struct Payloads<T> {
+    // (start offset, value) pairs ordered by start offset.
+    vals: Vec<(usize, T)>
+}
+
+impl<T> Payloads<T> {
+    /// Compute the values of `lexemes` (which must be in input order) by calling `f` with each
+    /// lexeme and its text in `input`: if `f` returns `None`, the lexeme has no value.
+    pub fn new<TokId, S, F>(input: &S, lexemes: &[Lexeme<TokId>], mut f: F) -> Self
+        where TokId: TokenId,
+              S: Source + ?Sized,
+              F: FnMut(&Lexeme<TokId>, &str) -> Option<T>
+    {
+        let mut vals = Vec::new();
+        for l in lexemes {
+            if l.len() == 0 {
+                continue;
+            }
+            if let Some(v) = f(l, &lexeme_text(input, l)) {
+                vals.push((l.start(), v));
+            }
+        }
+        Payloads{vals}
+    }
+
+    /// Return the value of `lexeme`, if it has one.
+    pub fn get<TokId: TokenId>(&self, lexeme: &Lexeme<TokId>) -> Option<&T> {
+        if lexeme.len() == 0 {
+            return None;
+        }
+        self.vals.binary_search_by_key(&lexeme.start(), |&(off, _)| off)
+                 .ok()
+                 .map(|i| &self.vals[i].1)
+    }
+
+    /// Return the value of the terminal node `node`, if it has one. Nonterminal nodes never have
+    /// values.
+    pub fn node<TokId: TokenId>(&self, node: &Node<TokId>) -> Option<&T> {
+        match *node {
+            Node::Term{ref lexeme} => self.get(lexeme),
+            Node::Nonterm{..} => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use num_traits::ToPrimitive;
+    use parser::{Node, RTParserBuilder};
+    use parser::test::build_parse_env;
+    use super::Payloads;
+
+    #[test]
+    fn payloads() {
+        let lexs = "%%
+\\+ '+'
+[0-9]+ 'INT'
+[ ] ;
+";
+        let grms = "%start E
+%%
+E: E '+' 'INT'
+ | 'INT' ;
+";
+
+        let us = "1 + 23 + 456";
+        let (grm, sgraph, stable, lexemes) = build_parse_env(&lexs, &grms, &us);
+        let int = u32::from(grm.term_idx("INT").unwrap()).to_u16().unwrap();
+        let pls = Payloads::new(&us, &lexemes, |l, s| {
+            if l.tok_id() == int { s.parse::<u64>().ok() } else { None }
+        });
+        let pt = RTParserBuilder::new(&grm, &sgraph, &stable).parse(&lexemes).unwrap();
+        let mut sum = 0;
+        let mut st = vec![&pt];
+        while let Some(n) = st.pop() {
+            match *n {
+                Node::Term{..} => sum += pls.node(n).cloned().unwrap_or(0),
+                Node::Nonterm{ref nodes, ..} => st.extend(nodes)
+            }
+        }
+        assert_eq!(sum, 480);
+        assert_eq!(pls.get(&lexemes[1]), None);
+        assert_eq!(pls.get(&lexemes[4]), Some(&456));
+    }
+}
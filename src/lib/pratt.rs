@@ -0,0 +1,293 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::cmp::{max, Reverse};
+use std::collections::{HashMap, HashSet};
+
+use cfgrammar::{NTIdx, PIdx, Symbol, TIdx};
+use cfgrammar::yacc::YaccGrammar;
+use lrlex::Lexeme;
+
+use parser::{Node, Parser};
+use tokid::TokenId;
+
+/// The associativity of a binary operator, as declared by `%left`, `%right`, or `%nonassoc`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Assoc {
+    Left,
+    Right,
+    Nonassoc
+}
+
+/// An operator-precedence (precedence climbing) sub-parser for a single expression rule `E` (see
+/// `RTParserBuilder::pratt`). Each of `E`'s productions must be either a binary operator
+/// `E: E 'op' E`, where `'op'` has a precedence, or an operand: a non-empty production starting
+/// with a terminal and whose only nonterminal is `E` itself (e.g. `E: 'INT'` or
+/// `E: '(' E ')'`).
+pub struct PrattRule {
+    nt_idx: NTIdx,
+    // The level and associativity of each binary operator.
+    infixes: HashMap<TIdx, (u32, Assoc)>,
+    // The operand productions, longest first, so that e.g. `'ID' '(' E ')'` is preferred to
+    // `'ID'`.
+    operands: Vec<PIdx>
+}
+
+impl PrattRule {
+    /// Create a sub-parser for the rule `nt_idx` in `grm`, where `precs` gives the level (higher
+    /// levels bind more tightly) and associativity of operators. Returns an error describing the
+    /// first production which can't be parsed by precedence alone.
+    pub(crate) fn new(grm: &YaccGrammar, nt_idx: NTIdx, precs: &[(TIdx, u32, Assoc)])
+                   -> Result<Self, String>
+    {
+        let name = grm.nonterm_name(nt_idx);
+        let mut infixes = HashMap::new();
+        let mut operands = Vec::new();
+        for &p_idx in grm.nonterm_to_prods(nt_idx) {
+            let prod = grm.prod(p_idx);
+            match prod.first() {
+                Some(&Symbol::Nonterm(n)) if n == nt_idx => {
+                    let t_idx = match prod.get(1) {
+                        Some(&Symbol::Term(t_idx))
+                            if prod.len() == 3 && prod[2] == Symbol::Nonterm(nt_idx) => t_idx,
+                        _ => return Err(format!("Rule '{}' has a left-recursive production \
+                                                 which is not a binary operator", name))
+                    };
+                    let op = grm.term_name(t_idx).unwrap_or("$");
+                    let &(_, level, assoc) = precs.iter()
+                                                  .find(|p| p.0 == t_idx)
+                                                  .ok_or_else(|| {
+                                                      format!("Operator '{}' of rule '{}' has \
+                                                               no precedence", op, name)
+                                                  })?;
+                    if infixes.insert(t_idx, (level, assoc)).is_some() {
+                        return Err(format!("Operator '{}' of rule '{}' is used in more than one \
+                                            production", op, name));
+                    }
+                },
+                Some(&Symbol::Term(_)) => {
+                    if prod.iter().any(|s| match *s {
+                                           Symbol::Nonterm(n) => n != nt_idx,
+                                           Symbol::Term(_) => false
+                                       })
+                    {
+                        return Err(format!("Rule '{}' has an operand referring to another rule",
+                                           name));
+                    }
+                    operands.push(p_idx);
+                },
+                _ => return Err(format!("Rule '{}' has an operand which does not start with a \
+                                         terminal", name))
+            }
+        }
+        operands.sort_by_key(|&p_idx| Reverse(grm.prod(p_idx).len()));
+        Ok(PrattRule{nt_idx, infixes, operands})
+    }
+
+    /// Return the rule this sub-parser parses.
+    pub(crate) fn nt_idx(&self) -> NTIdx {
+        self.nt_idx
+    }
+
+    /// Parse the longest instance of the rule starting at `la_idx`, returning its parse tree
+    /// (which is identical to the one the LR parser would have built) and the index of the lexeme
+    /// after it. If there is no such instance (e.g. because of a syntax error), return
+    /// `Err(furthest)`, where `furthest` is the index of the furthest lexeme examined.
+    ///
+    /// Each expression is parsed with explicit operand and operator stacks, and expressions
+    /// nested in operands (e.g. `'(' E ')'`) with an explicit stack of frames, so that deeply
+    /// nested input can't overflow the call stack. If an operand production fails to match, the
+    /// next is tried from the same position; if none matches, the enclosing expression fails, as
+    /// does the operand (if any) it is nested in.
+    pub(crate) fn parse<TokId: TokenId>(&self, psr: &Parser<TokId>, la_idx: usize)
+                                     -> Result<(Node<TokId>, usize), usize>
+    {
+        let mut la_idx = la_idx;
+        let mut furthest = la_idx;
+        // The positions at which no operand can be parsed. Since an operand (including any
+        // expressions nested in it) is parsed the same way wherever it occurs, a position only
+        // ever needs to fail once.
+        let mut failed = HashSet::new();
+        let mut frames = vec![Frame::Expr{operands: Vec::new(), operators: Vec::new()}];
+        let mut step = Step::StartOperand;
+        loop {
+            step = match step {
+                Step::StartOperand => {
+                    if failed.contains(&la_idx) {
+                        Step::Failed
+                    } else {
+                        frames.push(Frame::Operand{start: la_idx, alt: 0, nodes: Vec::new()});
+                        Step::MatchOperand
+                    }
+                },
+                Step::MatchOperand => {
+                    let (start, alt, mut nodes) = match frames.pop() {
+                        Some(Frame::Operand{start, alt, nodes}) => (start, alt, nodes),
+                        _ => unreachable!()
+                    };
+                    let prod = psr.grm.prod(self.operands[alt]);
+                    loop {
+                        match prod.get(nodes.len()) {
+                            Some(&Symbol::Term(t_idx)) => {
+                                furthest = max(furthest, la_idx);
+                                if psr.next_tidx(la_idx) != t_idx {
+                                    break Step::NextOperand(start, alt);
+                                }
+                                nodes.push(Node::Term{lexeme: psr.next_lexeme(la_idx)});
+                                la_idx += 1;
+                            },
+                            Some(&Symbol::Nonterm(_)) => {
+                                frames.push(Frame::Operand{start, alt, nodes});
+                                frames.push(Frame::Expr{operands: Vec::new(),
+                                                        operators: Vec::new()});
+                                break Step::StartOperand;
+                            },
+                            None => {
+                                break Step::Operand(Node::Nonterm{nonterm_idx: self.nt_idx, nodes});
+                            }
+                        }
+                    }
+                },
+                Step::NextOperand(start, alt) => {
+                    la_idx = start;
+                    if alt + 1 < self.operands.len() {
+                        frames.push(Frame::Operand{start, alt: alt + 1, nodes: Vec::new()});
+                        Step::MatchOperand
+                    } else {
+                        failed.insert(start);
+                        Step::Failed
+                    }
+                },
+                Step::Failed => {
+                    // The expression on top of the stack has failed, so the operand it is nested
+                    // in (if any) must try its next production.
+                    frames.pop();
+                    match frames.pop() {
+                        Some(Frame::Operand{start, alt, ..}) => Step::NextOperand(start, alt),
+                        Some(Frame::Expr{..}) => unreachable!(),
+                        None => return Err(furthest)
+                    }
+                },
+                Step::Operand(node) => {
+                    let (mut operands, mut operators) = match frames.pop() {
+                        Some(Frame::Expr{operands, operators}) => (operands, operators),
+                        _ => unreachable!()
+                    };
+                    operands.push(node);
+                    furthest = max(furthest, la_idx);
+                    if let Some(&(level, assoc)) = self.infixes.get(&psr.next_tidx(la_idx)) {
+                        // Reduce the operators whose right operand can't contain this one. If
+                        // the last of them is non-associative and at the same level as this one,
+                        // the two can't be chained.
+                        let mut prev = None;
+                        loop {
+                            let (op_level, op_assoc) = match operators.last() {
+                                Some(&(_, op_level, op_assoc)) => (op_level, op_assoc),
+                                None => break
+                            };
+                            let rhs_min_level = if op_assoc == Assoc::Right {
+                                                    op_level
+                                                } else {
+                                                    op_level + 1
+                                                };
+                            if rhs_min_level <= level {
+                                break;
+                            }
+                            self.reduce(&mut operands, &mut operators);
+                            prev = Some((op_level, op_assoc));
+                        }
+                        operators.push((psr.next_lexeme(la_idx), level, assoc));
+                        frames.push(Frame::Expr{operands, operators});
+                        if prev == Some((level, Assoc::Nonassoc)) {
+                            Step::Failed
+                        } else {
+                            la_idx += 1;
+                            Step::StartOperand
+                        }
+                    } else {
+                        while !operators.is_empty() {
+                            self.reduce(&mut operands, &mut operators);
+                        }
+                        debug_assert_eq!(operands.len(), 1);
+                        let node = operands.pop().unwrap();
+                        match frames.pop() {
+                            Some(Frame::Operand{start, alt, mut nodes}) => {
+                                nodes.push(node);
+                                frames.push(Frame::Operand{start, alt, nodes});
+                                Step::MatchOperand
+                            },
+                            Some(Frame::Expr{..}) => unreachable!(),
+                            None => return Ok((node, la_idx))
+                        }
+                    }
+                }
+            };
+        }
+    }
+
+    /// Replace the operator on top of `operators`, and the two operands on top of `operands`,
+    /// with a single operand.
+    fn reduce<TokId: TokenId>(&self,
+                              operands: &mut Vec<Node<TokId>>,
+                              operators: &mut Vec<(Lexeme<TokId>, u32, Assoc)>)
+    {
+        let (lexeme, _, _) = operators.pop().unwrap();
+        let rhs = operands.pop().unwrap();
+        let lhs = operands.pop().unwrap();
+        operands.push(Node::Nonterm{nonterm_idx: self.nt_idx,
+                                    nodes: vec![lhs, Node::Term{lexeme}, rhs]});
+    }
+}
+
+/// A partially parsed expression, or operand, in `PrattRule::parse`.
+enum Frame<TokId: TokenId> {
+    /// An expression whose complete operands, and the operators between them which haven't yet
+    /// been reduced, are on these stacks.
+    Expr{operands: Vec<Node<TokId>>, operators: Vec<(Lexeme<TokId>, u32, Assoc)>},
+    /// An operand starting at `start` which is being matched against the `alt`th operand
+    /// production, the first `nodes.len()` symbols of which have been matched.
+    Operand{start: usize, alt: usize, nodes: Vec<Node<TokId>>}
+}
+
+/// What `PrattRule::parse` is to do next.
+enum Step<TokId: TokenId> {
+    /// Start an operand at the current position, in the expression on top of the stack.
+    StartOperand,
+    /// Continue matching the operand on top of the stack at the current position.
+    MatchOperand,
+    /// The operand starting at the given position failed to match the given production.
+    NextOperand(usize, usize),
+    /// The expression on top of the stack failed.
+    Failed,
+    /// The given operand was parsed for the expression on top of the stack.
+    Operand(Node<TokId>)
+}
@@ -0,0 +1,134 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::cmp;
+use std::time::Instant;
+
+use lrtable::StIdx;
+
+use cpctplus;
+use events::TStack;
+use panic;
+use parser::{Parser, ParseRepair, Recoverer, RecoveryLimits, RecoveryOptions};
+use tokid::TokenId;
+
+/// A recoverer which races CPCT+ against panic mode. Panic mode (which doesn't change the parse
+/// stack, and is cheap enough to run to completion first) finds a repair sequence of some cost
+/// `c`; CPCT+, which finds repair sequences in increasing order of cost, then searches only for
+/// repair sequences costing at most `c`. If CPCT+ finds any in time, they are used; if it runs
+/// out of time, or would have to consider more expensive repair sequences, panic mode has won and
+/// its repair sequence is used instead. Compared to CPCT+, the search is thus often much smaller,
+/// and is never larger, but a repair sequence is only lost if it costs more than panic mode's.
+struct Race;
+
+pub(crate) fn recoverer<'a, TokId: TokenId>
+                       (_: &'a Parser<TokId>)
+                     -> Box<Recoverer<TokId> + 'a>
+{
+    Box::new(Race)
+}
+
+impl<TokId: TokenId> Recoverer<TokId> for Race {
+    fn recover(&self,
+               finish_by: Instant,
+               parser: &Parser<TokId>,
+               in_la_idx: usize,
+               in_pstack: &mut Vec<StIdx>,
+               tstack: &mut TStack<TokId>)
+           -> (usize, Vec<Vec<ParseRepair>>, RecoveryLimits)
+    {
+        let (panic_la_idx, panic_rprs, _) = panic::recoverer(parser).recover(finish_by,
+                                                                             parser,
+                                                                             in_la_idx,
+                                                                             in_pstack,
+                                                                             tstack);
+        if panic_rprs.is_empty() {
+            return cpctplus::recoverer(parser).recover(finish_by,
+                                                       parser,
+                                                       in_la_idx,
+                                                       in_pstack,
+                                                       tstack);
+        }
+        // Panic mode's repair sequence consists only of deletions.
+        let panic_cost = (in_la_idx..panic_la_idx).fold(0u32, |c, i| {
+                             c.saturating_add(u32::from((parser.term_cost)(parser.next_tidx(i))))
+                         });
+        let max_repair_cost = cmp::min(parser.recovery.max_repair_cost, panic_cost);
+        let capped = Parser{recovery: RecoveryOptions{max_repair_cost, ..parser.recovery},
+                            ..*parser};
+        let (la_idx, rprs, mut limits) = cpctplus::recoverer(&capped).recover(finish_by,
+                                                                              &capped,
+                                                                              in_la_idx,
+                                                                              in_pstack,
+                                                                              tstack);
+        if rprs.is_empty() {
+            log_debug!("Race: panic mode won for the error at lexeme {}", in_la_idx);
+            return (panic_la_idx, panic_rprs, RecoveryLimits::default());
+        }
+        // Only the user's ceiling is worth reporting.
+        limits.cost_ceiling &= capped.recovery.max_repair_cost == parser.recovery.max_repair_cost;
+        (la_idx, rprs, limits)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use parser::{ParseRepair, RecoveryKind};
+    use parser::test::{CALLS_GRMS, CALLS_LEXS, do_parse};
+
+    #[test]
+    fn race() {
+        // CPCT+ finds a single repair sequence, costing 3, which deletes the second ')' and
+        // completes the call to g.
+        let (_, pr) = do_parse(RecoveryKind::CPCTPlus, CALLS_LEXS, CALLS_GRMS, "f() ) g");
+        let (_, errs) = pr.unwrap_err();
+        assert_eq!(errs.len(), 1);
+
+        // Panic mode's deletion of the second ')' costs only 1, and CPCT+ can find nothing as
+        // cheap, so panic mode wins; there is then a second error at the end of the input, where
+        // panic mode can do nothing, and CPCT+ is used as normal.
+        let (grm, pr) = do_parse(RecoveryKind::Race, CALLS_LEXS, CALLS_GRMS, "f() ) g");
+        let (pt, errs) = pr.unwrap_err();
+        assert!(pt.is_some());
+        assert_eq!(errs.len(), 2);
+        assert_eq!(errs[0].repairs(), &vec![vec![ParseRepair::Delete]]);
+        assert_eq!(errs[1].repairs(),
+                   &vec![vec![ParseRepair::Insert(grm.term_idx("(").unwrap()),
+                              ParseRepair::Insert(grm.term_idx(")").unwrap())]]);
+
+        // When CPCT+ finds repairs as cheap as panic mode's, they are used.
+        let (_, pr) = do_parse(RecoveryKind::Race, CALLS_LEXS, CALLS_GRMS, "f() ) g()");
+        let (_, errs) = pr.unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].repairs(), &vec![vec![ParseRepair::Delete]]);
+    }
+}
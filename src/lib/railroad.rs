@@ -0,0 +1,205 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::fmt::Write;
+
+use cfgrammar::{NTIdx, Symbol};
+use cfgrammar::yacc::YaccGrammar;
+
+// The approximate width of a character in the diagrams' (monospace) font.
+const CHAR_WIDTH: usize = 8;
+const BOX_HEIGHT: usize = 24;
+// The horizontal space between, and around, the symbols of a production.
+const GAP: usize = 16;
+// The vertical space given to each production.
+const ROW_HEIGHT: usize = 40;
+// The horizontal space given to the rails which join a rule's productions.
+const RAIL: usize = 20;
+
+const STYLE: &str = "body { font-family: sans-serif; }
+svg path { fill: none; stroke: #333; stroke-width: 2; }
+svg rect { fill: #ffc; stroke: #333; stroke-width: 2; }
+svg rect.term { fill: #dfd; }
+svg text { font-family: monospace; font-size: 14px; }
+";
+
+/// Return a standalone HTML document describing `grm` as railroad (i.e. syntax) diagrams, with
+/// one inline SVG diagram for each rule, suitable for documenting the language a grammar
+/// defines. Each production is drawn as a path through its symbols, with terminals in rounded
+/// boxes and rules in square boxes; each rule's box links to that rule's diagram, and each
+/// diagram lists the rules which use it. The start rule comes first, followed by the other rules
+/// in the order they are defined.
+pub fn railroad_html(grm: &YaccGrammar) -> String {
+    let start_nt = grm.prod_to_nonterm(grm.start_prod());
+    let mut nt_idxs = Vec::new();
+    // cfgrammar's start rule is an implicit rule whose only production is the user's start rule.
+    if let Some(&Symbol::Nonterm(nt_idx)) = grm.prod(grm.start_prod()).get(0) {
+        nt_idxs.push(nt_idx);
+    }
+    for i in 0..grm.nonterms_len() as usize {
+        let nt_idx = NTIdx::from(i as u32);
+        if nt_idx != start_nt && !nt_idxs.contains(&nt_idx) {
+            nt_idxs.push(nt_idx);
+        }
+    }
+
+    let mut s = String::new();
+    s.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<style>\n");
+    s.push_str(STYLE);
+    s.push_str("</style>\n</head>\n<body>\n");
+    // Does the rule `user_idx` refer to the rule `nt_idx`?
+    let uses = |user_idx: NTIdx, nt_idx: NTIdx| {
+        grm.nonterm_to_prods(user_idx)
+           .iter()
+           .any(|&p_idx| grm.prod(p_idx).contains(&Symbol::Nonterm(nt_idx)))
+    };
+    for &nt_idx in &nt_idxs {
+        let name = escape(grm.nonterm_name(nt_idx));
+        writeln!(s, "<h2 id=\"rule-{}\">{}</h2>", name, name).unwrap();
+        s.push_str(&rule_svg(grm, nt_idx));
+        let used_by = nt_idxs.iter()
+                             .filter(|&&user_idx| uses(user_idx, nt_idx))
+                             .map(|&user_idx| {
+                                 let n = escape(grm.nonterm_name(user_idx));
+                                 format!("<a href=\"#rule-{}\">{}</a>", n, n)
+                             })
+                             .collect::<Vec<_>>();
+        if !used_by.is_empty() {
+            writeln!(s, "<p>Used by: {}</p>", used_by.join(", ")).unwrap();
+        }
+    }
+    s.push_str("</body>\n</html>\n");
+    s
+}
+
+/// Return an SVG railroad diagram for the rule `nt_idx`.
+fn rule_svg(grm: &YaccGrammar, nt_idx: NTIdx) -> String {
+    // Each production as a sequence of (label, is terminal) pairs.
+    let rows = grm.nonterm_to_prods(nt_idx)
+                  .iter()
+                  .map(|&p_idx| {
+                      grm.prod(p_idx)
+                         .iter()
+                         .map(|sym| match *sym {
+                             Symbol::Term(t_idx) => (grm.term_name(t_idx).unwrap(), true),
+                             Symbol::Nonterm(sym_idx) => (grm.nonterm_name(sym_idx), false)
+                         })
+                         .collect::<Vec<_>>()
+                  })
+                  .collect::<Vec<_>>();
+    let box_width = |label: &str, term: bool| {
+        // Terminals are drawn with quotes around them.
+        (label.chars().count() + if term { 2 } else { 0 }) * CHAR_WIDTH + GAP
+    };
+    let inner_width = rows.iter()
+                          .map(|row| {
+                              row.iter().map(|&(l, term)| box_width(l, term) + GAP).sum::<usize>()
+                          })
+                          .max()
+                          .unwrap_or(0) + GAP;
+    let width = inner_width + 2 * RAIL;
+    let height = rows.len().max(1) * ROW_HEIGHT;
+    let row_y = |i: usize| i * ROW_HEIGHT + ROW_HEIGHT / 2;
+
+    let mut s = String::new();
+    writeln!(s, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">",
+             width, height).unwrap();
+    // The entry and exit lines, and the rails joining the productions to them.
+    let last_y = row_y(rows.len().max(1) - 1);
+    writeln!(s, "<path d=\"M0 {y} h{r} M{x} {y} h{r}\"/>",
+             y = row_y(0), r = RAIL, x = width - RAIL).unwrap();
+    if rows.len() > 1 {
+        writeln!(s, "<path d=\"M{} {y0} V{y1} M{} {y0} V{y1}\"/>",
+                 RAIL, width - RAIL, y0 = row_y(0), y1 = last_y).unwrap();
+    }
+    for (i, row) in rows.iter().enumerate() {
+        let y = row_y(i);
+        writeln!(s, "<path d=\"M{} {} H{}\"/>", RAIL, y, width - RAIL).unwrap();
+        let mut x = RAIL + GAP;
+        for &(label, term) in row {
+            let w = box_width(label, term);
+            let top = y - BOX_HEIGHT / 2;
+            let text = if term { format!("'{}'", label) } else { label.to_owned() };
+            let text = format!("<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+                               x + w / 2, y + 5, escape(&text));
+            if term {
+                writeln!(s, "<rect class=\"term\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+                             rx=\"{}\"/>{}",
+                         x, top, w, BOX_HEIGHT, BOX_HEIGHT / 2, text).unwrap();
+            } else {
+                writeln!(s, "<a href=\"#rule-{}\"><rect x=\"{}\" y=\"{}\" width=\"{}\" \
+                             height=\"{}\"/>{}</a>",
+                         escape(label), x, top, w, BOX_HEIGHT, text).unwrap();
+            }
+            x += w + GAP;
+        }
+    }
+    s.push_str("</svg>\n");
+    s
+}
+
+/// Escape `s` for inclusion in HTML text or attribute values.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+     .replace('<', "&lt;")
+     .replace('>', "&gt;")
+     .replace('"', "&quot;")
+     .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod test {
+    use cfgrammar::yacc::{yacc_grm, YaccKind};
+    use super::railroad_html;
+
+    #[test]
+    fn railroad() {
+        let src = "%start Expr
+%%
+Term: 'INT' | '(' Expr ')';
+Expr: Expr '<' Term | Term | ;
+";
+        let grm = yacc_grm(YaccKind::Original, src).unwrap();
+        let html = railroad_html(&grm);
+        // The start rule comes first, even though it is defined second.
+        let expr = html.find("<h2 id=\"rule-Expr\">Expr</h2>").unwrap();
+        let term = html.find("<h2 id=\"rule-Term\">Term</h2>").unwrap();
+        assert!(expr < term);
+        assert_eq!(html.matches("<svg ").count(), 2);
+        // Terminals are quoted and escaped; rules link to their diagrams.
+        assert!(html.contains(">&#39;&lt;&#39;</text>"));
+        assert!(html.contains("<a href=\"#rule-Term\"><rect "));
+        assert!(html[term..].contains("<p>Used by: <a href=\"#rule-Expr\">Expr</a></p>"));
+        assert!(html[expr..term].contains(
+            "<p>Used by: <a href=\"#rule-Expr\">Expr</a>, <a href=\"#rule-Term\">Term</a></p>"));
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::cmp;
+
+use lrlex::Lexeme;
+use lrtable::StIdx;
+
+use parser::RTParserBuilder;
+use tokid::TokenId;
+
+/// Shrink `lexemes` to a subsequence for which `fails` still returns `true`, using Zeller and
+/// Hildebrandt's delta debugging algorithm ("ddmin"). `fails` typically parses the lexemes it is
+/// passed and checks for the symptom of a bug (e.g. a panic caught with `catch_unwind`, or
+/// recovery taking too long). The result is 1-minimal: removing any single lexeme from it makes
+/// `fails` return `false`. If `fails(lexemes)` is `false`, `lexemes` is returned unchanged.
+///
+/// The returned lexemes retain their start offsets and lengths, so their text can be recovered
+/// from the original input (e.g. with `lexeme_str`) to form a reduced input for a bug report.
+pub fn reduce_lexemes<TokId, F>(lexemes: &[Lexeme<TokId>], mut fails: F) -> Vec<Lexeme<TokId>>
+                         where TokId: TokenId,
+                               F: FnMut(&[Lexeme<TokId>]) -> bool
+{
+    let mut cur = lexemes.to_vec();
+    if !fails(&cur) {
+        return cur;
+    }
+    // The number of chunks `cur` is split into.
+    let mut n = 2;
+    while cur.len() >= 2 {
+        let chunk = (cur.len() + n - 1) / n;
+        let mut reduced = false;
+        // First see if any one chunk fails on its own...
+        for start in (0..cur.len()).step_by(chunk) {
+            let end = cmp::min(start + chunk, cur.len());
+            if fails(&cur[start..end]) {
+                cur = cur[start..end].to_vec();
+                n = 2;
+                reduced = true;
+                break;
+            }
+        }
+        // ...and, if not, whether removing any one chunk still fails.
+        if !reduced {
+            for start in (0..cur.len()).step_by(chunk) {
+                let end = cmp::min(start + chunk, cur.len());
+                let mut cmpl = cur[..start].to_vec();
+                cmpl.extend_from_slice(&cur[end..]);
+                if fails(&cmpl) {
+                    cur = cmpl;
+                    n = cmp::max(n - 1, 2);
+                    reduced = true;
+                    break;
+                }
+            }
+        }
+        if !reduced {
+            if n >= cur.len() {
+                break;
+            }
+            n = cmp::min(n * 2, cur.len());
+        }
+    }
+    cur
+}
+
+/// Shrink `lexemes`, which `rtpb` fails to parse, to a subsequence which fails in the same way:
+/// with the same number of errors, each detected in the same state at the same terminal, and
+/// each either with or without repairs as before (see `reduce_lexemes`). This is intended to turn
+/// a large input which exposes a problem with a grammar, or with error recovery, into an
+/// actionable bug report. Since recovery is time limited, a fixed clock (see
+/// `RTParserBuilder::clock`) makes the reduction more predictable. If `rtpb` parses `lexemes`
+/// without error, they are returned unchanged.
+pub fn reduce_failure<TokId>(rtpb: &RTParserBuilder<TokId>, lexemes: &[Lexeme<TokId>])
+                          -> Vec<Lexeme<TokId>>
+                       where TokId: TokenId
+{
+    let signature = |ls: &[Lexeme<TokId>]| -> Vec<(StIdx, TokId, bool)> {
+        match rtpb.parse(&ls.to_vec()) {
+            Ok(_) => Vec::new(),
+            Err((_, errs)) => {
+                errs.iter()
+                    .map(|e| (e.state_idx(), e.lexeme().tok_id(), e.repairs().is_empty()))
+                    .collect()
+            }
+        }
+    };
+    let orig = signature(lexemes);
+    if orig.is_empty() {
+        return lexemes.to_vec();
+    }
+    reduce_lexemes(lexemes, |ls| signature(ls) == orig)
+}
+
+#[cfg(test)]
+mod test {
+    use lrlex::Lexeme;
+    use parser::{lexeme_str, RTParserBuilder};
+    use parser::test::calls_env;
+    use super::{reduce_failure, reduce_lexemes};
+
+    #[test]
+    fn reduce() {
+        let us = "f()g()h)i()j(()k()";
+        let (grm, sgraph, stable, lexemes) = calls_env(&us);
+        let close = grm.term_idx(")").unwrap();
+        let is_close = |l: &Lexeme<u16>| u32::from(l.tok_id()) == u32::from(close);
+        let reduced = reduce_lexemes(&lexemes, |ls| ls.iter().filter(|l| is_close(l)).count() >= 2);
+        assert_eq!(reduced.len(), 2);
+        assert!(reduced.iter().all(|l| is_close(l)));
+
+        let rtpb = RTParserBuilder::new(&grm, &sgraph, &stable);
+        let (_, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert_eq!(errs.len(), 2);
+        let reduced = reduce_failure(&rtpb, &lexemes);
+        assert!(reduced.len() < lexemes.len());
+        let (_, rerrs) = rtpb.parse(&reduced).unwrap_err();
+        assert_eq!(rerrs.len(), 2);
+        for (e, re) in errs.iter().zip(rerrs.iter()) {
+            assert_eq!(e.state_idx(), re.state_idx());
+            assert_eq!(lexeme_str(&us, e.lexeme()), lexeme_str(&us, re.lexeme()));
+        }
+
+        let (_, _, _, lexemes) = calls_env("f()");
+        assert_eq!(reduce_failure(&rtpb, &lexemes), lexemes);
+    }
+}
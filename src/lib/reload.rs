@@ -0,0 +1,166 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::error::Error;
+use std::fs::metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use cfgrammar::yacc::{yacc_grm, YaccGrammar, YaccKind};
+use lrtable::{Minimiser, from_yacc, StateGraph, StateTable};
+
+use builder::read_grammar;
+
+/// A grammar and the tables built from it.
+pub type Tables = (YaccGrammar, StateGraph, StateTable);
+
+/// Rebuilds a grammar's tables whenever its file changes on disk, for grammar development tools
+/// and playgrounds which construct parsers at run-time (statically compiled grammars are rebuilt
+/// by Cargo instead). Changes are noticed by polling the file's modification time and size with
+/// `poll`, so no background threads are involved.
+///
+/// The tables are handed out as an `Arc`, and replaced as a whole when the grammar is reloaded:
+/// a parse which is using the old tables carries on with them, and only later calls to `tables`
+/// see the new ones. A `GrammarWatcher` can be shared between threads. Note that reloading the
+/// grammar can change its terminals' indices, so the lexer's rule IDs must be set afresh (from
+/// the new grammar's `terms_map`) whenever `poll` returns `Ok(true)`.
+pub struct GrammarWatcher {
+    path: PathBuf,
+    yacc_kind: YaccKind,
+    // The modification time and size of the grammar file when it was last loaded, and the tables
+    // built from it.
+    state: Mutex<(Option<(SystemTime, u64)>, Arc<Tables>)>
+}
+
+impl GrammarWatcher {
+    /// Load the grammar at `path`, which must be a grammar of kind `yacc_kind`, and build its
+    /// tables.
+    pub fn new<P: AsRef<Path>>(path: P, yacc_kind: YaccKind) -> Result<Self, Box<Error>> {
+        let path = path.as_ref().to_owned();
+        let stamp = stamp(&path);
+        let tables = build_tables(&path, yacc_kind)?;
+        Ok(GrammarWatcher{path, yacc_kind, state: Mutex::new((stamp, Arc::new(tables)))})
+    }
+
+    /// Return the most recently built tables.
+    pub fn tables(&self) -> Arc<Tables> {
+        Arc::clone(&self.state.lock().unwrap().1)
+    }
+
+    /// If the grammar file has changed since it was last loaded, reload it and rebuild its tables,
+    /// returning `Ok(true)`; if it hasn't changed, return `Ok(false)`. If the changed grammar can't
+    /// be loaded (e.g. because it is half-edited and thus invalid), the error is returned and the
+    /// previous tables remain in use; the grammar is not reloaded again until the file changes
+    /// again.
+    pub fn poll(&self) -> Result<bool, Box<Error>> {
+        let mut state = self.state.lock().unwrap();
+        let stamp = stamp(&self.path);
+        if stamp == state.0 {
+            return Ok(false);
+        }
+        state.0 = stamp;
+        state.1 = Arc::new(build_tables(&self.path, self.yacc_kind)?);
+        Ok(true)
+    }
+}
+
+/// Return the modification time and size of the file at `path`, or `None` if they can't be
+/// determined (e.g. because the file is in the middle of being replaced).
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let md = metadata(path).ok()?;
+    Some((md.modified().ok()?, md.len()))
+}
+
+fn build_tables(path: &Path, yacc_kind: YaccKind) -> Result<Tables, Box<Error>> {
+    let s = read_grammar(path)?;
+    let grm = yacc_grm(yacc_kind, &s)
+                  .map_err(|e| format!("Invalid grammar '{}': {:?}", path.display(), e))?;
+    let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager)
+                               .map_err(|e| format!("Can't build state table: {:?}", e))?;
+    Ok((grm, sgraph, stable))
+}
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+    use std::fs::{File, remove_file};
+    use std::io::Write;
+    use std::process;
+
+    use cfgrammar::yacc::YaccKind;
+    use lrlex::build_lex;
+    use num_traits::ToPrimitive;
+    use parser::RTParserBuilder;
+    use super::GrammarWatcher;
+
+    #[test]
+    fn watch() {
+        let path = temp_dir().join(format!("lrpar_watch_{}.y", process::id()));
+        let write = |s: &str| File::create(&path).unwrap().write_all(s.as_bytes()).unwrap();
+        let valid = |w: &GrammarWatcher, input: &str| {
+            let tables = w.tables();
+            let (ref grm, ref sgraph, ref stable) = *tables;
+            let mut lexerdef = build_lex::<u16>("%%
+a 'A'
+b 'B'
+").unwrap();
+            let rule_ids = grm.terms_map().iter()
+                                          .map(|(&n, &i)| (n, u32::from(i).to_u16().unwrap()))
+                                          .collect();
+            lexerdef.set_rule_ids(&rule_ids);
+            let lexemes = lexerdef.lexer(input).lexemes().unwrap();
+            RTParserBuilder::new(grm, sgraph, stable).parse(&lexemes).is_ok()
+        };
+
+        write("%start S\n%%\nS: 'A';\n");
+        let w = GrammarWatcher::new(&path, YaccKind::Original).unwrap();
+        assert!(!w.poll().unwrap());
+        assert!(valid(&w, "a"));
+        assert!(!valid(&w, "ab"));
+
+        // An in-flight parse keeps the tables it started with.
+        let old = w.tables();
+        write("%start S\n%%\nS: 'A' 'B' | 'A';\n");
+        assert!(w.poll().unwrap());
+        assert!(!w.poll().unwrap());
+        assert!(valid(&w, "ab"));
+        assert_eq!(old.0.prods_len() + 1, w.tables().0.prods_len());
+
+        // An invalid grammar leaves the previous tables in place.
+        write("%start S\n%%\nS: 'A' 'B' | 'A' | T;\n");
+        assert!(w.poll().is_err());
+        assert!(valid(&w, "ab"));
+
+        remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,424 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::cell::Cell;
+use std::time::Instant;
+
+use cactus::Cactus;
+use cfgrammar::TIdx;
+use lrlex::Lexeme;
+use lrtable::{Action, StIdx};
+
+use astar::LimitReached;
+use events::TStack;
+use mf::{apply_repairs, rank_cnds, simplify_repairs};
+use parser::{flatten_repair, Parser, ParseRepair, PStack, RecoveryLimits};
+use tokid::TokenId;
+
+pub(crate) const PARSE_AT_LEAST: usize = 3; // N in Corchuelo et al.
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Repair {
+    /// Insert a `Symbol::Term` with idx `term_idx`.
+    InsertTerm(TIdx),
+    /// Delete a symbol.
+    Delete,
+    /// Shift a symbol.
+    Shift
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum RepairMerge {
+    Repair(Repair),
+    Merge(Repair, Cactus<Cactus<RepairMerge>>),
+    Terminator
+}
+
+/// The parts of the search for repairs which the CPCT+ and MF recoverers share. The recoverers
+/// differ in which neighbours they generate for a node, and in how they order the nodes they
+/// search, but check for success, and merge and report repair sequences, identically, so that a
+/// fix to one (or a new recoverer built on this) applies to all of them.
+pub(crate) struct Engine<'a, TokId: TokenId> where TokId: 'a {
+    pub(crate) parser: &'a Parser<'a, TokId>,
+    // Set if a node wasn't edited further for reaching `RTParserBuilder::max_repair_len`.
+    capped: Cell<bool>,
+    // Set if a neighbour was discarded for exceeding `RTParserBuilder::max_repair_cost`.
+    cost_ceiling: Cell<bool>,
+    // Set to the limit which cut the search short, if any.
+    limit: Cell<Option<LimitReached>>
+}
+
+impl<'a, TokId: TokenId> Engine<'a, TokId> {
+    pub(crate) fn new(parser: &'a Parser<'a, TokId>) -> Self {
+        Engine{parser,
+               capped: Cell::new(false),
+               cost_ceiling: Cell::new(false),
+               limit: Cell::new(None)}
+    }
+
+    /// Prepare for a search from the parse stack `in_pstack`, returning the start node's parse
+    /// stack and repair sequence.
+    pub(crate) fn start(&self, in_pstack: &[StIdx]) -> (Cactus<StIdx>, Cactus<RepairMerge>) {
+        self.capped.set(false);
+        self.cost_ceiling.set(false);
+        self.limit.set(None);
+        let mut pstack = Cactus::new();
+        for st in in_pstack.iter() {
+            pstack = pstack.child(*st);
+        }
+        (pstack, Cactus::new().child(RepairMerge::Terminator))
+    }
+
+    /// Replay each of the repair sequences which the repair cache (see
+    /// `RTParserBuilder::repair_cache`) remembers for the error at `in_la_idx` with the parse stack
+    /// `in_pstack`, starting from the start node's parse stack `start_pstack` and repair sequence
+    /// `start_repairs` (see `start`), and shifting afterwards as the search would until it
+    /// succeeds. Returns the `(parse stack, lexeme index, repair sequence, cost)` of each resulting
+    /// success node, which can seed the search. Repair sequences which now exceed the maximum
+    /// repair cost or length are skipped.
+    pub(crate) fn seeds(&self,
+                        in_la_idx: usize,
+                        in_pstack: &PStack,
+                        start_pstack: &Cactus<StIdx>,
+                        start_repairs: &Cactus<RepairMerge>)
+                     -> Vec<(Cactus<StIdx>, usize, Cactus<RepairMerge>, u32)>
+    {
+        let parser = self.parser;
+        let mut seeds = Vec::new();
+        'seeds: for rprs in parser.cached_repairs(in_la_idx, in_pstack) {
+            let mut pstack = start_pstack.clone();
+            let mut la_idx = in_la_idx;
+            let mut repairs = start_repairs.clone();
+            let mut cf = 0;
+            for r in rprs.iter().flat_map(flatten_repair) {
+                let rpr = match r {
+                    ParseRepair::Insert(t_idx) => {
+                        cf = match parser.add_cost(cf, t_idx) {
+                            Some(cf) => cf,
+                            None => continue 'seeds
+                        };
+                        let next_lexeme = parser.next_lexeme(la_idx);
+                        let new_lexeme = Lexeme::new(TokId::from(u32::from(t_idx)).unwrap(),
+                                                     next_lexeme.start(), 0);
+                        pstack = parser.lr_cactus(Some(new_lexeme), la_idx, la_idx + 1, pstack,
+                                                  &mut None).1;
+                        Repair::InsertTerm(t_idx)
+                    },
+                    ParseRepair::Delete => {
+                        cf = match parser.add_cost(cf, parser.next_tidx(la_idx)) {
+                            Some(cf) => cf,
+                            None => continue 'seeds
+                        };
+                        la_idx += 1;
+                        Repair::Delete
+                    },
+                    ParseRepair::Shift => {
+                        let (new_la_idx, new_pstack) =
+                            parser.lr_cactus(None, la_idx, la_idx + 1, pstack, &mut None);
+                        la_idx = new_la_idx;
+                        pstack = new_pstack;
+                        Repair::Shift
+                    },
+                      ParseRepair::InsertSeq(_)
+                    | ParseRepair::Substitute{..}
+                    | ParseRepair::Shifts(_) => unreachable!()
+                };
+                repairs = repairs.child(RepairMerge::Repair(rpr));
+            }
+            if parser.recovery.max_repair_len.map_or(false, |m| num_edits(&repairs) > m) {
+                continue;
+            }
+            // Remembered repair sequences have had their trailing shifts removed.
+            let mut shifts = 0;
+            while !self.success(&pstack, la_idx, &repairs) {
+                if shifts == PARSE_AT_LEAST {
+                    continue 'seeds;
+                }
+                let (new_la_idx, new_pstack) =
+                    parser.lr_cactus(None, la_idx, la_idx + 1, pstack, &mut None);
+                if new_la_idx == la_idx {
+                    continue 'seeds;
+                }
+                la_idx = new_la_idx;
+                pstack = new_pstack;
+                repairs = repairs.child(RepairMerge::Repair(Repair::Shift));
+                shifts += 1;
+            }
+            seeds.push((pstack, la_idx, repairs, cf));
+        }
+        seeds
+    }
+
+    /// Can `repairs` be extended with further inserts and deletes? If not (recording that the
+    /// maximum repair length was reached), only shifts (and, for MF, reductions) are possible.
+    pub(crate) fn can_edit(&self, repairs: &Cactus<RepairMerge>) -> bool {
+        match self.parser.recovery.max_repair_len {
+            Some(m) if num_edits(repairs) >= m => {
+                self.capped.set(true);
+                false
+            },
+            _ => true
+        }
+    }
+
+    /// Return the cost of `cf` extended by inserting or deleting `t_idx`, or `None` (recording
+    /// that the ceiling was reached) if that would exceed `RTParserBuilder::max_repair_cost`.
+    pub(crate) fn add_cost(&self, cf: u32, t_idx: TIdx) -> Option<u32> {
+        let c = self.parser.add_cost(cf, t_idx);
+        if c.is_none() {
+            self.cost_ceiling.set(true);
+        }
+        c
+    }
+
+    /// Is the node with the parse stack `pstack`, at `la_idx`, with the repair sequence `repairs`
+    /// a success node?
+    pub(crate) fn success(&self,
+                          pstack: &Cactus<StIdx>,
+                          la_idx: usize,
+                          repairs: &Cactus<RepairMerge>)
+                       -> bool
+    {
+        // As presented in both Corchuelo et al. and Kim Yi, one type of success is if N symbols
+        // are parsed in one go. Indeed, without such a check, the search space quickly becomes
+        // too big. There isn't a way of encoding this check in r3s_n, so we check instead for its
+        // result: if the last N ('PARSE_AT_LEAST' in this library) repairs are shifts, then we've
+        // found a success node.
+        if ends_with_parse_at_least_shifts(repairs) {
+            return true;
+        }
+
+        match self.parser.stable.action(*pstack.val().unwrap(), self.parser.next_tidx(la_idx)) {
+            Some(Action::Accept) => true,
+            _ => false,
+        }
+    }
+
+    /// Turn the repair sequences `cnds` of the success nodes found by the recoverer `name` (which
+    /// is only used in log messages) for the error at `in_la_idx` into the recoverer's result:
+    /// rank and order them, and apply the best to `in_pstack` and `tstack`. `limit` is the limit
+    /// which cut the search short, if any.
+    pub(crate) fn finish(&self,
+                         name: &str,
+                         finish_by: Instant,
+                         in_la_idx: usize,
+                         in_pstack: &mut Vec<StIdx>,
+                         tstack: &mut TStack<TokId>,
+                         cnds: Vec<Cactus<RepairMerge>>,
+                         limit: Option<LimitReached>)
+                      -> (usize, Vec<Vec<ParseRepair>>, RecoveryLimits)
+    {
+        let parser = self.parser;
+        self.limit.set(limit);
+        if let Some(l) = limit {
+            log_debug!("{}: search for the error at lexeme {} reached its {:?} limit",
+                       name, in_la_idx, l);
+        }
+
+        log_debug!("{}: {} candidates for the error at lexeme {}", name, cnds.len(), in_la_idx);
+        if cnds.is_empty() {
+            return (in_la_idx, vec![], self.limits());
+        }
+
+        let full_rprs = collect_repairs(cnds);
+        let mut rnk_rprs = rank_cnds(parser,
+                                     finish_by,
+                                     in_la_idx,
+                                     &in_pstack,
+                                     full_rprs);
+        log_debug!("{}: {} repair sequences left after ranking", name, rnk_rprs.len());
+        if rnk_rprs.is_empty() {
+            return (in_la_idx, vec![], self.limits());
+        }
+        simplify_repairs(&mut rnk_rprs);
+        parser.prefer_minimal_span(in_la_idx, &mut rnk_rprs);
+        parser.prefer_similar(in_la_idx, &mut rnk_rprs);
+        parser.prefer_anchors(in_la_idx, &mut rnk_rprs);
+        let la_idx = apply_repairs(parser,
+                                   in_la_idx,
+                                   in_pstack,
+                                   &mut Some(tstack),
+                                   &rnk_rprs[0]);
+
+        (la_idx, rnk_rprs, self.limits())
+    }
+
+    fn limits(&self) -> RecoveryLimits {
+        RecoveryLimits{repair_len: self.capped.get(),
+                       cost_ceiling: self.cost_ceiling.get(),
+                       memory: self.limit.get() == Some(LimitReached::Memory),
+                       expansions: self.limit.get() == Some(LimitReached::Expansions)}
+    }
+}
+
+/// Return the last repair in `repairs`, or `None` if it is empty.
+pub(crate) fn last_repair(repairs: &Cactus<RepairMerge>) -> Option<Repair> {
+    match repairs.val().unwrap() {
+        &RepairMerge::Repair(r) => Some(r),
+        &RepairMerge::Merge(x, _) => Some(x),
+        &RepairMerge::Terminator => None
+    }
+}
+
+/// Are the repair sequences of two nodes with the same parse stack and lexeme index compatible,
+/// such that the nodes can be merged?
+pub(crate) fn compatible(repairs: &Cactus<RepairMerge>, other: &Cactus<RepairMerge>) -> bool {
+    // This is subtle: we're not looking for repair sequences which are exactly equivalent, but
+    // ones that are compatible. This is necessary so that we can merge compatible nodes. Our
+    // definition of compatible repair sequences is: they must end with exactly the same number of
+    // shifts (ending with zero shifts is fine); and if one repair sequence ends in a delete, the
+    // other must do so as well.
+
+    match (last_repair(repairs), last_repair(other)) {
+        (Some(Repair::Delete), Some(Repair::Delete)) => (),
+        (Some(Repair::Delete), _) | (_, Some(Repair::Delete)) => return false,
+        (_, _) => ()
+    }
+
+    let num_shifts = |c: &Cactus<RepairMerge>| {
+        let mut n = 0;
+        for r in c.vals() {
+            match r {
+                  &RepairMerge::Repair(Repair::Shift)
+                | &RepairMerge::Merge(Repair::Shift, _) => n += 1,
+                _ => break
+            }
+        }
+        n
+    };
+    num_shifts(repairs) == num_shifts(other)
+}
+
+/// Merge the compatible repair sequence `new` into `old`.
+pub(crate) fn merge(old: &mut Cactus<RepairMerge>, new: Cactus<RepairMerge>) {
+    if *old == new {
+        // If the repair sequences are identical, then merging is pointless.
+        return;
+    }
+    let merge = match old.val().unwrap() {
+        &RepairMerge::Repair(r) => {
+            RepairMerge::Merge(r, Cactus::new().child(new))
+        },
+        &RepairMerge::Merge(r, ref v) => {
+            RepairMerge::Merge(r, v.child(new))
+        },
+        _ => unreachable!()
+    };
+    *old = old.parent().unwrap().child(merge);
+}
+
+/// How many inserts and deletes does `repairs` contain?
+pub(crate) fn num_edits(repairs: &Cactus<RepairMerge>) -> usize {
+    repairs.vals()
+           .filter(|r| {
+               match **r {
+                   RepairMerge::Repair(Repair::Shift)
+                 | RepairMerge::Merge(Repair::Shift, _)
+                 | RepairMerge::Terminator => false,
+                   _ => true
+               }
+           })
+           .count()
+}
+
+/// Do `repairs` end with enough Shift repairs to be considered a success node?
+pub(crate) fn ends_with_parse_at_least_shifts(repairs: &Cactus<RepairMerge>) -> bool {
+    let mut shfts = 0;
+    for x in repairs.vals().take(PARSE_AT_LEAST) {
+        match x {
+            &RepairMerge::Repair(Repair::Shift) => shfts += 1,
+            &RepairMerge::Merge(Repair::Shift, _) => shfts += 1,
+            _ => return false
+        }
+    }
+    shfts == PARSE_AT_LEAST
+}
+
+/// Convert the (merged) repair sequences of the success nodes found by a search into something
+/// more usable: one group of repair sequences per success node.
+fn collect_repairs(cnds: Vec<Cactus<RepairMerge>>) -> Vec<Vec<Vec<ParseRepair>>>
+{
+    fn traverse(rm: &Cactus<RepairMerge>) -> Vec<Vec<Repair>> {
+        let mut out = Vec::new();
+        match rm.val().unwrap() {
+            &RepairMerge::Repair(r) => {
+                let parents = traverse(&rm.parent().unwrap());
+                if parents.is_empty() {
+                    out.push(vec![r]);
+                } else {
+                    for mut pc in parents {
+                        pc.push(r);
+                        out.push(pc);
+                    }
+                }
+            },
+            &RepairMerge::Merge(r, ref vc) => {
+                let parents = traverse(&rm.parent().unwrap());
+                if parents.is_empty() {
+                    out.push(vec![r]);
+                } else {
+                    for mut pc in parents {
+                        pc.push(r);
+                        out.push(pc);
+                    }
+                }
+                for c in vc.vals() {
+                    for mut pc in traverse(c) {
+                        out.push(pc);
+                    }
+                }
+            }
+            &RepairMerge::Terminator => ()
+        }
+        out
+    }
+
+    let mut all_rprs = Vec::with_capacity(cnds.len());
+    for cnd in cnds {
+        all_rprs.push(traverse(&cnd).into_iter()
+                                    .map(|x| repair_to_parse_repair(&x))
+                                    .collect::<Vec<_>>());
+    }
+    all_rprs
+}
+
+fn repair_to_parse_repair(from: &[Repair]) -> Vec<ParseRepair> {
+    from.iter()
+        .map(|y| {
+             match *y {
+                 Repair::InsertTerm(term_idx) => ParseRepair::Insert(term_idx),
+                 Repair::Delete => ParseRepair::Delete,
+                 Repair::Shift => ParseRepair::Shift,
+             }
+         })
+        .collect()
+}
@@ -0,0 +1,276 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::fmt;
+
+use cfgrammar::TIdx;
+use cfgrammar::yacc::YaccGrammar;
+
+use localise::{fill, Localisation};
+use parser::{lexeme_str, Node, ParseRepair};
+use tokid::{tok_tidx, TokenId};
+
+/// The version of the snapshot formats. This is incremented whenever the output of `NodeSnapshot`
+/// or `RepairsSnapshot` changes, so that users with golden files know when they need to be
+/// regenerated. The snapshot formats are versioned independently of `Node::pp`, whose output is
+/// intended for humans and may change at any point.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A compact, one-line, rendering of a parse tree intended for golden-file testing. Nonterminals
+/// are rendered as `(Name child_1 ... child_n)` and terminals as `Name "text"` e.g.:
+/// ```text
+/// (E (T ID "a") + "+" (E (T ID "b")))
+/// ```
+pub struct NodeSnapshot<'a, TokId: TokenId> where TokId: 'a {
+    grm: &'a YaccGrammar,
+    input: &'a str,
+    node: &'a Node<TokId>
+}
+
+impl<'a, TokId: TokenId> NodeSnapshot<'a, TokId> {
+    pub(crate) fn new(grm: &'a YaccGrammar, input: &'a str, node: &'a Node<TokId>) -> Self {
+        NodeSnapshot{grm, input, node}
+    }
+}
+
+impl<'a, TokId: TokenId> fmt::Display for NodeSnapshot<'a, TokId> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // As with Node::pp, we use an explicit stack so that deep trees can't overflow the call
+        // stack. Each entry is either Some(node) (a node yet to be rendered) or None (the closing
+        // bracket of a nonterminal).
+        let mut st = vec![Some(self.node)];
+        let mut first = true;
+        while let Some(e) = st.pop() {
+            match e {
+                Some(&Node::Term{lexeme}) => {
+                    if !first {
+                        f.write_str(" ")?;
+                    }
+                    let t_idx = tok_tidx(self.grm, lexeme.tok_id());
+                    write!(f, "{} ", self.grm.term_name(t_idx).unwrap())?;
+                    write_quoted(f, lexeme_str(self.input, &lexeme))?;
+                },
+                Some(&Node::Nonterm{nonterm_idx, ref nodes}) => {
+                    if !first {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "({}", self.grm.nonterm_name(nonterm_idx))?;
+                    st.push(None);
+                    for x in nodes.iter().rev() {
+                        st.push(Some(x));
+                    }
+                },
+                None => f.write_str(")")?
+            }
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+/// A canonical, one-line, rendering of a repair sequence intended for golden-file testing e.g.:
+/// ```text
+/// Insert "ID", Delete, Shift, Insert {"+" "ID", "*"}
+/// ```
+pub struct RepairsSnapshot<'a> {
+    grm: &'a YaccGrammar,
+    repairs: &'a [ParseRepair],
+    // If not `None`, terminal names and phrases are translated with this table. Localised
+    // renderings are for people, not golden files, so this is only used by error messages.
+    loc: Option<&'a Localisation>
+}
+
+impl<'a> RepairsSnapshot<'a> {
+    pub fn new(grm: &'a YaccGrammar, repairs: &'a [ParseRepair]) -> Self {
+        RepairsSnapshot{grm, repairs, loc: None}
+    }
+
+    /// As `new`, but with terminal names and phrases translated by `loc`.
+    pub(crate) fn localised(grm: &'a YaccGrammar,
+                            repairs: &'a [ParseRepair],
+                            loc: &'a Localisation)
+                         -> Self
+    {
+        RepairsSnapshot{grm, repairs, loc: Some(loc)}
+    }
+
+    fn term(&self, t_idx: TIdx) -> &'a str {
+        let n = self.grm.term_name(t_idx).unwrap();
+        self.loc.map_or(n, |loc| loc.term(n))
+    }
+
+    fn phrase(&self, phrase: &'a str) -> &'a str {
+        self.loc.map_or(phrase, |loc| loc.phrase(phrase))
+    }
+
+    /// Return the (translated) phrase `phrase` with its `{}`s replaced by `args`.
+    fn phrase_with(&self, phrase: &'a str, args: &[&str]) -> String {
+        fill(self.phrase(phrase), args)
+    }
+}
+
+impl<'a> fmt::Display for RepairsSnapshot<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, r) in self.repairs.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            match *r {
+                ParseRepair::Insert(t_idx) => {
+                    write!(f, "{} ", self.phrase("Insert"))?;
+                    write_quoted(f, self.term(t_idx))?;
+                },
+                ParseRepair::InsertSeq(ref seqs) => {
+                    write!(f, "{} {{", self.phrase("Insert"))?;
+                    for (j, seq) in seqs.iter().enumerate() {
+                        if j > 0 {
+                            f.write_str(", ")?;
+                        }
+                        for (k, t_idx) in seq.iter().enumerate() {
+                            if k > 0 {
+                                f.write_str(" ")?;
+                            }
+                            write_quoted(f, self.term(*t_idx))?;
+                        }
+                    }
+                    f.write_str("}")?;
+                },
+                ParseRepair::Delete => f.write_str(self.phrase("Delete"))?,
+                ParseRepair::Shift => f.write_str(self.phrase("Shift"))?,
+                ParseRepair::Substitute{delete_span, insert_term} => {
+                    let term = quoted(self.term(insert_term));
+                    if delete_span == 1 {
+                        f.write_str(&self.phrase_with("Replace with {}", &[&term]))?;
+                    } else {
+                        let span = delete_span.to_string();
+                        f.write_str(&self.phrase_with("Replace {} with {}", &[&span, &term]))?;
+                    }
+                },
+                ParseRepair::Shifts(n) => write!(f, "{} {}", self.phrase("Shift"), n)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write `s` to `f` in double quotes (see `quoted`).
+fn write_quoted(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_str(&quoted(s))
+}
+
+/// Return `s` in double quotes, escaping backslashes, double quotes, and common control characters
+/// so that the output always fits on one line.
+fn quoted(s: &str) -> String {
+    let mut q = String::with_capacity(s.len() + 2);
+    q.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => q.push_str("\\\\"),
+            '"' => q.push_str("\\\""),
+            '\n' => q.push_str("\\n"),
+            '\r' => q.push_str("\\r"),
+            '\t' => q.push_str("\\t"),
+            _ => q.push(c)
+        }
+    }
+    q.push('"');
+    q
+}
+
+#[cfg(test)]
+mod test {
+    use parser::RecoveryKind;
+    use parser::test::do_parse;
+    use super::RepairsSnapshot;
+
+    #[test]
+    fn node_snapshot() {
+        let lexs = "%%
+[a-zA-Z_] 'ID'
+\\+ '+'
+\\\" '\"'
+";
+        let grms = "%start E
+%%
+E: T '+' E
+ | T
+ | '\"' ;
+T: 'ID';
+";
+
+        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, "a+b");
+        assert_eq!(pr.unwrap().snapshot(&grm, "a+b").to_string(),
+                   "(E (T ID \"a\") + \"+\" (E (T ID \"b\")))");
+        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, "\"");
+        assert_eq!(pr.unwrap().snapshot(&grm, "\"").to_string(), "(E \" \"\\\"\")");
+    }
+
+    #[test]
+    fn repairs_snapshot() {
+        let lexs = "%%
+a 'A'
+b 'B'
+c 'C'
+";
+        let grms = "%start S
+%%
+S: T U 'C';
+T: 'A';
+U: 'B';
+";
+
+        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, "c");
+        let (_, errs) = pr.unwrap_err();
+        let rprs = errs[0].repairs().iter()
+                                    .map(|x| RepairsSnapshot::new(&grm, x).to_string())
+                                    .collect::<Vec<_>>();
+        assert_eq!(rprs, vec!["Insert \"A\", Insert \"B\""]);
+    }
+
+    #[test]
+    fn non_ascii_snapshot() {
+        let lexs = "%%
+é 'É'
+λ 'λ'
+";
+        let grms = "%start S
+%%
+S: 'É' 'λ';
+";
+
+        let (grm, pr) = do_parse(RecoveryKind::MF, &lexs, &grms, "é");
+        let (pt, errs) = pr.unwrap_err();
+        assert_eq!(RepairsSnapshot::new(&grm, &errs[0].repairs()[0]).to_string(),
+                   "Insert \"λ\"");
+        assert_eq!(pt.unwrap().snapshot(&grm, "é").to_string(), "(S É \"é\" λ \"\")");
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::borrow::Cow;
+
+use lrlex::Lexeme;
+
+use annotations::line_col;
+
+/// The text that lexemes were produced from. Lexemes only record byte offsets, so anything which
+/// needs their text (e.g. pretty printing, typo-aware recovery, and diagnostics) asks a `Source`
+/// for it. `str` and `String` are `Source`s; implementing `Source` for other representations
+/// (e.g. memory-mapped files, or the ropes used by editors) allows them to be used without first
+/// copying the whole input into a contiguous buffer.
+pub trait Source {
+    /// Return the length of the input in bytes.
+    fn len(&self) -> usize;
+
+    /// Return `true` if the input is empty.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the text of the `len` bytes starting at byte offset `start`, which must lie on
+    /// character boundaries. Implementations should borrow the text where they can: it is only
+    /// copied if it is not stored contiguously.
+    fn text(&self, start: usize, len: usize) -> Cow<str>;
+
+    /// Return the 1-based (line, column) of the byte offset `off`. Columns are counted in
+    /// characters rather than bytes. The default implementation scans all the text before `off`:
+    /// representations which index lines (as most ropes do) should override it.
+    fn line_col(&self, off: usize) -> (usize, usize) {
+        line_col(&self.text(0, off), off)
+    }
+}
+
+impl Source for str {
+    fn len(&self) -> usize {
+        str::len(self)
+    }
+
+    fn text(&self, start: usize, len: usize) -> Cow<str> {
+        Cow::Borrowed(&self[start..start + len])
+    }
+}
+
+impl Source for String {
+    fn len(&self) -> usize {
+        String::len(self)
+    }
+
+    fn text(&self, start: usize, len: usize) -> Cow<str> {
+        Cow::Borrowed(&self[start..start + len])
+    }
+}
+
+impl<'a, S: Source + ?Sized> Source for &'a S {
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn text(&self, start: usize, len: usize) -> Cow<str> {
+        (**self).text(start, len)
+    }
+
+    fn line_col(&self, off: usize) -> (usize, usize) {
+        (**self).line_col(off)
+    }
+}
+
+/// Return the text in `src` that `lexeme` spans.
+pub fn lexeme_text<'a, S, TokId>(src: &'a S, lexeme: &Lexeme<TokId>) -> Cow<'a, str>
+                              where S: Source + ?Sized,
+                                    TokId: Copy
+{
+    src.text(lexeme.start(), lexeme.len())
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use annotations::github_annotations;
+    use lrlex::Lexeme;
+    use parser::{Node, RecoveryKind};
+    use parser::test::{CALLS_GRMS, CALLS_LEXS, do_parse};
+    use super::{lexeme_text, Source};
+
+    /// A source stored in fixed-size chunks, as a rope might be.
+    struct Chunks(Vec<String>);
+
+    impl Source for Chunks {
+        fn len(&self) -> usize {
+            self.0.iter().map(|c| c.len()).sum()
+        }
+
+        fn text(&self, start: usize, len: usize) -> Cow<str> {
+            let mut s = String::new();
+            let mut off = 0;
+            for c in &self.0 {
+                let (c_start, c_end) = (off, off + c.len());
+                if c_end > start && c_start < start + len {
+                    let from = start.max(c_start) - c_start;
+                    let to = (start + len).min(c_end) - c_start;
+                    s.push_str(&c[from..to]);
+                }
+                off = c_end;
+            }
+            Cow::Owned(s)
+        }
+    }
+
+    #[test]
+    fn chunks() {
+        let us = "ab()\ncdef(";
+        let src = Chunks(vec!["ab(".to_owned(), ")\ncd".to_owned(), "ef(".to_owned()]);
+        assert_eq!(src.len(), us.len());
+        assert!(!src.is_empty());
+        assert_eq!(Source::text(us, 2, 6), src.text(2, 6));
+        assert_eq!(lexeme_text(&src, &Lexeme::new(0u16, 5, 4)), "cdef");
+        assert_eq!(src.line_col(7), (2, 3));
+        assert_eq!(src.line_col(7), us.line_col(7));
+
+        let (grm, pr) = do_parse(RecoveryKind::MF, CALLS_LEXS, CALLS_GRMS, &us);
+        let (pt, errs) = pr.unwrap_err();
+        let pt: Node<u16> = pt.unwrap();
+        assert_eq!(pt.pp(&grm, &src), pt.pp(&grm, &us));
+        assert_eq!(github_annotations(&grm, "x", &src, &errs),
+                   github_annotations(&grm, "x", &us, &errs));
+    }
+}
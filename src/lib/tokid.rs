@@ -0,0 +1,185 @@
+// Copyright (c) 2018 King's College London
+// created by the Software Development Team <http://soft-dev.org/>
+//
+// The Universal Permissive License (UPL), Version 1.0
+//
+// Subject to the condition set forth below, permission is hereby granted to any person obtaining a
+// copy of this software, associated documentation and/or data (collectively the "Software"), free
+// of charge and under any and all copyright rights in the Software, and any and all patent rights
+// owned or freely licensable by each licensor hereunder covering either (i) the unmodified
+// Software as contributed to or provided by such licensor, or (ii) the Larger Works (as defined
+// below), to deal in both
+//
+// (a) the Software, and
+// (b) any piece of software and/or hardware listed in the lrgrwrks.txt file
+// if one is included with the Software (each a "Larger Work" to which the Software is contributed
+// by such licensors),
+//
+// without restriction, including without limitation the rights to copy, create derivative works
+// of, display, perform, and distribute the Software and make, use, sell, offer for sale, import,
+// export, have made, and have sold the Software and the Larger Work(s), and to sublicense the
+// foregoing rights on either these or other terms.
+//
+// This license is subject to the following condition: The above copyright notice and either this
+// complete permission notice or at a minimum a reference to the UPL must be included in all copies
+// or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use cfgrammar::TIdx;
+use cfgrammar::yacc::YaccGrammar;
+use num_traits::{PrimInt, Unsigned};
+use typename::TypeName;
+
+/// The number of high bits of a token ID reserved for its `Channel`.
+const CHANNEL_BITS: usize = 2;
+
+/// The channel a lexeme belongs to, encoded in the top two bits of its token ID (see
+/// `TokenId::with_channel`), so that (for example) comments can be kept in the lexemes, and in
+/// parse trees, while remaining distinguishable from the lexemes the grammar is really about.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Channel {
+    /// The channel of ordinary lexemes: all token IDs without channel bits are on it.
+    Default,
+    /// The channel of lexemes, such as comments, which are of no interest to most consumers.
+    Hidden,
+    /// The channel of lexemes which represent lexing errors.
+    Error
+}
+
+/// The type of the token IDs of lexemes (conventionally the type parameter `TokId`) which lrpar
+/// requires throughout. This is implemented for every unsigned integer type: smaller types make
+/// lexemes, and statically compiled grammars, more compact, but users who don't care about
+/// compactness can simply use `usize`. There is never any need to implement it by hand.
+///
+/// The top two bits of a token ID can be used to record the lexeme's `Channel`. The parser
+/// ignores these bits, and preserves them in the lexemes of parse trees, provided that the
+/// grammar's terminals all fit in the remaining bits (e.g. a grammar with at most 64 terminals if
+/// `TokId` is `u8`); if they don't, channels can't be used.
+pub trait TokenId: Debug + Hash + PrimInt + TryFrom<usize> + TypeName + Unsigned {
+    /// Return the channel recorded in this token ID. The fourth, unused, bit pattern is treated
+    /// as `Channel::Error`.
+    fn channel(self) -> Channel {
+        match (self >> channel_shift::<Self>()).to_u8().unwrap() {
+            0 => Channel::Default,
+            1 => Channel::Hidden,
+            _ => Channel::Error
+        }
+    }
+
+    /// Return this token ID with its channel set to `channel`.
+    fn with_channel(self, channel: Channel) -> Self {
+        let bits = match channel {
+            Channel::Default => 0,
+            Channel::Hidden => 1,
+            Channel::Error => 2
+        };
+        self.without_channel() | (Self::from(bits).unwrap() << channel_shift::<Self>())
+    }
+
+    /// Return this token ID with its channel bits cleared, i.e. the terminal it represents.
+    fn without_channel(self) -> Self {
+        self & (Self::max_value() >> CHANNEL_BITS)
+    }
+}
+
+impl<T> TokenId for T where T: Debug + Hash + PrimInt + TryFrom<usize> + TypeName + Unsigned {}
+
+/// Return the amount a token ID must be shifted right by to leave only its channel bits.
+fn channel_shift<TokId: TokenId>() -> usize {
+    TokId::zero().count_zeros() as usize - CHANNEL_BITS
+}
+
+/// Can all of `grm`'s terminals be represented as `TokId`s without using the channel bits?
+pub(crate) fn channels_fit<TokId: TokenId>(grm: &YaccGrammar) -> bool {
+    TokId::max_value().without_channel()
+                      .to_usize()
+                      .map_or(true, |m| grm.terms_len() as usize <= m + 1)
+}
+
+/// Return the terminal in `grm` that the token ID `tok_id` represents, ignoring its channel bits
+/// if `grm` leaves room for them.
+pub(crate) fn tok_tidx<TokId: TokenId>(grm: &YaccGrammar, tok_id: TokId) -> TIdx {
+    let tok_id = if channels_fit::<TokId>(grm) { tok_id.without_channel() } else { tok_id };
+    TIdx::from(tok_id.to_u32().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use cfgrammar::yacc::{yacc_grm, YaccKind};
+    use lrlex::build_lex;
+    use lrlex::Lexeme;
+    use lrtable::{Minimiser, from_yacc};
+    use parser::{Node, RTParserBuilder};
+    use parser::test::{CALLS_GRMS, CALLS_LEXS, calls_env};
+    use super::{Channel, TokenId};
+
+    #[test]
+    fn usize_tok_ids() {
+        let mut lexerdef = build_lex::<usize>(CALLS_LEXS).unwrap();
+        let grm = yacc_grm(YaccKind::Original, CALLS_GRMS).unwrap();
+        let (sgraph, stable) = from_yacc(&grm, Minimiser::Pager).unwrap();
+        let rule_ids = grm.terms_map().iter()
+                                      .map(|(&n, &i)| (n, usize::from(i)))
+                                      .collect();
+        lexerdef.set_rule_ids(&rule_ids);
+        let lexemes = lexerdef.lexer("f()g)").lexemes().unwrap();
+        let rtpb = RTParserBuilder::<usize>::new(&grm, &sgraph, &stable);
+        let (pt, errs) = rtpb.parse(&lexemes).unwrap_err();
+        assert!(pt.is_some());
+        assert_eq!(errs[0].lexeme().tok_id(), usize::from(grm.term_idx(")").unwrap()));
+    }
+
+    #[test]
+    fn channels() {
+        assert_eq!(5u8.channel(), Channel::Default);
+        assert_eq!(5u8.with_channel(Channel::Hidden), 0b0100_0101);
+        assert_eq!(5u8.with_channel(Channel::Error).channel(), Channel::Error);
+        assert_eq!(5u8.with_channel(Channel::Error).with_channel(Channel::Default), 5);
+        assert_eq!(5u32.with_channel(Channel::Hidden).without_channel(), 5);
+
+        let (grm, sgraph, stable, lexemes) = calls_env("f()g()");
+        let lexemes = lexemes.iter()
+                             .enumerate()
+                             .map(|(i, l)| {
+                                 let tok_id = if i == 3 {
+                                                  l.tok_id().with_channel(Channel::Hidden)
+                                              } else {
+                                                  l.tok_id()
+                                              };
+                                 Lexeme::new(tok_id, l.start(), l.len())
+                             })
+                             .collect::<Vec<_>>();
+        let pt = RTParserBuilder::new(&grm, &sgraph, &stable).parse(&lexemes).unwrap();
+        assert_eq!(pt.pp(&grm, "f()g()"), "Calls
+ Calls
+  Calls
+  Call
+   ID f
+   ( (
+   ) )
+ Call
+  ID g
+  ( (
+  ) )
+");
+        let mut chans = Vec::new();
+        let mut st = vec![&pt];
+        while let Some(n) = st.pop() {
+            match *n {
+                Node::Term{lexeme} => chans.push(lexeme.tok_id().channel()),
+                Node::Nonterm{ref nodes, ..} => st.extend(nodes.iter().rev())
+            }
+        }
+        assert_eq!(chans, vec![Channel::Default, Channel::Default, Channel::Default,
+                               Channel::Hidden, Channel::Default, Channel::Default]);
+    }
+}
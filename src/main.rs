@@ -41,12 +41,15 @@ use std::{env, process};
 use std::fs::File;
 use std::io::{Read, stderr, Write};
 use std::path::Path;
+use std::time::Instant;
 
 use getopts::Options;
+use cfgrammar::TIdx;
 use cfgrammar::yacc::{yacc_grm, YaccKind};
 use lrlex::build_lex;
 use lrtable::{Minimiser, from_yacc};
-use lrpar::parser::{parse_rcvry, ParseRepair, RecoveryKind};
+use lrpar::{automaton_summary, conflict_examples, github_annotations, literal_lex_rules, sarif};
+use lrpar::parser::{ParseRepair, RecoveryKind, RTParserBuilder};
 use num_traits::ToPrimitive;
 
 fn usage(prog: &str, msg: &str) -> ! {
@@ -59,7 +62,7 @@ fn usage(prog: &str, msg: &str) -> ! {
         writeln!(&mut stderr(), "{}", msg).ok();
     }
     writeln!(&mut stderr(),
-             "Usage: {} [-r <cpctplus|cpctplusdyndist|mf|none>] [-y <eco|original>] <lexer.l> <parser.y> <input file>",
+             "Usage: {} [-a] [-t] [-f <github|sarif|text>] [-r <cpctplus|cpctplusdyndist|mf|panic|race|none>] [-y <eco|original>] <lexer.l> <parser.y> <input file>",
              leaf).ok();
     process::exit(1);
 }
@@ -81,10 +84,18 @@ fn main() {
     let args: Vec<String> = env::args().collect();
     let prog = &args[0];
     let matches = match Options::new()
+                                .optflag("a", "automaton",
+                                         "Print the automaton, and an example of each conflict, \
+                                          to stderr")
+                                .optopt("f", "format",
+                                        "Format errors are reported in (default: text)",
+                                        "github|sarif|text")
                                 .optflag("h", "help", "")
                                 .optopt("r", "recoverer",
                                         "Recoverer to be used (default: mf)",
-                                        "cpctplus|mf|none")
+                                        "cpctplus|mf|panic|race|none")
+                                .optflag("t", "timing",
+                                         "Print the time taken by each phase to stderr")
                                 .optopt("y", "yaccvariant",
                                         "Yacc variant to be parsed (default: Original)",
                                         "Original|Eco")
@@ -103,12 +114,26 @@ fn main() {
             match &*s.to_lowercase() {
                 "cpctplus" => RecoveryKind::CPCTPlus,
                 "mf" => RecoveryKind::MF,
+                "panic" => RecoveryKind::Panic,
+                "race" => RecoveryKind::Race,
                 "none" => RecoveryKind::None,
                 _ => usage(prog, &format!("Unknown recoverer '{}'.", s))
             }
         }
     };
 
+    let format = match matches.opt_str("f") {
+        None => "text".to_owned(),
+        Some(s) => {
+            let s = s.to_lowercase();
+            match &*s {
+                "github" | "sarif" | "text" => (),
+                _ => usage(prog, &format!("Unknown format '{}'.", s))
+            }
+            s
+        }
+    };
+
     let yacckind = match matches.opt_str("y") {
         None => YaccKind::Original,
         Some(s) => {
@@ -125,7 +150,8 @@ fn main() {
     }
 
     let lex_l_path = &matches.free[0];
-    let mut lexerdef = match build_lex::<u16>(&read_file(lex_l_path)) {
+    let mut lex_src = read_file(lex_l_path);
+    let mut lexerdef = match build_lex::<u16>(&lex_src) {
         Ok(ast) => ast,
         Err(s) => {
             writeln!(&mut stderr(), "{}: {}", &lex_l_path, &s).ok();
@@ -141,6 +167,7 @@ fn main() {
             process::exit(1);
         }
     };
+    let before = Instant::now();
     let (sgraph, stable) = match from_yacc(&grm, Minimiser::Pager) {
         Ok(x) => x,
         Err(s) => {
@@ -148,11 +175,28 @@ fn main() {
             process::exit(1);
         }
     };
+    let table_time = before.elapsed();
+    if matches.opt_present("a") {
+        write!(&mut stderr(), "{}", automaton_summary(&grm, &sgraph, &stable)).ok();
+        for ex in conflict_examples(&grm, &sgraph, &stable) {
+            writeln!(&mut stderr(), "Conflict example: {}", ex.pp(&grm)).ok();
+        }
+    }
 
+    let rule_ids = grm.terms_map().iter()
+                                  .map(|(&n, &i)| (n, usize::from(i).to_u16().unwrap()))
+                                  .collect();
+    // Literal terminals (e.g. '+') which the lexer doesn't define are lexed as themselves.
+    let literal_rules = literal_lex_rules(lexerdef.set_rule_ids(&rule_ids)
+                                                  .0
+                                                  .iter()
+                                                  .flat_map(|ts| ts.iter().cloned()));
+    if !literal_rules.is_empty() {
+        lex_src.push('\n');
+        lex_src.push_str(&literal_rules);
+        lexerdef = build_lex::<u16>(&lex_src).unwrap();
+    }
     {
-        let rule_ids = grm.terms_map().iter()
-                                      .map(|(&n, &i)| (n, usize::from(i).to_u16().unwrap()))
-                                      .collect();
         let (missing_from_lexer, missing_from_parser) = lexerdef.set_rule_ids(&rule_ids);
         if let Some(tokens) = missing_from_parser {
             writeln!(&mut stderr(), "Warning: these tokens are defined in the lexer but not referenced in the\ngrammar:").ok();
@@ -177,12 +221,39 @@ fn main() {
         }
     }
 
-    let input = read_file(&matches.free[2]);
+    let input_path = &matches.free[2];
+    let input = read_file(input_path);
+    let before = Instant::now();
     let lexer = lexerdef.lexer(&input);
     let lexemes = lexer.lexemes().unwrap();
-    let term_cost = |_| 1; // Cost of inserting/deleting a terminal
-    match parse_rcvry::<u16, _>(recoverykind, &grm, &term_cost, &sgraph, &stable, &lexemes) {
-        Ok(pt) => println!("{}", pt.pp(&grm, &input)),
+    let lex_time = before.elapsed();
+    let term_cost = |_: TIdx| 1; // Cost of inserting/deleting a terminal
+    let before = Instant::now();
+    let pr = RTParserBuilder::<u16>::new(&grm, &sgraph, &stable).recoverer(recoverykind)
+                                                                .term_costs(&term_cost)
+                                                                .substitutions(true)
+                                                                .typo_aware(&input)
+                                                                .parse(&lexemes);
+    if matches.opt_present("t") {
+        writeln!(&mut stderr(), "Tables: {:?}\nLexing: {:?} ({} lexemes)\nParsing: {:?}",
+                 table_time, lex_time, lexemes.len(), before.elapsed()).ok();
+    }
+    match pr {
+        Ok(pt) => {
+            match &*format {
+                "github" => (),
+                "sarif" => println!("{}", sarif::<u16>(&grm, input_path, &input, &[])),
+                _ => println!("{}", pt.pp(&grm, &input))
+            }
+        },
+        Err((_, ref errs)) if format == "github" => {
+            print!("{}", github_annotations(&grm, input_path, &input, errs));
+            process::exit(1);
+        },
+        Err((_, ref errs)) if format == "sarif" => {
+            println!("{}", sarif(&grm, input_path, &input, errs));
+            process::exit(1);
+        },
         Err((o_pt, errs)) => {
             match o_pt {
                 Some(pt) => println!("{}", pt.pp(&grm, &input)),
@@ -192,9 +263,12 @@ fn main() {
                 let (line, col) = lexer.line_and_col(e.lexeme()).unwrap();
                 if e.repairs().is_empty() {
                     println!("Error at line {} col {}. No repairs found.", line, col);
-                    continue;
+                } else {
+                    println!("Error at line {} col {}. Repairs found:", line, col);
+                }
+                if let Some(t_idx) = e.suggestion() {
+                    println!("  Did you mean \"{}\"?", grm.term_name(t_idx).unwrap());
                 }
-                println!("Error at line {} col {}. Repairs found:", line, col);
                 for repair in e.repairs() {
                     let mut lex_idx = e.lexeme_idx();
                     let mut out = vec![];
@@ -228,6 +302,20 @@ fn main() {
                                     out.push(format!("Shift \"{}\"", t));
                                 }
                                 lex_idx += 1;
+                            },
+                            ParseRepair::Substitute{delete_span, insert_term} => {
+                                let first = lexemes[lex_idx];
+                                let last = lexemes[lex_idx + delete_span - 1];
+                                let t = input[first.start()..last.start() + last.len()]
+                                             .replace("\n", "\\n");
+                                out.push(format!("Replace \"{}\" with \"{}\"",
+                                                 t,
+                                                 grm.term_name(insert_term).unwrap()));
+                                lex_idx += delete_span;
+                            },
+                            ParseRepair::Shifts(n) => {
+                                out.push(format!("Shift {}", n));
+                                lex_idx += n;
                             }
                         }
                     }